@@ -0,0 +1,17 @@
+//! Generates the C header for the library in `$OUT_DIR/bft.h`. The copy in `include/bft.h`,
+//! which is kept in the repository, is only updated when `BFT_UPDATE_HEADER` is set, so that
+//! builds don't write to the source tree.
+
+fn main() {
+    let crate_dir = std::env::var("CARGO_MANIFEST_DIR").expect("Cargo should set the crate dir.");
+    let out_dir = std::env::var("OUT_DIR").expect("Cargo should set the output dir.");
+    println!("cargo:rerun-if-changed=src/lib.rs");
+    println!("cargo:rerun-if-changed=cbindgen.toml");
+    println!("cargo:rerun-if-env-changed=BFT_UPDATE_HEADER");
+    let bindings =
+        cbindgen::generate(&crate_dir).expect("The header should be generated from the crate.");
+    bindings.write_to_file(format!("{out_dir}/bft.h"));
+    if std::env::var_os("BFT_UPDATE_HEADER").is_some() {
+        bindings.write_to_file(format!("{crate_dir}/include/bft.h"));
+    }
+}
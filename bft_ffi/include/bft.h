@@ -0,0 +1,90 @@
+#ifndef BFT_H
+#define BFT_H
+
+/* This file is generated from src/lib.rs by cbindgen. Do not edit it by hand. */
+
+#include <stdarg.h>
+#include <stdbool.h>
+#include <stddef.h>
+#include <stdint.h>
+#include <stdlib.h>
+
+// The result of calling a library function.
+typedef enum BftStatus {
+  // The call succeeded.
+  BFT_STATUS_OK = 0,
+  // A required pointer was null.
+  BFT_STATUS_NULL_ARGUMENT,
+  // The program's brackets don't match.
+  BFT_STATUS_PARSE_ERROR,
+  // The program failed while running.
+  BFT_STATUS_RUNTIME_ERROR,
+  // The program executed more instructions than the limit allows.
+  BFT_STATUS_STEP_LIMIT,
+  // The program wrote more output than fits in the output buffer.
+  BFT_STATUS_OUTPUT_FULL,
+} BftStatus;
+
+// A parsed program, ready to run.
+typedef struct BftProgram BftProgram;
+
+// Limits on a program's resources while it runs.
+typedef struct BftLimits {
+  // Number of cells on the tape, or 0 for the default of 30,000.
+  size_t cells;
+  // Whether the tape grows when the head moves off its right end.
+  bool extensible;
+  // The most instructions the program may execute, or 0 for no limit.
+  uint64_t max_steps;
+} BftLimits;
+
+#ifdef __cplusplus
+extern "C" {
+#endif // __cplusplus
+
+// Parse the program in the `len` bytes at `code`, checking that its brackets match.
+//
+// Returns null if the program is invalid, or too long to parse. The program must be released
+// with `bft_program_free`.
+//
+// # Safety
+// `code` must point to `len` readable bytes.
+struct BftProgram *bft_parse(const uint8_t *code, size_t len);
+
+// Release a program returned by `bft_parse`. Null is ignored.
+//
+// # Safety
+// `program` must be null, or a program from `bft_parse` that hasn't already been released.
+void bft_program_free(struct BftProgram *program);
+
+// Run `program`, reading its input from the `input_len` bytes at `input`, and writing its output
+// to the `output_capacity` bytes at `output`. The number of bytes written is stored in
+// `output_len`, even if the program fails. If `limits` is null, the default tape is used with no
+// step limit.
+//
+// Once the input runs out, the program reads the end of input, leaving cells unchanged.
+//
+// # Safety
+// `program` must come from `bft_parse`, and not have been released. `input` must point to
+// `input_len` readable bytes, and `output` to `output_capacity` writable bytes, unless the
+// length is 0, when the pointer may be null. `limits` must be null or point to a `BftLimits`,
+// and `output_len` must point to a writable `size_t`.
+enum BftStatus bft_run(const struct BftProgram *program,
+                       const struct BftLimits *limits,
+                       const uint8_t *input,
+                       size_t input_len,
+                       uint8_t *output,
+                       size_t output_capacity,
+                       size_t *output_len);
+
+// A description of the last error on the calling thread, or null if there hasn't been one.
+//
+// The string is owned by the library, and stays valid until the next library call on the same
+// thread.
+const char *bft_last_error(void);
+
+#ifdef __cplusplus
+}  // extern "C"
+#endif  // __cplusplus
+
+#endif  /* BFT_H */
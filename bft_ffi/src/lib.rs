@@ -0,0 +1,342 @@
+//! A C interface for embedding the Brainf*ck interpreter in other applications.
+//!
+//! The C declarations are in `include/bft.h`, which is generated from this crate, and updated
+//! by building it with `BFT_UPDATE_HEADER` set. A
+//! program is parsed with `bft_parse`, run any number of times with `bft_run`, and released with
+//! `bft_program_free`. When a function fails, `bft_last_error` describes what went wrong.
+#![warn(missing_docs)]
+
+use std::cell::RefCell;
+use std::ffi::{c_char, CString};
+use std::io;
+use std::io::Write;
+use std::num::NonZeroUsize;
+use std::ptr;
+
+use bft_interp::{StepOutcome, VMError, BFVM};
+use bft_types::{BFprogram, MAX_SOURCE_LEN};
+
+thread_local! {
+    static LAST_ERROR: RefCell<Option<CString>> = const { RefCell::new(None) };
+}
+
+/// Record `message` as the error for `bft_last_error` to return.
+fn set_error(message: &impl ToString) {
+    let message = CString::new(message.to_string().replace('\0', " "))
+        .expect("Interior nul bytes have been removed.");
+    LAST_ERROR.with(|error| *error.borrow_mut() = Some(message));
+}
+
+/// The result of calling a library function.
+#[repr(C)]
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum BftStatus {
+    /// The call succeeded.
+    Ok = 0,
+
+    /// A required pointer was null.
+    NullArgument,
+
+    /// The program's brackets don't match.
+    ParseError,
+
+    /// The program failed while running.
+    RuntimeError,
+
+    /// The program executed more instructions than the limit allows.
+    StepLimit,
+
+    /// The program wrote more output than fits in the output buffer.
+    OutputFull,
+}
+
+/// Limits on a program's resources while it runs.
+#[repr(C)]
+#[derive(Clone, Copy, Debug)]
+pub struct BftLimits {
+    /// Number of cells on the tape, or 0 for the default of 30,000.
+    pub cells: usize,
+
+    /// Whether the tape grows when the head moves off its right end.
+    pub extensible: bool,
+
+    /// The most instructions the program may execute, or 0 for no limit.
+    pub max_steps: u64,
+}
+
+/// A parsed program, ready to run.
+pub struct BftProgram(BFprogram);
+
+/// Parse the program in the `len` bytes at `code`, checking that its brackets match.
+///
+/// Returns null if the program is invalid, or too long to parse. The program must be released
+/// with `bft_program_free`.
+///
+/// # Safety
+/// `code` must point to `len` readable bytes.
+#[no_mangle]
+pub unsafe extern "C" fn bft_parse(code: *const u8, len: usize) -> *mut BftProgram {
+    if code.is_null() {
+        set_error(&"code is null");
+        return ptr::null_mut();
+    }
+    // Parsing panics on longer programs, which mustn't unwind into the caller.
+    if len > MAX_SOURCE_LEN {
+        set_error(&format!(
+            "the program is longer than {MAX_SOURCE_LEN} bytes"
+        ));
+        return ptr::null_mut();
+    }
+    // SAFETY: The caller guarantees that `code` points to `len` bytes.
+    let code = unsafe { std::slice::from_raw_parts(code, len) };
+    let mut program = BFprogram::new("<ffi>", code);
+    match program.validate_brackets() {
+        Ok(()) => Box::into_raw(Box::new(BftProgram(program))),
+        Err(err) => {
+            set_error(&err);
+            ptr::null_mut()
+        }
+    }
+}
+
+/// Release a program returned by `bft_parse`. Null is ignored.
+///
+/// # Safety
+/// `program` must be null, or a program from `bft_parse` that hasn't already been released.
+#[no_mangle]
+pub unsafe extern "C" fn bft_program_free(program: *mut BftProgram) {
+    if !program.is_null() {
+        // SAFETY: The caller guarantees that the program came from `bft_parse`, which created
+        // it with `Box::into_raw`.
+        drop(unsafe { Box::from_raw(program) });
+    }
+}
+
+/// Writes to a fixed buffer, failing once it is full.
+struct BufferWriter<'a> {
+    buffer: &'a mut [u8],
+    len: usize,
+    full: bool,
+}
+
+impl Write for BufferWriter<'_> {
+    fn write(&mut self, data: &[u8]) -> io::Result<usize> {
+        let space = &mut self.buffer[self.len..];
+        if space.is_empty() && !data.is_empty() {
+            self.full = true;
+            return Err(io::ErrorKind::WriteZero.into());
+        }
+        let n = data.len().min(space.len());
+        space[..n].copy_from_slice(&data[..n]);
+        self.len += n;
+        Ok(n)
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        Ok(())
+    }
+}
+
+/// Run `program` to completion, within `limits`.
+fn run(
+    program: &BFprogram,
+    limits: BftLimits,
+    mut input: &[u8],
+    output: &mut BufferWriter,
+) -> BftStatus {
+    let mut vm: BFVM<u8> = BFVM::new(NonZeroUsize::new(limits.cells), limits.extensible);
+    let mut steps = 0u64;
+    loop {
+        match vm.step(program, &mut input, output) {
+            Ok(StepOutcome::Finished | StepOutcome::BrokenPipe) => return BftStatus::Ok,
+            Ok(StepOutcome::Running) => {}
+            Err(VMError::IOError(..)) if output.full => {
+                set_error(&"the output buffer is full");
+                return BftStatus::OutputFull;
+            }
+            Err(err) => {
+                set_error(&err);
+                return BftStatus::RuntimeError;
+            }
+        }
+        steps += 1;
+        if limits.max_steps != 0 && steps >= limits.max_steps {
+            set_error(&format!("step limit of {} exceeded", limits.max_steps));
+            return BftStatus::StepLimit;
+        }
+    }
+}
+
+/// Run `program`, reading its input from the `input_len` bytes at `input`, and writing its output
+/// to the `output_capacity` bytes at `output`. The number of bytes written is stored in
+/// `output_len`, even if the program fails. If `limits` is null, the default tape is used with no
+/// step limit.
+///
+/// Once the input runs out, the program reads the end of input, leaving cells unchanged.
+///
+/// # Safety
+/// `program` must come from `bft_parse`, and not have been released. `input` must point to
+/// `input_len` readable bytes, and `output` to `output_capacity` writable bytes, unless the
+/// length is 0, when the pointer may be null. `limits` must be null or point to a `BftLimits`,
+/// and `output_len` must point to a writable `size_t`.
+#[no_mangle]
+pub unsafe extern "C" fn bft_run(
+    program: *const BftProgram,
+    limits: *const BftLimits,
+    input: *const u8,
+    input_len: usize,
+    output: *mut u8,
+    output_capacity: usize,
+    output_len: *mut usize,
+) -> BftStatus {
+    if program.is_null()
+        || output_len.is_null()
+        || (input.is_null() && input_len != 0)
+        || (output.is_null() && output_capacity != 0)
+    {
+        set_error(&"a required pointer is null");
+        return BftStatus::NullArgument;
+    }
+    // SAFETY: The caller guarantees that each pointer is valid, and the checks above make sure
+    // that null pointers are only used with empty buffers.
+    let (program, limits, input, output) = unsafe {
+        (
+            &(*program).0,
+            limits.as_ref().copied().unwrap_or(BftLimits {
+                cells: 0,
+                extensible: false,
+                max_steps: 0,
+            }),
+            if input.is_null() {
+                &[][..]
+            } else {
+                std::slice::from_raw_parts(input, input_len)
+            },
+            if output.is_null() {
+                &mut [][..]
+            } else {
+                std::slice::from_raw_parts_mut(output, output_capacity)
+            },
+        )
+    };
+    let mut writer = BufferWriter {
+        buffer: output,
+        len: 0,
+        full: false,
+    };
+    let status = run(program, limits, input, &mut writer);
+    // SAFETY: Checked for null above, and the caller guarantees that it is writable.
+    unsafe { *output_len = writer.len };
+    status
+}
+
+/// A description of the last error on the calling thread, or null if there hasn't been one.
+///
+/// The string is owned by the library, and stays valid until the next library call on the same
+/// thread.
+#[no_mangle]
+pub extern "C" fn bft_last_error() -> *const c_char {
+    LAST_ERROR.with(|error| error.borrow().as_ref().map_or(ptr::null(), |e| e.as_ptr()))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::ffi::CStr;
+
+    fn last_error() -> String {
+        // SAFETY: The library returns a valid C string when there is an error.
+        unsafe { CStr::from_ptr(bft_last_error()) }
+            .to_string_lossy()
+            .into_owned()
+    }
+
+    fn run_code(
+        code: &[u8],
+        limits: Option<BftLimits>,
+        input: &[u8],
+        capacity: usize,
+    ) -> (BftStatus, Vec<u8>) {
+        let mut output = vec![0; capacity];
+        let mut len = 0;
+        // SAFETY: Each pointer refers to a live buffer of the given length.
+        unsafe {
+            let program = bft_parse(code.as_ptr(), code.len());
+            assert!(!program.is_null());
+            let limits = limits.as_ref().map_or(ptr::null(), ptr::from_ref);
+            let status = bft_run(
+                program,
+                limits,
+                input.as_ptr(),
+                input.len(),
+                output.as_mut_ptr(),
+                output.len(),
+                &raw mut len,
+            );
+            bft_program_free(program);
+            output.truncate(len);
+            (status, output)
+        }
+    }
+
+    #[test]
+    fn running() {
+        assert_eq!(
+            run_code(b",.,.,.,.", None, b"echo", 10),
+            (BftStatus::Ok, b"echo".to_vec())
+        );
+    }
+
+    #[test]
+    fn parse_errors() {
+        // SAFETY: The code is a live buffer of the given length.
+        let program = unsafe { bft_parse(b"+]".as_ptr(), 2) };
+        assert!(program.is_null());
+        assert_eq!(
+            last_error(),
+            "Unexpected closing bracket ']' at [<ffi>:1:2]"
+        );
+
+        // Nothing is read from a program that's too long, so no buffer that long is needed.
+        if let Some(len) = MAX_SOURCE_LEN.checked_add(1) {
+            // SAFETY: `bft_parse` checks the length before reading the code.
+            let program = unsafe { bft_parse(b"+".as_ptr(), len) };
+            assert!(program.is_null());
+            assert_eq!(
+                last_error(),
+                format!("the program is longer than {MAX_SOURCE_LEN} bytes")
+            );
+        }
+    }
+
+    #[test]
+    fn header_is_up_to_date() {
+        assert_eq!(
+            include_str!(concat!(env!("OUT_DIR"), "/bft.h")),
+            include_str!("../include/bft.h"),
+            "Build with BFT_UPDATE_HEADER set to update include/bft.h"
+        );
+    }
+
+    #[test]
+    fn limits() {
+        let limits = BftLimits {
+            cells: 2,
+            extensible: false,
+            max_steps: 100,
+        };
+        assert_eq!(
+            run_code(b"+[]", Some(limits), b"", 0),
+            (BftStatus::StepLimit, Vec::new())
+        );
+        assert_eq!(last_error(), "step limit of 100 exceeded");
+        assert_eq!(
+            run_code(b">>", Some(limits), b"", 0).0,
+            BftStatus::RuntimeError
+        );
+        assert_eq!(
+            run_code(b"+.+.+.", None, b"", 2),
+            (BftStatus::OutputFull, vec![1, 2])
+        );
+    }
+}
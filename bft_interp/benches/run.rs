@@ -0,0 +1,96 @@
+//! Benchmarks for running large programs, like those written by other programs, where how much
+//! of the program fits in the cache matters as much as the work each instruction does.
+
+use criterion::{criterion_group, criterion_main, Criterion, Throughput};
+
+use bft_interp::{VmConfig, VmPool, BFVM};
+use bft_types::BFprogram;
+
+/// Number of times the block of code is repeated in the generated program.
+const BLOCKS: usize = 100_000;
+
+/// A program of about a million instructions, which sets each cell in turn to two, and moves it
+/// to the next cell with a short loop.
+fn generate() -> BFprogram {
+    let code = "++[->+<]>".repeat(BLOCKS) + "\n";
+    let mut program = BFprogram::new("bench.b", code.as_bytes());
+    program
+        .validate_brackets()
+        .expect("The generated program should have balanced brackets.");
+    program
+}
+
+/// A program that spends almost all of its time in a tight inner loop, which runs about 65
+/// thousand times.
+fn nested_loops() -> BFprogram {
+    let mut program = BFprogram::new("bench.b", b"-[>-[->+>++<<]<-]");
+    program
+        .validate_brackets()
+        .expect("The nested loops should have balanced brackets.");
+    program
+}
+
+fn run(c: &mut Criterion) {
+    let program = generate();
+    let mut group = c.benchmark_group("BFVM::run");
+    group.sample_size(20);
+    group.throughput(Throughput::Elements(program.instructions().len() as u64));
+    group.bench_function("generated", |b| {
+        b.iter(|| {
+            let mut vm: BFVM<u8> = BFVM::new(None, true);
+            vm.run(&program, &mut &b""[..], &mut Vec::new())
+                .expect("The generated program should run.");
+        });
+    });
+    group.finish();
+}
+
+fn tight_loops(c: &mut Criterion) {
+    let program = nested_loops();
+    let mut group = c.benchmark_group("tight loops");
+    group.sample_size(10);
+    group.bench_function("run", |b| {
+        b.iter(|| {
+            let mut vm: BFVM<u8> = BFVM::new(None, false);
+            vm.run(&program, &mut &b""[..], &mut Vec::new())
+                .expect("The nested loops should run.");
+        });
+    });
+    group.bench_function("run_fast_unchecked", |b| {
+        b.iter(|| {
+            let mut vm: BFVM<u8> = BFVM::new(None, false);
+            vm.run_fast_unchecked(&program, &mut &b""[..], &mut Vec::new())
+                .expect("The nested loops should run.");
+        });
+    });
+    group.finish();
+}
+
+/// Many runs of a short program, where making the VM costs more than running it.
+fn short_programs(c: &mut Criterion) {
+    let mut program = BFprogram::new("bench.b", b"++++++[>++++++++<-]>.");
+    program
+        .validate_brackets()
+        .expect("The short program should have balanced brackets.");
+    let mut group = c.benchmark_group("short programs");
+    group.bench_function("new VM", |b| {
+        b.iter(|| {
+            let mut vm: BFVM<u8> = BFVM::new(None, false);
+            vm.run(&program, &mut &b""[..], &mut Vec::new())
+                .expect("The short program should run.");
+        });
+    });
+    let mut pool: VmPool<u8> = VmPool::new(1);
+    group.bench_function("pooled VM", |b| {
+        b.iter(|| {
+            let mut vm = pool.checkout(VmConfig::default());
+            vm.run(&program, &mut &b""[..], &mut Vec::new())
+                .expect("The short program should run.");
+            pool.checkin(VmConfig::default(), vm);
+        });
+    });
+    group.finish();
+}
+
+criterion_group!(benches, run, tight_loops, short_programs);
+criterion_main!(benches);
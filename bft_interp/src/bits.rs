@@ -0,0 +1,116 @@
+//! Adapters between bit-level programs and byte-oriented I/O.
+//!
+//! Programs on a tape of single bits read and write one bit at a time. These adapters pack those
+//! bits into bytes, least significant bit first, so bit-level programs can work with normal
+//! stdin and stdout.
+
+use std::io;
+use std::io::{Read, Write};
+
+/// Supplies the bits of the bytes read from an underlying reader, one bit per byte of output.
+#[derive(Debug)]
+pub struct BitReader<R> {
+    inner: R,
+    byte: u8,
+    remaining: u8,
+}
+
+impl<R: Read> BitReader<R> {
+    /// Wrap `inner`, which supplies the bytes to split into bits.
+    pub fn new(inner: R) -> Self {
+        BitReader {
+            inner,
+            byte: 0,
+            remaining: 0,
+        }
+    }
+}
+
+impl<R: Read> Read for BitReader<R> {
+    /// Fill `buf` with bits, each stored as a byte holding either 0 or 1.
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        for (count, slot) in buf.iter_mut().enumerate() {
+            if self.remaining == 0 {
+                let mut byte = [0];
+                if self.inner.read(&mut byte)? == 0 {
+                    return Ok(count);
+                }
+                self.byte = byte[0];
+                self.remaining = 8;
+            }
+            *slot = self.byte & 1;
+            self.byte >>= 1;
+            self.remaining -= 1;
+        }
+        Ok(buf.len())
+    }
+}
+
+/// Packs bits into bytes before writing them to an underlying writer.
+///
+/// Each byte written is treated as a single bit, which is set if the byte is non-zero. Flushing
+/// writes out any partially filled byte, padded with zeros, so it should only happen once the
+/// program has finished.
+#[derive(Debug)]
+pub struct BitWriter<W: Write> {
+    inner: W,
+    byte: u8,
+    count: u8,
+}
+
+impl<W: Write> BitWriter<W> {
+    /// Wrap `inner`, which receives the packed bytes.
+    pub fn new(inner: W) -> Self {
+        BitWriter {
+            inner,
+            byte: 0,
+            count: 0,
+        }
+    }
+}
+
+impl<W: Write> Write for BitWriter<W> {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        for bit in buf {
+            self.byte |= u8::from(*bit != 0) << self.count;
+            self.count += 1;
+            if self.count == 8 {
+                self.inner.write_all(&[self.byte])?;
+                self.byte = 0;
+                self.count = 0;
+            }
+        }
+        Ok(buf.len())
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        if self.count != 0 {
+            self.inner.write_all(&[self.byte])?;
+            self.byte = 0;
+            self.count = 0;
+        }
+        self.inner.flush()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn reading_bits() {
+        let mut reader = BitReader::new(&b"A\x80"[..]);
+        let mut bits = Vec::new();
+        reader.read_to_end(&mut bits).unwrap();
+        assert_eq!(bits, vec![1, 0, 0, 0, 0, 0, 1, 0, 0, 0, 0, 0, 0, 0, 0, 1]);
+    }
+
+    #[test]
+    fn writing_bits() {
+        let mut bytes = Vec::new();
+        let mut writer = BitWriter::new(&mut bytes);
+        writer.write_all(&[1, 0, 0, 0, 0, 0, 1, 0, 1, 1]).unwrap();
+        writer.flush().unwrap();
+        assert_eq!(bytes, b"A\x03");
+    }
+}
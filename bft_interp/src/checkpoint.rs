@@ -0,0 +1,471 @@
+//! Saving the state of a running VM, so that a long run can be picked up again after it stops.
+//!
+//! A checkpoint holds everything that changes as a program runs: the tapes, heads and program
+//! counter of every thread, the procedures defined so far, the storage register, the random
+//! number generator, the fuel left and the counts of instructions executed and bytes read and
+//! written. What the VM was configured with, like its limits and cost model, isn't saved, so a
+//! checkpoint is restored into a VM set up the same way as the one it was taken from.
+//!
+//! Checkpoints end with a checksum, and record which program they were taken from, so one that
+//! was only partly written, or belongs to another program, is rejected rather than restored.
+
+use alloc::collections::{BTreeMap, VecDeque};
+use alloc::sync::Arc;
+use alloc::vec::Vec;
+use core::error::Error;
+use core::fmt;
+use core::fmt::{Display, Formatter, Write};
+use core::num::{NonZeroU32, NonZeroUsize};
+
+use bft_types::BFprogram;
+
+use crate::{CellKind, Rng, Thread, BFVM};
+
+/// The bytes every checkpoint starts with, including the version of the format.
+const MAGIC: &[u8; 8] = b"BFTCKPT\x01";
+
+/// Why a checkpoint couldn't be restored.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum CheckpointError {
+    /// It isn't a checkpoint, or it was only partly written, or it has been changed since.
+    Corrupt,
+
+    /// It was taken while running a different program.
+    WrongProgram,
+}
+
+impl Display for CheckpointError {
+    fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::Corrupt => write!(f, "The checkpoint is damaged or incomplete"),
+            Self::WrongProgram => write!(f, "The checkpoint was taken from a different program"),
+        }
+    }
+}
+
+impl Error for CheckpointError {}
+
+/// The 64-bit FNV-1a hash, used both to check that a checkpoint is intact and to tell programs
+/// apart.
+struct Fnv1a(u64);
+
+impl Fnv1a {
+    fn new() -> Self {
+        Fnv1a(0xcbf2_9ce4_8422_2325)
+    }
+
+    fn update(&mut self, data: &[u8]) {
+        for byte in data {
+            self.0 = (self.0 ^ u64::from(*byte)).wrapping_mul(0x0100_0000_01b3);
+        }
+    }
+}
+
+impl Write for Fnv1a {
+    fn write_str(&mut self, s: &str) -> fmt::Result {
+        self.update(s.as_bytes());
+        Ok(())
+    }
+}
+
+/// A fingerprint of the instructions of `program`, which doesn't depend on how it was spelled.
+fn fingerprint(program: &BFprogram) -> u64 {
+    let mut hash = Fnv1a::new();
+    for inst in program.instructions() {
+        // Writing to a hash can't fail.
+        let _ = write!(hash, "{:?};", inst.instruction());
+    }
+    hash.0
+}
+
+/// Appends the parts of a checkpoint to its bytes.
+struct Writer(Vec<u8>);
+
+impl Writer {
+    fn u8(&mut self, value: u8) {
+        self.0.push(value);
+    }
+
+    fn u64(&mut self, value: u64) {
+        self.0.extend_from_slice(&value.to_le_bytes());
+    }
+
+    fn usize(&mut self, value: usize) {
+        self.u64(value as u64);
+    }
+
+    fn option(&mut self, value: Option<u64>) {
+        self.u8(u8::from(value.is_some()));
+        self.u64(value.unwrap_or(0));
+    }
+
+    fn tape<C: CellKind>(&mut self, tape: &[C], head: usize) {
+        self.usize(head);
+        self.usize(tape.len());
+        self.0.extend(tape.iter().map(CellKind::get_value));
+    }
+
+    fn thread<C: CellKind>(&mut self, thread: &Thread<C>) {
+        self.usize(thread.pc);
+        self.usize(thread.tape_index);
+        self.tape(&thread.tape, thread.head);
+        self.usize(thread.other_tapes.len());
+        for (tape, head) in &thread.other_tapes {
+            self.tape(tape, *head);
+        }
+        self.usize(thread.call_stack.len());
+        for ret in &thread.call_stack {
+            self.usize(*ret);
+        }
+    }
+}
+
+/// Takes the parts of a checkpoint from its bytes, failing if they run out.
+struct Reader<'a>(&'a [u8]);
+
+impl Reader<'_> {
+    fn bytes(&mut self, len: usize) -> Result<&[u8], CheckpointError> {
+        if self.0.len() < len {
+            return Err(CheckpointError::Corrupt);
+        }
+        let (bytes, rest) = self.0.split_at(len);
+        self.0 = rest;
+        Ok(bytes)
+    }
+
+    fn u8(&mut self) -> Result<u8, CheckpointError> {
+        Ok(self.bytes(1)?[0])
+    }
+
+    fn bool(&mut self) -> Result<bool, CheckpointError> {
+        match self.u8()? {
+            0 => Ok(false),
+            1 => Ok(true),
+            _ => Err(CheckpointError::Corrupt),
+        }
+    }
+
+    fn u64(&mut self) -> Result<u64, CheckpointError> {
+        let bytes = self
+            .bytes(8)?
+            .try_into()
+            .map_err(|_| CheckpointError::Corrupt)?;
+        Ok(u64::from_le_bytes(bytes))
+    }
+
+    /// A number that is at most `max`, such as an index or the length of something that
+    /// follows.
+    fn usize(&mut self, max: usize) -> Result<usize, CheckpointError> {
+        usize::try_from(self.u64()?)
+            .ok()
+            .filter(|value| *value <= max)
+            .ok_or(CheckpointError::Corrupt)
+    }
+
+    fn option(&mut self) -> Result<Option<u64>, CheckpointError> {
+        let some = self.bool()?;
+        let value = self.u64()?;
+        Ok(some.then_some(value))
+    }
+
+    fn tape<C: CellKind + Default>(&mut self) -> Result<(Arc<Vec<C>>, usize), CheckpointError> {
+        let head = self.usize(usize::MAX)?;
+        let len = self.usize(self.0.len())?;
+        if head >= len && len > 0 {
+            return Err(CheckpointError::Corrupt);
+        }
+        let tape = self
+            .bytes(len)?
+            .iter()
+            .map(|value| {
+                let mut cell = C::default();
+                cell.set_value(*value);
+                cell
+            })
+            .collect();
+        Ok((Arc::new(tape), head))
+    }
+
+    /// A thread running a program of `len` instructions on `tapes` tapes.
+    fn thread<C: CellKind + Default>(
+        &mut self,
+        len: usize,
+        tapes: Option<usize>,
+    ) -> Result<Thread<C>, CheckpointError> {
+        let pc = self.usize(len)?;
+        let tape_index = self.usize(usize::MAX)?;
+        let (tape, head) = self.tape()?;
+        let count = self.usize(self.0.len())?;
+        if tapes.is_some_and(|tapes| tapes != count + 1) || tape_index > count {
+            return Err(CheckpointError::Corrupt);
+        }
+        let other_tapes = (0..count)
+            .map(|_| self.tape())
+            .collect::<Result<VecDeque<_>, _>>()?;
+        let depth = self.usize(self.0.len())?;
+        let call_stack = (0..depth)
+            .map(|_| self.usize(len.saturating_sub(1)))
+            .collect::<Result<_, _>>()?;
+        Ok(Thread {
+            tape,
+            head,
+            pc,
+            call_stack,
+            other_tapes,
+            tape_index,
+        })
+    }
+}
+
+impl<C: CellKind + Clone> BFVM<C> {
+    /// Save the state of the VM while it runs `program`, between two steps, so that
+    /// [`BFVM::restore`] can carry on from here.
+    #[must_use]
+    pub fn checkpoint(&self, program: &BFprogram) -> Vec<u8> {
+        let mut out = Writer(Vec::from(&MAGIC[..]));
+        out.u64(fingerprint(program));
+        out.thread(&Thread {
+            tape: Arc::clone(&self.tape),
+            head: self.head,
+            pc: self.pc,
+            call_stack: self.call_stack.clone(),
+            other_tapes: self.other_tapes.clone(),
+            tape_index: self.tape_index,
+        });
+        out.usize(self.waiting.len());
+        for thread in &self.waiting {
+            out.thread(thread);
+        }
+        out.usize(self.procedures.len());
+        for (number, start) in &self.procedures {
+            out.u8(*number);
+            out.usize(*start);
+        }
+        out.u64(self.time_slice.get().into());
+        out.u64(self.turn_left.into());
+        out.u8(self.shared_tapes.into());
+        out.u8(self.storage);
+        out.option(self.exit_status.map(u64::from));
+        out.option(self.rng.as_ref().map(Rng::state));
+        out.option(self.fuel.as_ref().map(|fuel| fuel.remaining));
+        out.usize(self.input_read);
+        out.usize(self.output_written);
+        out.u8(self.output_closed.into());
+        out.u64(self.executed);
+        let mut checksum = Fnv1a::new();
+        checksum.update(&out.0);
+        out.u64(checksum.0);
+        out.0
+    }
+}
+
+impl<C: CellKind + Default> BFVM<C> {
+    /// Carry on running `program` from `checkpoint`, which [`BFVM::checkpoint`] saved while
+    /// running it. Only the state that changes as the program runs is restored, so the VM should
+    /// be set up as the one the checkpoint was taken from was. If the VM is metered, it's left
+    /// with the fuel it had when the checkpoint was taken.
+    ///
+    /// # Errors
+    /// This fails without changing the VM if the checkpoint is damaged or incomplete, or if it
+    /// was taken while running a different program.
+    pub fn restore(
+        &mut self,
+        program: &BFprogram,
+        checkpoint: &[u8],
+    ) -> Result<(), CheckpointError> {
+        self.restore_from(
+            Some(fingerprint(program)),
+            program.instructions().len(),
+            checkpoint,
+        )
+    }
+
+    /// A VM in the state saved in `checkpoint`, whatever program it was taken from, for looking
+    /// at rather than running. It has the default configuration, without any limits.
+    ///
+    /// # Errors
+    /// This fails if the checkpoint is damaged or incomplete.
+    pub fn from_checkpoint(checkpoint: &[u8]) -> Result<Self, CheckpointError> {
+        let mut vm = Self::new(Some(NonZeroUsize::MIN), false);
+        vm.restore_from(None, usize::MAX, checkpoint)?;
+        Ok(vm)
+    }
+
+    /// Restore the VM from `checkpoint`, checking that it was taken from a program with the
+    /// given fingerprint, if any, and `len` instructions.
+    fn restore_from(
+        &mut self,
+        program: Option<u64>,
+        len: usize,
+        checkpoint: &[u8],
+    ) -> Result<(), CheckpointError> {
+        let (state, checksum) = checkpoint
+            .split_last_chunk::<8>()
+            .ok_or(CheckpointError::Corrupt)?;
+        let mut hash = Fnv1a::new();
+        hash.update(state);
+        if !state.starts_with(MAGIC) || hash.0 != u64::from_le_bytes(*checksum) {
+            return Err(CheckpointError::Corrupt);
+        }
+        let mut input = Reader(&state[MAGIC.len()..]);
+        let fingerprint = input.u64()?;
+        if program.is_some_and(|program| program != fingerprint) {
+            return Err(CheckpointError::WrongProgram);
+        }
+
+        let current: Thread<C> = input.thread(len, None)?;
+        let tapes = Some(current.other_tapes.len() + 1);
+        let count = input.usize(input.0.len())?;
+        let waiting = (0..count)
+            .map(|_| input.thread(len, tapes))
+            .collect::<Result<VecDeque<_>, _>>()?;
+        let count = input.usize(input.0.len())?;
+        let mut procedures = BTreeMap::new();
+        for _ in 0..count {
+            procedures.insert(input.u8()?, input.usize(len.saturating_sub(1))?);
+        }
+        let time_slice = u32::try_from(input.u64()?)
+            .ok()
+            .and_then(NonZeroU32::new)
+            .ok_or(CheckpointError::Corrupt)?;
+        let turn_left = u32::try_from(input.usize(time_slice.get() as usize)?)
+            .map_err(|_| CheckpointError::Corrupt)?;
+        let shared_tapes = input.bool()?;
+        // Only with shared tapes do the waiting threads leave their tapes to the running one.
+        let has_tapes = |thread: &Thread<C>| {
+            !thread.tape.is_empty() && thread.other_tapes.iter().all(|(tape, _)| !tape.is_empty())
+        };
+        if !has_tapes(&current) || !(shared_tapes || waiting.iter().all(has_tapes)) {
+            return Err(CheckpointError::Corrupt);
+        }
+        let storage = input.u8()?;
+        let exit_status = input
+            .option()?
+            .map(u8::try_from)
+            .transpose()
+            .map_err(|_| CheckpointError::Corrupt)?;
+        let rng = input.option()?.map(Rng::from_seed);
+        let fuel = input.option()?;
+        let input_read = input.usize(usize::MAX)?;
+        let output_written = input.usize(usize::MAX)?;
+        let output_closed = input.bool()?;
+        let executed = input.u64()?;
+        if !input.0.is_empty() {
+            return Err(CheckpointError::Corrupt);
+        }
+
+        self.tape = current.tape;
+        self.head = current.head;
+        self.pc = current.pc;
+        self.call_stack = current.call_stack;
+        self.other_tapes = current.other_tapes;
+        self.tape_index = current.tape_index;
+        self.waiting = waiting;
+        self.procedures = procedures;
+        self.time_slice = time_slice;
+        self.turn_left = turn_left;
+        self.shared_tapes = shared_tapes;
+        self.storage = storage;
+        self.exit_status = exit_status;
+        self.rng = rng;
+        if let (Some(current), Some(remaining)) = (&mut self.fuel, fuel) {
+            current.remaining = remaining;
+        }
+        self.last_executed = None;
+        self.input_read = input_read;
+        self.output_written = output_written;
+        self.output_closed = output_closed;
+        self.executed = executed;
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::StepOutcome;
+    use bft_types::{Alphabet, Extension};
+
+    fn program(code: &[u8]) -> BFprogram {
+        let mut alphabet = Alphabet::default();
+        for extension in [
+            Extension::Brainfork,
+            Extension::Random,
+            Extension::Multitape,
+        ] {
+            alphabet.add_extension(extension).unwrap();
+        }
+        let mut program = BFprogram::with_alphabet("mod.test", code, &alphabet);
+        program.validate_brackets().unwrap();
+        program
+    }
+
+    #[test]
+    fn resuming() {
+        let code = program(b"+++[>?.<-]Y^++[-.]_>,.");
+        let new_vm = || {
+            BFVM::<u8>::new(NonZeroUsize::new(8), false)
+                .with_seed(3)
+                .with_tapes(NonZeroUsize::new(2).unwrap())
+        };
+        let mut expected = Vec::new();
+        new_vm().run(&code, &mut &b"ab"[..], &mut expected).unwrap();
+
+        // Stop part way through, at every step in turn, and carry on from a checkpoint in a new
+        // VM, with the input that hadn't been read yet.
+        for stop in 0.. {
+            let mut vm = new_vm();
+            let (mut input, mut output) = (&b"ab"[..], Vec::new());
+            for _ in 0..stop {
+                vm.step(&code, &mut input, &mut output).unwrap();
+            }
+            let checkpoint = vm.checkpoint(&code);
+            let mut resumed = new_vm().with_seed(99);
+            resumed.restore(&code, &checkpoint).unwrap();
+            assert_eq!(resumed.executed(), vm.executed());
+            assert_eq!(resumed.input_read(), vm.input_read());
+            resumed.run(&code, &mut input, &mut output).unwrap();
+            assert_eq!(output, expected);
+            if vm.step(&code, &mut input, &mut Vec::new()).unwrap() == StepOutcome::Finished {
+                break;
+            }
+        }
+    }
+
+    #[test]
+    fn rejecting() {
+        let code = program(b"+[-]");
+        let mut vm: BFVM<u8> = BFVM::new(None, false);
+        vm.step(&code, &mut &b""[..], &mut Vec::new()).unwrap();
+        let checkpoint = vm.checkpoint(&code);
+
+        let mut fresh: BFVM<u8> = BFVM::new(None, false);
+        assert_eq!(
+            fresh.restore(&program(b"-[+]"), &checkpoint),
+            Err(CheckpointError::WrongProgram)
+        );
+        for len in 0..checkpoint.len() {
+            assert_eq!(
+                fresh.restore(&code, &checkpoint[..len]),
+                Err(CheckpointError::Corrupt)
+            );
+        }
+        let mut changed = checkpoint.clone();
+        changed[20] ^= 1;
+        assert_eq!(
+            fresh.restore(&code, &changed),
+            Err(CheckpointError::Corrupt)
+        );
+        assert_eq!(fresh.pc(), 0);
+        fresh.restore(&code, &checkpoint).unwrap();
+        assert_eq!((fresh.pc(), fresh.tape()[0]), (1, 1));
+
+        // It can be looked at without knowing the program.
+        let vm = BFVM::<u8>::from_checkpoint(&checkpoint).unwrap();
+        assert_eq!((vm.pc(), vm.tape().len()), (1, 30000));
+        assert_eq!(
+            BFVM::<u8>::from_checkpoint(&changed).err(),
+            Some(CheckpointError::Corrupt)
+        );
+    }
+}
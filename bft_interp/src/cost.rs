@@ -0,0 +1,72 @@
+//! Metering execution, by charging each instruction against a budget of fuel.
+
+use alloc::sync::Arc;
+use core::fmt;
+
+use bft_types::Instruction;
+
+/// The price of each thing a program can do, in units of fuel.
+///
+/// Every method has a default, so a model only needs to override what it prices differently.
+/// The defaults charge one unit for each instruction, and nothing more for I/O or tape growth.
+///
+/// ```
+/// use bft_interp::{CostModel, BFVM};
+/// use bft_types::Instruction;
+///
+/// /// Output is ten times as expensive as anything else.
+/// struct ExpensiveOutput;
+///
+/// impl CostModel for ExpensiveOutput {
+///     fn output(&self) -> u64 {
+///         9
+///     }
+/// }
+///
+/// let vm: BFVM<u8> = BFVM::new(None, false).with_fuel(1000, ExpensiveOutput);
+/// ```
+pub trait CostModel {
+    /// The cost of executing `inst`.
+    fn instruction(&self, inst: Instruction) -> u64 {
+        let _ = inst;
+        1
+    }
+
+    /// The extra cost of reading a byte of input.
+    fn input(&self) -> u64 {
+        0
+    }
+
+    /// The extra cost of writing a byte of output.
+    fn output(&self) -> u64 {
+        0
+    }
+
+    /// The extra cost of growing the tape to `cells` cells.
+    fn tape_growth(&self, cells: usize) -> u64 {
+        let _ = cells;
+        0
+    }
+}
+
+/// The default cost model, where every instruction costs one unit, so the fuel is a limit on the
+/// number of steps.
+#[derive(Clone, Copy, Debug, Default)]
+pub struct Uniform;
+
+impl CostModel for Uniform {}
+
+/// The fuel left for a metered VM, and the prices it is spent at.
+#[derive(Clone)]
+pub(crate) struct Fuel {
+    pub(crate) remaining: u64,
+    pub(crate) model: Arc<dyn CostModel + Send + Sync>,
+}
+
+impl fmt::Debug for Fuel {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("Fuel")
+            .field("remaining", &self.remaining)
+            .finish_non_exhaustive()
+    }
+}
@@ -0,0 +1,107 @@
+//! [`miette::Diagnostic`] for the errors from running a program, so that applications using
+//! miette can report them with labels pointing at the instruction that failed.
+//!
+//! As with the errors from [`bft_types`], the program's text needs to be attached to the report
+//! for the labels to be shown.
+
+use std::fmt::Display;
+use std::iter;
+
+use miette::{Diagnostic, LabeledSpan, SourceCode};
+
+use crate::{BftError, VMError};
+
+impl Diagnostic for VMError {
+    fn code<'a>(&'a self) -> Option<Box<dyn Display + 'a>> {
+        let code = match self {
+            Self::InvalidHeadPosition(..) => "bft::head_position",
+            Self::UnmatchedBracket(..) => "bft::brackets",
+            Self::IOError(..) => "bft::io",
+            Self::UndefinedProcedure(..) => "bft::undefined_procedure",
+            Self::Unsupported(..) => "bft::unsupported",
+            Self::OutOfFuel(..) => "bft::out_of_fuel",
+            Self::Cancelled(_) => "bft::cancelled",
+            Self::LimitExceeded(..) => "bft::limit",
+        };
+        Some(Box::new(code))
+    }
+
+    fn help<'a>(&'a self) -> Option<Box<dyn Display + 'a>> {
+        let help = match self {
+            Self::InvalidHeadPosition(..) => "make the tape longer, or let it grow",
+            Self::UnmatchedBracket(..) => "validate the brackets before running the program",
+            _ => return None,
+        };
+        Some(Box::new(help))
+    }
+
+    fn labels(&self) -> Option<Box<dyn Iterator<Item = LabeledSpan> + '_>> {
+        let inst = self.instruction()?;
+        Some(Box::new(iter::once(LabeledSpan::new_primary_with_span(
+            Some(inst.instruction().to_string()),
+            (inst.offset(), 1),
+        ))))
+    }
+}
+
+impl BftError {
+    /// The error being wrapped, as a diagnostic, if it's one.
+    fn diagnostic(&self) -> Option<&dyn Diagnostic> {
+        match self {
+            Self::Parse(err) => Some(err),
+            Self::Brackets(err) => Some(err),
+            Self::Extension(err) => Some(err),
+            Self::Runtime(err) => Some(err),
+            Self::Io(_) | Self::Other(_) => None,
+        }
+    }
+}
+
+impl Diagnostic for BftError {
+    fn code<'a>(&'a self) -> Option<Box<dyn Display + 'a>> {
+        self.diagnostic()?.code()
+    }
+
+    fn help<'a>(&'a self) -> Option<Box<dyn Display + 'a>> {
+        self.diagnostic()?.help()
+    }
+
+    fn source_code(&self) -> Option<&dyn SourceCode> {
+        self.diagnostic()?.source_code()
+    }
+
+    fn labels(&self) -> Option<Box<dyn Iterator<Item = LabeledSpan> + '_>> {
+        self.diagnostic()?.labels()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::BFVM;
+    use bft_types::BFprogram;
+    use miette::{NamedSource, NarratableReportHandler, Report};
+    use std::num::NonZeroUsize;
+
+    #[test]
+    fn reporting() {
+        let code = "+\n>>";
+        let mut program = BFprogram::new("a.b", code.as_bytes());
+        program.validate_brackets().unwrap();
+        let err: BftError = BFVM::<u8>::new(NonZeroUsize::new(2), false)
+            .run(&program, &mut std::io::empty(), &mut std::io::sink())
+            .unwrap_err()
+            .into();
+        assert_eq!(err.code().unwrap().to_string(), "bft::head_position");
+        let label = err.labels().unwrap().next().unwrap();
+        assert_eq!(label.offset(), 3);
+
+        let report = Report::new(err).with_source_code(NamedSource::new("a.b", code));
+        let mut out = String::new();
+        NarratableReportHandler::new()
+            .render_report(&mut out, report.as_ref())
+            .unwrap();
+        assert!(out.contains("label at line 2, column 2: Move right one location"));
+        assert!(out.contains("help: make the tape longer"));
+    }
+}
@@ -0,0 +1,209 @@
+//! A single error type for everything that can go wrong loading, checking and running a program.
+
+use alloc::boxed::Box;
+use alloc::string::{String, ToString};
+use core::error::Error;
+use core::fmt;
+use core::fmt::{Display, Formatter};
+
+use bft_types::{AlphabetError, BracketMatchError, ExtensionError};
+
+use crate::{IoError, VMError};
+
+/// Anything that can go wrong loading, checking or running a program, so that applications can
+/// handle every kind of failure through one type.
+///
+/// Each variant is displayed as the error it wraps, and [`Error::source`] continues with that
+/// error's source.
+#[derive(Debug)]
+#[non_exhaustive]
+pub enum BftError {
+    /// The tokens given for reading a program aren't valid.
+    Parse(AlphabetError),
+
+    /// The program's brackets or procedures don't match.
+    Brackets(BracketMatchError),
+
+    /// The program uses an extension that isn't enabled.
+    Extension(ExtensionError),
+
+    /// Running the program failed.
+    Runtime(VMError),
+
+    /// Reading the program, or other input or output outside of running it, failed.
+    Io(IoError),
+
+    /// Any other error, such as from the application using the VM.
+    Other(Box<dyn Error + Send + Sync>),
+}
+
+impl BftError {
+    /// Whether the program is invalid, so it couldn't be run at all.
+    #[must_use]
+    pub fn is_invalid_program(&self) -> bool {
+        matches!(
+            self,
+            Self::Parse(_) | Self::Brackets(_) | Self::Extension(_)
+        )
+    }
+
+    /// The error being wrapped.
+    fn inner(&self) -> &(dyn Error + 'static) {
+        match self {
+            Self::Parse(err) => err,
+            Self::Brackets(err) => err,
+            Self::Extension(err) => err,
+            Self::Runtime(err) => err,
+            Self::Io(err) => err,
+            Self::Other(err) => &**err,
+        }
+    }
+}
+
+impl Display for BftError {
+    fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
+        Display::fmt(self.inner(), f)
+    }
+}
+
+impl Error for BftError {
+    fn source(&self) -> Option<&(dyn Error + 'static)> {
+        self.inner().source()
+    }
+}
+
+impl From<AlphabetError> for BftError {
+    fn from(err: AlphabetError) -> Self {
+        Self::Parse(err)
+    }
+}
+
+impl From<BracketMatchError> for BftError {
+    fn from(err: BracketMatchError) -> Self {
+        Self::Brackets(err)
+    }
+}
+
+impl From<ExtensionError> for BftError {
+    fn from(err: ExtensionError) -> Self {
+        Self::Extension(err)
+    }
+}
+
+impl From<VMError> for BftError {
+    fn from(err: VMError) -> Self {
+        Self::Runtime(err)
+    }
+}
+
+impl From<IoError> for BftError {
+    fn from(err: IoError) -> Self {
+        Self::Io(err)
+    }
+}
+
+impl From<String> for BftError {
+    fn from(message: String) -> Self {
+        Self::Other(message.into())
+    }
+}
+
+impl From<&str> for BftError {
+    fn from(message: &str) -> Self {
+        Self::Other(message.into())
+    }
+}
+
+/// Sort a boxed error into one of the variants, if it's one of the errors they wrap.
+macro_rules! sort_boxed {
+    ($err:ident, $other:expr) => {{
+        let $err = match $err.downcast::<BftError>() {
+            Ok(err) => return *err,
+            Err(err) => err,
+        };
+        let $err = match $err.downcast::<VMError>() {
+            Ok(err) => return Self::Runtime(*err),
+            Err(err) => err,
+        };
+        let $err = match $err.downcast::<BracketMatchError>() {
+            Ok(err) => return Self::Brackets(*err),
+            Err(err) => err,
+        };
+        let $err = match $err.downcast::<ExtensionError>() {
+            Ok(err) => return Self::Extension(*err),
+            Err(err) => err,
+        };
+        let $err = match $err.downcast::<AlphabetError>() {
+            Ok(err) => return Self::Parse(*err),
+            Err(err) => err,
+        };
+        match $err.downcast::<IoError>() {
+            Ok(err) => Self::Io(*err),
+            Err($err) => $other,
+        }
+    }};
+}
+
+impl From<Box<dyn Error + Send + Sync>> for BftError {
+    /// Unwrap `err` if it's one of the errors wrapped by a variant, and otherwise keep it as
+    /// [`BftError::Other`].
+    fn from(err: Box<dyn Error + Send + Sync>) -> Self {
+        sort_boxed!(err, Self::Other(err))
+    }
+}
+
+impl From<Box<dyn Error>> for BftError {
+    /// Unwrap `err` if it's one of the errors wrapped by a variant. Other errors can't be kept
+    /// as they are, as they may not be [`Send`], so they become [`BftError::Other`] with the same
+    /// message, but without a source.
+    fn from(err: Box<dyn Error>) -> Self {
+        sort_boxed!(err, Self::Other(err.to_string().into()))
+    }
+}
+
+#[cfg(all(test, feature = "std"))]
+mod tests {
+    use super::*;
+    use crate::BFVM;
+    use bft_types::BFprogram;
+    use core::num::NonZeroUsize;
+    use std::io;
+
+    #[test]
+    fn wrapping() {
+        let mut program = BFprogram::new("a.b", &b"<"[..]);
+        program.validate_brackets().unwrap();
+        let err: BftError = BFVM::<u8>::new(NonZeroUsize::new(1), false)
+            .run(&program, &mut io::empty(), &mut io::sink())
+            .unwrap_err()
+            .into();
+        assert!(matches!(
+            err,
+            BftError::Runtime(VMError::InvalidHeadPosition(..))
+        ));
+        assert_eq!(
+            err.to_string(),
+            "Head moved to invalid position from 0 at [a.b:1:1]"
+        );
+        assert!(!err.is_invalid_program());
+    }
+
+    #[test]
+    fn sorting_boxed_errors() {
+        let err = BFprogram::new("a.b", &b"]"[..])
+            .validate_brackets()
+            .unwrap_err();
+        let boxed: Box<dyn Error> = Box::new(err);
+        let err = BftError::from(boxed);
+        assert!(matches!(err, BftError::Brackets(_)));
+        assert!(err.is_invalid_program());
+
+        let boxed: Box<dyn Error + Send + Sync> = Box::new(io::Error::other("disk full"));
+        assert!(matches!(BftError::from(boxed), BftError::Io(_)));
+
+        let boxed: Box<dyn Error> = "Unknown setting".into();
+        let err = BftError::from(boxed);
+        assert!(matches!(err, BftError::Other(_)));
+        assert_eq!(err.to_string(), "Unknown setting");
+    }
+}
@@ -0,0 +1,183 @@
+//! Finding the loops that can run without checking the head at each instruction.
+//!
+//! An innermost loop whose body only adds to cells and moves the head, and moves it back to where
+//! it started, touches the same cells on every iteration. Which cells those are, relative to the
+//! head, is worked out before the program runs, so a single check when the loop starts shows that
+//! none of its iterations can move the head off the tape.
+
+use alloc::collections::BTreeMap;
+use alloc::string::String;
+use alloc::vec::Vec;
+
+use bft_types::{BFprogram, Instruction};
+
+/// A loop that can run without checking the head, compiled to what each iteration does.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub(crate) struct FastLoop {
+    /// Index of the loop's `]`.
+    pub(crate) end: usize,
+
+    /// What each iteration adds to each cell it changes, by offset from the head, modulo 256.
+    pub(crate) adds: Vec<(isize, u8)>,
+
+    /// The offset of the leftmost cell the body moves to.
+    pub(crate) lowest: isize,
+
+    /// The offset of the rightmost cell the body moves to.
+    pub(crate) highest: isize,
+
+    /// Number of instructions each iteration executes, including the `]`.
+    pub(crate) len: u64,
+}
+
+/// A loop that [`crate::BFVM::run_fast_unchecked`] compiled, and how often it ran, recorded by
+/// a VM made with [`crate::BFVM::with_fusion_stats`].
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct FusedLoop {
+    /// Index of the loop's `]`.
+    pub end: usize,
+
+    /// What each iteration adds to each cell it changes, by offset from the head, modulo 256.
+    pub adds: Vec<(isize, u8)>,
+
+    /// Number of times the loop ran compiled.
+    pub runs: u64,
+
+    /// Number of iterations it ran compiled, over all its runs.
+    pub iterations: u64,
+
+    /// Number of times it was reached but had to run one instruction at a time, because its
+    /// cells weren't all on the tape, or the run was metered or had started threads.
+    pub fallbacks: u64,
+}
+
+impl FastLoop {
+    /// Whether each iteration subtracts exactly 1 from the cell the loop starts on, so that the
+    /// loop runs as many times as that cell's value.
+    pub(crate) fn counted(&self) -> bool {
+        self.adds.contains(&(0, 255))
+    }
+}
+
+impl FusedLoop {
+    pub(crate) fn new(fast: &FastLoop) -> Self {
+        FusedLoop {
+            end: fast.end,
+            adds: fast.adds.clone(),
+            runs: 0,
+            iterations: 0,
+            fallbacks: 0,
+        }
+    }
+
+    /// The shortest loop that does what this one does, visiting the cells it changes from left
+    /// to right, so that loops written differently but compiled the same can be counted together.
+    #[must_use]
+    pub fn shape(&self) -> String {
+        let mut shape = String::from("[");
+        let mut offset = 0;
+        for (to, n) in &self.adds {
+            let (step, count) = if *to < offset {
+                ('<', offset - to)
+            } else {
+                ('>', to - offset)
+            };
+            shape.extend(core::iter::repeat_n(step, count.unsigned_abs()));
+            offset = *to;
+            if *n <= 128 {
+                shape.extend(core::iter::repeat_n('+', usize::from(*n)));
+            } else {
+                shape.extend(core::iter::repeat_n('-', 256 - usize::from(*n)));
+            }
+        }
+        let step = if offset < 0 { '>' } else { '<' };
+        shape.extend(core::iter::repeat_n(step, offset.unsigned_abs()));
+        shape.push(']');
+        shape
+    }
+}
+
+/// The loops in `program` that can run without checking the head, by the index of their `[`.
+pub(crate) fn fast_loops(program: &BFprogram) -> BTreeMap<usize, FastLoop> {
+    let instructions = program.instructions();
+    let mut loops = BTreeMap::new();
+    for start in 0..instructions.len() {
+        if *instructions[start].instruction() != Instruction::BeginLoop {
+            continue;
+        }
+        let Some(end) = program.matching_bracket(start) else {
+            continue;
+        };
+        let mut added: BTreeMap<isize, u8> = BTreeMap::new();
+        let (mut offset, mut lowest, mut highest) = (0isize, 0isize, 0isize);
+        let simple = instructions[start + 1..end].iter().all(|inst| {
+            match inst.instruction() {
+                Instruction::MoveLeft => offset -= 1,
+                Instruction::MoveRight => offset += 1,
+                Instruction::Increment => {
+                    let n = added.entry(offset).or_default();
+                    *n = n.wrapping_add(1);
+                }
+                Instruction::Decrement => {
+                    let n = added.entry(offset).or_default();
+                    *n = n.wrapping_sub(1);
+                }
+                _ => return false,
+            }
+            lowest = lowest.min(offset);
+            highest = highest.max(offset);
+            true
+        });
+        if simple && offset == 0 {
+            loops.insert(
+                start,
+                FastLoop {
+                    end,
+                    adds: added.into_iter().filter(|(_, n)| *n != 0).collect(),
+                    lowest,
+                    highest,
+                    len: (end - start) as u64,
+                },
+            );
+        }
+    }
+    loops
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn loops(code: &str) -> BTreeMap<usize, FastLoop> {
+        let mut program = BFprogram::new("mod.test", code.as_bytes());
+        program.validate_brackets().unwrap();
+        fast_loops(&program)
+    }
+
+    #[test]
+    fn finding_loops() {
+        let found = loops("+[->++<<+>]");
+        assert_eq!(
+            found[&1],
+            FastLoop {
+                end: 10,
+                adds: alloc::vec![(-1, 1), (0, 255), (1, 2)],
+                lowest: -1,
+                highest: 1,
+                len: 9,
+            }
+        );
+        // Only innermost loops that move the head back to where it started are fast.
+        assert_eq!(loops("[>[-]<-][>][.-]").keys().collect::<Vec<_>>(), [&2]);
+    }
+
+    #[test]
+    fn shapes() {
+        let shape = |code: &str| FusedLoop::new(&loops(code)[&0]).shape();
+        assert_eq!(shape("[-]"), "[-]");
+        assert_eq!(shape("[>+<-]"), "[->+<]");
+        assert_eq!(shape("[->++<<+>]"), "[<+>->++<]");
+        assert_eq!(shape("[<+>>-<]"), "[<+>>-<]");
+        assert_eq!(shape("[+-]"), "[]");
+    }
+}
@@ -0,0 +1,409 @@
+//! A VM with its tape in a fixed-size array, so running a program doesn't allocate.
+
+use bft_types::{BFprogram, InputInstruction, Instruction};
+
+use crate::rng::Rng;
+use crate::{bitwise, io, ByteRead, ByteWrite, CellKind, StepOutcome, VMError};
+
+/// A Brainf*ck interpreter with a tape of `N` cells held inline, for microcontrollers and other
+/// places where the heap is unavailable or scarce.
+///
+/// The tape can't grow, and there is a single thread on a single tape, so forks, procedures and
+/// the multitape instructions fail with [`VMError::Unsupported`]. Otherwise it runs programs the
+/// same way as [`crate::BFVM`].
+///
+/// ```
+/// use bft_interp::FixedVM;
+/// use bft_types::BFprogram;
+///
+/// let mut program = BFprogram::new("doc.test", b"++>+++[<+>-]<.");
+/// program.validate_brackets().unwrap();
+/// let mut vm: FixedVM<u8, 16> = FixedVM::new();
+/// let mut output = Vec::new();
+/// vm.run(&program, &mut &b""[..], &mut output).unwrap();
+/// assert_eq!(output, [5]);
+/// ```
+#[derive(Clone, Debug)]
+pub struct FixedVM<C, const N: usize> {
+    /// Block of memory for the program to work on.
+    tape: [C; N],
+
+    /// Index of where the program is pointing to in the tape.
+    head: usize,
+
+    /// Index of the next instruction to be executed.
+    pc: usize,
+
+    /// Index of the most recently executed instruction.
+    last_executed: Option<usize>,
+
+    /// The storage register used by the Extended Type I instructions.
+    storage: u8,
+
+    /// The exit status requested by the program, if it halted with one.
+    exit_status: Option<u8>,
+
+    /// Source of random bytes, created when a program first asks for one unless a seed was given.
+    rng: Option<Rng>,
+
+    /// Whether the output was closed by whatever was reading it, which stopped the program.
+    output_closed: bool,
+}
+
+impl<C: Copy + Default, const N: usize> FixedVM<C, N> {
+    /// Construct a new VM with a clean tape.
+    #[must_use]
+    pub fn new() -> Self {
+        const { assert!(N > 0, "The tape needs at least one cell") };
+        FixedVM {
+            tape: [C::default(); N],
+            head: 0,
+            pc: 0,
+            last_executed: None,
+            storage: 0,
+            exit_status: None,
+            rng: None,
+            output_closed: false,
+        }
+    }
+}
+
+impl<C: Copy + Default, const N: usize> Default for FixedVM<C, N> {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl<C, const N: usize> FixedVM<C, N> {
+    /// Seed the random numbers used by the `?` instruction, so that runs can be reproduced.
+    #[must_use]
+    pub fn with_seed(mut self, seed: u64) -> Self {
+        self.rng = Some(Rng::from_seed(seed));
+        self
+    }
+
+    /// The contents of the tape.
+    #[must_use]
+    pub fn tape(&self) -> &[C; N] {
+        &self.tape
+    }
+
+    /// The current position of the head on the tape.
+    #[must_use]
+    pub fn head(&self) -> usize {
+        self.head
+    }
+
+    /// The index of the next instruction that will be executed.
+    #[must_use]
+    pub fn pc(&self) -> usize {
+        self.pc
+    }
+
+    /// The index of the instruction executed by the most recent call to [`FixedVM::step`].
+    #[must_use]
+    pub fn last_executed(&self) -> Option<usize> {
+        self.last_executed
+    }
+
+    /// The exit status the program asked for when it halted, if it did.
+    #[must_use]
+    pub fn exit_status(&self) -> Option<u8> {
+        self.exit_status
+    }
+
+    /// Whether the program was stopped because its output was closed, as reported by
+    /// [`StepOutcome::BrokenPipe`].
+    #[must_use]
+    pub fn output_closed(&self) -> bool {
+        self.output_closed
+    }
+}
+
+impl<C: CellKind, const N: usize> FixedVM<C, N> {
+    /// Execute a single instruction of `program`, reading from `input` and writing to `output`
+    /// as needed.
+    ///
+    /// # Errors
+    /// This will return an error if the head moves off the tape, if a loop has no matching
+    /// bracket, if the instruction isn't supported, or if there is an error with the input or
+    /// output. Output being closed by whatever is reading it isn't an error: the program is
+    /// stopped and this returns [`StepOutcome::BrokenPipe`].
+    pub fn step<R: ByteRead, W: ByteWrite>(
+        &mut self,
+        program: &BFprogram,
+        input: &mut R,
+        output: &mut W,
+    ) -> Result<StepOutcome, VMError> {
+        let len = program.instructions().len();
+        self.last_executed = None;
+        let Some(inst) = program.instructions().get(self.pc) else {
+            return Ok(StepOutcome::Finished);
+        };
+        self.last_executed = Some(self.pc);
+        if matches!(
+            inst.instruction(),
+            Instruction::EndProgram | Instruction::Halt
+        ) {
+            if *inst.instruction() == Instruction::Halt {
+                self.exit_status = Some(self.tape[self.head].get_value());
+            }
+            self.pc = len;
+            return Ok(StepOutcome::Finished);
+        }
+        self.execute(program, *inst, input, output)?;
+        if self.output_closed {
+            self.pc = len;
+            return Ok(StepOutcome::BrokenPipe);
+        }
+        self.pc += 1;
+
+        if self.pc < len {
+            Ok(StepOutcome::Running)
+        } else {
+            Ok(StepOutcome::Finished)
+        }
+    }
+
+    /// Execute `inst`, the instruction at the program counter.
+    fn execute<R: ByteRead, W: ByteWrite>(
+        &mut self,
+        program: &BFprogram,
+        inst: InputInstruction,
+        input: &mut R,
+        output: &mut W,
+    ) -> Result<(), VMError> {
+        match inst.instruction() {
+            Instruction::MoveLeft => {
+                if self.head == 0 {
+                    return Err(VMError::InvalidHeadPosition(
+                        program.source().clone(),
+                        inst,
+                        self.head,
+                    ));
+                }
+                self.head -= 1;
+            }
+            Instruction::MoveRight => {
+                if self.head + 1 == N {
+                    return Err(VMError::InvalidHeadPosition(
+                        program.source().clone(),
+                        inst,
+                        self.head,
+                    ));
+                }
+                self.head += 1;
+            }
+            Instruction::Increment => self.tape[self.head].increment(),
+            Instruction::Decrement => self.tape[self.head].decrement(),
+            Instruction::Input => match input.read_byte() {
+                // At the end of the input the cell is left unchanged.
+                Ok(None) => {}
+                Ok(Some(byte)) => self.tape[self.head].set_value(byte),
+                Err(err) => return Err(VMError::IOError(program.source().clone(), inst, err)),
+            },
+            Instruction::Output => match output.write_byte(self.tape[self.head].get_value()) {
+                Ok(()) => {}
+                Err(err) if io::is_broken_pipe(&err) => self.output_closed = true,
+                Err(err) => return Err(VMError::IOError(program.source().clone(), inst, err)),
+            },
+            Instruction::BeginLoop => {
+                if self.tape[self.head].is_zero() {
+                    self.pc = self.jump_target(program, inst)?;
+                }
+            }
+            Instruction::EndLoop => {
+                if !self.tape[self.head].is_zero() {
+                    self.pc = self.jump_target(program, inst)?;
+                }
+            }
+            // Handled by `step`.
+            Instruction::EndProgram | Instruction::Halt => {}
+            Instruction::Store
+            | Instruction::Retrieve
+            | Instruction::ShiftRight
+            | Instruction::ShiftLeft
+            | Instruction::Not
+            | Instruction::Xor
+            | Instruction::And
+            | Instruction::Or => {
+                let value = self.tape[self.head].get_value();
+                if let Some(value) = bitwise(*inst.instruction(), value, &mut self.storage) {
+                    self.tape[self.head].set_value(value);
+                }
+            }
+            Instruction::Random => {
+                let value = self.rng.get_or_insert_with(Rng::from_entropy).next_byte();
+                self.tape[self.head].set_value(value);
+            }
+            Instruction::BeginProcedure
+            | Instruction::EndProcedure
+            | Instruction::CallProcedure
+            | Instruction::Fork
+            | Instruction::NextTape
+            | Instruction::PreviousTape => {
+                return Err(VMError::Unsupported(program.source().clone(), inst));
+            }
+        }
+        Ok(())
+    }
+
+    /// Run `program` from the current program counter until it finishes, or until its output is
+    /// closed, which [`FixedVM::output_closed`] reports.
+    ///
+    /// # Errors
+    /// See [`FixedVM::step`] for the errors that can occur.
+    pub fn run<R: ByteRead, W: ByteWrite>(
+        &mut self,
+        program: &BFprogram,
+        input: &mut R,
+        output: &mut W,
+    ) -> Result<(), VMError> {
+        while self.step(program, input, output)? == StepOutcome::Running {}
+        if let (Some(inst), false) = (program.instructions().last(), self.output_closed) {
+            match output.flush() {
+                Ok(()) => {}
+                Err(err) if io::is_broken_pipe(&err) => self.output_closed = true,
+                Err(err) => return Err(VMError::IOError(program.source().clone(), *inst, err)),
+            }
+        }
+        Ok(())
+    }
+
+    fn jump_target(&self, program: &BFprogram, inst: InputInstruction) -> Result<usize, VMError> {
+        program
+            .matching_bracket(self.pc)
+            .ok_or_else(|| VMError::UnmatchedBracket(program.source().clone(), inst))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use alloc::vec::Vec;
+    use bft_types::{Alphabet, Extension};
+    #[cfg(feature = "std")]
+    use proptest::prelude::*;
+
+    fn run<const N: usize>(
+        code: &[u8],
+        extensions: &[Extension],
+        input: &[u8],
+    ) -> (FixedVM<u8, N>, Result<Vec<u8>, VMError>) {
+        let mut alphabet = Alphabet::default();
+        for extension in extensions {
+            alphabet.add_extension(*extension).unwrap();
+        }
+        let mut program = BFprogram::with_alphabet("fixed.test", code, &alphabet);
+        program.validate_brackets().unwrap();
+        let mut vm = FixedVM::new();
+        let mut output = Vec::new();
+        let result = vm.run(&program, &mut &input[..], &mut output);
+        (vm, result.map(|()| output))
+    }
+
+    #[test]
+    fn running() {
+        let (vm, output) = run::<4>(b",[>+<-]>.", &[], b"\x03");
+        assert_eq!(output.unwrap(), [3]);
+        assert_eq!(vm.tape(), &[0, 3, 0, 0]);
+        assert_eq!(vm.head(), 1);
+    }
+
+    #[test]
+    fn tape_bounds() {
+        let (vm, output) = run::<2>(b">>", &[], b"");
+        assert!(matches!(output, Err(VMError::InvalidHeadPosition(_, _, 1))));
+        assert_eq!(vm.pc(), 1);
+        assert!(matches!(
+            run::<2>(b"<", &[], b"").1,
+            Err(VMError::InvalidHeadPosition(_, _, 0))
+        ));
+    }
+
+    #[test]
+    fn extensions() {
+        let (_, output) = run::<2>(b"+++$>!}.", &[Extension::Ext1], b"");
+        assert_eq!(output.unwrap(), [1]);
+        let (vm, output) = run::<2>(b"+++@.", &[Extension::Halt], b"");
+        assert!(output.unwrap().is_empty());
+        assert_eq!(vm.exit_status(), Some(3));
+        assert!(matches!(
+            run::<2>(b"+Y", &[Extension::Brainfork], b"").1,
+            Err(VMError::Unsupported(..))
+        ));
+    }
+
+    /// Programs of up to 64 of the eight standard instructions, with any `]` that has no `[`
+    /// dropped and any loops left open closed, so that the brackets always match.
+    #[cfg(feature = "std")]
+    fn programs() -> impl Strategy<Value = Vec<u8>> {
+        prop::collection::vec(0..8usize, 0..64).prop_map(|picks| {
+            let mut code = Vec::with_capacity(picks.len());
+            let mut depth = 0usize;
+            for pick in picks {
+                let inst = b"+-<>[].,"[pick];
+                match inst {
+                    b'[' => depth += 1,
+                    b']' if depth == 0 => continue,
+                    b']' => depth -= 1,
+                    _ => {}
+                }
+                code.push(inst);
+            }
+            code.extend(core::iter::repeat_n(b']', depth));
+            code
+        })
+    }
+
+    #[cfg(feature = "std")]
+    proptest! {
+        /// Each way of storing the tape runs programs the same way: the fixed array here, a
+        /// `BFVM` tape of the same length, and a `BFVM` tape that starts with one cell and grows.
+        /// The growing tape is only compared until the fixed ones run off their end.
+        #[test]
+        fn tapes_agree(
+            code in programs(),
+            input in prop::collection::vec(any::<u8>(), 0..8),
+        ) {
+            use crate::BFVM;
+            use core::num::NonZeroUsize;
+
+            let mut program = BFprogram::new("fixed.test", &code);
+            program.validate_brackets().unwrap();
+            let mut fixed: FixedVM<u8, 16> = FixedVM::new();
+            let mut dense: BFVM<u8> = BFVM::new(NonZeroUsize::new(16), false);
+            let mut growing: BFVM<u8> = BFVM::new(NonZeroUsize::new(1), true);
+            let (mut fixed_in, mut dense_in, mut growing_in) = (&input[..], &input[..], &input[..]);
+            let (mut fixed_out, mut dense_out, mut growing_out) = (Vec::new(), Vec::new(), Vec::new());
+            // The steps are bounded like fuel, as generated programs often loop forever.
+            for _ in 0..10_000 {
+                let fixed_step = fixed
+                    .step(&program, &mut fixed_in, &mut fixed_out)
+                    .map_err(|err| err.to_string());
+                let dense_step = dense
+                    .step(&program, &mut dense_in, &mut dense_out)
+                    .map_err(|err| err.to_string());
+                prop_assert_eq!(&fixed_step, &dense_step);
+                let Ok(outcome) = fixed_step else {
+                    break;
+                };
+                let growing_step = growing
+                    .step(&program, &mut growing_in, &mut growing_out)
+                    .map_err(|err| err.to_string());
+                prop_assert_eq!(growing_step, Ok(outcome));
+                prop_assert_eq!(fixed.head(), dense.head());
+                prop_assert_eq!(fixed.head(), growing.head());
+                if outcome != StepOutcome::Running {
+                    break;
+                }
+            }
+            prop_assert_eq!(&fixed_out, &dense_out);
+            prop_assert_eq!(&fixed_out, &growing_out);
+            prop_assert_eq!(&fixed.tape()[..], dense.tape());
+            let visited = growing.tape().len().min(16);
+            prop_assert_eq!(&fixed.tape()[..visited], &growing.tape()[..visited]);
+            prop_assert!(fixed.tape()[visited..].iter().all(|cell| *cell == 0));
+        }
+    }
+}
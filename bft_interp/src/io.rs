@@ -0,0 +1,250 @@
+//! The input and output of programs, behind traits so that the VM doesn't need `std`.
+//!
+//! With the `std` feature, every [`std::io::Read`] is a [`ByteRead`] and every
+//! [`std::io::Write`] is a [`ByteWrite`]. Without it, byte slices and queues can be read from,
+//! and vectors and queues can be written to.
+//!
+//! Applications that produce input and consume output themselves, like GUIs and games, can use
+//! [`input_fn`] and [`output_fn`] to wrap closures instead of implementing the traits.
+
+#[cfg(not(feature = "std"))]
+use alloc::collections::VecDeque;
+#[cfg(not(feature = "std"))]
+use alloc::vec::Vec;
+#[cfg(not(feature = "std"))]
+use core::fmt;
+
+/// An error reading or writing a program's input or output.
+#[cfg(feature = "std")]
+pub type IoError = std::io::Error;
+
+/// An error reading or writing a program's input or output.
+#[cfg(not(feature = "std"))]
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct IoError(pub &'static str);
+
+#[cfg(not(feature = "std"))]
+impl fmt::Display for IoError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(self.0)
+    }
+}
+
+#[cfg(not(feature = "std"))]
+impl core::error::Error for IoError {}
+
+/// Whether `err` came from writing to output that whatever was reading it has closed, like a pipe
+/// to `head` once it has printed enough lines.
+#[cfg(feature = "std")]
+pub(crate) fn is_broken_pipe(err: &IoError) -> bool {
+    err.kind() == std::io::ErrorKind::BrokenPipe
+}
+
+/// Whether `err` came from writing to output that whatever was reading it has closed. Without
+/// `std` there are no pipes, so it never did.
+#[cfg(not(feature = "std"))]
+pub(crate) fn is_broken_pipe(_: &IoError) -> bool {
+    false
+}
+
+/// Somewhere a program reads its input from, a byte at a time.
+pub trait ByteRead {
+    /// Read the next byte, or `None` at the end of the input.
+    ///
+    /// # Errors
+    /// Fails if the input can't be read.
+    fn read_byte(&mut self) -> Result<Option<u8>, IoError>;
+}
+
+/// Somewhere a program writes its output to, a byte at a time.
+pub trait ByteWrite {
+    /// Write a single byte.
+    ///
+    /// # Errors
+    /// Fails if the output can't be written.
+    fn write_byte(&mut self, byte: u8) -> Result<(), IoError>;
+
+    /// Make sure that everything written so far has reached its destination.
+    ///
+    /// # Errors
+    /// Fails if the output can't be written.
+    fn flush(&mut self) -> Result<(), IoError> {
+        Ok(())
+    }
+}
+
+/// Input supplied by a closure. See [`input_fn`].
+#[derive(Clone, Debug)]
+pub struct InputFn<F>(F);
+
+/// Read input by calling `f`, which returns the next byte, or `None` at the end of the input.
+///
+/// ```
+/// use bft_interp::{input_fn, ByteRead};
+///
+/// let mut count = 0;
+/// let mut input = input_fn(|| {
+///     count += 1;
+///     (count <= 2).then_some(b'a' + count)
+/// });
+/// assert_eq!(input.read_byte().unwrap(), Some(b'b'));
+/// assert_eq!(input.read_byte().unwrap(), Some(b'c'));
+/// assert_eq!(input.read_byte().unwrap(), None);
+/// ```
+pub fn input_fn<F: FnMut() -> Option<u8>>(f: F) -> InputFn<F> {
+    InputFn(f)
+}
+
+impl<F: FnMut() -> Option<u8>> ByteRead for InputFn<F> {
+    fn read_byte(&mut self) -> Result<Option<u8>, IoError> {
+        Ok((self.0)())
+    }
+}
+
+/// Output consumed by a closure. See [`output_fn`].
+#[derive(Clone, Debug)]
+pub struct OutputFn<F>(F);
+
+/// Write output by calling `f` with each byte.
+///
+/// ```
+/// use bft_interp::{output_fn, ByteWrite};
+///
+/// let mut written = Vec::new();
+/// let mut output = output_fn(|byte| written.push(byte));
+/// output.write_byte(b'a').unwrap();
+/// assert_eq!(written, b"a");
+/// ```
+pub fn output_fn<F: FnMut(u8)>(f: F) -> OutputFn<F> {
+    OutputFn(f)
+}
+
+impl<F: FnMut(u8)> ByteWrite for OutputFn<F> {
+    fn write_byte(&mut self, byte: u8) -> Result<(), IoError> {
+        (self.0)(byte);
+        Ok(())
+    }
+}
+
+#[cfg(feature = "std")]
+impl<R: std::io::Read + ?Sized> ByteRead for R {
+    fn read_byte(&mut self) -> Result<Option<u8>, IoError> {
+        let mut buf = [0u8];
+        match self.read(&mut buf)? {
+            0 => Ok(None),
+            _ => Ok(Some(buf[0])),
+        }
+    }
+}
+
+#[cfg(feature = "std")]
+impl<W: std::io::Write + ?Sized> ByteWrite for W {
+    fn write_byte(&mut self, byte: u8) -> Result<(), IoError> {
+        self.write_all(&[byte])
+    }
+
+    fn flush(&mut self) -> Result<(), IoError> {
+        std::io::Write::flush(self)
+    }
+}
+
+#[cfg(not(feature = "std"))]
+impl<R: ByteRead + ?Sized> ByteRead for &mut R {
+    fn read_byte(&mut self) -> Result<Option<u8>, IoError> {
+        (**self).read_byte()
+    }
+}
+
+#[cfg(not(feature = "std"))]
+impl ByteRead for &[u8] {
+    fn read_byte(&mut self) -> Result<Option<u8>, IoError> {
+        let Some((first, rest)) = self.split_first() else {
+            return Ok(None);
+        };
+        *self = rest;
+        Ok(Some(*first))
+    }
+}
+
+#[cfg(not(feature = "std"))]
+impl ByteRead for VecDeque<u8> {
+    fn read_byte(&mut self) -> Result<Option<u8>, IoError> {
+        Ok(self.pop_front())
+    }
+}
+
+#[cfg(not(feature = "std"))]
+impl<W: ByteWrite + ?Sized> ByteWrite for &mut W {
+    fn write_byte(&mut self, byte: u8) -> Result<(), IoError> {
+        (**self).write_byte(byte)
+    }
+
+    fn flush(&mut self) -> Result<(), IoError> {
+        (**self).flush()
+    }
+}
+
+#[cfg(not(feature = "std"))]
+impl ByteWrite for Vec<u8> {
+    fn write_byte(&mut self, byte: u8) -> Result<(), IoError> {
+        self.push(byte);
+        Ok(())
+    }
+}
+
+#[cfg(not(feature = "std"))]
+impl ByteWrite for VecDeque<u8> {
+    fn write_byte(&mut self, byte: u8) -> Result<(), IoError> {
+        self.push_back(byte);
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::BFVM;
+    use alloc::collections::VecDeque;
+    use alloc::vec::Vec;
+    use bft_types::BFprogram;
+
+    #[test]
+    fn reading_and_writing_bytes() {
+        let mut input = &b"ab"[..];
+        assert_eq!(input.read_byte().unwrap(), Some(b'a'));
+        assert_eq!(input.read_byte().unwrap(), Some(b'b'));
+        assert_eq!(input.read_byte().unwrap(), None);
+
+        let mut output = Vec::new();
+        output.write_byte(b'c').unwrap();
+        ByteWrite::flush(&mut output).unwrap();
+        assert_eq!(output, b"c");
+    }
+
+    #[test]
+    fn running_with_queues() {
+        let mut program = BFprogram::new("io.test", b",.,.");
+        program.validate_brackets().unwrap();
+        let mut input: VecDeque<u8> = b"hi".iter().copied().collect();
+        let mut output = VecDeque::new();
+        let mut vm: BFVM<u8> = BFVM::new(None, false);
+        vm.run(&program, &mut input, &mut output).unwrap();
+        assert_eq!(output, b"hi");
+    }
+
+    #[test]
+    fn running_with_closures() {
+        let mut program = BFprogram::new("io.test", b",+.,+.");
+        program.validate_brackets().unwrap();
+        let mut input = b"ab".iter().copied();
+        let mut output = Vec::new();
+        let mut vm: BFVM<u8> = BFVM::new(None, false);
+        vm.run(
+            &program,
+            &mut input_fn(|| input.next()),
+            &mut output_fn(|byte| output.push(byte)),
+        )
+        .unwrap();
+        assert_eq!(output, b"bc");
+    }
+}
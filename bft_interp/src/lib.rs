@@ -1,23 +1,455 @@
 //! The Virtual Machine that will run our Brainf*ck program.
+//!
+//! Without the default `std` feature, the crate only needs `core` and `alloc`, with programs
+//! reading and writing through the [`ByteRead`] and [`ByteWrite`] traits.
 
+#![cfg_attr(not(feature = "std"), no_std)]
 #![warn(missing_docs)]
 
-use std::num::NonZeroUsize;
+extern crate alloc;
 
-use bft_types::BFprogram;
+use alloc::collections::{BTreeMap, VecDeque};
+use alloc::sync::Arc;
+use alloc::vec::Vec;
+use core::error::Error;
+use core::fmt;
+use core::fmt::{Display, Formatter};
+use core::mem;
+use core::num::{NonZeroU32, NonZeroUsize};
+#[cfg(feature = "std")]
+use std::sync::mpsc::{channel, Receiver, Sender};
 
-/// Brainf*ck interpreter internal state.
-#[allow(dead_code)]
+use bft_types::{BFprogram, InputInstruction, Instruction, SourceName};
+
+#[cfg(feature = "std")]
+pub mod bits;
+mod checkpoint;
+mod cost;
+#[cfg(feature = "miette")]
+mod diagnostic;
+mod error;
+mod fast;
+mod fixed;
+mod io;
+mod pool;
+mod rng;
+mod sandbox;
+mod stop;
+mod suspend;
+mod tape_diff;
+#[cfg(feature = "wasm")]
+pub mod wasm;
+
+pub use checkpoint::CheckpointError;
+use cost::Fuel;
+pub use cost::{CostModel, Uniform};
+pub use error::BftError;
+pub use fast::FusedLoop;
+pub use fixed::FixedVM;
+pub use io::{input_fn, output_fn, ByteRead, ByteWrite, InputFn, IoError, OutputFn};
+pub use pool::{VmConfig, VmPool};
+use rng::Rng;
+pub use sandbox::{Limit, SandboxLimits};
+pub use stop::StopHandle;
+pub use suspend::PollOutcome;
+pub use tape_diff::{CellChange, TapeDiff};
+
+/// The operations the VM needs to be able to perform on a single cell of the tape.
+pub trait CellKind {
+    /// Add one to the value of the cell, wrapping on overflow.
+    fn increment(&mut self);
+
+    /// Subtract one from the value of the cell, wrapping on underflow.
+    fn decrement(&mut self);
+
+    /// Add `n` to the value of the cell, as `n` increments would.
+    fn add(&mut self, n: u8) {
+        for _ in 0..n {
+            self.increment();
+        }
+    }
+
+    /// Store a byte read from the program's input.
+    fn set_value(&mut self, value: u8);
+
+    /// The byte to write to the program's output.
+    fn get_value(&self) -> u8;
+
+    /// Check if the cell holds zero, which controls looping.
+    fn is_zero(&self) -> bool;
+}
+
+impl CellKind for u8 {
+    fn increment(&mut self) {
+        *self = self.wrapping_add(1);
+    }
+
+    fn decrement(&mut self) {
+        *self = self.wrapping_sub(1);
+    }
+
+    fn add(&mut self, n: u8) {
+        *self = self.wrapping_add(n);
+    }
+
+    fn set_value(&mut self, value: u8) {
+        *self = value;
+    }
+
+    fn get_value(&self) -> u8 {
+        *self
+    }
+
+    fn is_zero(&self) -> bool {
+        *self == 0
+    }
+}
+
+/// Cells wider than a byte, for programs that count past 255. Input is stored as it is read,
+/// and only the lowest byte is written to the output.
+macro_rules! wide_cell {
+    ($($cell:ty),*) => {$(
+        impl CellKind for $cell {
+            fn increment(&mut self) {
+                *self = self.wrapping_add(1);
+            }
+
+            fn decrement(&mut self) {
+                *self = self.wrapping_sub(1);
+            }
+
+            fn add(&mut self, n: u8) {
+                *self = self.wrapping_add(n.into());
+            }
+
+            fn set_value(&mut self, value: u8) {
+                *self = value.into();
+            }
+
+            fn get_value(&self) -> u8 {
+                self.to_le_bytes()[0]
+            }
+
+            fn is_zero(&self) -> bool {
+                *self == 0
+            }
+        }
+    )*};
+}
+
+wide_cell!(u16, u32);
+
+/// A single bit, for dialects like Boolfuck. Incrementing or decrementing flips the bit.
+impl CellKind for bool {
+    fn increment(&mut self) {
+        *self = !*self;
+    }
+
+    fn decrement(&mut self) {
+        *self = !*self;
+    }
+
+    fn add(&mut self, n: u8) {
+        *self ^= n % 2 == 1;
+    }
+
+    fn set_value(&mut self, value: u8) {
+        *self = value & 1 != 0;
+    }
+
+    fn get_value(&self) -> u8 {
+        u8::from(*self)
+    }
+
+    fn is_zero(&self) -> bool {
+        !*self
+    }
+}
+
+/// Possible errors while running a program.
 #[derive(Debug)]
+pub enum VMError {
+    /// The head was moved off either end of the tape.
+    InvalidHeadPosition(SourceName, InputInstruction, usize),
+
+    /// A loop instruction was executed without a matching bracket. This happens when a program
+    /// is run without first calling [`BFprogram::validate_brackets`].
+    UnmatchedBracket(SourceName, InputInstruction),
+
+    /// Reading from the input, or writing to the output failed.
+    IOError(SourceName, InputInstruction, IoError),
+
+    /// A procedure was called before one with that number was defined.
+    UndefinedProcedure(SourceName, InputInstruction, u8),
+
+    /// The instruction needs something that the VM doesn't have, like a [`FixedVM`] starting a
+    /// thread.
+    Unsupported(SourceName, InputInstruction),
+
+    /// There isn't enough fuel left to execute the instruction. See [`BFVM::with_fuel`].
+    OutOfFuel(SourceName, InputInstruction),
+
+    /// The VM was asked to stop through a [`StopHandle`].
+    Cancelled(SourceName),
+
+    /// The instruction would go over one of the limits given by [`BFVM::with_sandbox`].
+    LimitExceeded(SourceName, InputInstruction, Limit),
+}
+
+impl Display for VMError {
+    fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::InvalidHeadPosition(source_name, inst, head) => write!(
+                f,
+                "Head moved to invalid position from {} at [{}:{}]",
+                head,
+                source_name.display(),
+                inst.location()
+            ),
+            Self::UnmatchedBracket(source_name, inst) => write!(
+                f,
+                "No matching bracket for loop at [{}:{}]",
+                source_name.display(),
+                inst.location()
+            ),
+            Self::IOError(source_name, inst, err) => write!(
+                f,
+                "I/O error \"{}\" at [{}:{}]",
+                err,
+                source_name.display(),
+                inst.location()
+            ),
+            Self::UndefinedProcedure(source_name, inst, procedure) => write!(
+                f,
+                "Call to undefined procedure {} at [{}:{}]",
+                procedure,
+                source_name.display(),
+                inst.location()
+            ),
+            Self::LimitExceeded(source_name, inst, limit) => write!(
+                f,
+                "{} limit exceeded at [{}:{}]",
+                limit,
+                source_name.display(),
+                inst.location()
+            ),
+            Self::Cancelled(source_name) => {
+                write!(f, "Execution of {} was cancelled", source_name.display())
+            }
+            Self::OutOfFuel(source_name, inst) => write!(
+                f,
+                "Ran out of fuel at [{}:{}]",
+                source_name.display(),
+                inst.location()
+            ),
+            Self::Unsupported(source_name, inst) => write!(
+                f,
+                "{} isn't supported by this VM at [{}:{}]",
+                inst.instruction(),
+                source_name.display(),
+                inst.location()
+            ),
+        }
+    }
+}
+
+impl Error for VMError {
+    fn source(&self) -> Option<&(dyn Error + 'static)> {
+        match self {
+            Self::IOError(_, _, err) => Some(err),
+            _ => None,
+        }
+    }
+}
+
+impl VMError {
+    /// The name of the program that was running.
+    #[must_use]
+    pub fn source_name(&self) -> &SourceName {
+        match self {
+            Self::InvalidHeadPosition(source_name, ..)
+            | Self::UnmatchedBracket(source_name, _)
+            | Self::IOError(source_name, ..)
+            | Self::UndefinedProcedure(source_name, ..)
+            | Self::Unsupported(source_name, _)
+            | Self::OutOfFuel(source_name, _)
+            | Self::Cancelled(source_name)
+            | Self::LimitExceeded(source_name, ..) => source_name,
+        }
+    }
+
+    /// The instruction that caused the error, if there was one.
+    #[must_use]
+    pub fn instruction(&self) -> Option<&InputInstruction> {
+        match self {
+            Self::InvalidHeadPosition(_, inst, _)
+            | Self::UnmatchedBracket(_, inst)
+            | Self::IOError(_, inst, _)
+            | Self::UndefinedProcedure(_, inst, _)
+            | Self::Unsupported(_, inst)
+            | Self::OutOfFuel(_, inst)
+            | Self::LimitExceeded(_, inst, _) => Some(inst),
+            Self::Cancelled(_) => None,
+        }
+    }
+}
+
+/// The state of the VM after executing a single instruction.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum StepOutcome {
+    /// There are more instructions to execute.
+    Running,
+
+    /// The program counter has moved past the end of the program.
+    Finished,
+
+    /// The program was stopped because its output was closed by whatever was reading it, like a
+    /// pipe to `head` once it has printed enough lines. Nothing more can be executed, as with
+    /// [`StepOutcome::Finished`], but the program didn't reach its end.
+    BrokenPipe,
+}
+
+/// Something that happened while running a program, sent to the receivers returned by
+/// [`BFVM::subscribe`].
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum VmEvent {
+    /// The program wrote a byte.
+    Output(u8),
+
+    /// The program is about to read a byte.
+    InputRequested,
+
+    /// The tape grew, and now has this many cells.
+    TapeGrown(usize),
+
+    /// Execution stopped at a breakpoint on the instruction at this index. The VM doesn't have
+    /// breakpoints itself, so this comes from a debugger calling [`BFVM::notify`].
+    BreakpointHit(usize),
+
+    /// The program finished, with the exit status it asked for, if it halted with one.
+    Halted(Option<u8>),
+}
+
+/// Execute one of the Extended Type I instructions on a cell holding `value`, returning the new
+/// value for the cell, if it changes.
+fn bitwise(inst: Instruction, value: u8, storage: &mut u8) -> Option<u8> {
+    match inst {
+        Instruction::Store => {
+            *storage = value;
+            None
+        }
+        Instruction::Retrieve => Some(*storage),
+        Instruction::ShiftRight => Some(value >> 1),
+        Instruction::ShiftLeft => Some(value << 1),
+        Instruction::Not => Some(!value),
+        Instruction::Xor => Some(value ^ *storage),
+        Instruction::And => Some(value & *storage),
+        Instruction::Or => Some(value | *storage),
+        _ => None,
+    }
+}
+
+/// Number of cells on the tape when no size is given.
+const DEFAULT_CELLS: usize = 30000;
+
+/// The state of a thread that is waiting for its turn to run.
+#[derive(Clone, Debug)]
+struct Thread<C> {
+    tape: Arc<Vec<C>>,
+    head: usize,
+    pc: usize,
+    call_stack: Vec<usize>,
+    other_tapes: VecDeque<(Arc<Vec<C>>, usize)>,
+    tape_index: usize,
+}
+
+/// Brainf*ck interpreter internal state.
+///
+/// When a program starts extra threads, these fields hold the state of the thread that is
+/// currently running, and the others wait their turn in `waiting`.
+///
+/// Cloning a VM takes a snapshot of its state. The tapes are shared until one of the copies
+/// writes to them.
+#[derive(Clone, Debug)]
 pub struct BFVM<C> {
-    /// Block of memory for the program to work on.
-    tape: Vec<C>,
+    /// Block of memory for the program to work on. Threads share their tape until one of them
+    /// writes to it.
+    tape: Arc<Vec<C>>,
 
     /// Index of where the program is pointing to in the tape.
     head: usize,
 
+    /// The tapes that aren't currently selected, along with their heads, in the order they will
+    /// be switched to.
+    other_tapes: VecDeque<(Arc<Vec<C>>, usize)>,
+
+    /// Which tape is currently selected, counting from 0.
+    tape_index: usize,
+
     /// When true, the VM is allowed to grow the tape for additional space as needed.
     growable: bool,
+
+    /// Index of the next instruction to be executed.
+    pc: usize,
+
+    /// Where each procedure defined so far starts, keyed by its number.
+    procedures: BTreeMap<u8, usize>,
+
+    /// Where to return to when each procedure currently running finishes.
+    call_stack: Vec<usize>,
+
+    /// Threads waiting to run, in the order they will be scheduled.
+    waiting: VecDeque<Thread<C>>,
+
+    /// Number of instructions each thread runs before the next one gets its turn.
+    time_slice: NonZeroU32,
+
+    /// Number of instructions the running thread has left in its turn.
+    turn_left: u32,
+
+    /// Whether threads share their tapes, rather than each having its own copy.
+    shared_tapes: bool,
+
+    /// The loops compiled by [`BFVM::run_fast_unchecked`], by the index of their `[`, and how
+    /// often they ran, if they are being recorded.
+    fusion_stats: Option<BTreeMap<usize, FusedLoop>>,
+
+    /// Index of the most recently executed instruction.
+    last_executed: Option<usize>,
+
+    /// The storage register used by the Extended Type I instructions.
+    storage: u8,
+
+    /// The exit status requested by the program, if it halted with one.
+    exit_status: Option<u8>,
+
+    /// Source of random bytes, created when a program first asks for one unless a seed was given.
+    rng: Option<Rng>,
+
+    /// Where to send events, for each receiver that hasn't been dropped yet.
+    #[cfg(feature = "std")]
+    subscribers: Vec<Sender<VmEvent>>,
+
+    /// The budget that each instruction is charged against, if execution is metered.
+    fuel: Option<Fuel>,
+
+    /// The flag that other threads set to stop execution, once one has asked for it.
+    stop: Option<StopHandle>,
+
+    /// Limits on the resources the program may use.
+    limits: SandboxLimits,
+
+    /// Number of bytes read so far.
+    input_read: usize,
+
+    /// Number of bytes written so far.
+    output_written: usize,
+
+    /// Whether the output was closed by whatever was reading it, which stopped the program.
+    output_closed: bool,
+
+    /// Number of instructions executed so far, by every thread.
+    executed: u64,
 }
 
 impl<C: Default> BFVM<C> {
@@ -26,34 +458,831 @@ impl<C: Default> BFVM<C> {
     /// `capcity` specifies the size of the interal tape to use. A `capacity` of 0 indicates that a
     /// tape with the default capacity should be generated. `growable` is a flag to specifiy if the tape is gowable.
     pub fn new(capacity: Option<NonZeroUsize>, growable: bool) -> BFVM<C> {
-        let c = capacity.map_or(30000, NonZeroUsize::get);
         let mut tape = Vec::new();
-        tape.resize_with(c, C::default);
+        tape.resize_with(
+            capacity.map_or(DEFAULT_CELLS, NonZeroUsize::get),
+            C::default,
+        );
+        Self::with_tape(Arc::new(tape), growable)
+    }
+
+    /// Return the VM to the state [`BFVM::new`] leaves it in, reusing the memory of its tape
+    /// unless a snapshot still shares it.
+    pub(crate) fn reset(&mut self, capacity: Option<NonZeroUsize>, growable: bool) {
+        let mut tape = Arc::try_unwrap(mem::take(&mut self.tape)).unwrap_or_default();
+        tape.clear();
+        tape.resize_with(
+            capacity.map_or(DEFAULT_CELLS, NonZeroUsize::get),
+            C::default,
+        );
+        *self = Self::with_tape(Arc::new(tape), growable);
+    }
+
+    /// A VM with clean internal state, working on `tape`.
+    fn with_tape(tape: Arc<Vec<C>>, growable: bool) -> BFVM<C> {
         BFVM {
             tape,
             head: 0,
+            other_tapes: VecDeque::new(),
+            tape_index: 0,
             growable,
+            pc: 0,
+            procedures: BTreeMap::new(),
+            call_stack: Vec::new(),
+            waiting: VecDeque::new(),
+            time_slice: NonZeroU32::MIN,
+            turn_left: 0,
+            shared_tapes: false,
+            fusion_stats: None,
+            last_executed: None,
+            storage: 0,
+            exit_status: None,
+            rng: None,
+            #[cfg(feature = "std")]
+            subscribers: Vec::new(),
+            fuel: None,
+            stop: None,
+            limits: SandboxLimits::UNLIMITED,
+            input_read: 0,
+            output_written: 0,
+            output_closed: false,
+            executed: 0,
         }
     }
 }
 
 impl<C> BFVM<C> {
-    /// The main interpreter that takes a program and (eventually) interprets it.
-    pub fn interpret(&self, code: &BFprogram) {
-        for inst in code.instructions() {
-            println!(
-                "[{:?}:{}] {}",
-                code.source(),
-                inst.location(),
-                inst.instruction()
-            );
+    /// Give each thread `count` independent tapes, each the same size as the first, for use with
+    /// the multitape extension.
+    #[must_use]
+    pub fn with_tapes(mut self, count: NonZeroUsize) -> Self
+    where
+        C: Default,
+    {
+        let tape = Arc::new(self.tape.iter().map(|_| C::default()).collect());
+        self.other_tapes = (1..count.get()).map(|_| (Arc::clone(&tape), 0)).collect();
+        self
+    }
+
+    /// Set the cells of the first tape from `start` onwards to `values` before the program runs,
+    /// so that it can be given data without reading it from its input. The tape is lengthened if
+    /// it's too short to hold them.
+    #[must_use]
+    pub fn with_cells(mut self, start: usize, values: &[u8]) -> Self
+    where
+        C: CellKind + Clone + Default,
+    {
+        let tape = Arc::make_mut(&mut self.tape);
+        if tape.len() < start + values.len() {
+            tape.resize_with(start + values.len(), C::default);
+        }
+        for (cell, value) in tape[start..].iter_mut().zip(values) {
+            cell.set_value(*value);
         }
+        self
+    }
+
+    /// Let each thread run `instructions` instructions at a time before switching to the next,
+    /// rather than one. Threads always take their turns in the order they were started, so a
+    /// threaded program given the same input and seed runs the same way every time.
+    #[must_use]
+    pub fn with_time_slice(mut self, instructions: NonZeroU32) -> Self {
+        self.time_slice = instructions;
+        self
+    }
+
+    /// Let the threads started by a fork share their tapes, rather than each working on its own
+    /// copy, so that they can communicate through their cells.
+    ///
+    /// Threads take turns on a single OS thread, so the VM guarantees a simple memory model:
+    /// each instruction happens all at once, before or after each instruction of every other
+    /// thread, and every cell it writes is seen straight away by every thread. Increments from
+    /// different threads are never lost, and a loop sees the value its cell has at the moment it
+    /// is tested. Which instructions happen first depends only on the schedule, which
+    /// [`BFVM::with_time_slice`] sets. A fork still sets the forking thread's cell to 0 and the
+    /// new thread's cell, the one to its right, to 1, both on the shared tape.
+    #[must_use]
+    pub fn with_shared_tapes(mut self) -> Self {
+        self.shared_tapes = true;
+        self
+    }
+
+    /// Record which loops [`BFVM::run_fast_unchecked`] compiles, and how often each runs, for
+    /// [`BFVM::fusion_stats`].
+    #[must_use]
+    pub fn with_fusion_stats(mut self) -> Self {
+        self.fusion_stats = Some(BTreeMap::new());
+        self
+    }
+
+    /// The loops [`BFVM::run_fast_unchecked`] has compiled, by the index of their `[`, and how
+    /// often each ran, if the VM was made with [`BFVM::with_fusion_stats`].
+    #[must_use]
+    pub fn fusion_stats(&self) -> Option<&BTreeMap<usize, FusedLoop>> {
+        self.fusion_stats.as_ref()
+    }
+
+    /// Seed the random numbers used by the `?` instruction, so that runs can be reproduced.
+    /// Without a seed, each run gets different random numbers, except without the `std` feature,
+    /// where there is no source of randomness.
+    #[must_use]
+    pub fn with_seed(mut self, seed: u64) -> Self {
+        self.rng = Some(Rng::from_seed(seed));
+        self
+    }
+
+    /// Meter execution with `fuel` units to spend, at the prices given by `model`. Once an
+    /// instruction costs more than is left, [`BFVM::step`] fails with [`VMError::OutOfFuel`]
+    /// without executing it, so the program can be resumed after [`BFVM::add_fuel`].
+    ///
+    /// With the [`Uniform`] model, the fuel is the number of instructions that can be executed.
+    #[must_use]
+    pub fn with_fuel<M: CostModel + Send + Sync + 'static>(mut self, fuel: u64, model: M) -> Self {
+        self.fuel = Some(Fuel {
+            remaining: fuel,
+            model: Arc::new(model),
+        });
+        self
+    }
+
+    /// The fuel left to spend, if execution is metered.
+    #[must_use]
+    pub fn fuel(&self) -> Option<u64> {
+        self.fuel.as_ref().map(|fuel| fuel.remaining)
+    }
+
+    /// Top up the fuel of a metered VM. This does nothing if execution isn't metered.
+    pub fn add_fuel(&mut self, fuel: u64) {
+        if let Some(current) = &mut self.fuel {
+            current.remaining = current.remaining.saturating_add(fuel);
+        }
+    }
+
+    /// Restrict the resources the program may use, replacing any limits given before. A step
+    /// limit meters execution as [`BFVM::with_fuel`] does with the [`Uniform`] model, and a tape
+    /// that is already bigger than the memory limit is cut down to size.
+    #[must_use]
+    pub fn with_sandbox(mut self, limits: SandboxLimits) -> Self
+    where
+        C: Clone,
+    {
+        if let Some(max_memory) = limits.max_memory {
+            let max_memory = max_memory.max(1);
+            for tape in core::iter::once(&mut self.tape)
+                .chain(self.other_tapes.iter_mut().map(|(tape, _)| tape))
+            {
+                if tape.len() > max_memory {
+                    Arc::make_mut(tape).truncate(max_memory);
+                }
+            }
+        }
+        self.fuel = limits.max_steps.map(|steps| Fuel {
+            remaining: steps,
+            model: Arc::new(Uniform),
+        });
+        self.limits = limits;
+        self
+    }
+
+    /// Stop the program with [`Limit::Output`] when it tries to write more than `bytes` bytes,
+    /// rather than letting a runaway loop fill a disk or terminal. This replaces the output limit
+    /// from [`BFVM::with_sandbox`].
+    #[must_use]
+    pub fn with_max_output(mut self, bytes: usize) -> Self {
+        self.limits.max_output = Some(bytes);
+        self
+    }
+
+    /// A token that stops the VM when triggered, from any thread. [`BFVM::step`] checks it
+    /// before executing each instruction, and fails with [`VMError::Cancelled`] once it has been
+    /// triggered.
+    pub fn stop_handle(&mut self) -> StopHandle {
+        self.stop.get_or_insert_with(StopHandle::default).clone()
+    }
+
+    /// The contents of the tape.
+    #[must_use]
+    pub fn tape(&self) -> &[C] {
+        &self.tape
+    }
+
+    /// The number of instructions executed so far, by every thread, including one that failed.
+    #[must_use]
+    pub fn executed(&self) -> u64 {
+        self.executed
+    }
+
+    /// The number of bytes read so far, by every thread.
+    #[must_use]
+    pub fn input_read(&self) -> usize {
+        self.input_read
+    }
+
+    /// The number of bytes written so far, by every thread.
+    #[must_use]
+    pub fn output_written(&self) -> usize {
+        self.output_written
+    }
+
+    /// Whether the program was stopped because its output was closed, as reported by
+    /// [`StepOutcome::BrokenPipe`].
+    #[must_use]
+    pub fn output_closed(&self) -> bool {
+        self.output_closed
+    }
+
+    /// Which tape is currently selected, counting from 0.
+    #[must_use]
+    pub fn tape_index(&self) -> usize {
+        self.tape_index
+    }
+
+    /// The current position of the head on the tape.
+    #[must_use]
+    pub fn head(&self) -> usize {
+        self.head
+    }
+
+    /// The index of the next instruction that will be executed.
+    #[must_use]
+    pub fn pc(&self) -> usize {
+        self.pc
+    }
+
+    /// The index of the instruction executed by the most recent call to [`BFVM::step`]. The head
+    /// and tape belong to the thread that executed it until the next step.
+    #[must_use]
+    pub fn last_executed(&self) -> Option<usize> {
+        self.last_executed
+    }
+
+    /// The exit status the program asked for when it halted, if it did.
+    #[must_use]
+    pub fn exit_status(&self) -> Option<u8> {
+        self.exit_status
+    }
+
+    /// The number of threads that haven't finished yet, including the one currently running.
+    #[must_use]
+    pub fn thread_count(&self) -> usize {
+        1 + self.waiting.len()
+    }
+
+    /// Receive the events that happen from now on, like output being written and the program
+    /// finishing. Events are sent as they happen, and wait in the channel until they're received,
+    /// so another thread can report progress without slowing the VM down.
+    #[cfg(feature = "std")]
+    pub fn subscribe(&mut self) -> Receiver<VmEvent> {
+        let (sender, receiver) = channel();
+        self.subscribers.push(sender);
+        receiver
+    }
+
+    /// Send `event` to every subscriber. Without the `std` feature there are no subscribers, so
+    /// this does nothing.
+    pub fn notify(&mut self, event: VmEvent) {
+        #[cfg(feature = "std")]
+        self.subscribers
+            .retain(|subscriber| subscriber.send(event).is_ok());
+        #[cfg(not(feature = "std"))]
+        let _ = event;
+    }
+
+    /// Make `next` the running thread, returning the state of the thread it replaces. With
+    /// shared tapes, the tapes are handed over to `next`, which keeps its own heads and
+    /// selected tape, and the thread it replaces is left without any.
+    fn switch_to(&mut self, mut next: Thread<C>) -> Thread<C> {
+        if self.shared_tapes {
+            let count = self.other_tapes.len() + 1;
+            let mut tapes: Vec<Arc<Vec<C>>> = (0..count).map(|_| Arc::default()).collect();
+            tapes[self.tape_index] = mem::take(&mut self.tape);
+            for (i, (tape, _)) in self.other_tapes.iter_mut().enumerate() {
+                tapes[(self.tape_index + 1 + i) % count] = mem::take(tape);
+            }
+            next.tape = mem::take(&mut tapes[next.tape_index]);
+            for (i, (tape, _)) in next.other_tapes.iter_mut().enumerate() {
+                *tape = mem::take(&mut tapes[(next.tape_index + 1 + i) % count]);
+            }
+        }
+        Thread {
+            tape: mem::replace(&mut self.tape, next.tape),
+            head: mem::replace(&mut self.head, next.head),
+            pc: mem::replace(&mut self.pc, next.pc),
+            call_stack: mem::replace(&mut self.call_stack, next.call_stack),
+            other_tapes: mem::replace(&mut self.other_tapes, next.other_tapes),
+            tape_index: mem::replace(&mut self.tape_index, next.tape_index),
+        }
+    }
+
+    /// Switch to the next thread if the running one has used up its time slice or reached the
+    /// end of the program, returning whether there is an instruction left to execute.
+    fn schedule(&mut self, len: usize) -> bool {
+        if self.turn_left == 0 {
+            if let Some(next) = self.waiting.pop_front() {
+                let current = self.switch_to(next);
+                if current.pc < len {
+                    self.waiting.push_back(current);
+                }
+            }
+            self.turn_left = self.time_slice.get();
+        }
+        while self.pc >= len {
+            match self.waiting.pop_front() {
+                Some(next) => {
+                    self.switch_to(next);
+                    self.turn_left = self.time_slice.get();
+                }
+                None => return false,
+            }
+        }
+        true
     }
 }
 
-#[cfg(test)]
+impl<C: CellKind + Default + Clone> BFVM<C> {
+    /// Execute a single instruction of `program`, reading from `input` and writing to `output`
+    /// as needed.
+    ///
+    /// When the program has started extra threads, each call runs one instruction of the current
+    /// thread, switching to the next thread in turn once it has run its time slice, which is one
+    /// instruction unless [`BFVM::with_time_slice`] says otherwise. Once every thread has reached
+    /// the end of the program, this returns [`StepOutcome::Finished`] without doing anything
+    /// else. If the output is closed by whatever is reading it, the program is stopped and this
+    /// returns [`StepOutcome::BrokenPipe`].
+    ///
+    /// # Errors
+    /// This will return an error if the head moves off the tape, if a loop has no matching
+    /// bracket, if there is an error with the input or output, if it runs out of fuel, or if it
+    /// has been stopped.
+    pub fn step<R: ByteRead, W: ByteWrite>(
+        &mut self,
+        program: &BFprogram,
+        input: &mut R,
+        output: &mut W,
+    ) -> Result<StepOutcome, VMError> {
+        let len = program.instructions().len();
+        self.last_executed = None;
+        if self.stop.as_ref().is_some_and(StopHandle::is_stopped) {
+            return Err(VMError::Cancelled(program.source().clone()));
+        }
+        if !self.schedule(len) {
+            return Ok(StepOutcome::Finished);
+        }
+        self.turn_left -= 1;
+        let inst = &program.instructions()[self.pc];
+        if !self.limits.allow_extensions && inst.instruction().extension().is_some() {
+            return Err(VMError::Unsupported(program.source().clone(), *inst));
+        }
+        self.charge(program, *inst)?;
+        self.last_executed = Some(self.pc);
+        self.executed += 1;
+        if matches!(
+            inst.instruction(),
+            Instruction::EndProgram | Instruction::Halt
+        ) {
+            if *inst.instruction() == Instruction::Halt {
+                self.exit_status = Some(self.tape[self.head].get_value());
+            }
+            self.pc = len;
+            self.waiting.clear();
+            self.notify(VmEvent::Halted(self.exit_status));
+            return Ok(StepOutcome::Finished);
+        }
+        self.execute(program, *inst, input, output)?;
+        if self.output_closed {
+            self.pc = len;
+            self.waiting.clear();
+            self.notify(VmEvent::Halted(self.exit_status));
+            return Ok(StepOutcome::BrokenPipe);
+        }
+        self.pc += 1;
+
+        if self.pc < len || !self.waiting.is_empty() {
+            Ok(StepOutcome::Running)
+        } else {
+            self.notify(VmEvent::Halted(self.exit_status));
+            Ok(StepOutcome::Finished)
+        }
+    }
+
+    /// Write the current cell to `output` for `inst`, noting if the output has been closed.
+    fn write_output<W: ByteWrite>(
+        &mut self,
+        program: &BFprogram,
+        inst: InputInstruction,
+        output: &mut W,
+    ) -> Result<(), VMError> {
+        self.check_limit(Limit::Output, self.output_written, program, inst)?;
+        let value = self.tape[self.head].get_value();
+        match output.write_byte(value) {
+            Ok(()) => {}
+            Err(err) if io::is_broken_pipe(&err) => {
+                self.output_closed = true;
+                return Ok(());
+            }
+            Err(err) => return Err(VMError::IOError(program.source().clone(), inst, err)),
+        }
+        self.output_written += 1;
+        self.notify(VmEvent::Output(value));
+        Ok(())
+    }
+
+    /// Execute `inst`, the instruction at the program counter, for the current thread.
+    fn execute<R: ByteRead, W: ByteWrite>(
+        &mut self,
+        program: &BFprogram,
+        inst: InputInstruction,
+        input: &mut R,
+        output: &mut W,
+    ) -> Result<(), VMError> {
+        match inst.instruction() {
+            Instruction::MoveLeft => {
+                if self.head == 0 {
+                    return Err(VMError::InvalidHeadPosition(
+                        program.source().clone(),
+                        inst,
+                        self.head,
+                    ));
+                }
+                self.head -= 1;
+            }
+            Instruction::MoveRight => {
+                if self.head + 1 == self.tape.len() {
+                    if !self.growable {
+                        return Err(VMError::InvalidHeadPosition(
+                            program.source().clone(),
+                            inst,
+                            self.head,
+                        ));
+                    }
+                    self.grow_tape(program, inst)?;
+                }
+                self.head += 1;
+            }
+            Instruction::Increment => self.cell_mut().increment(),
+            Instruction::Decrement => self.cell_mut().decrement(),
+            Instruction::Input => {
+                self.check_limit(Limit::Input, self.input_read, program, inst)?;
+                self.notify(VmEvent::InputRequested);
+                match input.read_byte() {
+                    // At the end of the input the cell is left unchanged.
+                    Ok(None) => {}
+                    Ok(Some(byte)) => {
+                        self.input_read += 1;
+                        self.cell_mut().set_value(byte);
+                    }
+                    Err(err) => {
+                        return Err(VMError::IOError(program.source().clone(), inst, err));
+                    }
+                }
+            }
+            Instruction::Output => self.write_output(program, inst, output)?,
+            Instruction::BeginLoop => {
+                if self.tape[self.head].is_zero() {
+                    self.pc = self.jump_target(program, inst)?;
+                }
+            }
+            Instruction::EndLoop => {
+                if !self.tape[self.head].is_zero() {
+                    self.pc = self.jump_target(program, inst)?;
+                }
+            }
+            Instruction::BeginProcedure => {
+                self.procedures
+                    .insert(self.tape[self.head].get_value(), self.pc);
+                self.pc = self.jump_target(program, inst)?;
+            }
+            Instruction::EndProcedure => {
+                // Definitions are skipped over, so this is only reached when returning from a
+                // call.
+                if let Some(ret) = self.call_stack.pop() {
+                    self.pc = ret;
+                }
+            }
+            Instruction::CallProcedure => {
+                let procedure = self.tape[self.head].get_value();
+                let start = self.procedures.get(&procedure).ok_or_else(|| {
+                    VMError::UndefinedProcedure(program.source().clone(), inst, procedure)
+                })?;
+                self.call_stack.push(self.pc);
+                self.pc = *start;
+            }
+            Instruction::Fork => self.fork(program, inst)?,
+            // Handled by `step`, as it stops every thread.
+            Instruction::EndProgram | Instruction::Halt => {}
+            Instruction::Store
+            | Instruction::Retrieve
+            | Instruction::ShiftRight
+            | Instruction::ShiftLeft
+            | Instruction::Not
+            | Instruction::Xor
+            | Instruction::And
+            | Instruction::Or => self.bitwise(*inst.instruction()),
+            Instruction::Random => {
+                let value = self.rng.get_or_insert_with(Rng::from_entropy).next_byte();
+                self.cell_mut().set_value(value);
+            }
+            Instruction::NextTape => self.switch_tape(true),
+            Instruction::PreviousTape => self.switch_tape(false),
+        }
+        Ok(())
+    }
+
+    /// Take the cost of executing `inst` from the fuel, if execution is metered.
+    fn charge(&mut self, program: &BFprogram, inst: InputInstruction) -> Result<(), VMError> {
+        let Some(fuel) = &mut self.fuel else {
+            return Ok(());
+        };
+        let model = &fuel.model;
+        let mut cost = model.instruction(*inst.instruction());
+        match inst.instruction() {
+            Instruction::Input => cost += model.input(),
+            Instruction::Output => cost += model.output(),
+            Instruction::MoveRight | Instruction::Fork
+                if self.growable && self.head + 1 == self.tape.len() =>
+            {
+                cost += model.tape_growth(self.tape.len() + 1);
+            }
+            _ => {}
+        }
+        fuel.remaining = fuel
+            .remaining
+            .checked_sub(cost)
+            .ok_or_else(|| VMError::OutOfFuel(program.source().clone(), inst))?;
+        Ok(())
+    }
+
+    /// Add a cell to the end of the tape, if that is within the memory limit.
+    fn grow_tape(&mut self, program: &BFprogram, inst: InputInstruction) -> Result<(), VMError> {
+        self.check_limit(Limit::Memory, self.tape.len(), program, inst)?;
+        Arc::make_mut(&mut self.tape).push(C::default());
+        self.notify(VmEvent::TapeGrown(self.tape.len()));
+        Ok(())
+    }
+
+    /// Fail if `used` has already reached the `limit`.
+    fn check_limit(
+        &self,
+        limit: Limit,
+        used: usize,
+        program: &BFprogram,
+        inst: InputInstruction,
+    ) -> Result<(), VMError> {
+        let max = match limit {
+            Limit::Memory => self.limits.max_memory,
+            Limit::Output => self.limits.max_output,
+            Limit::Input => self.limits.max_input,
+        };
+        if max.is_some_and(|max| used >= max) {
+            return Err(VMError::LimitExceeded(
+                program.source().clone(),
+                inst,
+                limit,
+            ));
+        }
+        Ok(())
+    }
+
+    fn cell_mut(&mut self) -> &mut C {
+        &mut Arc::make_mut(&mut self.tape)[self.head]
+    }
+
+    /// Select the next tape, or the previous one if `forward` is false.
+    fn switch_tape(&mut self, forward: bool) {
+        let count = self.other_tapes.len() + 1;
+        let next = if forward {
+            self.other_tapes.pop_front()
+        } else {
+            self.other_tapes.pop_back()
+        };
+        let Some((tape, head)) = next else {
+            return;
+        };
+        let current = (
+            mem::replace(&mut self.tape, tape),
+            mem::replace(&mut self.head, head),
+        );
+        if forward {
+            self.other_tapes.push_back(current);
+            self.tape_index = (self.tape_index + 1) % count;
+        } else {
+            self.other_tapes.push_front(current);
+            self.tape_index = (self.tape_index + count - 1) % count;
+        }
+    }
+
+    /// Execute one of the Extended Type I instructions that work with the storage register.
+    fn bitwise(&mut self, inst: Instruction) {
+        let value = self.tape[self.head].get_value();
+        if let Some(value) = bitwise(inst, value, &mut self.storage) {
+            self.cell_mut().set_value(value);
+        }
+    }
+
+    /// Start a new thread, which runs from the instruction after the fork.
+    fn fork(&mut self, program: &BFprogram, inst: InputInstruction) -> Result<(), VMError> {
+        let child_head = self.head + 1;
+        if child_head == self.tape.len() {
+            if !self.growable {
+                return Err(VMError::InvalidHeadPosition(
+                    program.source().clone(),
+                    inst,
+                    self.head,
+                ));
+            }
+            self.grow_tape(program, inst)?;
+        }
+        self.cell_mut().set_value(0);
+        let mut child = Thread {
+            tape: Arc::clone(&self.tape),
+            head: child_head,
+            pc: self.pc + 1,
+            call_stack: self.call_stack.clone(),
+            other_tapes: self.other_tapes.clone(),
+            tape_index: self.tape_index,
+        };
+        if self.shared_tapes {
+            // The tapes are handed over when the child gets its turn, so it lets go of them
+            // first, or writing to them would copy them.
+            child.tape = Arc::default();
+            for (tape, _) in &mut child.other_tapes {
+                *tape = Arc::default();
+            }
+            Arc::make_mut(&mut self.tape)[child_head].set_value(1);
+        } else {
+            Arc::make_mut(&mut child.tape)[child_head].set_value(1);
+        }
+        self.waiting.push_back(child);
+        Ok(())
+    }
+
+    /// Run `program` from the current program counter until it finishes, or until its output is
+    /// closed, which [`BFVM::output_closed`] reports.
+    ///
+    /// # Errors
+    /// See [`BFVM::step`] for the errors that can occur.
+    pub fn run<R: ByteRead, W: ByteWrite>(
+        &mut self,
+        program: &BFprogram,
+        input: &mut R,
+        output: &mut W,
+    ) -> Result<(), VMError> {
+        while self.step(program, input, output)? == StepOutcome::Running {}
+        self.flush_output(program, output)
+    }
+
+    /// Run `program` like [`BFVM::run`], but run the loops that can be proven to keep the head on
+    /// the tape without checking it at each instruction, which is much faster for tight inner
+    /// loops.
+    ///
+    /// Such a loop is an innermost loop whose body only uses `+`, `-`, `<` and `>`, and moves
+    /// the head back to where it started. Before the program runs, each of these loops is
+    /// compiled to what one iteration adds to each cell, and the cells it moves between. When
+    /// the loop starts, a single check that those cells are on the tape proves that every
+    /// iteration stays on it, and the iterations then run without any further checks. Where
+    /// that can't be proven, because a loop does anything else or its cells are off the tape,
+    /// the instructions are run one at a time with all the usual checks, so errors are reported
+    /// exactly as [`BFVM::run`] would report them. Metered runs, and programs that have started
+    /// threads, are always run with the usual checks.
+    ///
+    /// A loop that subtracts exactly 1 from the cell it starts on each iteration runs as many
+    /// times as that cell's value, so it isn't iterated at all: each cell it changes has what one
+    /// iteration adds multiplied by that count added to it at once, wrapping as the cells do.
+    ///
+    /// The counts of instructions executed are kept up to date, but subscribers don't see the
+    /// instructions inside the fast loops one at a time.
+    ///
+    /// # Errors
+    /// See [`BFVM::step`] for the errors that can occur.
+    pub fn run_fast_unchecked<R: ByteRead, W: ByteWrite>(
+        &mut self,
+        program: &BFprogram,
+        input: &mut R,
+        output: &mut W,
+    ) -> Result<(), VMError> {
+        let loops = fast::fast_loops(program);
+        if let Some(stats) = &mut self.fusion_stats {
+            for (start, fast) in &loops {
+                stats.entry(*start).or_insert_with(|| FusedLoop::new(fast));
+            }
+        }
+        loop {
+            let start = self.pc;
+            let iterations = match loops.get(&start) {
+                Some(fast) if self.fuel.is_none() && self.waiting.is_empty() => {
+                    self.run_fast_loop(program, fast)?
+                }
+                _ => None,
+            };
+            if let Some(stats) = self
+                .fusion_stats
+                .as_mut()
+                .and_then(|stats| stats.get_mut(&start))
+            {
+                match iterations {
+                    Some(iterations) => {
+                        stats.runs += 1;
+                        stats.iterations += iterations;
+                    }
+                    None => stats.fallbacks += 1,
+                }
+            }
+            if iterations.is_some() {
+                if self.pc == program.instructions().len() {
+                    break;
+                }
+            } else if self.step(program, input, output)? != StepOutcome::Running {
+                break;
+            }
+        }
+        self.flush_output(program, output)
+    }
+
+    /// Run the loop `fast`, which starts at the program counter, without checking the head at
+    /// each instruction, returning the number of iterations it ran. Returns `None`, having done
+    /// nothing, if the cells the loop moves between aren't all on the tape.
+    fn run_fast_loop(
+        &mut self,
+        program: &BFprogram,
+        fast: &fast::FastLoop,
+    ) -> Result<Option<u64>, VMError> {
+        let head = self.head;
+        let on_tape = head
+            .checked_add_signed(fast.lowest)
+            .zip(head.checked_add_signed(fast.highest))
+            .is_some_and(|(_, highest)| highest < self.tape.len());
+        if !on_tape {
+            return Ok(None);
+        }
+        let start = self.pc;
+        let tape = Arc::make_mut(&mut self.tape);
+        let mut iterations = 0u64;
+        if fast.counted() {
+            let count = tape[head].get_value();
+            for (offset, n) in &fast.adds {
+                // SAFETY: The cells the loop moves between were checked to be on the tape above,
+                // and each offset is between them.
+                unsafe { tape.get_unchecked_mut(head.wrapping_add_signed(*offset)) }
+                    .add(n.wrapping_mul(count));
+            }
+            iterations = count.into();
+        }
+        while !tape[head].is_zero() {
+            if self.stop.as_ref().is_some_and(StopHandle::is_stopped) {
+                return Err(VMError::Cancelled(program.source().clone()));
+            }
+            for (offset, n) in &fast.adds {
+                // SAFETY: The cells from `head + fast.lowest` to `head + fast.highest` were
+                // checked to be on the tape above, each offset is between those, and the tape
+                // doesn't change length while the loop runs.
+                unsafe { tape.get_unchecked_mut(head.wrapping_add_signed(*offset)) }.add(*n);
+            }
+            iterations += 1;
+        }
+        self.executed += 1 + iterations * fast.len;
+        self.last_executed = Some(if iterations == 0 { start } else { fast.end });
+        self.pc = fast.end + 1;
+        if self.pc == program.instructions().len() {
+            self.notify(VmEvent::Halted(self.exit_status));
+        }
+        Ok(Some(iterations))
+    }
+
+    /// Flush `output` once `program` has finished, noting if it has been closed.
+    fn flush_output<W: ByteWrite>(
+        &mut self,
+        program: &BFprogram,
+        output: &mut W,
+    ) -> Result<(), VMError> {
+        if let (Some(inst), false) = (program.instructions().last(), self.output_closed) {
+            match output.flush() {
+                Ok(()) => {}
+                Err(err) if io::is_broken_pipe(&err) => self.output_closed = true,
+                Err(err) => return Err(VMError::IOError(program.source().clone(), *inst, err)),
+            }
+        }
+        Ok(())
+    }
+
+    fn jump_target(&self, program: &BFprogram, inst: InputInstruction) -> Result<usize, VMError> {
+        program
+            .matching_bracket(self.pc)
+            .ok_or_else(|| VMError::UnmatchedBracket(program.source().clone(), inst))
+    }
+}
+
+#[cfg(all(test, feature = "std"))]
 mod tests {
     use super::*;
+    use std::io;
+
+    fn program(code: &str) -> BFprogram {
+        let mut program = BFprogram::new("mod.test", code.as_bytes());
+        program
+            .validate_brackets()
+            .expect("Test programs should have balanced brackets.");
+        program
+    }
 
     #[test]
     fn new_vm() {
@@ -63,4 +1292,524 @@ mod tests {
         vm = BFVM::new(NonZeroUsize::new(0), false);
         assert_eq!(vm.tape.len(), 30000);
     }
+
+    #[test]
+    fn prefilled_cells() {
+        let vm: BFVM<u8> = BFVM::new(NonZeroUsize::new(4), false).with_cells(2, b"abc");
+        assert_eq!(vm.tape(), b"\0\0abc");
+
+        let mut vm: BFVM<u8> = BFVM::new(None, false).with_cells(1, b"hi");
+        let mut output = Vec::new();
+        vm.run(&program(">.>."), &mut io::empty(), &mut output)
+            .unwrap();
+        assert_eq!(output, b"hi");
+    }
+
+    #[test]
+    fn cell_wrapping() {
+        let mut c = 0u8;
+        c.decrement();
+        assert_eq!(c, 255);
+        c.increment();
+        assert!(c.is_zero());
+    }
+
+    #[test]
+    fn bit_cells() {
+        let mut c = false;
+        c.increment();
+        assert_eq!(c.get_value(), 1);
+        c.decrement();
+        assert!(c.is_zero());
+        c.set_value(3);
+        assert!(c);
+    }
+
+    #[test]
+    fn wide_cells() {
+        let mut code = BFprogram::new("mod.test", b"-.>,+.>++++[<<+>>-]<<.");
+        code.validate_brackets().unwrap();
+        let mut vm: BFVM<u16> = BFVM::new(NonZeroUsize::new(3), false);
+        let mut output = Vec::new();
+        vm.run(&code, &mut &b"\xff"[..], &mut output).unwrap();
+        assert_eq!(output, [0xff, 0, 3]);
+        assert_eq!(vm.tape(), [3, 0x100, 0]);
+
+        let mut vm: BFVM<u32> = BFVM::new(NonZeroUsize::new(3), false);
+        vm.run(&code, &mut &b"\xff"[..], &mut Vec::new()).unwrap();
+        assert_eq!(vm.tape(), [3, 0x100, 0]);
+    }
+
+    #[test]
+    fn bit_io() {
+        let alphabet = bft_types::Alphabet::boolfuck();
+        let mut code = BFprogram::with_alphabet("mod.test", b"+;+;;;;;+;+;>,;,;", &alphabet);
+        code.validate_brackets().unwrap();
+        let mut vm: BFVM<bool> = BFVM::new(None, false);
+        let mut output = Vec::new();
+        vm.run(
+            &code,
+            &mut bits::BitReader::new(&b"\x02"[..]),
+            &mut bits::BitWriter::new(&mut output),
+        )
+        .unwrap();
+        assert_eq!(output, b"A\x02");
+    }
+
+    #[test]
+    fn hello_world() {
+        let code = program(
+            "++++++++[>++++[>++>+++>+++>+<<<<-]>+>+>->>+[<]<-]>>.>---.+++++++..+++.>>.<-.<.+++.------.--------.>>+.>++.",
+        );
+        let mut vm: BFVM<u8> = BFVM::new(None, false);
+        let mut output = Vec::new();
+        vm.run(&code, &mut io::empty(), &mut output)
+            .expect("Program should run.");
+        assert_eq!(output, b"Hello World!\n");
+    }
+
+    #[test]
+    fn input_and_eof() {
+        let code = program(",>,>,");
+        let mut vm: BFVM<u8> = BFVM::new(None, false);
+        Arc::make_mut(&mut vm.tape)[2] = 7;
+        vm.run(&code, &mut &b"ab"[..], &mut io::sink())
+            .expect("Program should run.");
+        assert_eq!(&vm.tape()[..3], &[b'a', b'b', 7]);
+    }
+
+    #[test]
+    fn stepping() {
+        let code = program("+[-]");
+        let mut vm: BFVM<u8> = BFVM::new(None, false);
+        let mut step = || vm.step(&code, &mut io::empty(), &mut io::sink()).unwrap();
+        assert_eq!(step(), StepOutcome::Running);
+        assert_eq!(step(), StepOutcome::Running);
+        assert_eq!(step(), StepOutcome::Running);
+        assert_eq!(step(), StepOutcome::Finished);
+        assert_eq!(step(), StepOutcome::Finished);
+        assert_eq!(vm.pc(), 4);
+        assert_eq!(vm.tape()[0], 0);
+        assert_eq!(vm.executed(), 4);
+    }
+
+    #[test]
+    fn fast_unchecked_matches_run() {
+        let run = |code: &str, fast: bool| {
+            let code = program(code);
+            let mut vm: BFVM<u8> = BFVM::new(NonZeroUsize::new(8), false);
+            let mut output = Vec::new();
+            let result = if fast {
+                vm.run_fast_unchecked(&code, &mut &b"\x05"[..], &mut output)
+            } else {
+                vm.run(&code, &mut &b"\x05"[..], &mut output)
+            };
+            (
+                result.map_err(|err| err.to_string()),
+                output,
+                vm.tape().to_vec(),
+                vm.executed(),
+                vm.last_executed(),
+            )
+        };
+        for code in [
+            "++++[>+++[>++>+++<<-]<-]>>.>.",
+            ",[->+>--<<]>>>[-]<.",
+            "+[<+>-]",
+            "+[>>>>>>>>+<<<<<<<<-]",
+            "[->+<]",
+            "-[>+++>-<<-]>.>.",
+            ",[>-<+++>++<----]>.",
+        ] {
+            assert_eq!(run(code, true), run(code, false), "{code}");
+        }
+    }
+
+    #[test]
+    fn fusion_stats() {
+        let code = program("++++[>+++[>++>+++<<-]<-]+[>>>>>>>>+<<<<<<<<-]");
+        let mut vm: BFVM<u8> = BFVM::new(NonZeroUsize::new(8), true).with_fusion_stats();
+        vm.run_fast_unchecked(&code, &mut io::empty(), &mut io::sink())
+            .unwrap();
+        let stats = vm.fusion_stats().unwrap();
+        assert_eq!(stats.keys().collect::<Vec<_>>(), [&9, &25]);
+        let inner = &stats[&9];
+        assert_eq!((inner.end, inner.runs, inner.iterations), (20, 4, 12));
+        assert_eq!(inner.fallbacks, 0);
+        // The last loop's cells aren't all on the tape, so it runs one instruction at a time.
+        assert_eq!((stats[&25].runs, stats[&25].fallbacks), (0, 1));
+        assert_eq!(BFVM::<u8>::new(None, false).fusion_stats(), None);
+    }
+
+    #[test]
+    fn head_off_left_edge() {
+        let code = program("><<");
+        let mut vm: BFVM<u8> = BFVM::new(None, false);
+        let err = vm
+            .run(&code, &mut io::empty(), &mut io::sink())
+            .unwrap_err();
+        assert_eq!(
+            format!("{err}"),
+            "Head moved to invalid position from 0 at [mod.test:1:3]"
+        );
+    }
+
+    #[test]
+    fn head_off_right_edge() {
+        let code = program(">>");
+        let mut vm: BFVM<u8> = BFVM::new(NonZeroUsize::new(2), false);
+        assert!(matches!(
+            vm.run(&code, &mut io::empty(), &mut io::sink()),
+            Err(VMError::InvalidHeadPosition(_, _, 1))
+        ));
+
+        let mut vm: BFVM<u8> = BFVM::new(NonZeroUsize::new(2), true);
+        vm.run(&code, &mut io::empty(), &mut io::sink())
+            .expect("Tape should grow.");
+        assert_eq!(vm.head(), 2);
+        assert_eq!(vm.tape().len(), 3);
+    }
+
+    #[test]
+    fn procedures() {
+        let mut alphabet = bft_types::Alphabet::default();
+        alphabet
+            .add_extension(bft_types::Extension::Pbrain)
+            .unwrap();
+        let mut code = BFprogram::with_alphabet("mod.test", b"+(>+++<)::-:", &alphabet);
+        code.validate_brackets().unwrap();
+        let mut vm: BFVM<u8> = BFVM::new(None, false);
+        let err = vm
+            .run(&code, &mut io::empty(), &mut io::sink())
+            .unwrap_err();
+        assert_eq!(
+            format!("{err}"),
+            "Call to undefined procedure 0 at [mod.test:1:12]"
+        );
+        assert_eq!(&vm.tape()[..2], &[0, 6]);
+        assert!(vm.call_stack.is_empty());
+    }
+
+    #[test]
+    fn threads() {
+        let mut alphabet = bft_types::Alphabet::default();
+        alphabet
+            .add_extension(bft_types::Extension::Brainfork)
+            .unwrap();
+        let mut code = BFprogram::with_alphabet("mod.test", b"+>++<Y>.", &alphabet);
+        code.validate_brackets().unwrap();
+        let mut vm: BFVM<u8> = BFVM::new(None, false);
+        let mut output = Vec::new();
+        assert_eq!(
+            vm.step(&code, &mut io::empty(), &mut output).unwrap(),
+            StepOutcome::Running
+        );
+        vm.run(&code, &mut io::empty(), &mut output).unwrap();
+        // The threads alternate, and each has its own copy of the tape.
+        assert_eq!(output, vec![0, 2]);
+        assert_eq!(vm.thread_count(), 1);
+
+        // With a longer time slice, each thread runs that many instructions at a time.
+        let mut code = BFprogram::with_alphabet("mod.test", b"Y+..", &alphabet);
+        code.validate_brackets().unwrap();
+        let run = |vm: BFVM<u8>| {
+            let mut vm = vm;
+            let mut output = Vec::new();
+            vm.run(&code, &mut io::empty(), &mut output).unwrap();
+            output
+        };
+        assert_eq!(run(BFVM::new(None, false)), [2, 1, 2, 1]);
+        let sliced = BFVM::new(None, false).with_time_slice(NonZeroU32::new(2).unwrap());
+        assert_eq!(run(sliced), [2, 1, 1, 2]);
+
+        let mut code = BFprogram::with_alphabet("mod.test", b"Y", &alphabet);
+        code.validate_brackets().unwrap();
+        let mut vm: BFVM<u8> = BFVM::new(NonZeroUsize::new(1), false);
+        assert!(matches!(
+            vm.run(&code, &mut io::empty(), &mut io::sink()),
+            Err(VMError::InvalidHeadPosition(_, _, 0))
+        ));
+    }
+
+    #[test]
+    fn shared_tapes() {
+        let mut alphabet = bft_types::Alphabet::default();
+        for extension in [
+            bft_types::Extension::Brainfork,
+            bft_types::Extension::Multitape,
+        ] {
+            alphabet.add_extension(extension).unwrap();
+        }
+        let run = |code: &[u8], vm: BFVM<u8>| {
+            let mut code = BFprogram::with_alphabet("mod.test", code, &alphabet);
+            code.validate_brackets().unwrap();
+            let mut vm = vm.with_tapes(NonZeroUsize::new(2).unwrap());
+            vm.run(&code, &mut io::empty(), &mut io::sink()).unwrap();
+            (vm.tape_index(), vm.tape()[..3].to_vec())
+        };
+        // The new thread moves back to the forking thread's cell, then both add to cell 2.
+        let code = b"Y[<]>>+++++";
+        assert_eq!(run(code, BFVM::new(None, false)).1, [0, 1, 5]);
+        let shared = BFVM::new(None, false).with_shared_tapes();
+        assert_eq!(run(code, shared).1, [0, 1, 10]);
+
+        // The threads switch tapes at different times, and keep their own heads on each.
+        let code = b"Y[<]^+_>>+^";
+        let shared = BFVM::new(None, false).with_shared_tapes();
+        assert_eq!(run(code, shared), (1, vec![2, 0, 0]));
+        let code = b"Y[<]^+_>>+";
+        let shared = BFVM::new(None, false).with_shared_tapes();
+        assert_eq!(run(code, shared), (0, vec![0, 1, 2]));
+    }
+
+    #[test]
+    fn extended_type_one() {
+        let mut alphabet = bft_types::Alphabet::default();
+        alphabet.add_extension(bft_types::Extension::Ext1).unwrap();
+        let run = |code: &[u8]| {
+            let mut code = BFprogram::with_alphabet("mod.test", code, &alphabet);
+            code.validate_brackets().unwrap();
+            let mut vm: BFVM<u8> = BFVM::new(NonZeroUsize::new(4), false);
+            let mut output = Vec::new();
+            vm.run(&code, &mut std::io::empty(), &mut output).unwrap();
+            (vm.tape().to_vec(), output)
+        };
+        assert_eq!(run(b"+++$>!{>!}>!~").0, [3, 6, 1, 252]);
+        assert_eq!(run(b"+++$>+++++^>!&>!|").0, [3, 6, 3, 3]);
+        assert_eq!(run(b"+.@+.").1, [1]);
+    }
+
+    #[test]
+    fn random_bytes() {
+        let mut alphabet = bft_types::Alphabet::default();
+        alphabet
+            .add_extension(bft_types::Extension::Random)
+            .unwrap();
+        let code = BFprogram::with_alphabet("mod.test", b"?>?>?>?", &alphabet);
+        let run = |vm: BFVM<u8>| {
+            let mut vm = vm;
+            vm.run(&code, &mut std::io::empty(), &mut std::io::sink())
+                .unwrap();
+            vm.tape().to_vec()
+        };
+        let seeded = run(BFVM::new(NonZeroUsize::new(4), false).with_seed(7));
+        assert_eq!(
+            seeded,
+            run(BFVM::new(NonZeroUsize::new(4), false).with_seed(7))
+        );
+        assert_ne!(
+            seeded,
+            run(BFVM::new(NonZeroUsize::new(4), false).with_seed(8))
+        );
+    }
+
+    #[test]
+    fn multiple_tapes() {
+        let mut alphabet = bft_types::Alphabet::default();
+        alphabet
+            .add_extension(bft_types::Extension::Multitape)
+            .unwrap();
+        let code = BFprogram::with_alphabet("mod.test", b"+>++^+++_.__.^", &alphabet);
+        let mut vm: BFVM<u8> =
+            BFVM::new(NonZeroUsize::new(2), false).with_tapes(NonZeroUsize::new(3).unwrap());
+        let mut output = Vec::new();
+        vm.run(&code, &mut std::io::empty(), &mut output).unwrap();
+        // Each tape keeps its own head, so the second tape's cell 0 is written.
+        assert_eq!(output, [2, 3]);
+        assert_eq!(vm.tape_index(), 2);
+        assert_eq!(vm.tape(), [0, 0]);
+
+        // With a single tape, switching does nothing.
+        let mut vm: BFVM<u8> = BFVM::new(NonZeroUsize::new(2), false);
+        vm.run(&code, &mut std::io::empty(), &mut output).unwrap();
+        assert_eq!(vm.tape(), [1, 5]);
+        assert_eq!(vm.tape_index(), 0);
+    }
+
+    #[test]
+    fn halting() {
+        let mut alphabet = bft_types::Alphabet::default();
+        alphabet.add_extension(bft_types::Extension::Halt).unwrap();
+        let mut code = BFprogram::with_alphabet("mod.test", b"+++[>+++[.@]]+", &alphabet);
+        code.validate_brackets().unwrap();
+        let mut vm: BFVM<u8> = BFVM::new(None, false);
+        let mut output = Vec::new();
+        vm.run(&code, &mut std::io::empty(), &mut output).unwrap();
+        assert_eq!(output, [3]);
+        assert_eq!(vm.exit_status(), Some(3));
+        assert_eq!(vm.tape()[0], 3);
+
+        let mut vm: BFVM<u8> = BFVM::new(None, false);
+        vm.run(&program("+"), &mut std::io::empty(), &mut output)
+            .unwrap();
+        assert_eq!(vm.exit_status(), None);
+    }
+
+    #[test]
+    fn events() {
+        let mut vm: BFVM<u8> = BFVM::new(NonZeroUsize::new(1), true);
+        let events = vm.subscribe();
+        let dropped = vm.subscribe();
+        drop(dropped);
+        vm.run(&program(",>+."), &mut &b"a"[..], &mut io::sink())
+            .unwrap();
+        assert_eq!(
+            events.try_iter().collect::<Vec<_>>(),
+            [
+                VmEvent::InputRequested,
+                VmEvent::TapeGrown(2),
+                VmEvent::Output(1),
+                VmEvent::Halted(None),
+            ]
+        );
+        assert_eq!(vm.subscribers.len(), 1);
+    }
+
+    #[test]
+    fn fuel() {
+        let code = program("+[>+<-]");
+        let mut vm: BFVM<u8> = BFVM::new(None, false).with_fuel(5, Uniform);
+        assert!(matches!(
+            vm.run(&code, &mut io::empty(), &mut io::sink()),
+            Err(VMError::OutOfFuel(_, _))
+        ));
+        assert_eq!(vm.pc(), 5);
+        assert_eq!(vm.fuel(), Some(0));
+        vm.add_fuel(100);
+        vm.run(&code, &mut io::empty(), &mut io::sink()).unwrap();
+        assert_eq!(vm.fuel(), Some(98));
+    }
+
+    struct Pricey;
+
+    impl CostModel for Pricey {
+        fn output(&self) -> u64 {
+            10
+        }
+
+        fn tape_growth(&self, cells: usize) -> u64 {
+            u64::try_from(cells).unwrap()
+        }
+    }
+
+    #[test]
+    fn cost_models() {
+        let mut vm: BFVM<u8> = BFVM::new(NonZeroUsize::new(1), true).with_fuel(100, Pricey);
+        vm.run(&program(">.>"), &mut io::empty(), &mut io::sink())
+            .unwrap();
+        assert_eq!(vm.fuel(), Some(100 - 3 - 2 - 10 - 3));
+    }
+
+    #[test]
+    fn stopping() {
+        let code = program("+");
+        let mut vm: BFVM<u8> = BFVM::new(None, false);
+        let handle = vm.stop_handle();
+        handle.clone().stop();
+        assert!(matches!(
+            vm.run(&code, &mut io::empty(), &mut io::sink()),
+            Err(VMError::Cancelled(_))
+        ));
+        assert_eq!(vm.pc(), 0);
+        handle.reset();
+        vm.run(&code, &mut io::empty(), &mut io::sink()).unwrap();
+        assert_eq!(vm.tape()[0], 1);
+    }
+
+    #[test]
+    fn sandboxing() {
+        let limits = SandboxLimits {
+            max_memory: Some(2),
+            max_output: Some(1),
+            max_input: Some(1),
+            ..SandboxLimits::UNLIMITED
+        };
+        let run = |code: &str| {
+            let mut vm: BFVM<u8> = BFVM::new(None, true).with_sandbox(limits);
+            vm.run(&program(code), &mut &b"ab"[..], &mut io::sink())
+                .err()
+        };
+        assert!(run(">,.").is_none());
+        assert!(matches!(
+            run(">>"),
+            Some(VMError::LimitExceeded(_, _, Limit::Memory))
+        ));
+        assert!(matches!(
+            run(",,"),
+            Some(VMError::LimitExceeded(_, _, Limit::Input))
+        ));
+        assert_eq!(
+            run("..").unwrap().to_string(),
+            "Output limit exceeded at [mod.test:1:2]"
+        );
+
+        let mut alphabet = bft_types::Alphabet::default();
+        alphabet
+            .add_extension(bft_types::Extension::Random)
+            .unwrap();
+        let mut code = BFprogram::with_alphabet("mod.test", b"+?", &alphabet);
+        code.validate_brackets().unwrap();
+        let mut vm: BFVM<u8> = BFVM::new(None, false).with_sandbox(SandboxLimits::conservative());
+        assert!(matches!(
+            vm.run(&code, &mut io::empty(), &mut io::sink()),
+            Err(VMError::Unsupported(_, _))
+        ));
+        assert_eq!(vm.tape().len(), 30000);
+        assert_eq!(vm.fuel(), Some(100_000_000 - 1));
+    }
+
+    #[test]
+    fn output_limit() {
+        let mut vm: BFVM<u8> = BFVM::new(None, false).with_max_output(3);
+        let mut output = Vec::new();
+        assert!(matches!(
+            vm.run(&program("+[.]"), &mut io::empty(), &mut output),
+            Err(VMError::LimitExceeded(_, _, Limit::Output))
+        ));
+        assert_eq!(output, [1, 1, 1]);
+    }
+
+    /// Output that is closed after the given number of bytes, like a pipe to `head -c`.
+    struct Closing(usize);
+
+    impl io::Write for Closing {
+        fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+            if self.0 == 0 {
+                return Err(io::ErrorKind::BrokenPipe.into());
+            }
+            self.0 -= 1;
+            Ok(buf.len().min(1))
+        }
+
+        fn flush(&mut self) -> io::Result<()> {
+            Ok(())
+        }
+    }
+
+    #[test]
+    fn broken_pipe() {
+        let code = program("+[.]");
+        let mut vm: BFVM<u8> = BFVM::new(None, false);
+        vm.run(&code, &mut io::empty(), &mut Closing(3)).unwrap();
+        assert!(vm.output_closed());
+        assert_eq!(vm.executed(), 9);
+
+        let mut vm: BFVM<u8> = BFVM::new(None, false);
+        let mut output = Closing(0);
+        let mut step = || vm.step(&code, &mut io::empty(), &mut output).unwrap();
+        assert_eq!(step(), StepOutcome::Running);
+        assert_eq!(step(), StepOutcome::Running);
+        assert_eq!(step(), StepOutcome::BrokenPipe);
+        assert_eq!(step(), StepOutcome::Finished);
+    }
+
+    #[test]
+    fn unvalidated_program() {
+        let code = BFprogram::new("mod.test", b"[]");
+        let mut vm: BFVM<u8> = BFVM::new(None, false);
+        assert!(matches!(
+            vm.run(&code, &mut io::empty(), &mut io::sink()),
+            Err(VMError::UnmatchedBracket(_, _))
+        ));
+    }
 }
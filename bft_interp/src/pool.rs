@@ -0,0 +1,139 @@
+//! Keeping VMs to use again, for servers and graders that run many short programs.
+//!
+//! Making a VM allocates its whole tape, which can take longer than running a short program on
+//! it. A pool keeps the VMs it's given back, with their tapes cleared, and hands them out again
+//! instead of making new ones.
+
+use alloc::collections::BTreeMap;
+use alloc::vec::Vec;
+use core::num::NonZeroUsize;
+
+use crate::BFVM;
+
+/// How the VMs in a [`VmPool`] are made, which they are kept by: the arguments to [`BFVM::new`].
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub struct VmConfig {
+    /// Number of cells on the tape, or `None` for the default.
+    pub cells: Option<NonZeroUsize>,
+
+    /// Whether the tape may grow.
+    pub growable: bool,
+}
+
+/// A pool of VMs, cleared and ready to run programs, kept by their [`VmConfig`].
+///
+/// VMs are taken from the pool with [`VmPool::checkout`], and given back with
+/// [`VmPool::checkin`] once they're finished with. A VM from the pool is in the same state as one
+/// from [`BFVM::new`], so any options like fuel and limits have to be given again each time.
+/// The pool isn't shared between threads by itself, so servers put it behind a lock.
+///
+/// ```
+/// use bft_interp::{VmConfig, VmPool};
+/// use bft_types::BFprogram;
+///
+/// let mut program = BFprogram::new("doc.test", b"++[>+<-]>.");
+/// program.validate_brackets().unwrap();
+/// let mut pool: VmPool<u8> = VmPool::new(4);
+/// let config = VmConfig::default();
+/// for _ in 0..100 {
+///     let mut vm = pool.checkout(config);
+///     let mut output = Vec::new();
+///     vm.run(&program, &mut &b""[..], &mut output).unwrap();
+///     assert_eq!(output, [2]);
+///     pool.checkin(config, vm);
+/// }
+/// assert_eq!(pool.idle(config), 1);
+/// ```
+#[derive(Debug)]
+pub struct VmPool<C> {
+    idle: BTreeMap<VmConfig, Vec<BFVM<C>>>,
+
+    /// Most VMs kept for each configuration.
+    max_idle: usize,
+}
+
+impl<C: Default> VmPool<C> {
+    /// An empty pool, which keeps up to `max_idle` VMs for each configuration. VMs given back
+    /// beyond that are dropped.
+    #[must_use]
+    pub fn new(max_idle: usize) -> Self {
+        VmPool {
+            idle: BTreeMap::new(),
+            max_idle,
+        }
+    }
+
+    /// Take a VM made with `config` from the pool, or make a new one if there are none.
+    pub fn checkout(&mut self, config: VmConfig) -> BFVM<C> {
+        self.idle
+            .get_mut(&config)
+            .and_then(Vec::pop)
+            .unwrap_or_else(|| BFVM::new(config.cells, config.growable))
+    }
+
+    /// Give back a VM that was made with `config`, so that it can be used again. Its tape is
+    /// cleared, and the rest of its state reset, before it's kept.
+    pub fn checkin(&mut self, config: VmConfig, mut vm: BFVM<C>) {
+        let idle = self.idle.entry(config).or_default();
+        if idle.len() < self.max_idle {
+            vm.reset(config.cells, config.growable);
+            idle.push(vm);
+        }
+    }
+
+    /// Number of VMs made with `config` waiting in the pool.
+    #[must_use]
+    pub fn idle(&self, config: VmConfig) -> usize {
+        self.idle.get(&config).map_or(0, Vec::len)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use bft_types::BFprogram;
+
+    #[test]
+    fn reusing_vms() {
+        let mut program = BFprogram::new("mod.test", b"+>++>+++<<[>]+++++");
+        program.validate_brackets().unwrap();
+        let config = VmConfig {
+            cells: NonZeroUsize::new(2),
+            growable: true,
+        };
+        let mut pool: VmPool<u8> = VmPool::new(1);
+        let mut vm = pool.checkout(config).with_fuel(100, crate::Uniform);
+        vm.run(&program, &mut &b""[..], &mut Vec::new()).unwrap();
+        assert_eq!(vm.tape(), [1, 2, 3, 5]);
+        let tape = vm.tape().as_ptr();
+        pool.checkin(config, vm);
+
+        // The VM comes back as if it were new, with the same tape.
+        let vm = pool.checkout(config);
+        assert_eq!(vm.tape(), [0, 0]);
+        assert_eq!(vm.tape().as_ptr(), tape);
+        assert_eq!((vm.head(), vm.pc(), vm.executed()), (0, 0, 0));
+        assert_eq!(vm.fuel(), None);
+        assert_eq!(pool.idle(config), 0);
+
+        // Only as many VMs as asked for are kept, and only for their own configuration.
+        let other = pool.checkout(config);
+        pool.checkin(config, vm);
+        pool.checkin(config, other);
+        assert_eq!(pool.idle(config), 1);
+        assert_eq!(pool.idle(VmConfig::default()), 0);
+    }
+
+    #[test]
+    fn shared_tapes() {
+        let mut pool: VmPool<u8> = VmPool::new(1);
+        let config = VmConfig::default();
+        let mut vm = pool.checkout(config).with_cells(0, &[7]);
+        let snapshot = vm.clone();
+        pool.checkin(config, vm);
+        // The snapshot keeps its tape, and the pooled VM gets a new one.
+        vm = pool.checkout(config);
+        assert_eq!(snapshot.tape()[0], 7);
+        assert_eq!(vm.tape()[0], 0);
+    }
+}
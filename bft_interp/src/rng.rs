@@ -0,0 +1,63 @@
+//! A small pseudo-random number generator for the `?` instruction.
+
+#[cfg(feature = "std")]
+use std::collections::hash_map::RandomState;
+#[cfg(feature = "std")]
+use std::hash::{BuildHasher, Hasher};
+
+/// A `SplitMix64` generator. It isn't suitable for cryptography, but is fast, and produces the same
+/// sequence every time for a given seed.
+#[derive(Clone, Debug)]
+pub(crate) struct Rng {
+    state: u64,
+}
+
+impl Rng {
+    /// A generator that produces the sequence for `seed`.
+    pub(crate) fn from_seed(seed: u64) -> Self {
+        Rng { state: seed }
+    }
+
+    /// A generator seeded from the operating system's randomness.
+    #[cfg(feature = "std")]
+    pub(crate) fn from_entropy() -> Self {
+        Self::from_seed(RandomState::new().build_hasher().finish())
+    }
+
+    /// Without `std` there's no randomness to seed from, so every unseeded generator produces
+    /// the same sequence.
+    #[cfg(not(feature = "std"))]
+    pub(crate) fn from_entropy() -> Self {
+        Self::from_seed(0x5eed)
+    }
+
+    /// The state of the generator, which [`Rng::from_seed`] carries on from.
+    pub(crate) fn state(&self) -> u64 {
+        self.state
+    }
+
+    /// The next random byte in the sequence.
+    pub(crate) fn next_byte(&mut self) -> u8 {
+        self.state = self.state.wrapping_add(0x9e37_79b9_7f4a_7c15);
+        let mut z = self.state;
+        z = (z ^ (z >> 30)).wrapping_mul(0xbf58_476d_1ce4_e5b9);
+        z = (z ^ (z >> 27)).wrapping_mul(0x94d0_49bb_1331_11eb);
+        (z ^ (z >> 31)).to_le_bytes()[0]
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use alloc::vec::Vec;
+
+    #[test]
+    fn seeded_sequences_repeat() {
+        let mut a = Rng::from_seed(42);
+        let mut b = Rng::from_seed(42);
+        let a: Vec<u8> = (0..16).map(|_| a.next_byte()).collect();
+        let b: Vec<u8> = (0..16).map(|_| b.next_byte()).collect();
+        assert_eq!(a, b);
+        assert!(a.iter().any(|byte| *byte != a[0]));
+    }
+}
@@ -0,0 +1,78 @@
+//! Limits for running programs that can't be trusted.
+
+use core::fmt;
+use core::fmt::{Display, Formatter};
+
+/// Limits on what a program may do, applied all at once with [`crate::BFVM::with_sandbox`].
+/// A limit of `None` leaves that resource unlimited.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct SandboxLimits {
+    /// Maximum number of instructions the program may execute.
+    pub max_steps: Option<u64>,
+
+    /// Maximum number of cells on each tape.
+    pub max_memory: Option<usize>,
+
+    /// Maximum number of bytes the program may write.
+    pub max_output: Option<usize>,
+
+    /// Maximum number of bytes the program may read.
+    pub max_input: Option<usize>,
+
+    /// Allow instructions from the language extensions.
+    pub allow_extensions: bool,
+}
+
+impl SandboxLimits {
+    /// No limits at all, which is how a VM starts.
+    pub const UNLIMITED: SandboxLimits = SandboxLimits {
+        max_steps: None,
+        max_memory: None,
+        max_output: None,
+        max_input: None,
+        allow_extensions: true,
+    };
+
+    /// Limits that are generous enough for typical programs, while stopping runaway ones
+    /// quickly: 100 million steps, the traditional 30000 cells, a MiB of input and output, and
+    /// no extensions.
+    #[must_use]
+    pub fn conservative() -> Self {
+        SandboxLimits {
+            max_steps: Some(100_000_000),
+            max_memory: Some(30_000),
+            max_output: Some(1 << 20),
+            max_input: Some(1 << 20),
+            allow_extensions: false,
+        }
+    }
+}
+
+impl Default for SandboxLimits {
+    fn default() -> Self {
+        Self::UNLIMITED
+    }
+}
+
+/// The resources that [`SandboxLimits`] restricts, other than steps, which run out like fuel.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Limit {
+    /// The number of cells on a tape.
+    Memory,
+
+    /// The number of bytes written.
+    Output,
+
+    /// The number of bytes read.
+    Input,
+}
+
+impl Display for Limit {
+    fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::Memory => write!(f, "Memory"),
+            Self::Output => write!(f, "Output"),
+            Self::Input => write!(f, "Input"),
+        }
+    }
+}
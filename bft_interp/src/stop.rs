@@ -0,0 +1,40 @@
+//! Stopping a running program from another thread.
+
+use alloc::sync::Arc;
+use core::sync::atomic::{AtomicBool, Ordering};
+
+/// A token for stopping a VM, from [`crate::BFVM::stop_handle`]. Clones of it stop the same VM,
+/// and it can be sent to other threads.
+///
+/// ```
+/// use bft_interp::{VMError, BFVM};
+/// use bft_types::BFprogram;
+///
+/// let mut program = BFprogram::new("doc.test", b"+[]");
+/// program.validate_brackets().unwrap();
+/// let mut vm: BFVM<u8> = BFVM::new(None, false);
+/// let handle = vm.stop_handle();
+/// std::thread::spawn(move || handle.stop());
+/// let result = vm.run(&program, &mut &b""[..], &mut Vec::new());
+/// assert!(matches!(result, Err(VMError::Cancelled(_))));
+/// ```
+#[derive(Clone, Debug, Default)]
+pub struct StopHandle(Arc<AtomicBool>);
+
+impl StopHandle {
+    /// Ask the VM to stop before it executes its next instruction.
+    pub fn stop(&self) {
+        self.0.store(true, Ordering::Relaxed);
+    }
+
+    /// Check if the VM has been asked to stop.
+    #[must_use]
+    pub fn is_stopped(&self) -> bool {
+        self.0.load(Ordering::Relaxed)
+    }
+
+    /// Withdraw a request to stop, so the VM can be run again.
+    pub fn reset(&self) {
+        self.0.store(false, Ordering::Relaxed);
+    }
+}
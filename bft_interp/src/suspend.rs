@@ -0,0 +1,120 @@
+//! Running a program a piece at a time, suspending it whenever it needs input, for embeddings
+//! like GUIs and games that can't block while they wait for the user.
+
+use bft_types::{BFprogram, Instruction};
+
+use crate::{ByteWrite, CellKind, StepOutcome, VMError, BFVM};
+
+/// Why [`BFVM::run_until_input`] or [`BFVM::resume_with`] returned.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum PollOutcome {
+    /// The program is waiting to read a byte, which is given to it with [`BFVM::resume_with`].
+    NeedsInput,
+
+    /// Every thread has reached the end of the program.
+    Finished,
+
+    /// The program was stopped because its output was closed, as with
+    /// [`StepOutcome::BrokenPipe`].
+    BrokenPipe,
+}
+
+/// How the program stopped after a step that ended with `outcome`, if it did.
+fn stopped(outcome: StepOutcome) -> Option<PollOutcome> {
+    match outcome {
+        StepOutcome::Running => None,
+        StepOutcome::Finished => Some(PollOutcome::Finished),
+        StepOutcome::BrokenPipe => Some(PollOutcome::BrokenPipe),
+    }
+}
+
+impl<C: CellKind + Default + Clone> BFVM<C> {
+    /// Run `program`, writing to `output`, until it finishes or is about to read a byte. Nothing
+    /// is read: when this returns [`PollOutcome::NeedsInput`], the program waits at its `,` until
+    /// it is given a byte with [`BFVM::resume_with`].
+    ///
+    /// # Errors
+    /// Fails for the same reasons as [`BFVM::step`].
+    pub fn run_until_input<W: ByteWrite>(
+        &mut self,
+        program: &BFprogram,
+        output: &mut W,
+    ) -> Result<PollOutcome, VMError> {
+        let len = program.instructions().len();
+        let mut no_input: &[u8] = &[];
+        loop {
+            if !self.schedule(len) {
+                return Ok(PollOutcome::Finished);
+            }
+            if *program.instructions()[self.pc].instruction() == Instruction::Input {
+                return Ok(PollOutcome::NeedsInput);
+            }
+            if let Some(outcome) = stopped(self.step(program, &mut no_input, output)?) {
+                return Ok(outcome);
+            }
+        }
+    }
+
+    /// Give the program the `byte` it is waiting for, or `None` for the end of the input, and
+    /// run it until it needs another, as with [`BFVM::run_until_input`]. A program that isn't
+    /// waiting for input is run until it is before being given the byte.
+    ///
+    /// # Errors
+    /// Fails for the same reasons as [`BFVM::step`].
+    pub fn resume_with<W: ByteWrite>(
+        &mut self,
+        program: &BFprogram,
+        byte: Option<u8>,
+        output: &mut W,
+    ) -> Result<PollOutcome, VMError> {
+        match self.run_until_input(program, output)? {
+            PollOutcome::NeedsInput => {}
+            outcome => return Ok(outcome),
+        }
+        match stopped(self.step(program, &mut byte.as_slice(), output)?) {
+            Some(outcome) => Ok(outcome),
+            None => self.run_until_input(program, output),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use alloc::vec::Vec;
+
+    #[test]
+    fn suspending() {
+        let mut code = BFprogram::new("mod.test", b"+.,+.,+.");
+        code.validate_brackets().unwrap();
+        let mut vm: BFVM<u8> = BFVM::new(None, false);
+        let mut output = Vec::new();
+        assert_eq!(
+            vm.run_until_input(&code, &mut output).unwrap(),
+            PollOutcome::NeedsInput
+        );
+        assert_eq!(output, [1]);
+        // Polling again without giving it a byte leaves it waiting.
+        assert_eq!(
+            vm.run_until_input(&code, &mut output).unwrap(),
+            PollOutcome::NeedsInput
+        );
+        assert_eq!(vm.executed(), 2);
+        assert_eq!(
+            vm.resume_with(&code, Some(b'a'), &mut output).unwrap(),
+            PollOutcome::NeedsInput
+        );
+        assert_eq!(output, [1, b'b']);
+        // At the end of the input the cell is left unchanged.
+        assert_eq!(
+            vm.resume_with(&code, None, &mut output).unwrap(),
+            PollOutcome::Finished
+        );
+        assert_eq!(output, [1, b'b', b'c']);
+        assert_eq!(vm.input_read(), 1);
+        assert_eq!(
+            vm.resume_with(&code, Some(b'x'), &mut output).unwrap(),
+            PollOutcome::Finished
+        );
+    }
+}
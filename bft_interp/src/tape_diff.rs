@@ -0,0 +1,132 @@
+//! Comparing two snapshots of a VM, to see what a phase of a program did between them.
+
+use alloc::vec::Vec;
+
+use crate::{CellKind, BFVM};
+
+/// A cell that holds a different value in the later snapshot.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct CellChange {
+    /// Which tape the cell is on, counting from 0.
+    pub tape: usize,
+
+    /// The index of the cell on its tape.
+    pub cell: usize,
+
+    /// The value in the earlier snapshot.
+    pub old: u8,
+
+    /// The value in the later snapshot.
+    pub new: u8,
+}
+
+/// What changed between two snapshots of a VM, from [`BFVM::tape_diff`]. Only the thread that
+/// was running when each snapshot was taken is compared.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct TapeDiff {
+    /// The cells that changed, in order of tape and then cell.
+    pub changes: Vec<CellChange>,
+
+    /// Where the head on each tape was in the earlier snapshot and in the later one.
+    pub heads: Vec<(usize, usize)>,
+
+    /// Which tape was selected in the earlier snapshot and in the later one.
+    pub tape_index: (usize, usize),
+
+    /// The number of instructions executed by the earlier snapshot and by the later one.
+    pub executed: (u64, u64),
+}
+
+impl<C> BFVM<C> {
+    /// The tapes of the running thread, and their heads, in order.
+    fn tapes(&self) -> Vec<(&[C], usize)> {
+        let count = self.other_tapes.len() + 1;
+        let mut tapes = alloc::vec![(&[][..], 0); count];
+        tapes[self.tape_index] = (&self.tape[..], self.head);
+        for (i, (tape, head)) in self.other_tapes.iter().enumerate() {
+            tapes[(self.tape_index + 1 + i) % count] = (&tape[..], *head);
+        }
+        tapes
+    }
+}
+
+impl<C: CellKind> BFVM<C> {
+    /// Compare this snapshot of a VM with a `later` one, finding the cells that changed and how
+    /// the heads moved. A tape that is shorter in one snapshot, or missing, is treated as though
+    /// its missing cells were zero.
+    #[must_use]
+    pub fn tape_diff(&self, later: &BFVM<C>) -> TapeDiff {
+        let (before, after) = (self.tapes(), later.tapes());
+        let mut changes = Vec::new();
+        let mut heads = Vec::new();
+        for tape in 0..before.len().max(after.len()) {
+            let (old, old_head) = before.get(tape).copied().unwrap_or_default();
+            let (new, new_head) = after.get(tape).copied().unwrap_or_default();
+            heads.push((old_head, new_head));
+            let value = |cells: &[C], cell: usize| cells.get(cell).map_or(0, C::get_value);
+            for cell in 0..old.len().max(new.len()) {
+                let (old, new) = (value(old, cell), value(new, cell));
+                if old != new {
+                    changes.push(CellChange {
+                        tape,
+                        cell,
+                        old,
+                        new,
+                    });
+                }
+            }
+        }
+        TapeDiff {
+            changes,
+            heads,
+            tape_index: (self.tape_index, later.tape_index),
+            executed: (self.executed, later.executed),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use bft_types::{Alphabet, BFprogram, Extension};
+    use core::num::NonZeroUsize;
+
+    #[test]
+    fn diffing() {
+        let mut alphabet = Alphabet::default();
+        alphabet.add_extension(Extension::Multitape).unwrap();
+        let mut code = BFprogram::with_alphabet("mod.test", b"++>+^>>-<", &alphabet);
+        code.validate_brackets().unwrap();
+        let mut vm: BFVM<u8> =
+            BFVM::new(NonZeroUsize::new(3), true).with_tapes(NonZeroUsize::new(2).unwrap());
+        let (mut input, mut output) = (&b""[..], Vec::new());
+        for _ in 0..3 {
+            vm.step(&code, &mut input, &mut output).unwrap();
+        }
+        let before = vm.clone();
+        vm.run(&code, &mut input, &mut output).unwrap();
+
+        let diff = before.tape_diff(&vm);
+        assert_eq!(
+            diff.changes,
+            [
+                CellChange {
+                    tape: 0,
+                    cell: 1,
+                    old: 0,
+                    new: 1
+                },
+                CellChange {
+                    tape: 1,
+                    cell: 2,
+                    old: 0,
+                    new: 255
+                },
+            ]
+        );
+        assert_eq!(diff.heads, [(1, 1), (0, 1)]);
+        assert_eq!(diff.tape_index, (0, 1));
+        assert_eq!(diff.executed, (3, 9));
+        assert!(vm.tape_diff(&vm).changes.is_empty());
+    }
+}
@@ -0,0 +1,147 @@
+//! Bindings for running programs from JavaScript, built with `wasm-bindgen`.
+//!
+//! ```js
+//! const program = parse(",[.,]");
+//! const output = run_with_input(program, new TextEncoder().encode("hi"));
+//!
+//! const machine = new Machine(program, () => 65, (byte) => console.log(byte));
+//! while (machine.step()) {
+//!     console.log(machine.head(), machine.tape());
+//! }
+//! ```
+
+use std::io;
+use std::io::{Read, Write};
+
+use js_sys::Function;
+use wasm_bindgen::prelude::*;
+
+use bft_types::BFprogram;
+
+use crate::{StepOutcome, BFVM};
+
+/// A parsed program, with matching brackets.
+#[wasm_bindgen]
+pub struct Program(BFprogram);
+
+/// Parse `code`, checking that its brackets match.
+///
+/// # Errors
+/// Fails if the brackets don't match.
+#[wasm_bindgen]
+pub fn parse(code: &str) -> Result<Program, JsError> {
+    let mut program = BFprogram::new("<wasm>", code.as_bytes());
+    program
+        .validate_brackets()
+        .map_err(|err| JsError::new(&err.to_string()))?;
+    Ok(Program(program))
+}
+
+/// Run `program` to completion with the given input, returning its output.
+///
+/// # Errors
+/// Fails if the program fails.
+#[wasm_bindgen]
+pub fn run_with_input(program: &Program, input: &[u8]) -> Result<Vec<u8>, JsError> {
+    let mut vm: BFVM<u8> = BFVM::new(None, true);
+    let mut output = Vec::new();
+    vm.run(&program.0, &mut &input[..], &mut output)
+        .map_err(|err| JsError::new(&err.to_string()))?;
+    Ok(output)
+}
+
+/// Input read by calling a JavaScript function, which returns a byte, or `null` or `undefined`
+/// at the end of the input.
+struct CallbackInput(Function);
+
+impl Read for CallbackInput {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        if buf.is_empty() {
+            return Ok(0);
+        }
+        let value = self
+            .0
+            .call0(&JsValue::NULL)
+            .map_err(|err| io::Error::other(format!("{err:?}")))?;
+        match value.as_f64() {
+            #[allow(clippy::cast_possible_truncation, clippy::cast_sign_loss)]
+            Some(byte) => {
+                buf[0] = byte as u8;
+                Ok(1)
+            }
+            None => Ok(0),
+        }
+    }
+}
+
+/// Output written by calling a JavaScript function with each byte.
+struct CallbackOutput(Function);
+
+impl Write for CallbackOutput {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        for byte in buf {
+            self.0
+                .call1(&JsValue::NULL, &JsValue::from(*byte))
+                .map_err(|err| io::Error::other(format!("{err:?}")))?;
+        }
+        Ok(buf.len())
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        Ok(())
+    }
+}
+
+/// A program being run one instruction at a time, with I/O through JavaScript callbacks.
+#[wasm_bindgen]
+pub struct Machine {
+    program: BFprogram,
+    vm: BFVM<u8>,
+    input: CallbackInput,
+    output: CallbackOutput,
+}
+
+#[wasm_bindgen]
+impl Machine {
+    /// Prepare to run `program`, calling `read` for each byte of input and `write` with each
+    /// byte of output.
+    #[wasm_bindgen(constructor)]
+    #[must_use]
+    pub fn new(program: &Program, read: Function, write: Function) -> Machine {
+        Machine {
+            program: program.0.clone(),
+            vm: BFVM::new(None, true),
+            input: CallbackInput(read),
+            output: CallbackOutput(write),
+        }
+    }
+
+    /// Execute a single instruction, returning false once the program has finished.
+    ///
+    /// # Errors
+    /// Fails if the instruction fails.
+    pub fn step(&mut self) -> Result<bool, JsError> {
+        self.vm
+            .step(&self.program, &mut self.input, &mut self.output)
+            .map(|outcome| outcome == StepOutcome::Running)
+            .map_err(|err| JsError::new(&err.to_string()))
+    }
+
+    /// A copy of the tape.
+    #[must_use]
+    pub fn tape(&self) -> Vec<u8> {
+        self.vm.tape().to_vec()
+    }
+
+    /// The position of the head on the tape.
+    #[must_use]
+    pub fn head(&self) -> usize {
+        self.vm.head()
+    }
+
+    /// The index of the next instruction to execute.
+    #[must_use]
+    pub fn pc(&self) -> usize {
+        self.vm.pc()
+    }
+}
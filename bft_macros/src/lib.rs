@@ -0,0 +1,47 @@
+//! Procedural macros for embedding Brainf*ck programs in Rust code.
+#![warn(missing_docs)]
+
+use proc_macro::TokenStream;
+use quote::quote;
+use syn::{parse_macro_input, LitStr};
+
+use bft_types::BFprogram;
+
+/// Build a [`BFprogram`] from a string literal, checking at compile time that its brackets match.
+///
+/// The program's brackets have already been validated, so it is ready to run.
+///
+/// ```
+/// use bft_macros::bf;
+/// let program = bf!("++[>+<-].");
+/// assert_eq!(program.instructions().len(), 9);
+/// assert_eq!(program.matching_bracket(2), Some(7));
+/// ```
+///
+/// Mismatched brackets are reported as compiler errors on the literal:
+///
+/// ```compile_fail
+/// use bft_macros::bf;
+/// let program = bf!("+[>+<-");
+/// ```
+#[proc_macro]
+pub fn bf(input: TokenStream) -> TokenStream {
+    let literal = parse_macro_input!(input as LitStr);
+    let code = literal.value();
+    let mut program = BFprogram::new("bf!", code.as_bytes());
+    if let Err(err) = program.validate_brackets() {
+        return syn::Error::new(literal.span(), err)
+            .to_compile_error()
+            .into();
+    }
+    quote! {
+        {
+            let mut program = ::bft_types::BFprogram::new("bf!", #literal.as_bytes());
+            program
+                .validate_brackets()
+                .expect("Brackets were checked when the program was compiled.");
+            program
+        }
+    }
+    .into()
+}
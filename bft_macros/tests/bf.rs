@@ -0,0 +1,20 @@
+use bft_macros::bf;
+use bft_types::Instruction;
+
+#[test]
+fn embedded_program() {
+    let program = bf!("+[-] clear\n>.");
+    assert_eq!(program.instructions().len(), 6);
+    assert_eq!(
+        *program.instructions()[5].instruction(),
+        Instruction::Output
+    );
+    assert_eq!(program.instructions()[4].location(), "2:1");
+    assert_eq!(program.matching_bracket(1), Some(3));
+}
+
+#[test]
+fn raw_strings() {
+    let program = bf!(r#"[">"]"#);
+    assert_eq!(program.instructions().len(), 3);
+}
@@ -0,0 +1,180 @@
+//! Python bindings for the Brainf*ck interpreter, built with `pyo3`.
+//!
+//! ```python
+//! import bft
+//!
+//! program = bft.Program(",[.,]")
+//! vm = bft.VM(program)
+//! assert vm.run(b"hi") == b"hi"
+//!
+//! vm = bft.VM(bft.Program("+[>+<+]"), cells=10)
+//! vm.step()
+//! saved = vm.snapshot()
+//! vm.step()
+//! vm.restore(saved)
+//! ```
+#![warn(missing_docs)]
+
+use std::collections::VecDeque;
+use std::num::NonZeroUsize;
+
+use pyo3::exceptions::{PyRuntimeError, PyValueError};
+use pyo3::prelude::*;
+use pyo3::types::PyBytes;
+
+use bft_interp::{StepOutcome, BFVM};
+use bft_types::BFprogram;
+
+/// A parsed program, with matching brackets.
+#[pyclass(module = "bft", frozen)]
+pub struct Program(BFprogram);
+
+#[pymethods]
+impl Program {
+    /// Parse `code`, checking that its brackets match.
+    #[new]
+    fn new(code: &str) -> PyResult<Self> {
+        let mut program = BFprogram::new("<python>", code.as_bytes());
+        program
+            .validate_brackets()
+            .map_err(|err| PyValueError::new_err(err.to_string()))?;
+        Ok(Program(program))
+    }
+
+    fn __len__(&self) -> usize {
+        self.0.instructions().len()
+    }
+}
+
+/// The state of a VM, from `VM.snapshot()`.
+#[pyclass(module = "bft", frozen)]
+pub struct Snapshot {
+    vm: BFVM<u8>,
+    input: VecDeque<u8>,
+}
+
+/// A virtual machine running a program.
+#[pyclass(module = "bft")]
+pub struct VM {
+    program: BFprogram,
+    vm: BFVM<u8>,
+    /// Input fed to the program that it hasn't read yet.
+    input: VecDeque<u8>,
+    /// Output from the program that hasn't been taken yet.
+    output: Vec<u8>,
+}
+
+#[pymethods]
+impl VM {
+    /// Prepare to run `program` on a tape of `cells` cells, which grows if `extensible` is set.
+    #[new]
+    #[pyo3(signature = (program, cells = None, extensible = false))]
+    fn new(program: &Program, cells: Option<usize>, extensible: bool) -> Self {
+        VM {
+            program: program.0.clone(),
+            vm: BFVM::new(cells.and_then(NonZeroUsize::new), extensible),
+            input: VecDeque::new(),
+            output: Vec::new(),
+        }
+    }
+
+    /// Run the program to completion, with `input` added to any input already fed to it,
+    /// returning everything it has written that hasn't been taken yet.
+    fn run<'py>(&mut self, py: Python<'py>, input: &[u8]) -> PyResult<Bound<'py, PyBytes>> {
+        self.feed(input);
+        while self.step()? {}
+        Ok(self.take_output(py))
+    }
+
+    /// Add `input` to the end of the program's input.
+    fn feed(&mut self, input: &[u8]) {
+        self.input.extend(input);
+    }
+
+    /// Execute a single instruction, returning false once the program has finished.
+    fn step(&mut self) -> PyResult<bool> {
+        self.vm
+            .step(&self.program, &mut self.input, &mut self.output)
+            .map(|outcome| outcome == StepOutcome::Running)
+            .map_err(|err| PyRuntimeError::new_err(err.to_string()))
+    }
+
+    /// Take everything the program has written so far.
+    fn take_output<'py>(&mut self, py: Python<'py>) -> Bound<'py, PyBytes> {
+        PyBytes::new(py, &std::mem::take(&mut self.output))
+    }
+
+    /// The contents of the tape.
+    #[getter]
+    fn tape<'py>(&self, py: Python<'py>) -> Bound<'py, PyBytes> {
+        PyBytes::new(py, self.vm.tape())
+    }
+
+    /// The position of the head on the tape.
+    #[getter]
+    fn head(&self) -> usize {
+        self.vm.head()
+    }
+
+    /// The index of the next instruction to execute.
+    #[getter]
+    fn pc(&self) -> usize {
+        self.vm.pc()
+    }
+
+    /// Save the state of the VM, including the input it hasn't read yet.
+    fn snapshot(&self) -> Snapshot {
+        Snapshot {
+            vm: self.vm.clone(),
+            input: self.input.clone(),
+        }
+    }
+
+    /// Go back to a state saved with `snapshot`. Output that hasn't been taken is kept.
+    fn restore(&mut self, snapshot: &Snapshot) {
+        self.vm = snapshot.vm.clone();
+        self.input.clone_from(&snapshot.input);
+    }
+}
+
+/// A Brainf*ck interpreter.
+#[pymodule]
+fn bft(m: &Bound<'_, PyModule>) -> PyResult<()> {
+    m.add_class::<Program>()?;
+    m.add_class::<VM>()?;
+    m.add_class::<Snapshot>()?;
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn running() {
+        Python::attach(|py| {
+            let program = Program::new(",.,.").unwrap();
+            let mut vm = VM::new(&program, None, false);
+            let output = vm.run(py, b"hi").unwrap();
+            assert_eq!(output.as_bytes(), b"hi");
+            assert!(Program::new("]").is_err());
+        });
+    }
+
+    #[test]
+    fn snapshots() {
+        Python::attach(|py| {
+            let program = Program::new("+.+.").unwrap();
+            let mut vm = VM::new(&program, Some(4), false);
+            vm.step().unwrap();
+            let saved = vm.snapshot();
+            vm.step().unwrap();
+            vm.step().unwrap();
+            assert_eq!(vm.tape(py).as_bytes(), [2, 0, 0, 0]);
+            vm.restore(&saved);
+            assert_eq!(vm.pc(), 1);
+            assert_eq!(vm.tape(py).as_bytes(), [1, 0, 0, 0]);
+            assert_eq!(vm.take_output(py).as_bytes(), [1]);
+        });
+    }
+}
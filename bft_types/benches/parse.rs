@@ -0,0 +1,40 @@
+//! Benchmarks for parsing large programs, like those written by other programs.
+
+use criterion::{criterion_group, criterion_main, BenchmarkId, Criterion, Throughput};
+
+use bft_types::BFprogram;
+
+/// Size of each generated program, in bytes.
+const SIZE: usize = 4 << 20;
+
+/// A program of about `SIZE` bytes, made by repeating `line`.
+fn generate(line: &str) -> Vec<u8> {
+    line.bytes().cycle().take(SIZE).collect()
+}
+
+fn parse(c: &mut Criterion) {
+    let sources = [
+        // Machine-generated code, with nothing but instructions on long lines.
+        ("dense", generate(&("+>-<[->+<]>.,".repeat(20) + "\n"))),
+        // Hand-written code, with short lines and comments between the instructions.
+        (
+            "commented",
+            generate("Add the two cells together: [->+<] and then print the sum .\n"),
+        ),
+    ];
+    let mut group = c.benchmark_group("BFprogram::new");
+    group.throughput(Throughput::Bytes(SIZE as u64));
+    for (name, source) in &sources {
+        group.bench_with_input(BenchmarkId::from_parameter(name), source, |b, source| {
+            b.iter(|| BFprogram::new("bench.b", source));
+        });
+        #[cfg(feature = "rayon")]
+        group.bench_with_input(BenchmarkId::new("parallel", name), source, |b, source| {
+            b.iter(|| BFprogram::new_parallel("bench.b", source));
+        });
+    }
+    group.finish();
+}
+
+criterion_group!(benches, parse);
+criterion_main!(benches);
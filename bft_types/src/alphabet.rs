@@ -0,0 +1,383 @@
+//! Custom spellings for the Brainf*ck instructions, for running trivial substitution dialects.
+
+use alloc::string::{String, ToString};
+use alloc::vec;
+use alloc::vec::Vec;
+use core::error::Error;
+use core::fmt;
+use core::fmt::{Display, Formatter};
+
+use crate::{Extension, Instruction};
+
+/// The set of tokens recognised as instructions when parsing a program.
+///
+/// Tokens are matched longest first, so a dialect can use tokens that are prefixes of one
+/// another. Any run of whitespace within a token matches any run of whitespace in the source,
+/// so multi-word tokens like `Ook. Ook?` still match when split across lines.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Alphabet {
+    tokens: Vec<(Vec<u8>, Instruction)>,
+}
+
+/// A piece of a source file: either an instruction, or the comment text between instructions.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Token<'a> {
+    /// An instruction, and the text it was written as.
+    Instruction(Instruction, &'a [u8]),
+
+    /// Text that isn't part of an instruction, including whitespace.
+    Comment(&'a [u8]),
+}
+
+/// Problems with the tokens given for an [`Alphabet`].
+#[derive(Debug, PartialEq, Eq)]
+pub enum AlphabetError {
+    /// A token was empty, or made entirely of whitespace.
+    EmptyToken(Instruction),
+
+    /// The same token was given for more than one instruction.
+    DuplicateToken(String),
+
+    /// Two extensions use the same token, so can't be enabled together.
+    ExtensionClash(Extension, Extension, char),
+}
+
+impl Display for AlphabetError {
+    fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::EmptyToken(inst) => write!(f, "Empty token for instruction '{inst}'"),
+            Self::DuplicateToken(token) => write!(f, "Token '{token}' is used more than once"),
+            Self::ExtensionClash(first, second, token) => write!(
+                f,
+                "The {first} and {second} extensions both use '{token}', so can't be enabled \
+                 together"
+            ),
+        }
+    }
+}
+
+impl Error for AlphabetError {}
+
+impl Default for Alphabet {
+    /// The standard Brainf*ck alphabet.
+    fn default() -> Self {
+        Alphabet {
+            tokens: b"<>+-,.[]"
+                .iter()
+                .filter_map(|c| Some((vec![*c], Instruction::from_byte(*c)?)))
+                .collect(),
+        }
+    }
+}
+
+impl Alphabet {
+    /// Build an alphabet from pairs of instructions and the tokens that represent them. An
+    /// instruction may be given more than once to allow several spellings.
+    ///
+    /// # Errors
+    /// Returns an error if a token is empty, or if a token is repeated.
+    ///
+    /// ```
+    /// use bft_types::{Alphabet, BFprogram, Instruction};
+    /// let alphabet = Alphabet::new([
+    ///     (Instruction::Increment, "inc"),
+    ///     (Instruction::Output, "out"),
+    ///     (Instruction::Output, "output"),
+    /// ])
+    /// .expect("Tokens should be valid.");
+    /// let program = BFprogram::with_alphabet("doc.test", b"inc output", &alphabet);
+    /// assert_eq!(program.instructions().len(), 2);
+    /// assert_eq!(program.instructions()[1].location(), "1:5");
+    /// ```
+    pub fn new<I, T>(tokens: I) -> Result<Self, AlphabetError>
+    where
+        I: IntoIterator<Item = (Instruction, T)>,
+        T: Into<Vec<u8>>,
+    {
+        let mut alphabet = Alphabet { tokens: Vec::new() };
+        for (inst, token) in tokens {
+            let token = token.into();
+            if token.iter().all(u8::is_ascii_whitespace) {
+                return Err(AlphabetError::EmptyToken(inst));
+            }
+            if alphabet.tokens.iter().any(|(t, _)| *t == token) {
+                return Err(AlphabetError::DuplicateToken(
+                    String::from_utf8_lossy(&token).into_owned(),
+                ));
+            }
+            alphabet.tokens.push((token, inst));
+        }
+        Ok(alphabet)
+    }
+
+    /// The Boolfuck alphabet, for programs running on a tape of bits. `+` flips the current bit,
+    /// `,` reads a bit and `;` writes one, while `.` and `-` have no meaning.
+    #[must_use]
+    pub fn boolfuck() -> Self {
+        Alphabet {
+            tokens: vec![
+                (vec![b'<'], Instruction::MoveLeft),
+                (vec![b'>'], Instruction::MoveRight),
+                (vec![b'+'], Instruction::Increment),
+                (vec![b','], Instruction::Input),
+                (vec![b';'], Instruction::Output),
+                (vec![b'['], Instruction::BeginLoop),
+                (vec![b']'], Instruction::EndLoop),
+            ],
+        }
+    }
+
+    /// The Ook! alphabet, where each instruction is a pair of `Ook.`, `Ook?` or `Ook!` words.
+    #[must_use]
+    pub fn ook() -> Self {
+        Alphabet {
+            tokens: vec![
+                (b"Ook? Ook.".to_vec(), Instruction::MoveLeft),
+                (b"Ook. Ook?".to_vec(), Instruction::MoveRight),
+                (b"Ook. Ook.".to_vec(), Instruction::Increment),
+                (b"Ook! Ook!".to_vec(), Instruction::Decrement),
+                (b"Ook. Ook!".to_vec(), Instruction::Input),
+                (b"Ook! Ook.".to_vec(), Instruction::Output),
+                (b"Ook! Ook?".to_vec(), Instruction::BeginLoop),
+                (b"Ook? Ook!".to_vec(), Instruction::EndLoop),
+            ],
+        }
+    }
+
+    /// Add the tokens used by `extension` to the alphabet. Either all of them are added, or,
+    /// on failure, none are.
+    ///
+    /// # Errors
+    /// Returns an error if one of the extension's tokens is already in use, naming the other
+    /// extension if that's what uses it.
+    pub fn add_extension(&mut self, extension: Extension) -> Result<(), AlphabetError> {
+        for (c, _) in extension.tokens() {
+            let Some((_, inst)) = self.tokens.iter().find(|(t, _)| t.as_slice() == [*c]) else {
+                continue;
+            };
+            let token = char::from(*c);
+            return Err(match inst.extension() {
+                Some(other) if other != extension => {
+                    AlphabetError::ExtensionClash(other, extension, token)
+                }
+                _ => AlphabetError::DuplicateToken(token.to_string()),
+            });
+        }
+        self.tokens
+            .extend(extension.tokens().iter().map(|(c, inst)| (vec![*c], *inst)));
+        Ok(())
+    }
+
+    /// The tokens in the alphabet, along with the instructions they represent.
+    pub fn tokens(&self) -> impl Iterator<Item = (&[u8], Instruction)> {
+        self.tokens.iter().map(|(t, inst)| (t.as_slice(), *inst))
+    }
+
+    /// The preferred token for `inst`, which is the first one given for it.
+    #[must_use]
+    pub fn token_for(&self, inst: Instruction) -> Option<&[u8]> {
+        self.tokens
+            .iter()
+            .find(|(_, i)| *i == inst)
+            .map(|(t, _)| t.as_slice())
+    }
+
+    /// Split `data` into instructions and the comments between them. Nothing is lost, so the
+    /// source can be rebuilt from the tokens.
+    ///
+    /// ```
+    /// use bft_types::{Alphabet, Instruction, Token};
+    /// let tokens = Alphabet::default().tokenize(b"+ add");
+    /// assert_eq!(
+    ///     tokens,
+    ///     [Token::Instruction(Instruction::Increment, b"+"), Token::Comment(b" add")]
+    /// );
+    /// ```
+    #[must_use]
+    pub fn tokenize<'a>(&self, data: &'a [u8]) -> Vec<Token<'a>> {
+        let mut tokens = Vec::new();
+        let mut comment_start = 0;
+        let mut pos = 0;
+        while pos < data.len() {
+            if let Some((inst, len)) = self.next_token(&data[pos..]) {
+                if comment_start < pos {
+                    tokens.push(Token::Comment(&data[comment_start..pos]));
+                }
+                tokens.push(Token::Instruction(inst, &data[pos..pos + len]));
+                pos += len;
+                comment_start = pos;
+            } else {
+                pos += 1;
+            }
+        }
+        if comment_start < pos {
+            tokens.push(Token::Comment(&data[comment_start..pos]));
+        }
+        tokens
+    }
+
+    /// Find the longest token at the start of `data`, returning its instruction and the number
+    /// of bytes it covers.
+    #[must_use]
+    pub fn next_token(&self, data: &[u8]) -> Option<(Instruction, usize)> {
+        self.tokens
+            .iter()
+            .filter_map(|(token, inst)| Some((*inst, match_len(token, data)?)))
+            .max_by_key(|(_, len)| *len)
+    }
+}
+
+/// Check if `token` matches the start of `data`, returning the number of bytes matched.
+fn match_len(token: &[u8], data: &[u8]) -> Option<usize> {
+    let mut t = 0;
+    let mut d = 0;
+    while t < token.len() {
+        if token[t].is_ascii_whitespace() {
+            let start = d;
+            while data.get(d).is_some_and(u8::is_ascii_whitespace) {
+                d += 1;
+            }
+            if d == start {
+                return None;
+            }
+            while token.get(t).is_some_and(u8::is_ascii_whitespace) {
+                t += 1;
+            }
+        } else if data.get(d) == Some(&token[t]) {
+            t += 1;
+            d += 1;
+        } else {
+            return None;
+        }
+    }
+    Some(d)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn matching_tokens() {
+        assert_eq!(match_len(b"ab", b"abc"), Some(2));
+        assert_eq!(match_len(b"ab", b"a"), None);
+        assert_eq!(match_len(b"a b", b"a \n\tbc"), Some(5));
+        assert_eq!(match_len(b"a b", b"ab"), None);
+    }
+
+    #[test]
+    fn longest_match() {
+        let alphabet = Alphabet::new([
+            (Instruction::Increment, "a"),
+            (Instruction::Decrement, "aa"),
+        ])
+        .unwrap();
+        assert_eq!(
+            alphabet.next_token(b"aaa"),
+            Some((Instruction::Decrement, 2))
+        );
+        assert_eq!(
+            alphabet.next_token(b"ab"),
+            Some((Instruction::Increment, 1))
+        );
+        assert_eq!(alphabet.next_token(b"ba"), None);
+    }
+
+    #[test]
+    fn invalid_tokens() {
+        assert_eq!(
+            Alphabet::new([(Instruction::Input, " ")]),
+            Err(AlphabetError::EmptyToken(Instruction::Input))
+        );
+        assert_eq!(
+            Alphabet::new([(Instruction::Input, "x"), (Instruction::Output, "x")]),
+            Err(AlphabetError::DuplicateToken(String::from("x")))
+        );
+    }
+
+    #[test]
+    fn default_alphabet() {
+        let alphabet = Alphabet::default();
+        assert_eq!(alphabet.tokens().count(), 8);
+        assert_eq!(
+            alphabet.next_token(b"[-]"),
+            Some((Instruction::BeginLoop, 1))
+        );
+    }
+
+    #[test]
+    fn ook_alphabet() {
+        let alphabet = Alphabet::ook();
+        assert_eq!(
+            alphabet.next_token(b"Ook!\nOok? Ook."),
+            Some((Instruction::BeginLoop, 9))
+        );
+        assert_eq!(
+            alphabet.token_for(Instruction::EndLoop),
+            Some(&b"Ook? Ook!"[..])
+        );
+    }
+
+    #[test]
+    fn extensions() {
+        let mut alphabet = Alphabet::default();
+        alphabet.add_extension(Extension::Pbrain).unwrap();
+        assert_eq!(alphabet.tokens().count(), 11);
+        assert_eq!(
+            alphabet.next_token(b":"),
+            Some((Instruction::CallProcedure, 1))
+        );
+        assert_eq!(
+            alphabet.add_extension(Extension::Pbrain),
+            Err(AlphabetError::DuplicateToken(String::from("(")))
+        );
+    }
+
+    #[test]
+    fn clashing_extensions() {
+        assert_eq!(Extension::Ext1.clash(Extension::Multitape), Some(b'^'));
+        assert_eq!(Extension::Pbrain.clash(Extension::Multitape), None);
+
+        let mut alphabet = Alphabet::default();
+        alphabet.add_extension(Extension::Ext1).unwrap();
+        let before = alphabet.clone();
+        let err = alphabet.add_extension(Extension::Multitape).unwrap_err();
+        assert_eq!(
+            err,
+            AlphabetError::ExtensionClash(Extension::Ext1, Extension::Multitape, '^')
+        );
+        assert_eq!(
+            err.to_string(),
+            "The ext1 and multitape extensions both use '^', so can't be enabled together"
+        );
+        // Nothing is added when an extension can't be.
+        assert_eq!(alphabet, before);
+
+        // A custom alphabet's tokens belong to no extension.
+        let mut alphabet = Alphabet::new([(Instruction::Increment, "?")]).unwrap();
+        assert_eq!(
+            alphabet.add_extension(Extension::Random),
+            Err(AlphabetError::DuplicateToken(String::from("?")))
+        );
+    }
+
+    #[test]
+    fn halt_and_ext1() {
+        assert_eq!(Extension::Halt.clash(Extension::Ext1), Some(b'@'));
+        for (first, second) in [
+            (Extension::Halt, Extension::Ext1),
+            (Extension::Ext1, Extension::Halt),
+        ] {
+            let mut alphabet = Alphabet::default();
+            alphabet.add_extension(first).unwrap();
+            assert_eq!(
+                alphabet.add_extension(second),
+                Err(AlphabetError::ExtensionClash(first, second, '@'))
+            );
+            assert_eq!(
+                alphabet.next_token(b"@").map(|(inst, _)| inst.extension()),
+                Some(Some(first))
+            );
+        }
+    }
+}
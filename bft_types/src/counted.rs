@@ -0,0 +1,303 @@
+//! Parsing programs with runs of arithmetic and moves merged into single counted instructions,
+//! for consumers that don't need to know where each character was.
+//!
+//! Machine-generated programs can contain runs of millions of `+`, which take 16 bytes each as
+//! [`InputInstruction`](crate::InputInstruction)s, but a single [`CountedInstruction`] here.
+
+use alloc::vec;
+use alloc::vec::Vec;
+use core::convert::AsRef;
+
+use crate::{
+    check_source_len, packed, BracketMatchError, Instruction, SourceName, SourcePath,
+    BYTE_INSTRUCTIONS, NO_MATCH,
+};
+
+/// An operation in a [`CountedProgram`].
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Op {
+    /// Add `n` to the current cell, which is a run of `+` and `-`. Cells wrap, so `n` should be
+    /// reduced modulo the cell size.
+    Add {
+        /// Number of `+` less the number of `-`.
+        n: i64,
+    },
+
+    /// Move the head `n` cells to the right, or to the left if `n` is negative, which is a run
+    /// of `>` and `<`.
+    Move {
+        /// Number of `>` less the number of `<`.
+        n: i64,
+    },
+
+    /// Any other instruction, which is never merged.
+    Single(Instruction),
+}
+
+/// A counted instruction, and the span of source it was parsed from.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct CountedInstruction {
+    op: Op,
+    line_number: u32,
+    char_number: u32,
+    offset: u32,
+    len: u32,
+}
+
+impl CountedInstruction {
+    /// The operation.
+    #[must_use]
+    pub fn op(&self) -> Op {
+        self.op
+    }
+
+    /// The line of the source file that the run starts on, starting at 1.
+    #[must_use]
+    pub fn line_number(&self) -> usize {
+        self.line_number as usize
+    }
+
+    /// The column within the line that the run starts at, starting at 1.
+    #[must_use]
+    pub fn char_number(&self) -> usize {
+        self.char_number as usize
+    }
+
+    /// The byte offset within the source file of the start of the run.
+    #[must_use]
+    pub fn offset(&self) -> usize {
+        self.offset as usize
+    }
+
+    /// The number of bytes from the start of the run to the end of its last instruction,
+    /// including any comments between them.
+    #[must_use]
+    pub fn len(&self) -> usize {
+        self.len as usize
+    }
+
+    /// Whether the span is empty, which it never is, as each run has at least one instruction.
+    #[must_use]
+    pub fn is_empty(&self) -> bool {
+        self.len == 0
+    }
+}
+
+/// A Brainf*ck program parsed into counted instructions.
+#[derive(Clone, Debug)]
+pub struct CountedProgram {
+    source_name: SourceName,
+    ops: Vec<CountedInstruction>,
+    /// The index of the bracket matching each operation, once they have been validated, or
+    /// [`NO_MATCH`] for operations that aren't brackets.
+    brackets: Vec<u32>,
+}
+
+/// The sign of `inst`, if it can be merged into a run, and whether the run is a move.
+fn step(inst: Instruction) -> Option<(bool, i64)> {
+    match inst {
+        Instruction::Increment => Some((false, 1)),
+        Instruction::Decrement => Some((false, -1)),
+        Instruction::MoveRight => Some((true, 1)),
+        Instruction::MoveLeft => Some((true, -1)),
+        _ => None,
+    }
+}
+
+impl CountedProgram {
+    /// Parse standard Brainf*ck into counted instructions. Runs of `+` and `-`, and of `>` and
+    /// `<`, are merged even when there are comments between them.
+    ///
+    /// ```
+    /// use bft_types::counted::{CountedProgram, Op};
+    /// let program = CountedProgram::new("doc.test", b"+++ ++-\n>>[-]");
+    /// let ops: Vec<_> = program.instructions().iter().map(|inst| inst.op()).collect();
+    ///
+    /// assert_eq!(ops[0], Op::Add { n: 4 });
+    /// assert_eq!(ops[1], Op::Move { n: 2 });
+    /// assert_eq!(program.instructions()[0].len(), 7);
+    /// assert_eq!(program.instructions()[1].line_number(), 2);
+    /// ```
+    ///
+    /// # Panics
+    /// Panics if `data` is longer than [`MAX_SOURCE_LEN`](crate::MAX_SOURCE_LEN).
+    #[must_use]
+    pub fn new<P: AsRef<SourcePath>>(source_name: P, data: &[u8]) -> CountedProgram {
+        check_source_len(data);
+        let mut ops: Vec<CountedInstruction> = Vec::new();
+        let mut line_number = 1;
+        let mut line_start = 0;
+        // Whether the last operation is a run that the next instruction can join.
+        let mut open_run = false;
+        for (offset, c) in data.iter().enumerate() {
+            let Some(inst) = BYTE_INSTRUCTIONS[usize::from(*c)] else {
+                if *c == b'\n' {
+                    line_number += 1;
+                    line_start = offset + 1;
+                }
+                continue;
+            };
+            let op = if let Some((is_move, n)) = step(inst) {
+                if let Some(last) = ops.last_mut().filter(|_| open_run) {
+                    if let (Op::Move { n: total }, true) | (Op::Add { n: total }, false) =
+                        (&mut last.op, is_move)
+                    {
+                        *total += n;
+                        last.len = packed(offset + 1 - last.offset());
+                        continue;
+                    }
+                }
+                open_run = true;
+                if is_move {
+                    Op::Move { n }
+                } else {
+                    Op::Add { n }
+                }
+            } else {
+                open_run = false;
+                Op::Single(inst)
+            };
+            ops.push(CountedInstruction {
+                op,
+                line_number: packed(line_number),
+                char_number: packed(offset - line_start + 1),
+                offset: packed(offset),
+                len: 1,
+            });
+        }
+
+        CountedProgram {
+            source_name: SourceName::from(source_name.as_ref()),
+            ops,
+            brackets: Vec::new(),
+        }
+    }
+
+    /// The counted instructions.
+    #[must_use]
+    pub fn instructions(&self) -> &[CountedInstruction] {
+        &self.ops
+    }
+
+    /// The name of the source file for the program.
+    #[must_use]
+    pub fn source(&self) -> &SourceName {
+        &self.source_name
+    }
+
+    /// Find the index of the bracket matching the one at `idx`, once
+    /// [`CountedProgram::validate_brackets`] has succeeded.
+    #[must_use]
+    pub fn matching_bracket(&self, idx: usize) -> Option<usize> {
+        self.brackets
+            .get(idx)
+            .filter(|matched| **matched != NO_MATCH)
+            .map(|matched| *matched as usize)
+    }
+
+    /// Validate the program by ensuring that the brackets match.
+    ///
+    /// # Errors
+    /// Returns an error for the first bracket without a match.
+    pub fn validate_brackets(&mut self) -> Result<(), BracketMatchError> {
+        let mut stack = Vec::new();
+        let mut brackets = vec![NO_MATCH; self.ops.len()];
+        for (idx, inst) in self.ops.iter().enumerate() {
+            match inst.op {
+                Op::Single(Instruction::BeginLoop) => stack.push(idx),
+                Op::Single(Instruction::EndLoop) => {
+                    let Some(matched) = stack.pop() else {
+                        return Err(BracketMatchError::ExtraClosingBracket(
+                            self.source_name.clone(),
+                            inst.line_number(),
+                            inst.char_number(),
+                            inst.offset(),
+                        ));
+                    };
+                    brackets[matched] = packed(idx);
+                    brackets[idx] = packed(matched);
+                }
+                _ => {}
+            }
+        }
+        if let Some(idx) = stack.pop() {
+            let inst = self.ops[idx];
+            return Err(BracketMatchError::ExtraOpeningBracket(
+                self.source_name.clone(),
+                inst.line_number(),
+                inst.char_number(),
+                inst.offset(),
+            ));
+        }
+        self.brackets = brackets;
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn ops(code: &str) -> Vec<Op> {
+        CountedProgram::new("mod.test", code.as_bytes())
+            .instructions()
+            .iter()
+            .map(CountedInstruction::op)
+            .collect()
+    }
+
+    #[test]
+    fn merging_runs() {
+        assert_eq!(
+            ops("+++--><<,[.]"),
+            [
+                Op::Add { n: 1 },
+                Op::Move { n: -1 },
+                Op::Single(Instruction::Input),
+                Op::Single(Instruction::BeginLoop),
+                Op::Single(Instruction::Output),
+                Op::Single(Instruction::EndLoop),
+            ]
+        );
+        assert_eq!(
+            ops("+.+"),
+            [
+                Op::Add { n: 1 },
+                Op::Single(Instruction::Output),
+                Op::Add { n: 1 }
+            ]
+        );
+        let long_run = CountedProgram::new("mod.test", &vec![b'+'; 1 << 20]);
+        assert_eq!(long_run.instructions().len(), 1);
+        assert_eq!(long_run.instructions()[0].op(), Op::Add { n: 1 << 20 });
+    }
+
+    #[test]
+    fn spans() {
+        let program = CountedProgram::new("mod.test", b"a ++\n+ b>");
+        let run = program.instructions()[0];
+        assert_eq!((run.line_number(), run.char_number()), (1, 3));
+        assert_eq!((run.offset(), run.len()), (2, 4));
+        let run = program.instructions()[1];
+        assert_eq!((run.line_number(), run.char_number()), (2, 4));
+        assert_eq!((run.offset(), run.len()), (8, 1));
+    }
+
+    #[test]
+    fn brackets() {
+        let mut program = CountedProgram::new("mod.test", b"++[>+[-]<-]");
+        assert!(program.validate_brackets().is_ok());
+        assert_eq!(program.matching_bracket(1), Some(9));
+        assert_eq!(program.matching_bracket(4), Some(6));
+        assert_eq!(program.matching_bracket(0), None);
+        assert_eq!(
+            CountedProgram::new("mod.test", b"+\n]").validate_brackets(),
+            Err(BracketMatchError::ExtraClosingBracket(
+                "mod.test".into(),
+                2,
+                1,
+                2
+            ))
+        );
+    }
+}
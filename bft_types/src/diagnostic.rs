@@ -0,0 +1,100 @@
+//! [`miette::Diagnostic`] for the errors found checking a program, so that applications using
+//! miette can report them with labels pointing into the program.
+//!
+//! The errors don't keep the program's text, so it needs to be attached to the report for the
+//! labels to be shown:
+//!
+//! ```
+//! use bft_types::BFprogram;
+//! use miette::{NamedSource, Report};
+//!
+//! let code = "+[-";
+//! let err = BFprogram::new("doc.test", code.as_bytes())
+//!     .validate_brackets()
+//!     .unwrap_err();
+//! let report = Report::new(err).with_source_code(NamedSource::new("doc.test", code));
+//! ```
+
+use std::fmt::Display;
+use std::iter;
+
+use miette::{Diagnostic, LabeledSpan};
+
+use crate::{AlphabetError, BracketMatchError, ExtensionError};
+
+/// A label on the single byte at `offset`.
+fn label_at(offset: usize, text: &str) -> Box<dyn Iterator<Item = LabeledSpan>> {
+    Box::new(iter::once(LabeledSpan::new_primary_with_span(
+        Some(text.to_owned()),
+        (offset, 1),
+    )))
+}
+
+impl Diagnostic for AlphabetError {
+    fn code<'a>(&'a self) -> Option<Box<dyn Display + 'a>> {
+        Some(Box::new("bft::alphabet"))
+    }
+}
+
+impl Diagnostic for BracketMatchError {
+    fn code<'a>(&'a self) -> Option<Box<dyn Display + 'a>> {
+        Some(Box::new("bft::brackets"))
+    }
+
+    fn labels(&self) -> Option<Box<dyn Iterator<Item = LabeledSpan> + '_>> {
+        let text = match self {
+            Self::ExtraOpeningBracket(..) => "this loop is never closed",
+            Self::ExtraClosingBracket(..) => "no loop is open here",
+            Self::ExtraOpeningParen(..) => "this procedure is never closed",
+            Self::ExtraClosingParen(..) => "no procedure is open here",
+            Self::NestedProcedure(..) => "this is inside another procedure",
+            Self::NestedTooDeeply(..) => "this is nested too deeply",
+        };
+        Some(label_at(self.offset(), text))
+    }
+}
+
+impl Diagnostic for ExtensionError {
+    fn code<'a>(&'a self) -> Option<Box<dyn Display + 'a>> {
+        Some(Box::new("bft::extension"))
+    }
+
+    fn help<'a>(&'a self) -> Option<Box<dyn Display + 'a>> {
+        Some(Box::new(format!(
+            "enable the '{}' extension to use this instruction",
+            self.extension
+        )))
+    }
+
+    fn labels(&self) -> Option<Box<dyn Iterator<Item = LabeledSpan> + '_>> {
+        Some(label_at(self.offset, "needs an extension"))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::{Alphabet, BFprogram, Extension};
+    use miette::Diagnostic;
+
+    #[test]
+    fn labels() {
+        let err = BFprogram::new("a.b", b"+\n ]")
+            .validate_brackets()
+            .unwrap_err();
+        assert_eq!(err.code().unwrap().to_string(), "bft::brackets");
+        let label = err.labels().unwrap().next().unwrap();
+        assert_eq!((label.offset(), label.len()), (3, 1));
+        assert_eq!(label.label(), Some("no loop is open here"));
+
+        let mut alphabet = Alphabet::default();
+        alphabet.add_extension(Extension::Pbrain).unwrap();
+        let err = BFprogram::with_alphabet("a.b", b"+ :", &alphabet)
+            .validate_extensions(&[])
+            .unwrap_err();
+        assert_eq!(err.labels().unwrap().next().unwrap().offset(), 2);
+        assert_eq!(
+            err.help().unwrap().to_string(),
+            "enable the 'pbrain' extension to use this instruction"
+        );
+    }
+}
@@ -1,15 +1,72 @@
 //! Types for a Brainf*ck interpreter.
 
+#![cfg_attr(not(feature = "std"), no_std)]
 #![warn(missing_docs)]
-use std::cmp::Eq;
-use std::convert::AsRef;
-use std::error::Error;
-use std::fmt;
-use std::fmt::{Display, Formatter};
+
+extern crate alloc;
+
+use alloc::format;
+use alloc::string::String;
+use alloc::vec;
+use alloc::vec::Vec;
+use core::cmp::Eq;
+use core::convert::AsRef;
+use core::error::Error;
+use core::fmt;
+use core::fmt::{Display, Formatter};
+use core::iter;
+use core::ops::Range;
+use core::str::FromStr;
+#[cfg(feature = "std")]
 use std::fs::read;
+#[cfg(feature = "std")]
 use std::io;
+#[cfg(feature = "std")]
 use std::path::{Path, PathBuf};
 
+mod alphabet;
+pub mod counted;
+#[cfg(feature = "miette")]
+mod diagnostic;
+mod program_ref;
+
+pub use alphabet::{Alphabet, AlphabetError, Token};
+pub use program_ref::{BFprogramRef, Instructions};
+
+/// The name of the source that a program was read from.
+#[cfg(feature = "std")]
+pub type SourceName = PathBuf;
+
+/// How the name of a program's source is given when parsing it.
+#[cfg(feature = "std")]
+pub type SourcePath = Path;
+
+/// How the name of a program's source is given when parsing it.
+#[cfg(not(feature = "std"))]
+pub type SourcePath = str;
+
+/// The name of the source that a program was read from. Without `std` there are no paths, so
+/// this is plain text.
+#[cfg(not(feature = "std"))]
+#[derive(Clone, Debug, Default, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub struct SourceName(String);
+
+#[cfg(not(feature = "std"))]
+impl SourceName {
+    /// The name, for showing in messages.
+    #[must_use]
+    pub fn display(&self) -> &str {
+        &self.0
+    }
+}
+
+#[cfg(not(feature = "std"))]
+impl From<&str> for SourceName {
+    fn from(name: &str) -> Self {
+        SourceName(String::from(name))
+    }
+}
+
 /// Raw bytecodes for the brainf*ck VM.
 #[derive(Clone, Copy, Debug, PartialEq, Eq)]
 pub enum Instruction {
@@ -38,6 +95,73 @@ pub enum Instruction {
     /// If the value at the current position of the tape is not zero, jump backward to the matching
     /// begin loop.
     EndLoop,
+
+    /// Define a procedure, numbered by the value at the current position of the tape, that runs
+    /// the instructions up to the matching end procedure. Part of the pbrain extension.
+    BeginProcedure,
+
+    /// Return from the procedure being run. Part of the pbrain extension.
+    EndProcedure,
+
+    /// Run the procedure numbered by the value at the current position of the tape. Part of the
+    /// pbrain extension.
+    CallProcedure,
+
+    /// Start a new thread. The current cell is set to zero for the existing thread, while the new
+    /// thread starts one cell to the right, with that cell set to one. Part of the Brainfork
+    /// extension.
+    Fork,
+
+    /// Stop the program immediately. Part of the Extended Type I extension.
+    EndProgram,
+
+    /// Copy the value at the current position of the tape into the storage register. Part of the
+    /// Extended Type I extension.
+    Store,
+
+    /// Copy the storage register into the current position of the tape. Part of the Extended
+    /// Type I extension.
+    Retrieve,
+
+    /// Shift the value at the current position of the tape right by one bit. Part of the Extended
+    /// Type I extension.
+    ShiftRight,
+
+    /// Shift the value at the current position of the tape left by one bit. Part of the Extended
+    /// Type I extension.
+    ShiftLeft,
+
+    /// Invert the bits of the value at the current position of the tape. Part of the Extended
+    /// Type I extension.
+    Not,
+
+    /// Exclusive or the value at the current position of the tape with the storage register.
+    /// Part of the Extended Type I extension.
+    Xor,
+
+    /// And the value at the current position of the tape with the storage register. Part of the
+    /// Extended Type I extension.
+    And,
+
+    /// Or the value at the current position of the tape with the storage register. Part of the
+    /// Extended Type I extension.
+    Or,
+
+    /// Set the value at the current position of the tape to a random byte. Part of the random
+    /// extension.
+    Random,
+
+    /// Switch to the next tape, wrapping around to the first after the last. Part of the
+    /// multitape extension.
+    NextTape,
+
+    /// Switch to the previous tape, wrapping around to the last before the first. Part of the
+    /// multitape extension.
+    PreviousTape,
+
+    /// Stop the program immediately, using the value at the current position of the tape as the
+    /// exit status. Part of the halt extension.
+    Halt,
 }
 
 impl Display for Instruction {
@@ -51,12 +175,115 @@ impl Display for Instruction {
             Self::Output => write!(f, "Output the current byte"),
             Self::BeginLoop => write!(f, "Start looping"),
             Self::EndLoop => write!(f, "Finish looping"),
+            Self::BeginProcedure => write!(f, "Start defining a procedure"),
+            Self::EndProcedure => write!(f, "Finish defining a procedure"),
+            Self::CallProcedure => write!(f, "Call a procedure"),
+            Self::Fork => write!(f, "Fork a new thread"),
+            Self::EndProgram => write!(f, "End the program"),
+            Self::Store => write!(f, "Store the current byte"),
+            Self::Retrieve => write!(f, "Retrieve the stored byte"),
+            Self::ShiftRight => write!(f, "Shift the current byte right"),
+            Self::ShiftLeft => write!(f, "Shift the current byte left"),
+            Self::Not => write!(f, "Invert the current byte"),
+            Self::Xor => write!(f, "Xor the current byte with the stored byte"),
+            Self::And => write!(f, "And the current byte with the stored byte"),
+            Self::Or => write!(f, "Or the current byte with the stored byte"),
+            Self::Random => write!(f, "Randomise the current byte"),
+            Self::NextTape => write!(f, "Switch to the next tape"),
+            Self::PreviousTape => write!(f, "Switch to the previous tape"),
+            Self::Halt => write!(f, "Halt with the current byte as exit status"),
+        }
+    }
+}
+
+/// Number of bytes checked at once for instructions and newlines while parsing.
+const SCAN_WORD: usize = 8;
+
+/// Whether any of the bytes in `word` is an instruction or a newline. This checks all of them at
+/// once, in the way `memchr` does, so that long comments are skipped quickly.
+#[inline]
+fn has_special_byte(word: [u8; SCAN_WORD]) -> bool {
+    const ONES: u64 = u64::from_ne_bytes([0x01; SCAN_WORD]);
+    const HIGHS: u64 = u64::from_ne_bytes([0x80; SCAN_WORD]);
+    let word = u64::from_ne_bytes(word);
+    // A byte of `word ^ (ONES * c)` is zero exactly where `word` has `c`, and subtracting one
+    // from a zero byte is the only way for a byte below 0x80 to set its high bit.
+    let has = |c: u8| {
+        let x = word ^ (ONES * u64::from(c));
+        x.wrapping_sub(ONES) & !x & HIGHS
+    };
+    (has(b'<')
+        | has(b'>')
+        | has(b'+')
+        | has(b'-')
+        | has(b',')
+        | has(b'.')
+        | has(b'[')
+        | has(b']')
+        | has(b'\n'))
+        != 0
+}
+
+/// Parse `data` as the start of a source, adding its instructions to `src`, and return the number
+/// of lines it ends.
+fn scan(data: &[u8], src: &mut Vec<InputInstruction>) -> usize {
+    // Technically we should split on b'\n', b'\r\n', or '\r'.
+    // b'\r\n' will leave a b'\r' at the end of the line, this will be consumed without issue.
+    // b'\r' was only used as a line terminator by Macs, pre OS X. We'll assume that this won't
+    // be an issue...
+    let mut line_number = 1;
+    let mut line_start = 0;
+    let mut scan_byte = |offset: usize, c: u8| {
+        if let Some(inst) = BYTE_INSTRUCTIONS[usize::from(c)] {
+            src.push(InputInstruction {
+                inst,
+                len: 1,
+                line_number: packed(line_number),
+                char_number: packed(offset - line_start + 1),
+                offset: packed(offset),
+            });
+        } else if c == b'\n' {
+            line_number += 1;
+            line_start = offset + 1;
         }
+    };
+    let (words, rest) = data.as_chunks::<SCAN_WORD>();
+    let mut offset = 0;
+    for word in words {
+        // Comments are skipped a word at a time.
+        if has_special_byte(*word) {
+            for (idx, c) in word.iter().enumerate() {
+                scan_byte(offset + idx, *c);
+            }
+        }
+        offset += SCAN_WORD;
+    }
+    for (idx, c) in rest.iter().enumerate() {
+        scan_byte(offset + idx, *c);
     }
+    line_number - 1
 }
 
+/// Least number of bytes in each piece of a source parsed in parallel.
+#[cfg(feature = "rayon")]
+const PARALLEL_PIECE: usize = 1 << 20;
+
+/// The instruction each byte stands for in standard Brainf*ck, if any, so that parsing takes a
+/// single lookup for each byte.
+const BYTE_INSTRUCTIONS: [Option<Instruction>; 256] = {
+    let mut table = [None; 256];
+    let mut byte = 0;
+    while byte < table.len() {
+        #[allow(clippy::cast_possible_truncation)]
+        let inst = Instruction::from_byte(byte as u8);
+        table[byte] = inst;
+        byte += 1;
+    }
+    table
+};
+
 impl Instruction {
-    fn from_byte(c: u8) -> Option<Self> {
+    const fn from_byte(c: u8) -> Option<Self> {
         match c {
             b'<' => Some(Instruction::MoveLeft),
             b'>' => Some(Instruction::MoveRight),
@@ -69,14 +296,211 @@ impl Instruction {
             _ => None,
         }
     }
+
+    /// The extension that must be enabled to use this instruction, if any.
+    #[must_use]
+    pub fn extension(&self) -> Option<Extension> {
+        match self {
+            Self::BeginProcedure | Self::EndProcedure | Self::CallProcedure => {
+                Some(Extension::Pbrain)
+            }
+            Self::Fork => Some(Extension::Brainfork),
+            Self::EndProgram
+            | Self::Store
+            | Self::Retrieve
+            | Self::ShiftRight
+            | Self::ShiftLeft
+            | Self::Not
+            | Self::Xor
+            | Self::And
+            | Self::Or => Some(Extension::Ext1),
+            Self::Random => Some(Extension::Random),
+            Self::NextTape | Self::PreviousTape => Some(Extension::Multitape),
+            Self::Halt => Some(Extension::Halt),
+            _ => None,
+        }
+    }
+}
+
+/// Opt-in additions to the standard Brainf*ck instruction set.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+pub enum Extension {
+    /// Procedures defined with `(` and `)`, and called with `:`.
+    Pbrain,
+
+    /// Threads started with `Y`.
+    Brainfork,
+
+    /// Extended Brainf*ck Type I: `@` to end the program, a storage register used by `$` and
+    /// `!`, and the bitwise operations `}`, `{`, `~`, `^`, `&` and `|`.
+    Ext1,
+
+    /// Random bytes written with `?`.
+    Random,
+
+    /// Several independent tapes, each with its own head, switched between with `^` and `_`.
+    Multitape,
+
+    /// Halting with `@`, which uses the current cell as the exit status. Can't be enabled along
+    /// with ext1, whose `@` ends the program without one.
+    Halt,
+}
+
+impl Extension {
+    /// Every extension that is available.
+    pub const ALL: [Extension; 6] = [
+        Extension::Pbrain,
+        Extension::Brainfork,
+        Extension::Ext1,
+        Extension::Random,
+        Extension::Multitape,
+        Extension::Halt,
+    ];
+
+    /// The name used to enable the extension.
+    #[must_use]
+    pub fn name(&self) -> &'static str {
+        match self {
+            Self::Pbrain => "pbrain",
+            Self::Brainfork => "brainfork",
+            Self::Ext1 => "ext1",
+            Self::Random => "random",
+            Self::Multitape => "multitape",
+            Self::Halt => "halt",
+        }
+    }
+
+    /// The characters the extension adds, along with the instructions they represent.
+    #[must_use]
+    pub fn tokens(&self) -> &'static [(u8, Instruction)] {
+        match self {
+            Self::Pbrain => &[
+                (b'(', Instruction::BeginProcedure),
+                (b')', Instruction::EndProcedure),
+                (b':', Instruction::CallProcedure),
+            ],
+            Self::Brainfork => &[(b'Y', Instruction::Fork)],
+            Self::Ext1 => &[
+                (b'@', Instruction::EndProgram),
+                (b'$', Instruction::Store),
+                (b'!', Instruction::Retrieve),
+                (b'}', Instruction::ShiftRight),
+                (b'{', Instruction::ShiftLeft),
+                (b'~', Instruction::Not),
+                (b'^', Instruction::Xor),
+                (b'&', Instruction::And),
+                (b'|', Instruction::Or),
+            ],
+            Self::Random => &[(b'?', Instruction::Random)],
+            Self::Multitape => &[
+                (b'^', Instruction::NextTape),
+                (b'_', Instruction::PreviousTape),
+            ],
+            Self::Halt => &[(b'@', Instruction::Halt)],
+        }
+    }
+
+    /// A token used by both this extension and `other`, which stops them being enabled
+    /// together.
+    #[must_use]
+    pub fn clash(&self, other: Extension) -> Option<u8> {
+        self.tokens()
+            .iter()
+            .map(|(c, _)| *c)
+            .find(|c| other.tokens().iter().any(|(t, _)| t == c))
+    }
+}
+
+impl Display for Extension {
+    fn fmt(&self, f: &mut Formatter) -> fmt::Result {
+        write!(f, "{}", self.name())
+    }
+}
+
+impl FromStr for Extension {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        Self::ALL
+            .into_iter()
+            .find(|ext| ext.name() == s)
+            .ok_or_else(|| format!("Unknown extension '{s}'"))
+    }
+}
+
+/// Longest source that can be parsed, in bytes.
+///
+/// Positions within the source are stored in 32 bits, which halves the size of each
+/// [`InputInstruction`], so that more of a program fits in the cache while it runs.
+pub const MAX_SOURCE_LEN: usize = u32::MAX as usize;
+
+/// `n`, a position within a source no longer than [`MAX_SOURCE_LEN`], packed into 32 bits.
+fn packed(n: usize) -> u32 {
+    u32::try_from(n).unwrap_or(u32::MAX)
+}
+
+/// Fail if `data` is too long to be parsed.
+fn check_source_len(data: &[u8]) {
+    assert!(
+        data.len() <= MAX_SOURCE_LEN,
+        "Sources can't be longer than {MAX_SOURCE_LEN} bytes"
+    );
+}
+
+/// Read the source in the file at `path`, failing if it's too long to be parsed.
+#[cfg(feature = "std")]
+fn read_source<P: AsRef<Path>>(path: P) -> io::Result<Vec<u8>> {
+    let data = read(path)?;
+    if data.len() > MAX_SOURCE_LEN {
+        return Err(io::Error::new(
+            io::ErrorKind::InvalidData,
+            format!("Sources can't be longer than {MAX_SOURCE_LEN} bytes"),
+        ));
+    }
+    Ok(data)
+}
+
+/// A position in a source file, as a line and a column, both starting at 1.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub struct Location {
+    /// The line, starting at 1.
+    pub line: usize,
+
+    /// The column within the line, starting at 1.
+    pub column: usize,
+}
+
+impl Display for Location {
+    fn fmt(&self, f: &mut Formatter) -> fmt::Result {
+        write!(f, "{}:{}", self.line, self.column)
+    }
+}
+
+impl FromStr for Location {
+    type Err = String;
+
+    /// Parse a location written as `LINE:COL`.
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let invalid = || format!("Invalid location '{s}', expected LINE:COL");
+        let (line, column) = s.split_once(':').ok_or_else(invalid)?;
+        let parse = |n: &str| n.trim().parse().ok().filter(|n| *n > 0).ok_or_else(invalid);
+        Ok(Location {
+            line: parse(line)?,
+            column: parse(column)?,
+        })
+    }
 }
 
 /// Annotated bytecode instructions for brainf*ck.
 #[derive(Clone, Copy, Debug, PartialEq)]
 pub struct InputInstruction {
     inst: Instruction,
-    line_number: usize,
-    char_number: usize,
+    /// Number of bytes of the token the instruction was written as. This fits in the space left
+    /// over after `inst`, so it doesn't make the instruction any bigger.
+    len: u16,
+    line_number: u32,
+    char_number: u32,
+    offset: u32,
 }
 
 impl InputInstruction {
@@ -91,23 +515,90 @@ impl InputInstruction {
     pub fn instruction(&self) -> &Instruction {
         &self.inst
     }
+
+    /// The line of the source file that the instruction came from, starting at 1.
+    #[must_use]
+    pub fn line_number(&self) -> usize {
+        self.line_number as usize
+    }
+
+    /// The column within the line that the instruction came from, starting at 1.
+    #[must_use]
+    pub fn char_number(&self) -> usize {
+        self.char_number as usize
+    }
+
+    /// Where the instruction is in the source file, as a line and column.
+    #[must_use]
+    pub fn position(&self) -> Location {
+        Location {
+            line: self.line_number(),
+            column: self.char_number(),
+        }
+    }
+
+    /// The byte offset within the source file of the start of the instruction.
+    #[must_use]
+    pub fn offset(&self) -> usize {
+        self.offset as usize
+    }
+
+    /// Number of bytes of the token the instruction was written as. This is one for standard
+    /// Brainf*ck, but longer in dialects with multi-character tokens, like Ook!. Tokens longer
+    /// than [`u16::MAX`] bytes, which can only come from long runs of whitespace within them,
+    /// are reported as that long.
+    #[must_use]
+    pub fn token_len(&self) -> usize {
+        usize::from(self.len)
+    }
+
+    /// The token the instruction was written as, exactly as it appears in `source`, which must be
+    /// the source the program was parsed from.
+    ///
+    /// ```
+    /// use bft_types::{Alphabet, BFprogram};
+    /// let source = b"Ook. Ook.\nOok! Ook.";
+    /// let program = BFprogram::with_alphabet("doc.test", source, &Alphabet::ook());
+    ///
+    /// assert_eq!(program.instructions()[1].token(source), b"Ook! Ook.");
+    /// ```
+    ///
+    /// # Panics
+    /// Panics if the token isn't within `source`.
+    #[must_use]
+    pub fn token<'a>(&self, source: &'a [u8]) -> &'a [u8] {
+        &source[self.offset()..self.offset() + self.token_len()]
+    }
 }
 
-/// Possibile errors during the bracket matching algorithm.
+/// Possibile errors during the bracket matching algorithm. Each holds the name of the program,
+/// and the line, column and byte offset of the bracket that caused the error.
 #[allow(dead_code)]
 #[derive(Debug, PartialEq)]
 pub enum BracketMatchError {
     /// An opening bracket never found a matching closing bracket.
-    ExtraOpeningBracket(PathBuf, usize, usize),
+    ExtraOpeningBracket(SourceName, usize, usize, usize),
 
     /// A closing bracket was found when all opening brackets were matched.
-    ExtraClosingBracket(PathBuf, usize, usize),
+    ExtraClosingBracket(SourceName, usize, usize, usize),
+
+    /// A procedure definition was never closed.
+    ExtraOpeningParen(SourceName, usize, usize, usize),
+
+    /// A procedure definition was closed when none was open.
+    ExtraClosingParen(SourceName, usize, usize, usize),
+
+    /// A procedure was defined inside another procedure.
+    NestedProcedure(SourceName, usize, usize, usize),
+
+    /// A loop or procedure was nested more deeply than allowed.
+    NestedTooDeeply(SourceName, usize, usize, usize),
 }
 
 impl Display for BracketMatchError {
     fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
         match &self {
-            Self::ExtraClosingBracket(source_name, line_number, char_number) => {
+            Self::ExtraClosingBracket(source_name, line_number, char_number, _) => {
                 write!(
                     f,
                     "Unexpected closing bracket ']' at [{}:{}:{}]",
@@ -116,7 +607,7 @@ impl Display for BracketMatchError {
                     char_number
                 )
             }
-            Self::ExtraOpeningBracket(source_name, line_number, char_number) => {
+            Self::ExtraOpeningBracket(source_name, line_number, char_number, _) => {
                 write!(
                     f,
                     "Unmatched bracket '[' at [{}:{}:{}]",
@@ -125,28 +616,163 @@ impl Display for BracketMatchError {
                     char_number
                 )
             }
+            Self::ExtraOpeningParen(source_name, line_number, char_number, _) => {
+                write!(
+                    f,
+                    "Unmatched procedure '(' at [{}:{}:{}]",
+                    source_name.display(),
+                    line_number,
+                    char_number
+                )
+            }
+            Self::ExtraClosingParen(source_name, line_number, char_number, _) => {
+                write!(
+                    f,
+                    "Unexpected end of procedure ')' at [{}:{}:{}]",
+                    source_name.display(),
+                    line_number,
+                    char_number
+                )
+            }
+            Self::NestedProcedure(source_name, line_number, char_number, _) => {
+                write!(
+                    f,
+                    "Procedure defined inside another procedure at [{}:{}:{}]",
+                    source_name.display(),
+                    line_number,
+                    char_number
+                )
+            }
+            Self::NestedTooDeeply(source_name, line_number, char_number, _) => {
+                write!(
+                    f,
+                    "Brackets nested too deeply at [{}:{}:{}]",
+                    source_name.display(),
+                    line_number,
+                    char_number
+                )
+            }
+        }
+    }
+}
+
+impl BracketMatchError {
+    /// The name of the program with the error.
+    #[must_use]
+    pub fn source_name(&self) -> &SourceName {
+        match self {
+            Self::ExtraOpeningBracket(source_name, ..)
+            | Self::ExtraClosingBracket(source_name, ..)
+            | Self::ExtraOpeningParen(source_name, ..)
+            | Self::ExtraClosingParen(source_name, ..)
+            | Self::NestedProcedure(source_name, ..)
+            | Self::NestedTooDeeply(source_name, ..) => source_name,
+        }
+    }
+
+    /// The line and column of the bracket that caused the error.
+    #[must_use]
+    pub fn location(&self) -> (usize, usize) {
+        match self {
+            Self::ExtraOpeningBracket(_, line_number, char_number, _)
+            | Self::ExtraClosingBracket(_, line_number, char_number, _)
+            | Self::ExtraOpeningParen(_, line_number, char_number, _)
+            | Self::ExtraClosingParen(_, line_number, char_number, _)
+            | Self::NestedProcedure(_, line_number, char_number, _)
+            | Self::NestedTooDeeply(_, line_number, char_number, _) => (*line_number, *char_number),
+        }
+    }
+
+    /// The byte offset within the source file of the bracket that caused the error.
+    #[must_use]
+    pub fn offset(&self) -> usize {
+        match self {
+            Self::ExtraOpeningBracket(.., offset)
+            | Self::ExtraClosingBracket(.., offset)
+            | Self::ExtraOpeningParen(.., offset)
+            | Self::ExtraClosingParen(.., offset)
+            | Self::NestedProcedure(.., offset)
+            | Self::NestedTooDeeply(.., offset) => *offset,
         }
     }
 }
 
 impl Error for BracketMatchError {}
 
+/// An instruction from an extension that hasn't been enabled.
+#[derive(Debug, PartialEq)]
+pub struct ExtensionError {
+    source_name: SourceName,
+    line_number: usize,
+    char_number: usize,
+    offset: usize,
+    extension: Extension,
+}
+
+impl ExtensionError {
+    /// The extension that needs to be enabled.
+    #[must_use]
+    pub fn extension(&self) -> Extension {
+        self.extension
+    }
+
+    /// The name of the program with the error.
+    #[must_use]
+    pub fn source_name(&self) -> &SourceName {
+        &self.source_name
+    }
+
+    /// The line and column of the instruction from the extension.
+    #[must_use]
+    pub fn location(&self) -> (usize, usize) {
+        (self.line_number, self.char_number)
+    }
+
+    /// The byte offset within the source file of the instruction from the extension.
+    #[must_use]
+    pub fn offset(&self) -> usize {
+        self.offset
+    }
+}
+
+impl Display for ExtensionError {
+    fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "Instruction from the '{}' extension, which isn't enabled, at [{}:{}:{}]",
+            self.extension,
+            self.source_name.display(),
+            self.line_number,
+            self.char_number
+        )
+    }
+}
+
+impl Error for ExtensionError {}
+
 /// A container to hold an entire Brainf*ck program.
-#[derive(Debug)]
+#[derive(Clone, Debug)]
 pub struct BFprogram {
-    source_name: PathBuf,
+    source_name: SourceName,
     src: Vec<InputInstruction>,
-    brackets: Vec<(usize, usize)>,
+    /// The index of the bracket matching each instruction, once they have been validated, or
+    /// [`NO_MATCH`] for instructions that aren't brackets.
+    brackets: Vec<u32>,
 }
 
+/// Marks an instruction without a matching bracket. No program is long enough for this to be
+/// the index of an instruction.
+const NO_MATCH: u32 = u32::MAX;
+
 impl BFprogram {
     /// Load data from a source file, and parse it into bytecode.
     ///
     /// # Errors
-    /// This function will return an error if opening the file fails, or if there is an error
-    /// reading the bytes within the file.
+    /// This function will return an error if opening the file fails, if there is an error
+    /// reading the bytes within the file, or if the file is longer than [`MAX_SOURCE_LEN`].
+    #[cfg(feature = "std")]
     pub fn from_file<P: AsRef<Path>>(file_name: P) -> io::Result<Self> {
-        let data = read(&file_name)?;
+        let data = read_source(&file_name)?;
         Ok(Self::new(file_name, &data))
     }
 
@@ -178,31 +804,259 @@ impl BFprogram {
     ///
     /// assert!(iter.next().is_none());
     /// ```
-    pub fn new<P: AsRef<Path>>(source_name: P, data: &[u8]) -> BFprogram {
+    ///
+    /// # Panics
+    /// Panics if `data` is longer than [`MAX_SOURCE_LEN`].
+    pub fn new<P: AsRef<SourcePath>>(source_name: P, data: &[u8]) -> BFprogram {
+        check_source_len(data);
+        let mut src = Vec::new();
+        scan(data, &mut src);
+
+        BFprogram {
+            source_name: SourceName::from(source_name.as_ref()),
+            src,
+            brackets: Vec::new(),
+        }
+    }
+
+    /// Parse Extended ASCII text into Brainf*ck bytecode like [`BFprogram::new`], but split into
+    /// pieces at the ends of lines, which are parsed in parallel. This is only faster for
+    /// sources of many megabytes.
+    ///
+    /// ```
+    /// use bft_types::BFprogram;
+    /// let code = b"+[\n->+<]\n".repeat(1000);
+    ///
+    /// assert_eq!(
+    ///     BFprogram::new_parallel("doc.test", &code).instructions(),
+    ///     BFprogram::new("doc.test", &code).instructions()
+    /// );
+    /// ```
+    ///
+    /// # Panics
+    /// Panics if `data` is longer than [`MAX_SOURCE_LEN`].
+    #[cfg(feature = "rayon")]
+    pub fn new_parallel<P: AsRef<SourcePath>>(source_name: P, data: &[u8]) -> BFprogram {
+        use rayon::prelude::*;
+
+        check_source_len(data);
+        let mut pieces = Vec::new();
+        let mut start = 0;
+        while start < data.len() {
+            let end = (start + PARALLEL_PIECE).min(data.len());
+            let end = data[end..]
+                .iter()
+                .position(|c| *c == b'\n')
+                .map_or(data.len(), |pos| end + pos + 1);
+            pieces.push(start..end);
+            start = end;
+        }
+        let mut parsed: Vec<_> = pieces
+            .par_iter()
+            .map(|piece| {
+                let mut src = Vec::new();
+                let lines = scan(&data[piece.clone()], &mut src);
+                (src, lines)
+            })
+            .collect();
+
+        // Each piece was parsed as if it were the start of the source, so its lines and offsets
+        // are moved past the pieces before it. Columns are right already, as pieces start lines.
+        let mut lines_before = 0;
+        let shifts: Vec<_> = pieces
+            .iter()
+            .zip(&parsed)
+            .map(|(piece, (_, lines))| {
+                let shift = (packed(lines_before), packed(piece.start));
+                lines_before += lines;
+                shift
+            })
+            .collect();
+        parsed
+            .par_iter_mut()
+            .zip(shifts)
+            .for_each(|((src, _), (lines, offset))| {
+                for inst in src {
+                    inst.line_number += lines;
+                    inst.offset += offset;
+                }
+            });
+        let mut src = Vec::with_capacity(parsed.iter().map(|(src, _)| src.len()).sum());
+        for (piece, _) in parsed {
+            src.extend(piece);
+        }
+
+        BFprogram {
+            source_name: SourceName::from(source_name.as_ref()),
+            src,
+            brackets: Vec::new(),
+        }
+    }
+
+    /// Load data from a source file, and parse it into bytecode using the tokens in `alphabet`.
+    ///
+    /// # Errors
+    /// This function will return an error if opening the file fails, if there is an error
+    /// reading the bytes within the file, or if the file is longer than [`MAX_SOURCE_LEN`].
+    #[cfg(feature = "std")]
+    pub fn from_file_with_alphabet<P: AsRef<Path>>(
+        file_name: P,
+        alphabet: &Alphabet,
+    ) -> io::Result<Self> {
+        let data = read_source(&file_name)?;
+        Ok(Self::with_alphabet(file_name, &data, alphabet))
+    }
+
+    /// Parse text into Brainf*ck bytecode, recognising the tokens in `alphabet` as instructions.
+    /// Everything else in the text is treated as a comment.
+    ///
+    /// # Panics
+    /// Panics if `data` is longer than [`MAX_SOURCE_LEN`].
+    #[must_use]
+    pub fn with_alphabet<P: AsRef<SourcePath>>(
+        source_name: P,
+        data: &[u8],
+        alphabet: &Alphabet,
+    ) -> BFprogram {
+        check_source_len(data);
         let mut src = Vec::new();
-        // Technically we should split on b'\n', b'\r\n', or '\r'.
-        // b'\r\n' will leave a b'\r' at the end of the line, this will be consumed without issue.
-        // b'\r' was only used as a line terminator by Macs, pre OS X. We'll assume that this won't
-        // be an issue...
-        for (line_number, line) in data.split(|c| *c == b'\n').enumerate() {
-            for (char_number, c) in line.iter().enumerate() {
-                if let Some(inst) = Instruction::from_byte(*c) {
+        let mut line_number = 1;
+        let mut char_number = 1;
+        let mut pos = 0;
+        while pos < data.len() {
+            let len = match alphabet.next_token(&data[pos..]) {
+                Some((inst, len)) => {
                     src.push(InputInstruction {
                         inst,
-                        line_number: line_number + 1,
-                        char_number: char_number + 1,
+                        len: u16::try_from(len).unwrap_or(u16::MAX),
+                        line_number: packed(line_number),
+                        char_number: packed(char_number),
+                        offset: packed(pos),
                     });
+                    len
+                }
+                None => 1,
+            };
+            for c in &data[pos..pos + len] {
+                if *c == b'\n' {
+                    line_number += 1;
+                    char_number = 1;
+                } else {
+                    char_number += 1;
                 }
             }
+            pos += len;
         }
 
         BFprogram {
-            source_name: PathBuf::from(source_name.as_ref()),
+            source_name: SourceName::from(source_name.as_ref()),
             src,
             brackets: Vec::new(),
         }
     }
 
+    /// Update the program for an edit to its source, which replaces the bytes in `range` of
+    /// `old_source` with `new_text`, without parsing the rest of the source again.
+    ///
+    /// `old_source` is the source the program was parsed from, before the edit. Only the edited
+    /// text is parsed, as standard Brainf*ck, and the positions of the instructions after it are
+    /// moved. If the program's brackets were validated and the edit doesn't add or remove any,
+    /// their matches are kept. Otherwise [`BFprogram::validate_brackets`] must be called again.
+    ///
+    /// ```
+    /// use bft_types::BFprogram;
+    /// let mut program = BFprogram::new("doc.test", b"+[-]\n.");
+    /// program.validate_brackets().expect("Brackets should match.");
+    /// program.apply_edit(b"+[-]\n.", 1..1, b"\n>>");
+    ///
+    /// assert_eq!(program.instructions().len(), 7);
+    /// assert_eq!(program.instructions()[3].location(), "2:3");
+    /// assert_eq!(program.instructions()[6].location(), "3:1");
+    /// assert_eq!(program.matching_bracket(3), Some(5));
+    /// ```
+    ///
+    /// # Panics
+    /// Panics if `range` isn't within `old_source`, or if the edited source is longer than
+    /// [`MAX_SOURCE_LEN`].
+    pub fn apply_edit(&mut self, old_source: &[u8], range: Range<usize>, new_text: &[u8]) {
+        let removed = &old_source[range.clone()];
+        assert!(
+            old_source.len() - removed.len() + new_text.len() <= MAX_SOURCE_LEN,
+            "Sources can't be longer than {MAX_SOURCE_LEN} bytes"
+        );
+        let first = self.src.partition_point(|inst| inst.offset() < range.start);
+        let last = self.src.partition_point(|inst| inst.offset() < range.end);
+
+        // Find the line and the start of the line that the edit starts on, counting from the
+        // instruction before it rather than from the start of the source.
+        let (mut line_number, mut line_start, scanned) = match first.checked_sub(1) {
+            Some(idx) => {
+                let inst = self.src[idx];
+                let line_start = inst.offset() + 1 - inst.char_number();
+                (inst.line_number(), line_start, inst.offset())
+            }
+            None => (1, 0, 0),
+        };
+        for (idx, c) in old_source[scanned..range.start].iter().enumerate() {
+            if *c == b'\n' {
+                line_number += 1;
+                line_start = scanned + idx + 1;
+            }
+        }
+
+        let mut inserted = Vec::new();
+        let inserted_lines = scan(new_text, &mut inserted);
+        for inst in &mut inserted {
+            if inst.line_number == 1 {
+                inst.char_number += packed(range.start - line_start);
+            }
+            inst.line_number += packed(line_number - 1);
+            inst.offset += packed(range.start);
+        }
+
+        // The instructions after the edit move by the lines added or removed, and those on the
+        // line it ends on also move along that line.
+        let last_newline = |text: &[u8]| text.iter().rposition(|c| *c == b'\n');
+        let removed_lines = removed.split(|c| *c == b'\n').count() - 1;
+        let old_end_line = line_number + removed_lines;
+        let new_end_line_start =
+            last_newline(new_text).map_or(line_start, |pos| range.start + pos + 1);
+        for inst in &mut self.src[last..] {
+            let offset = inst.offset() - removed.len() + new_text.len();
+            if inst.line_number() == old_end_line {
+                inst.char_number = packed(offset - new_end_line_start + 1);
+            }
+            inst.line_number = packed(inst.line_number() - removed_lines + inserted_lines);
+            inst.offset = packed(offset);
+        }
+
+        let is_bracket = |inst: &InputInstruction| {
+            matches!(
+                inst.inst,
+                Instruction::BeginLoop
+                    | Instruction::EndLoop
+                    | Instruction::BeginProcedure
+                    | Instruction::EndProcedure
+            )
+        };
+        let brackets_changed =
+            self.src[first..last].iter().any(is_bracket) || inserted.iter().any(is_bracket);
+        let inserted_len = inserted.len();
+        self.src.splice(first..last, inserted);
+
+        if brackets_changed {
+            self.brackets = Vec::new();
+        } else if !self.brackets.is_empty() {
+            self.brackets
+                .splice(first..last, iter::repeat_n(NO_MATCH, inserted_len));
+            for matched in &mut self.brackets {
+                if *matched != NO_MATCH && *matched as usize >= last {
+                    *matched = packed(*matched as usize - (last - first) + inserted_len);
+                }
+            }
+        }
+    }
+
     /// `instructions` allows us to access the underlying bytecode instructions.
     #[must_use]
     pub fn instructions(&self) -> &[InputInstruction] {
@@ -211,10 +1065,141 @@ impl BFprogram {
 
     /// get the name of the source file for the program.
     #[must_use]
-    pub fn source(&self) -> &PathBuf {
+    pub fn source(&self) -> &SourceName {
         &self.source_name
     }
 
+    /// Find the index of the bracket matching the one at `idx`.
+    ///
+    /// This only returns a value once [`BFprogram::validate_brackets`] has succeeded, and `idx`
+    /// refers to a `[` or `]` instruction.
+    ///
+    /// ```
+    /// use bft_types::BFprogram;
+    /// let code = Vec::from("+[>[-]<]");
+    /// let mut program = BFprogram::new("doc.test", &code);
+    /// program.validate_brackets().expect("Brackets should match.");
+    ///
+    /// assert_eq!(program.matching_bracket(1), Some(7));
+    /// assert_eq!(program.matching_bracket(3), Some(5));
+    /// assert_eq!(program.matching_bracket(5), Some(3));
+    /// assert_eq!(program.matching_bracket(0), None);
+    /// ```
+    #[must_use]
+    pub fn matching_bracket(&self, idx: usize) -> Option<usize> {
+        self.brackets
+            .get(idx)
+            .filter(|matched| **matched != NO_MATCH)
+            .map(|matched| *matched as usize)
+    }
+
+    /// Extract the innermost loop containing `at` as a program of its own, so that it can be run
+    /// and tested apart from the rest of the program. `at` may be anywhere from the loop's `[` to
+    /// its `]`, including in the comments within it. The instructions keep their locations in the
+    /// original source, so errors from running the loop point to where it came from.
+    ///
+    /// Returns `None` if no loop contains `at`, or if the program's brackets haven't been
+    /// validated.
+    ///
+    /// ```
+    /// use bft_types::{BFprogram, Location};
+    /// let mut program = BFprogram::new("doc.test", b"+[>++\n[-<+>]<-]");
+    /// program.validate_brackets().expect("Brackets should match.");
+    /// let inner = program
+    ///     .slice_loop(Location { line: 2, column: 3 })
+    ///     .expect("A loop should contain this location.");
+    ///
+    /// assert_eq!(inner.instructions().len(), 6);
+    /// assert_eq!(inner.instructions()[0].location(), "2:1");
+    /// assert_eq!(inner.matching_bracket(0), Some(5));
+    /// assert!(program.slice_loop(Location { line: 1, column: 1 }).is_none());
+    /// ```
+    #[must_use]
+    pub fn slice_loop(&self, at: Location) -> Option<BFprogram> {
+        let mut open = Vec::new();
+        for (idx, inst) in self.src.iter().enumerate() {
+            if inst.position() > at {
+                break;
+            }
+            match inst.inst {
+                Instruction::BeginLoop => open.push(idx),
+                Instruction::EndLoop if inst.position() == at => {
+                    open.push(self.matching_bracket(idx)?);
+                    break;
+                }
+                Instruction::EndLoop => {
+                    open.pop();
+                }
+                _ => {}
+            }
+        }
+        let start = *open.last()?;
+        let end = self.matching_bracket(start)?;
+        Some(BFprogram {
+            source_name: self.source_name.clone(),
+            src: self.src[start..=end].to_vec(),
+            brackets: self.brackets[start..=end]
+                .iter()
+                .map(|matched| match *matched {
+                    NO_MATCH => NO_MATCH,
+                    matched => matched - packed(start),
+                })
+                .collect(),
+        })
+    }
+
+    /// Validate the program by ensuring that it only uses instructions from the standard set, or
+    /// from one of the `enabled` extensions.
+    ///
+    /// # Errors
+    /// Returns an error for the first instruction that needs an extension which isn't enabled.
+    ///
+    /// ```
+    /// use bft_types::{Alphabet, BFprogram, Extension};
+    /// let mut alphabet = Alphabet::default();
+    /// alphabet.add_extension(Extension::Ext1).expect("Extension should be valid.");
+    /// let program = BFprogram::with_alphabet("doc.test", b"+$>!@", &alphabet);
+    ///
+    /// assert!(program.validate_extensions(&[Extension::Ext1]).is_ok());
+    /// assert!(program.validate_extensions(&[Extension::Pbrain]).is_err());
+    /// ```
+    pub fn validate_extensions(&self, enabled: &[Extension]) -> Result<(), ExtensionError> {
+        match self.disallowed_instructions(enabled).into_iter().next() {
+            Some(err) => Err(err),
+            None => Ok(()),
+        }
+    }
+
+    /// Find every instruction that needs an extension which isn't one of the `enabled` ones, in
+    /// the order they appear. Unlike [`BFprogram::validate_extensions`], this doesn't stop at the
+    /// first one, for tools such as editors that report every problem at once.
+    ///
+    /// ```
+    /// use bft_types::{Alphabet, BFprogram, Extension};
+    /// let mut alphabet = Alphabet::default();
+    /// alphabet.add_extension(Extension::Ext1).expect("Extension should be valid.");
+    /// let program = BFprogram::with_alphabet("doc.test", b"+$>!@", &alphabet);
+    ///
+    /// assert_eq!(program.disallowed_instructions(&[]).len(), 3);
+    /// assert!(program.disallowed_instructions(&[Extension::Ext1]).is_empty());
+    /// ```
+    #[must_use]
+    pub fn disallowed_instructions(&self, enabled: &[Extension]) -> Vec<ExtensionError> {
+        self.src
+            .iter()
+            .filter_map(|inst| {
+                let extension = inst.inst.extension()?;
+                (!enabled.contains(&extension)).then(|| ExtensionError {
+                    source_name: self.source_name.clone(),
+                    line_number: inst.line_number(),
+                    char_number: inst.char_number(),
+                    offset: inst.offset(),
+                    extension,
+                })
+            })
+            .collect()
+    }
+
     /// Validate the program by ensuring that the brackets match.
     ///
     /// # Errors
@@ -229,47 +1214,143 @@ impl BFprogram {
     /// assert!(program.validate_brackets().is_ok());
     /// ```
     pub fn validate_brackets(&mut self) -> Result<(), BracketMatchError> {
+        self.validate_brackets_with_max_depth(usize::MAX)
+    }
+
+    /// Validate the program by ensuring that the brackets match, and that loops and procedures
+    /// are nested no more than `max_depth` deep. Limiting the depth protects tools that work
+    /// through the nesting from programs written to exhaust them.
+    ///
+    /// # Errors
+    /// Returns an error for the first bracket without a match, or the first one nested too
+    /// deeply.
+    ///
+    /// ```
+    /// use bft_types::BFprogram;
+    /// let mut program = BFprogram::new("doc.test", b"[[-]]");
+    ///
+    /// assert!(program.validate_brackets_with_max_depth(1).is_err());
+    /// assert!(program.validate_brackets_with_max_depth(2).is_ok());
+    /// ```
+    pub fn validate_brackets_with_max_depth(
+        &mut self,
+        max_depth: usize,
+    ) -> Result<(), BracketMatchError> {
+        let (brackets, errors) = self.match_brackets(max_depth, false);
+        if let Some(err) = errors.into_iter().next() {
+            return Err(err);
+        }
+        self.brackets = brackets;
+        Ok(())
+    }
+
+    /// Match as many of the program's brackets as possible, returning an error for each one
+    /// that can't be matched, in the order they appear. This is for tools such as editors that
+    /// work on programs while they are being written, which are often broken.
+    ///
+    /// Afterwards, [`BFprogram::matching_bracket`] finds the brackets that did match. The program
+    /// can only be run if there were no errors.
+    ///
+    /// ```
+    /// use bft_types::BFprogram;
+    /// let mut program = BFprogram::new("doc.test", b"]+[>[-]");
+    /// let errors = program.match_brackets_partially();
+    ///
+    /// assert_eq!(errors.len(), 2);
+    /// assert_eq!(errors[0].location(), (1, 1));
+    /// assert_eq!(errors[1].location(), (1, 3));
+    /// assert_eq!(program.matching_bracket(4), Some(6));
+    /// ```
+    pub fn match_brackets_partially(&mut self) -> Vec<BracketMatchError> {
+        let (brackets, errors) = self.match_brackets(usize::MAX, true);
+        self.brackets = brackets;
+        errors
+    }
+
+    /// Match the program's brackets, nesting them no more than `max_depth` deep. Returns the
+    /// index of the bracket matching each instruction, and the errors found. Unless `recover` is
+    /// set, this stops at the first error.
+    fn match_brackets(
+        &self,
+        max_depth: usize,
+        recover: bool,
+    ) -> (Vec<u32>, Vec<BracketMatchError>) {
+        type Kind = fn(SourceName, usize, usize, usize) -> BracketMatchError;
+        let error = |kind: Kind, inst: &InputInstruction| {
+            kind(
+                self.source_name.clone(),
+                inst.line_number(),
+                inst.char_number(),
+                inst.offset(),
+            )
+        };
         let mut stack: Vec<usize> = Vec::new();
-        let mut brackets: Vec<(usize, usize)> = Vec::new();
+        let mut brackets = vec![NO_MATCH; self.src.len()];
+        let mut errors = Vec::new();
+        let mut procedures = 0usize;
 
         for (idx, inst) in self.src.iter().enumerate() {
+            let opening = |matched: &usize| *self.src[*matched].instruction();
             match *inst.instruction() {
-                Instruction::BeginLoop => {
+                Instruction::BeginLoop | Instruction::BeginProcedure
+                    if stack.len() == max_depth =>
+                {
+                    errors.push(error(BracketMatchError::NestedTooDeeply, inst));
+                }
+                Instruction::BeginLoop => stack.push(idx),
+                Instruction::BeginProcedure => {
+                    if procedures > 0 {
+                        errors.push(error(BracketMatchError::NestedProcedure, inst));
+                    }
+                    procedures += 1;
                     stack.push(idx);
                 }
-                Instruction::EndLoop => match stack.pop() {
-                    Some(matched_bracket) => {
-                        brackets.push((matched_bracket, idx));
+                Instruction::EndLoop => match stack.last() {
+                    Some(matched) if opening(matched) == Instruction::BeginLoop => {
+                        brackets[*matched] = packed(idx);
+                        brackets[idx] = packed(*matched);
+                        stack.pop();
                     }
-                    _ => {
-                        return Err(BracketMatchError::ExtraClosingBracket(
-                            self.source_name.clone(),
-                            inst.line_number,
-                            inst.char_number,
-                        ));
+                    _ => errors.push(error(BracketMatchError::ExtraClosingBracket, inst)),
+                },
+                Instruction::EndProcedure => match stack.last() {
+                    Some(matched) if opening(matched) == Instruction::BeginProcedure => {
+                        procedures -= 1;
+                        brackets[*matched] = packed(idx);
+                        brackets[idx] = packed(*matched);
+                        stack.pop();
                     }
+                    _ => errors.push(error(BracketMatchError::ExtraClosingParen, inst)),
                 },
                 _ => {}
             }
+            if !recover && !errors.is_empty() {
+                return (brackets, errors);
+            }
         }
 
-        if let Some(idx) = stack.pop() {
-            let inst = self.src[idx];
-            Err(BracketMatchError::ExtraOpeningBracket(
-                self.source_name.clone(),
-                inst.line_number,
-                inst.char_number,
-            ))
+        // Without recovering, only the innermost bracket left open is reported.
+        let unmatched = if recover {
+            stack.as_slice()
         } else {
-            self.brackets = brackets;
-            Ok(())
+            &stack[stack.len().saturating_sub(1)..]
+        };
+        for idx in unmatched {
+            let inst = &self.src[*idx];
+            errors.push(if *inst.instruction() == Instruction::BeginProcedure {
+                error(BracketMatchError::ExtraOpeningParen, inst)
+            } else {
+                error(BracketMatchError::ExtraOpeningBracket, inst)
+            });
         }
+        (brackets, errors)
     }
 }
 
 #[cfg(test)]
 mod tests {
     use super::*;
+    use alloc::string::ToString;
 
     #[test]
     fn instruction_display() {
@@ -320,12 +1401,101 @@ mod tests {
         }
     }
 
+    #[test]
+    fn positions_across_scanned_words() {
+        let data = b"comment\n  +  \nno instructions in these sixteen\n\n>>abcdefghijk<\r\n.";
+        let program = BFprogram::new("mod.test", data);
+        let positions: Vec<_> = program
+            .instructions()
+            .iter()
+            .map(|inst| {
+                (
+                    inst.inst,
+                    inst.line_number(),
+                    inst.char_number(),
+                    inst.offset(),
+                )
+            })
+            .collect();
+        assert_eq!(
+            positions,
+            [
+                (Instruction::Increment, 2, 3, 10),
+                (Instruction::MoveRight, 5, 1, 48),
+                (Instruction::MoveRight, 5, 2, 49),
+                (Instruction::MoveLeft, 5, 14, 61),
+                (Instruction::Output, 6, 1, 64),
+            ]
+        );
+    }
+
+    #[cfg(feature = "rayon")]
+    #[test]
+    fn parallel_parsing() {
+        let mut code = b"Add [->+<]\r\n".repeat(PARALLEL_PIECE / 4);
+        code.extend_from_slice(b"\n\n  no newline at the end .");
+        let parallel = BFprogram::new_parallel("mod.test", &code);
+        assert_eq!(
+            parallel.instructions(),
+            BFprogram::new("mod.test", &code).instructions()
+        );
+        assert_eq!(
+            parallel.instructions().last().unwrap().location(),
+            "262147:25"
+        );
+    }
+
+    #[test]
+    fn incremental_edits() {
+        let source = b"++[>+ comment\n<-]\r\n>.  [-]\n,";
+        let edits: [(Range<usize>, &[u8]); 9] = [
+            (0..0, b"+"),
+            (3..3, b"\n\n"),
+            (5..13, b""),
+            (4..16, b"x\ny>"),
+            (13..14, b" "),
+            (17..17, b"[]"),
+            (19..27, b"."),
+            (26..27, b"\n<\n"),
+            (0..source.len(), b"-"),
+        ];
+        for (range, new_text) in edits {
+            let mut edited = source.to_vec();
+            edited.splice(range.clone(), new_text.iter().copied());
+            let mut expected = BFprogram::new("mod.test", &edited);
+            expected.validate_brackets().unwrap();
+
+            let mut program = BFprogram::new("mod.test", source);
+            program.validate_brackets().unwrap();
+            program.apply_edit(source, range.clone(), new_text);
+            assert_eq!(
+                program.instructions(),
+                expected.instructions(),
+                "{range:?} {new_text:?}"
+            );
+            if program.brackets.is_empty() {
+                program.validate_brackets().unwrap();
+            }
+            assert_eq!(
+                program.brackets, expected.brackets,
+                "{range:?} {new_text:?}"
+            );
+        }
+    }
+
+    #[test]
+    fn compact_instructions() {
+        assert_eq!(core::mem::size_of::<InputInstruction>(), 16);
+    }
+
     #[test]
     fn location() {
         let inst = InputInstruction {
             inst: Instruction::Increment,
+            len: 1,
             line_number: 100,
             char_number: 42,
+            offset: 4000,
         };
         assert_eq!(inst.location(), "100:42");
     }
@@ -335,14 +1505,14 @@ mod tests {
         assert_eq!(
             format!(
                 "{}",
-                BracketMatchError::ExtraOpeningBracket("mod.test".into(), 12, 45)
+                BracketMatchError::ExtraOpeningBracket("mod.test".into(), 12, 45, 600)
             ),
             "Unmatched bracket '[' at [mod.test:12:45]"
         );
         assert_eq!(
             format!(
                 "{}",
-                BracketMatchError::ExtraClosingBracket("mod.test".into(), 42, 78)
+                BracketMatchError::ExtraClosingBracket("mod.test".into(), 42, 78, 2000)
             ),
             "Unexpected closing bracket ']' at [mod.test:42:78]"
         );
@@ -364,7 +1534,8 @@ mod tests {
             Err(BracketMatchError::ExtraClosingBracket(
                 "mod.test".into(),
                 1,
-                9
+                9,
+                8
             ))
         );
     }
@@ -378,7 +1549,8 @@ mod tests {
             Err(BracketMatchError::ExtraOpeningBracket(
                 "mod.test".into(),
                 1,
-                1
+                1,
+                0
             ))
         );
     }
@@ -392,12 +1564,228 @@ mod tests {
             Err(BracketMatchError::ExtraClosingBracket(
                 "mod.test".into(),
                 1,
-                5
+                5,
+                4
             ))
         );
     }
 
     #[test]
+    fn custom_alphabet() {
+        let alphabet = Alphabet::new([
+            (Instruction::Increment, "Ook. Ook."),
+            (Instruction::Output, "Ook! Ook."),
+            (Instruction::BeginLoop, "Ook! Ook?"),
+            (Instruction::EndLoop, "Ook? Ook!"),
+        ])
+        .unwrap();
+        let code = Vec::from("Ook. Ook. Ook! Ook?\nOok?\nOok! x Ook! Ook.");
+        let mut program = BFprogram::with_alphabet("mod.test", &code, &alphabet);
+        assert!(program.validate_brackets().is_ok());
+        let found: Vec<(Instruction, String)> = program
+            .instructions()
+            .iter()
+            .map(|i| (i.inst, i.location()))
+            .collect();
+        assert_eq!(
+            found,
+            vec![
+                (Instruction::Increment, String::from("1:1")),
+                (Instruction::BeginLoop, String::from("1:11")),
+                (Instruction::EndLoop, String::from("2:1")),
+                (Instruction::Output, String::from("3:8")),
+            ]
+        );
+        let offsets: Vec<usize> = program
+            .instructions()
+            .iter()
+            .map(InputInstruction::offset)
+            .collect();
+        assert_eq!(offsets, [0, 10, 20, 32]);
+        let program = BFprogram::new("mod.test", b"+\n\n  [");
+        assert_eq!(program.instructions()[1].offset(), 5);
+    }
+
+    #[test]
+    fn default_alphabet_matches_new() {
+        let code = Vec::from("+[->,<]\n comment .");
+        assert_eq!(
+            BFprogram::with_alphabet("mod.test", &code, &Alphabet::default()).src,
+            BFprogram::new("mod.test", &code).src
+        );
+    }
+
+    fn pbrain_program(code: &str) -> BFprogram {
+        let mut alphabet = Alphabet::default();
+        alphabet.add_extension(Extension::Pbrain).unwrap();
+        BFprogram::with_alphabet("mod.test", code.as_bytes(), &alphabet)
+    }
+
+    #[test]
+    fn extension_names() {
+        for ext in Extension::ALL {
+            assert_eq!(ext.name().parse::<Extension>(), Ok(ext));
+        }
+        assert_eq!(
+            "nope".parse::<Extension>(),
+            Err(String::from("Unknown extension 'nope'"))
+        );
+    }
+
+    #[test]
+    fn extension_validation() {
+        let mut alphabet = Alphabet::default();
+        alphabet.add_extension(Extension::Ext1).unwrap();
+        let program = BFprogram::with_alphabet("mod.test", b"+\n>~", &alphabet);
+        assert_eq!(
+            program.instructions()[2].instruction().extension(),
+            Some(Extension::Ext1)
+        );
+        let err = program.validate_extensions(&[]).unwrap_err();
+        assert_eq!(err.extension(), Extension::Ext1);
+        assert_eq!(
+            err.to_string(),
+            "Instruction from the 'ext1' extension, which isn't enabled, at [mod.test:2:2]"
+        );
+        assert!(BFprogram::new("mod.test", b"+@")
+            .validate_extensions(&[])
+            .is_ok());
+    }
+
+    #[test]
+    fn procedures() {
+        let mut program = pbrain_program("+([-]):");
+        assert!(program.validate_brackets().is_ok());
+        assert_eq!(program.matching_bracket(1), Some(5));
+        assert_eq!(
+            program.instructions()[6].instruction().extension(),
+            Some(Extension::Pbrain)
+        );
+
+        // Without the extension, the parentheses are comments.
+        assert_eq!(
+            BFprogram::new("mod.test", b"+([-]):").instructions().len(),
+            4
+        );
+    }
+
+    #[test]
+    fn procedure_nesting() {
+        assert_eq!(
+            pbrain_program("(()").validate_brackets(),
+            Err(BracketMatchError::NestedProcedure(
+                "mod.test".into(),
+                1,
+                2,
+                1
+            ))
+        );
+        assert_eq!(
+            pbrain_program("([)]").validate_brackets(),
+            Err(BracketMatchError::ExtraClosingParen(
+                "mod.test".into(),
+                1,
+                3,
+                2
+            ))
+        );
+        assert_eq!(
+            pbrain_program("[(])").validate_brackets(),
+            Err(BracketMatchError::ExtraClosingBracket(
+                "mod.test".into(),
+                1,
+                3,
+                2
+            ))
+        );
+        assert_eq!(
+            pbrain_program("+(").validate_brackets(),
+            Err(BracketMatchError::ExtraOpeningParen(
+                "mod.test".into(),
+                1,
+                2,
+                1
+            ))
+        );
+        assert_eq!(
+            format!(
+                "{}",
+                BracketMatchError::NestedProcedure("mod.test".into(), 4, 2, 10)
+            ),
+            "Procedure defined inside another procedure at [mod.test:4:2]"
+        );
+    }
+
+    #[test]
+    fn nesting_limit() {
+        let mut program = BFprogram::new("mod.test", b"+[>[-]\n<[>[-]<-]]");
+        assert_eq!(
+            program.validate_brackets_with_max_depth(2),
+            Err(BracketMatchError::NestedTooDeeply(
+                "mod.test".into(),
+                2,
+                4,
+                10
+            ))
+        );
+        assert!(program.validate_brackets_with_max_depth(3).is_ok());
+        assert_eq!(program.matching_bracket(1), Some(15));
+
+        let depth = 500_000;
+        let code = "[".repeat(depth) + &"]".repeat(depth);
+        let mut program = BFprogram::new("mod.test", code.as_bytes());
+        assert!(program.validate_brackets().is_ok());
+        assert!(program.validate_brackets_with_max_depth(depth - 1).is_err());
+    }
+
+    #[test]
+    fn slicing_loops() {
+        let at = |line, column| Location { line, column };
+        let mut program = BFprogram::new("mod.test", b"+[ outer\n>[-] inner\n<-]");
+        assert!(program.slice_loop(at(1, 2)).is_none());
+        program.validate_brackets().unwrap();
+        let sliced = |location| program.slice_loop(location).map(|p| p.instructions().len());
+        assert_eq!(sliced(at(1, 2)), Some(8));
+        // Comments are inside the loop around them.
+        assert_eq!(sliced(at(1, 5)), Some(8));
+        assert_eq!(sliced(at(2, 3)), Some(3));
+        assert_eq!(sliced(at(2, 4)), Some(3));
+        assert_eq!(sliced(at(2, 6)), Some(8));
+        assert_eq!(sliced(at(3, 3)), Some(8));
+        assert_eq!(sliced(at(3, 4)), None);
+        assert_eq!(sliced(at(1, 1)), None);
+
+        assert_eq!("12:3".parse(), Ok(at(12, 3)));
+        assert!("12".parse::<Location>().is_err());
+        assert!("0:3".parse::<Location>().is_err());
+        assert_eq!(at(2, 10).to_string(), "2:10");
+    }
+
+    #[test]
+    fn partial_matching() {
+        let mut alphabet = Alphabet::default();
+        alphabet.add_extension(Extension::Pbrain).unwrap();
+        let mut program = BFprogram::with_alphabet("mod.test", b"([)(-])]", &alphabet);
+        let errors = program.match_brackets_partially();
+        assert_eq!(
+            errors,
+            [
+                BracketMatchError::ExtraClosingParen("mod.test".into(), 1, 3, 2),
+                BracketMatchError::NestedProcedure("mod.test".into(), 1, 4, 3),
+                BracketMatchError::ExtraClosingBracket("mod.test".into(), 1, 6, 5),
+                BracketMatchError::ExtraOpeningParen("mod.test".into(), 1, 1, 0),
+            ]
+        );
+        assert_eq!(program.matching_bracket(1), Some(7));
+        assert_eq!(program.matching_bracket(3), Some(6));
+        assert_eq!(program.matching_bracket(0), None);
+        // Without recovering, the first error is the same.
+        let first = errors.into_iter().next();
+        assert_eq!(program.validate_brackets().err(), first);
+    }
+
+    #[test]
+    #[cfg(feature = "std")]
     fn loading_from_file() {
         let file_name: PathBuf = PathBuf::from("../data/session1.txt");
         let program = BFprogram::from_file(file_name).expect("Program should load.");
@@ -408,8 +1796,10 @@ mod tests {
             inst,
             Some(&InputInstruction {
                 inst: Instruction::Increment,
+                len: 1,
                 line_number: 8,
-                char_number: 4
+                char_number: 4,
+                offset: 142,
             })
         );
     }
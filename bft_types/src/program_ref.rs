@@ -0,0 +1,178 @@
+//! A view of a program that borrows its source, for embedders that already hold the text in
+//! memory and would rather not build a [`BFprogram`] each time they look at it.
+
+use alloc::vec::Vec;
+
+use crate::{
+    check_source_len, packed, BFprogram, BracketMatchError, InputInstruction, Instruction,
+    SourceName, SourcePath, BYTE_INSTRUCTIONS,
+};
+
+/// A standard Brainf*ck program, borrowed from the buffer holding its source.
+///
+/// Nothing is parsed until the instructions are asked for, and then they are parsed as they are
+/// iterated over, without allocating.
+///
+/// ```
+/// use bft_types::{BFprogramRef, Instruction};
+/// let code = String::from("+[-] read ,");
+/// let program = BFprogramRef::new("doc.test", code.as_bytes());
+///
+/// assert_eq!(program.instructions().count(), 5);
+/// assert_eq!(
+///     program.instructions().last().map(|inst| *inst.instruction()),
+///     Some(Instruction::Input)
+/// );
+/// assert!(program.validate_brackets().is_ok());
+/// ```
+#[derive(Clone, Copy, Debug)]
+pub struct BFprogramRef<'a> {
+    source_name: &'a SourcePath,
+    data: &'a [u8],
+}
+
+impl<'a> BFprogramRef<'a> {
+    /// View `data` as a program, with the name `source_name`.
+    ///
+    /// # Panics
+    /// Panics if `data` is longer than [`MAX_SOURCE_LEN`](crate::MAX_SOURCE_LEN).
+    #[must_use]
+    pub fn new<P: AsRef<SourcePath> + ?Sized>(source_name: &'a P, data: &'a [u8]) -> Self {
+        check_source_len(data);
+        BFprogramRef {
+            source_name: source_name.as_ref(),
+            data,
+        }
+    }
+
+    /// The name of the source of the program.
+    #[must_use]
+    pub fn source_name(&self) -> &'a SourcePath {
+        self.source_name
+    }
+
+    /// The text of the program.
+    #[must_use]
+    pub fn text(&self) -> &'a [u8] {
+        self.data
+    }
+
+    /// The program's instructions, parsed as they are iterated over.
+    #[must_use]
+    pub fn instructions(&self) -> Instructions<'a> {
+        Instructions {
+            data: self.data,
+            offset: 0,
+            line_number: 1,
+            line_start: 0,
+        }
+    }
+
+    /// Validate the program by ensuring that the brackets match, which only allocates to hold
+    /// the brackets that are open.
+    ///
+    /// # Errors
+    /// Returns an error for the first bracket without a match.
+    pub fn validate_brackets(&self) -> Result<(), BracketMatchError> {
+        let error = |inst: InputInstruction, make: fn(_, _, _, _) -> BracketMatchError| {
+            make(
+                SourceName::from(self.source_name),
+                inst.line_number(),
+                inst.char_number(),
+                inst.offset(),
+            )
+        };
+        let mut open = Vec::new();
+        for inst in self.instructions() {
+            match inst.inst {
+                Instruction::BeginLoop => open.push(inst),
+                Instruction::EndLoop if open.pop().is_none() => {
+                    return Err(error(inst, BracketMatchError::ExtraClosingBracket));
+                }
+                _ => {}
+            }
+        }
+        match open.pop() {
+            Some(inst) => Err(error(inst, BracketMatchError::ExtraOpeningBracket)),
+            None => Ok(()),
+        }
+    }
+
+    /// Parse the program into a [`BFprogram`], which can be run.
+    #[must_use]
+    pub fn to_program(&self) -> BFprogram {
+        BFprogram::new(self.source_name, self.data)
+    }
+}
+
+/// The instructions in a [`BFprogramRef`], parsed as they are iterated over.
+#[derive(Clone, Debug)]
+pub struct Instructions<'a> {
+    data: &'a [u8],
+
+    /// Offset of the next byte to parse.
+    offset: usize,
+    line_number: usize,
+    line_start: usize,
+}
+
+impl Iterator for Instructions<'_> {
+    type Item = InputInstruction;
+
+    fn next(&mut self) -> Option<InputInstruction> {
+        while let Some(c) = self.data.get(self.offset) {
+            let offset = self.offset;
+            self.offset += 1;
+            if let Some(inst) = BYTE_INSTRUCTIONS[usize::from(*c)] {
+                return Some(InputInstruction {
+                    inst,
+                    len: 1,
+                    line_number: packed(self.line_number),
+                    char_number: packed(offset - self.line_start + 1),
+                    offset: packed(offset),
+                });
+            }
+            if *c == b'\n' {
+                self.line_number += 1;
+                self.line_start = offset + 1;
+            }
+        }
+        None
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn matches_owned_program() {
+        let code = b"Add [->+<]\r\n and print .\n\n,";
+        let program = BFprogramRef::new("mod.test", code);
+        assert!(program
+            .instructions()
+            .eq(program.to_program().instructions().iter().copied()));
+    }
+
+    #[test]
+    fn unmatched_brackets() {
+        assert_eq!(
+            BFprogramRef::new("mod.test", b"[\n[]").validate_brackets(),
+            Err(BracketMatchError::ExtraOpeningBracket(
+                "mod.test".into(),
+                1,
+                1,
+                0
+            ))
+        );
+        assert_eq!(
+            BFprogramRef::new("mod.test", b"+]").validate_brackets(),
+            Err(BracketMatchError::ExtraClosingBracket(
+                "mod.test".into(),
+                1,
+                2,
+                1
+            ))
+        );
+    }
+}
@@ -0,0 +1,22 @@
+//! Parse arbitrary bytes as a program and match its brackets, which should fail cleanly rather
+//! than panic, however the brackets are nested.
+
+#![no_main]
+
+use bft_types::{BFprogram, Instruction};
+use libfuzzer_sys::fuzz_target;
+
+fuzz_target!(|data: &[u8]| {
+    let mut program = BFprogram::new("fuzz.b", data);
+    if program.validate_brackets().is_ok() {
+        for (idx, inst) in program.instructions().iter().enumerate() {
+            if matches!(
+                inst.instruction(),
+                Instruction::BeginLoop | Instruction::EndLoop
+            ) {
+                let other = program.matching_bracket(idx).unwrap();
+                assert_eq!(program.matching_bracket(other), Some(idx));
+            }
+        }
+    }
+});
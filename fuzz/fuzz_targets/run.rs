@@ -0,0 +1,48 @@
+//! Run generated programs with balanced brackets under strict limits, so that any panic,
+//! overflow or hang in the VM is found. Errors like moving the head off the tape are expected.
+
+#![no_main]
+
+use std::num::NonZeroUsize;
+
+use bft_interp::{SandboxLimits, BFVM};
+use bft_types::BFprogram;
+use libfuzzer_sys::fuzz_target;
+
+/// The limits each program runs under, which keep every run short.
+const LIMITS: SandboxLimits = SandboxLimits {
+    max_steps: Some(100_000),
+    max_memory: Some(1024),
+    max_output: Some(4096),
+    max_input: Some(4096),
+    allow_extensions: false,
+};
+
+/// Map each byte of `data` to one of the eight instructions, dropping each `]` without a `[` and
+/// closing any loops left open, so that the brackets always match.
+fn program(data: &[u8]) -> Vec<u8> {
+    let mut code = Vec::with_capacity(data.len());
+    let mut depth = 0usize;
+    for byte in data {
+        let inst = b"+-<>[].,"[usize::from(byte % 8)];
+        match inst {
+            b'[' => depth += 1,
+            b']' if depth == 0 => continue,
+            b']' => depth -= 1,
+            _ => {}
+        }
+        code.push(inst);
+    }
+    code.extend(std::iter::repeat_n(b']', depth));
+    code
+}
+
+fuzz_target!(|data: &[u8]| {
+    let mut program = BFprogram::new("fuzz.b", &program(data));
+    program
+        .validate_brackets()
+        .expect("generated programs have balanced brackets");
+    let mut vm: BFVM<u8> = BFVM::new(NonZeroUsize::new(16), true).with_sandbox(LIMITS);
+    let mut input = data;
+    let _ = vm.run(&program, &mut input, &mut Vec::new());
+});
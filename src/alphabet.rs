@@ -0,0 +1,128 @@
+//! Loading custom instruction alphabets from TOML mapping files.
+//!
+//! A mapping file gives the token, or list of tokens, for each of the eight instructions:
+//!
+//! ```toml
+//! move_left = "Ook? Ook."
+//! move_right = "Ook. Ook?"
+//! increment = "Ook. Ook."
+//! decrement = "Ook! Ook!"
+//! input = "Ook. Ook!"
+//! output = "Ook! Ook."
+//! begin_loop = "Ook! Ook?"
+//! end_loop = ["Ook? Ook!"]
+//! ```
+
+use std::error::Error;
+use std::path::Path;
+
+use bft_types::{Alphabet, Instruction};
+
+/// The key used for each instruction in a mapping file.
+const KEYS: [(&str, Instruction); 8] = [
+    ("move_left", Instruction::MoveLeft),
+    ("move_right", Instruction::MoveRight),
+    ("increment", Instruction::Increment),
+    ("decrement", Instruction::Decrement),
+    ("input", Instruction::Input),
+    ("output", Instruction::Output),
+    ("begin_loop", Instruction::BeginLoop),
+    ("end_loop", Instruction::EndLoop),
+];
+
+/// Parse the contents of a mapping file into an [`Alphabet`].
+///
+/// # Errors
+/// Fails if the text isn't valid TOML, if any instruction is missing or unknown, or if the
+/// tokens don't form a valid alphabet.
+pub fn parse_alphabet(text: &str) -> Result<Alphabet, Box<dyn Error>> {
+    let table: toml::Table = text.parse()?;
+    if let Some(key) = table
+        .keys()
+        .find(|key| !KEYS.iter().any(|(name, _)| name == key))
+    {
+        return Err(format!("Unknown instruction '{key}' in alphabet").into());
+    }
+
+    let mut tokens = Vec::new();
+    for (name, inst) in KEYS {
+        match table.get(name) {
+            Some(toml::Value::String(token)) => tokens.push((inst, token.clone())),
+            Some(toml::Value::Array(values)) if !values.is_empty() => {
+                for value in values {
+                    let token = value
+                        .as_str()
+                        .ok_or_else(|| format!("Tokens for '{name}' must be strings"))?;
+                    tokens.push((inst, token.to_string()));
+                }
+            }
+            Some(_) => {
+                return Err(format!("'{name}' must be a string or a list of strings").into());
+            }
+            None => return Err(format!("Missing token for '{name}' in alphabet").into()),
+        }
+    }
+    Ok(Alphabet::new(tokens)?)
+}
+
+/// Load an [`Alphabet`] from a mapping file.
+///
+/// # Errors
+/// Fails if the file can't be read, or doesn't describe a valid alphabet.
+pub fn load_alphabet<P: AsRef<Path>>(path: P) -> Result<Alphabet, Box<dyn Error>> {
+    let text = std::fs::read_to_string(&path)?;
+    parse_alphabet(&text).map_err(|err| format!("{}: {}", path.as_ref().display(), err).into())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use bft_types::BFprogram;
+
+    const OOK: &str = r#"
+        move_left = "Ook? Ook."
+        move_right = "Ook. Ook?"
+        increment = "Ook. Ook."
+        decrement = "Ook! Ook!"
+        input = "Ook. Ook!"
+        output = "Ook! Ook."
+        begin_loop = "Ook! Ook?"
+        end_loop = ["Ook? Ook!"]
+    "#;
+
+    #[test]
+    fn parsing() {
+        let alphabet = parse_alphabet(OOK).unwrap();
+        let program = BFprogram::with_alphabet("mod.test", b"Ook. Ook. Ook! Ook.", &alphabet);
+        let insts: Vec<Instruction> = program
+            .instructions()
+            .iter()
+            .map(|i| *i.instruction())
+            .collect();
+        assert_eq!(insts, vec![Instruction::Increment, Instruction::Output]);
+    }
+
+    #[test]
+    fn errors() {
+        let missing = OOK.replace("input = \"Ook. Ook!\"", "");
+        assert_eq!(
+            parse_alphabet(&missing).unwrap_err().to_string(),
+            "Missing token for 'input' in alphabet"
+        );
+        let unknown = format!("{OOK}\nhalt = \"@\"");
+        assert_eq!(
+            parse_alphabet(&unknown).unwrap_err().to_string(),
+            "Unknown instruction 'halt' in alphabet"
+        );
+        let wrong_type = OOK.replace("[\"Ook? Ook!\"]", "3");
+        assert_eq!(
+            parse_alphabet(&wrong_type).unwrap_err().to_string(),
+            "'end_loop' must be a string or a list of strings"
+        );
+        let duplicate = OOK.replace("\"Ook! Ook!\"", "\"Ook. Ook.\"");
+        assert_eq!(
+            parse_alphabet(&duplicate).unwrap_err().to_string(),
+            "Token 'Ook. Ook.' is used more than once"
+        );
+    }
+}
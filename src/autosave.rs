@@ -0,0 +1,153 @@
+//! Saving checkpoints as a long run goes, so that it can be resumed after a crash or power loss.
+//!
+//! Checkpoints are written in turn to two files next to the program, `PROGRAM.autosave.0` and
+//! `PROGRAM.autosave.1`, so that one cut short by a crash never replaces the last good one. A run
+//! that is resumed carries on from the newest checkpoint that can be restored, skipping the input
+//! the program had already read, so it should be given the same input again. Output written
+//! after that checkpoint is written again. The checkpoints are removed once the run finishes.
+
+use std::error::Error;
+use std::fs;
+use std::fs::File;
+use std::io;
+use std::io::{Read, Write};
+use std::num::NonZeroU64;
+use std::path::{Path, PathBuf};
+
+use bft_interp::{StepOutcome, BFVM};
+use bft_types::BFprogram;
+
+/// The unit `--autosave` is given in.
+pub const MILLION: NonZeroU64 = NonZeroU64::new(1_000_000).unwrap();
+
+/// The pair of files that checkpoints of the program at `program` are written to in turn.
+pub fn paths(program: &Path) -> [PathBuf; 2] {
+    [0, 1].map(|n| {
+        let mut path = program.as_os_str().to_owned();
+        path.push(format!(".autosave.{n}"));
+        PathBuf::from(path)
+    })
+}
+
+/// Restore `vm` from the newest checkpoint in `paths` that was taken while running `program`,
+/// returning whether there was one.
+pub fn resume(program: &BFprogram, vm: &mut BFVM<u8>, paths: &[PathBuf; 2]) -> bool {
+    let mut newest: Option<BFVM<u8>> = None;
+    for path in paths {
+        let Ok(checkpoint) = fs::read(path) else {
+            continue;
+        };
+        let mut restored = vm.clone();
+        if restored.restore(program, &checkpoint).is_ok()
+            && newest
+                .as_ref()
+                .is_none_or(|newest| restored.executed() > newest.executed())
+        {
+            newest = Some(restored);
+        }
+    }
+    newest.map(|newest| *vm = newest).is_some()
+}
+
+/// Write `checkpoint` to `path`, making sure it has reached the disk.
+fn save(path: &Path, checkpoint: &[u8]) -> io::Result<()> {
+    let mut file = File::create(path)?;
+    file.write_all(checkpoint)?;
+    file.sync_all()
+}
+
+/// Run `program` to completion, or on from where it was restored, saving a checkpoint to one of
+/// `paths` in turn after every `every` instructions.
+///
+/// # Errors
+/// Fails if the program fails, or if skipping the input already read or saving a checkpoint
+/// fails.
+pub fn run_autosaved<R: Read, W: Write>(
+    program: &BFprogram,
+    vm: &mut BFVM<u8>,
+    input: &mut R,
+    output: &mut W,
+    every: NonZeroU64,
+    paths: &[PathBuf; 2],
+) -> Result<(), Box<dyn Error>> {
+    let read = u64::try_from(vm.input_read())?;
+    io::copy(&mut input.by_ref().take(read), &mut io::sink())?;
+    let mut saved = vm.executed() / every;
+    let mut outcome = StepOutcome::Running;
+    while outcome == StepOutcome::Running {
+        outcome = vm.step(program, input, output)?;
+        if outcome == StepOutcome::Running && vm.executed() / every > saved {
+            saved = vm.executed() / every;
+            // What the program has written so far is out before the checkpoint says it is.
+            output.flush()?;
+            let path = &paths[usize::from(saved % 2 == 1)];
+            save(path, &vm.checkpoint(program))?;
+        }
+    }
+    // Output closed by whatever is reading it stops the program quietly, as with `BFVM::run`.
+    match output.flush() {
+        Err(err) if err.kind() != io::ErrorKind::BrokenPipe => return Err(err.into()),
+        _ => {}
+    }
+    for path in paths {
+        match fs::remove_file(path) {
+            Err(err) if err.kind() != io::ErrorKind::NotFound => return Err(err.into()),
+            _ => {}
+        }
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn resuming() {
+        let dir = std::env::temp_dir().join(format!("bft-autosave-{}", std::process::id()));
+        fs::create_dir_all(&dir).unwrap();
+        let paths = paths(&dir.join("a.b"));
+        assert!(paths[1].ends_with("a.b.autosave.1"));
+        let mut program = BFprogram::new("a.b", b"++++[>,.<-]");
+        program.validate_brackets().unwrap();
+        let every = NonZeroU64::new(10).unwrap();
+
+        // A run that stops part way through leaves its checkpoints behind.
+        let mut vm: BFVM<u8> = BFVM::new(None, false).with_fuel(25, bft_interp::Uniform);
+        let mut output = Vec::new();
+        let result = run_autosaved(
+            &program,
+            &mut vm,
+            &mut &b"abcd"[..],
+            &mut output,
+            every,
+            &paths,
+        );
+        assert!(result.is_err());
+        assert_eq!(output, b"abc");
+        assert!(paths.iter().all(|path| path.exists()));
+
+        // Resuming carries on from the newest, after 20 instructions, given the input again.
+        let mut vm: BFVM<u8> = BFVM::new(None, false);
+        assert!(resume(&program, &mut vm, &paths));
+        assert_eq!(vm.executed(), 20);
+        let mut output = Vec::new();
+        run_autosaved(
+            &program,
+            &mut vm,
+            &mut &b"abcd"[..],
+            &mut output,
+            every,
+            &paths,
+        )
+        .unwrap();
+        assert_eq!(output, b"d");
+        assert!(!paths.iter().any(|path| path.exists()));
+
+        // Without a checkpoint there's nothing to resume.
+        let mut vm: BFVM<u8> = BFVM::new(None, false);
+        fs::write(&paths[0], b"not a checkpoint").unwrap();
+        assert!(!resume(&program, &mut vm, &paths));
+        fs::remove_dir_all(&dir).unwrap();
+    }
+}
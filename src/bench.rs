@@ -0,0 +1,109 @@
+//! Timing a program over repeated runs, to measure how fast bft runs it.
+//!
+//! Each run gets a fresh VM and the same input, and its output is thrown away, so that only the
+//! time taken to execute the program is measured.
+
+use std::error::Error;
+use std::io::Write;
+use std::num::NonZeroUsize;
+use std::time::{Duration, Instant};
+
+use bft_interp::{Uniform, BFVM};
+use bft_types::BFprogram;
+
+/// How to run the program being timed.
+#[derive(Clone, Copy, Debug)]
+pub struct Options {
+    /// Number of times to run the program.
+    pub runs: NonZeroUsize,
+
+    /// Number of cells on the program's tape.
+    pub cells: NonZeroUsize,
+
+    /// Maximum number of instructions the program may execute on each run.
+    pub max_steps: u64,
+}
+
+/// Run `program` on `input`, returning the number of instructions it executed and how long it
+/// took.
+fn time_run(
+    program: &BFprogram,
+    input: &[u8],
+    options: &Options,
+) -> Result<(u64, Duration), Box<dyn Error>> {
+    let mut vm: BFVM<u8> =
+        BFVM::new(Some(options.cells), false).with_fuel(options.max_steps, Uniform);
+    let started = Instant::now();
+    vm.run(program, &mut &input[..], &mut std::io::sink())?;
+    let elapsed = started.elapsed();
+    Ok((options.max_steps - vm.fuel().unwrap_or_default(), elapsed))
+}
+
+/// Run `program` on `input` as many times as `options` asks, writing the fastest, mean and
+/// slowest times to `out`, along with the instructions executed each second.
+///
+/// # Errors
+/// Fails if the program fails on any run, or the results can't be written.
+pub fn bench<W: Write>(
+    program: &BFprogram,
+    input: &[u8],
+    options: &Options,
+    out: &mut W,
+) -> Result<(), Box<dyn Error>> {
+    let mut executed = 0;
+    let mut times = Vec::with_capacity(options.runs.get());
+    for _ in 0..options.runs.get() {
+        let (steps, elapsed) = time_run(program, input, options)?;
+        executed = steps;
+        times.push(elapsed);
+    }
+    let total: Duration = times.iter().sum();
+    let runs = u32::try_from(times.len()).unwrap_or(u32::MAX);
+    let (min, max) = (times.iter().min(), times.iter().max());
+    let per_second =
+        u128::from(executed) * u128::from(runs) * 1_000_000_000 / total.as_nanos().max(1);
+    writeln!(out, "{runs} runs of {executed} instructions")?;
+    writeln!(
+        out,
+        "  min {:.3?}  mean {:.3?}  max {:.3?}",
+        min.copied().unwrap_or_default(),
+        total / runs,
+        max.copied().unwrap_or_default()
+    )?;
+    writeln!(out, "  {per_second} instructions/s")?;
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn program(code: &str) -> BFprogram {
+        let mut program = BFprogram::new("bench.test", code.as_bytes());
+        program.validate_brackets().unwrap();
+        program
+    }
+
+    const OPTIONS: Options = Options {
+        runs: NonZeroUsize::new(3).unwrap(),
+        cells: NonZeroUsize::new(16).unwrap(),
+        max_steps: 1000,
+    };
+
+    #[test]
+    fn timing() {
+        let mut out = Vec::new();
+        bench(&program(",.,."), b"ab", &OPTIONS, &mut out).unwrap();
+        let out = String::from_utf8(out).unwrap();
+        assert!(out.starts_with("3 runs of 4 instructions\n  min "), "{out}");
+        assert!(out.ends_with(" instructions/s\n"), "{out}");
+    }
+
+    #[test]
+    fn failures() {
+        let mut out = Vec::new();
+        assert!(bench(&program("+[]"), b"", &OPTIONS, &mut out).is_err());
+        assert!(bench(&program("<"), b"", &OPTIONS, &mut out).is_err());
+        assert!(out.is_empty());
+    }
+}
@@ -0,0 +1,489 @@
+//! A small structured language, bfasm, that compiles to Brainf*ck.
+//!
+//! Each line holds one statement, and `#` starts a comment:
+//!
+//! ```text
+//! var count char      # give each variable its own cell
+//! set count 3         # set a variable to a constant
+//! add char 65         # add a constant, or use `sub`; arithmetic wraps
+//! while count {       # repeat while the variable isn't zero
+//!     print char      # write the variable as a byte
+//!     add char 1
+//!     sub count 1
+//! }
+//! if char {           # run the block once if the variable isn't zero
+//!     print "done\n"  # write some text
+//! }
+//! read char           # read a byte into the variable
+//! ```
+
+use std::error::Error;
+use std::fmt;
+use std::fmt::{Display, Formatter};
+
+use serde_json::{json, Value};
+
+/// The deepest that blocks can be nested, which keeps compiling them from running out of stack.
+const MAX_NESTING: usize = 256;
+
+/// A problem found while compiling a bfasm program.
+#[derive(Debug, PartialEq, Eq)]
+pub struct AsmError {
+    line: usize,
+    message: String,
+}
+
+impl Display for AsmError {
+    fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
+        write!(f, "line {}: {}", self.line, self.message)
+    }
+}
+
+impl Error for AsmError {}
+
+/// A statement, with the cells of the variables it uses.
+enum Statement {
+    Add(usize, u8),
+    Set(usize, u8),
+    Print(usize),
+    PrintText(Vec<u8>),
+    Read(usize),
+    While(usize, Vec<Line>),
+    If(usize, Vec<Line>),
+}
+
+/// A statement, and the line it came from.
+type Line = (usize, Statement);
+
+/// A word of a line, or a quoted string.
+#[derive(Debug, PartialEq)]
+enum Word {
+    Name(String),
+    Text(Vec<u8>),
+}
+
+/// Split `line` into words, dropping any comment.
+fn words(line: &str) -> Result<Vec<Word>, String> {
+    let mut words = Vec::new();
+    let mut chars = line.chars().peekable();
+    while let Some(c) = chars.next() {
+        match c {
+            '#' => break,
+            c if c.is_whitespace() => {}
+            '"' => {
+                let mut text = String::new();
+                loop {
+                    match chars.next() {
+                        Some('"') => break,
+                        Some('\\') => text.push(match chars.next() {
+                            Some('n') => '\n',
+                            Some('t') => '\t',
+                            Some('0') => '\0',
+                            Some(c @ ('\\' | '"')) => c,
+                            other => {
+                                return Err(format!("unknown escape '\\{}'", other.unwrap_or(' ')))
+                            }
+                        }),
+                        Some(c) => text.push(c),
+                        None => return Err(String::from("unterminated string")),
+                    }
+                }
+                words.push(Word::Text(text.into_bytes()));
+            }
+            c => {
+                let mut name = String::from(c);
+                while let Some(c) = chars.next_if(|c| !c.is_whitespace() && *c != '#') {
+                    name.push(c);
+                }
+                words.push(Word::Name(name));
+            }
+        }
+    }
+    Ok(words)
+}
+
+/// Turns lines of source into statements, allocating a cell for each variable.
+#[derive(Default)]
+struct Parser {
+    variables: Vec<String>,
+    /// The number of blocks open.
+    depth: usize,
+}
+
+impl Parser {
+    fn variable(&self, name: &str) -> Result<usize, String> {
+        self.variables
+            .iter()
+            .position(|v| v == name)
+            .ok_or_else(|| format!("unknown variable '{name}'"))
+    }
+
+    /// Parse lines until the end of a block, or of the source if `opened` is `None`. Otherwise
+    /// `opened` is the line the block started on.
+    fn block<'a, I>(&mut self, lines: &mut I, opened: Option<usize>) -> Result<Vec<Line>, AsmError>
+    where
+        I: Iterator<Item = (usize, &'a str)>,
+    {
+        let mut statements = Vec::new();
+        while let Some((line, text)) = lines.next() {
+            let error = |message| AsmError { line, message };
+            let words = words(text).map_err(error)?;
+            let statement = match words.as_slice() {
+                [] => continue,
+                [Word::Name(close)] if close == "}" => {
+                    return match opened {
+                        Some(_) => Ok(statements),
+                        None => Err(error(String::from("unexpected '}'"))),
+                    };
+                }
+                [Word::Name(keyword), Word::Name(name), Word::Name(open)]
+                    if open == "{" && (keyword == "while" || keyword == "if") =>
+                {
+                    let cell = self.variable(name).map_err(error)?;
+                    if self.depth == MAX_NESTING {
+                        return Err(error(format!(
+                            "blocks are nested more than {MAX_NESTING} deep"
+                        )));
+                    }
+                    self.depth += 1;
+                    let body = self.block(lines, Some(line))?;
+                    self.depth -= 1;
+                    Some(if keyword == "while" {
+                        Statement::While(cell, body)
+                    } else {
+                        Statement::If(cell, body)
+                    })
+                }
+                [Word::Name(keyword), rest @ ..] => self.statement(keyword, rest).map_err(error)?,
+                [Word::Text(_), ..] => return Err(error(String::from("expected a statement"))),
+            };
+            if let Some(statement) = statement {
+                statements.push((line, statement));
+            }
+        }
+        match opened {
+            Some(line) => Err(AsmError {
+                line,
+                message: String::from("block is never closed"),
+            }),
+            None => Ok(statements),
+        }
+    }
+
+    /// Parse a single-line statement. Declarations don't produce a statement.
+    fn statement(&mut self, keyword: &str, args: &[Word]) -> Result<Option<Statement>, String> {
+        let constant = |value: &str| {
+            value
+                .parse::<i64>()
+                .map(|n| n.rem_euclid(256).to_le_bytes()[0])
+                .map_err(|_| format!("expected a number, found '{value}'"))
+        };
+        match (keyword, args) {
+            ("var", names) if !names.is_empty() => {
+                for name in names {
+                    let Word::Name(name) = name else {
+                        return Err(String::from("expected a variable name"));
+                    };
+                    if self.variables.contains(name) {
+                        return Err(format!("variable '{name}' is already declared"));
+                    }
+                    self.variables.push(name.clone());
+                }
+                Ok(None)
+            }
+            ("add" | "sub" | "set", [Word::Name(name), Word::Name(value)]) => {
+                let cell = self.variable(name)?;
+                let value = constant(value)?;
+                Ok(Some(match keyword {
+                    "add" => Statement::Add(cell, value),
+                    "sub" => Statement::Add(cell, value.wrapping_neg()),
+                    _ => Statement::Set(cell, value),
+                }))
+            }
+            ("print", [Word::Name(name)]) => Ok(Some(Statement::Print(self.variable(name)?))),
+            ("print", [Word::Text(text)]) => Ok(Some(Statement::PrintText(text.clone()))),
+            ("read", [Word::Name(name)]) => Ok(Some(Statement::Read(self.variable(name)?))),
+            _ => Err(format!("invalid '{keyword}' statement")),
+        }
+    }
+}
+
+/// A span of the generated code, and the line of bfasm it was generated from.
+#[derive(Debug, PartialEq, Eq)]
+pub struct Mapping {
+    /// Byte offset of the start of the span.
+    pub start: usize,
+
+    /// Byte offset of the end of the span.
+    pub end: usize,
+
+    /// The bfasm line.
+    pub line: usize,
+}
+
+/// A compiled bfasm program.
+#[derive(Debug)]
+pub struct Compiled {
+    /// The Brainf*ck code.
+    pub code: String,
+
+    /// Where each part of the code came from.
+    pub source_map: Vec<Mapping>,
+}
+
+impl Compiled {
+    /// The source map as a JSON array.
+    pub fn source_map_json(&self) -> Value {
+        self.source_map
+            .iter()
+            .map(|m| json!({"start": m.start, "end": m.end, "line": m.line}))
+            .collect()
+    }
+}
+
+/// Emits Brainf*ck for statements, keeping track of where the head is.
+struct Codegen {
+    code: String,
+    source_map: Vec<Mapping>,
+    head: usize,
+    /// The first cell not used by a variable or a temporary.
+    free: usize,
+    depth: usize,
+}
+
+impl Codegen {
+    fn move_to(&mut self, cell: usize) {
+        let (c, n) = if cell < self.head {
+            ('<', self.head - cell)
+        } else {
+            ('>', cell - self.head)
+        };
+        self.code.extend(std::iter::repeat_n(c, n));
+        self.head = cell;
+    }
+
+    fn add(&mut self, cell: usize, value: u8) {
+        self.move_to(cell);
+        if value < 128 {
+            self.code.extend(std::iter::repeat_n('+', value.into()));
+        } else {
+            self.code
+                .extend(std::iter::repeat_n('-', value.wrapping_neg().into()));
+        }
+    }
+
+    fn clear(&mut self, cell: usize) {
+        self.move_to(cell);
+        self.code.push_str("[-]");
+    }
+
+    /// Start a line of code for `line`, run `emit`, and record where it went.
+    fn chunk(&mut self, line: usize, emit: impl FnOnce(&mut Self)) {
+        self.code.extend(std::iter::repeat_n(' ', self.depth * 2));
+        let start = self.code.len();
+        emit(self);
+        self.source_map.push(Mapping {
+            start,
+            end: self.code.len(),
+            line,
+        });
+        self.code.push('\n');
+    }
+
+    fn block(&mut self, statements: &[Line]) {
+        for (line, statement) in statements {
+            self.statement(*line, statement);
+        }
+    }
+
+    /// Run `body` while `cell` isn't zero.
+    fn looped(&mut self, line: usize, cell: usize, body: impl FnOnce(&mut Self)) {
+        self.chunk(line, |gen| {
+            gen.move_to(cell);
+            gen.code.push('[');
+        });
+        self.depth += 1;
+        body(self);
+        self.depth -= 1;
+        self.chunk(line, |gen| {
+            gen.move_to(cell);
+            gen.code.push(']');
+        });
+    }
+
+    fn statement(&mut self, line: usize, statement: &Statement) {
+        match statement {
+            Statement::Add(cell, value) => self.chunk(line, |gen| gen.add(*cell, *value)),
+            Statement::Set(cell, value) => self.chunk(line, |gen| {
+                gen.clear(*cell);
+                gen.add(*cell, *value);
+            }),
+            Statement::Print(cell) => self.chunk(line, |gen| {
+                gen.move_to(*cell);
+                gen.code.push('.');
+            }),
+            Statement::Read(cell) => self.chunk(line, |gen| {
+                gen.move_to(*cell);
+                gen.code.push(',');
+            }),
+            Statement::PrintText(text) => self.chunk(line, |gen| {
+                let temp = gen.free;
+                gen.clear(temp);
+                let mut current = 0u8;
+                for c in text {
+                    gen.add(temp, c.wrapping_sub(current));
+                    gen.code.push('.');
+                    current = *c;
+                }
+                gen.clear(temp);
+            }),
+            Statement::While(cell, body) => self.looped(line, *cell, |gen| gen.block(body)),
+            Statement::If(cell, body) => {
+                // Copy the variable to a flag, using a second temporary to restore it.
+                let (flag, spare) = (self.free, self.free + 1);
+                self.free += 2;
+                self.chunk(line, |gen| {
+                    gen.clear(flag);
+                    gen.clear(spare);
+                });
+                self.looped(line, *cell, |gen| {
+                    gen.chunk(line, |gen| {
+                        gen.code.push('-');
+                        gen.add(flag, 1);
+                        gen.add(spare, 1);
+                    });
+                });
+                self.looped(line, spare, |gen| {
+                    gen.chunk(line, |gen| {
+                        gen.code.push('-');
+                        gen.add(*cell, 1);
+                    });
+                });
+                self.looped(line, flag, |gen| {
+                    gen.block(body);
+                    gen.chunk(line, |gen| gen.clear(flag));
+                });
+                self.free -= 2;
+            }
+        }
+    }
+}
+
+/// Compile the bfasm program in `source` to Brainf*ck.
+///
+/// # Errors
+/// Fails if the program isn't valid bfasm.
+pub fn compile(source: &str) -> Result<Compiled, AsmError> {
+    let mut parser = Parser::default();
+    let mut lines = source.lines().enumerate().map(|(n, text)| (n + 1, text));
+    let statements = parser.block(&mut lines, None)?;
+    let mut gen = Codegen {
+        code: String::new(),
+        source_map: Vec::new(),
+        head: 0,
+        free: parser.variables.len(),
+        depth: 0,
+    };
+    gen.block(&statements);
+    Ok(Compiled {
+        code: gen.code,
+        source_map: gen.source_map,
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use bft_interp::BFVM;
+    use bft_types::BFprogram;
+
+    fn run(source: &str, input: &[u8]) -> Vec<u8> {
+        let code = compile(source).unwrap().code;
+        let mut program = BFprogram::new("bfasm.test", code.as_bytes());
+        program.validate_brackets().unwrap();
+        let mut vm: BFVM<u8> = BFVM::new(None, false);
+        let mut output = Vec::new();
+        vm.run(&program, &mut &input[..], &mut output).unwrap();
+        output
+    }
+
+    #[test]
+    fn splitting_words() {
+        assert_eq!(
+            words(r#"print "a \"b\"\n" # comment"#),
+            Ok(vec![
+                Word::Name(String::from("print")),
+                Word::Text(b"a \"b\"\n".to_vec())
+            ])
+        );
+        assert!(words("print \"a").is_err());
+    }
+
+    #[test]
+    fn code_generation() {
+        let compiled = compile("var a b\nadd b 3\nsub a 1\nprint b").unwrap();
+        assert_eq!(compiled.code, ">+++\n<-\n>.\n");
+        assert_eq!(
+            compiled.source_map[1],
+            Mapping {
+                start: 5,
+                end: 7,
+                line: 3
+            }
+        );
+    }
+
+    #[test]
+    fn running() {
+        let source = "\
+var count char
+set count 3
+add char 65
+while count {
+    print char
+    add char 1
+    sub count 1
+}
+if char {
+    print \"!\\n\"
+}
+if count {
+    print \"never\"
+}
+read char
+print char
+";
+        assert_eq!(run(source, b"z"), b"ABC!\nz");
+    }
+
+    #[test]
+    fn errors() {
+        assert_eq!(
+            compile("var a\nadd b 1").unwrap_err().to_string(),
+            "line 2: unknown variable 'b'"
+        );
+        assert_eq!(
+            compile("var a\nwhile a {\nadd a 1")
+                .unwrap_err()
+                .to_string(),
+            "line 2: block is never closed"
+        );
+        assert_eq!(
+            compile("}").unwrap_err().to_string(),
+            "line 1: unexpected '}'"
+        );
+        assert_eq!(
+            compile("var a\nadd a x").unwrap_err().to_string(),
+            "line 2: expected a number, found 'x'"
+        );
+        let nested = format!("var a\n{}", "while a {\n".repeat(100_000));
+        assert_eq!(
+            compile(&nested).unwrap_err().to_string(),
+            format!(
+                "line {}: blocks are nested more than {MAX_NESTING} deep",
+                MAX_NESTING + 2
+            )
+        );
+    }
+}
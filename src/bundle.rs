@@ -0,0 +1,245 @@
+//! Bundling a program with the interpreter into a single executable, for `bft bundle`.
+//!
+//! A bundle is a copy of the `bft` executable with the program's source, and the options to run
+//! it with, appended to it. Operating systems load an executable from its headers and ignore
+//! anything after it, so the copy runs as `bft` does, and at startup finds the program at its
+//! end:
+//!
+//! ```text
+//! bft executable | source | JSON {"name", "options"} | source length | JSON length | BFTBNDL1
+//! ```
+//!
+//! with the lengths as little-endian `u64`s. A bundle runs the program and nothing else: its own
+//! command line is passed to the program as with `--args`, and bft's configuration file and
+//! environment variables are ignored, so it runs the same wherever it's copied to.
+
+use std::env;
+use std::error::Error;
+use std::ffi::OsString;
+use std::fs;
+use std::fs::File;
+use std::io;
+use std::io::{Read, Seek, SeekFrom};
+use std::path::{Path, PathBuf};
+
+use serde_json::{json, Value};
+
+/// The bytes that end a bundle, marking the executable as one.
+const MAGIC: &[u8; 8] = b"BFTBNDL1";
+
+/// The length of the lengths and the magic bytes at the end of a bundle.
+const TRAILER_LEN: usize = 24;
+
+/// A program bundled into an executable.
+#[derive(Debug, PartialEq, Eq)]
+pub struct Bundle {
+    /// The name of the program's file, for diagnostics.
+    pub name: String,
+
+    /// The program's source.
+    pub source: Vec<u8>,
+
+    /// The options to run the program with, as given to `bft run`.
+    pub options: Vec<String>,
+}
+
+impl Bundle {
+    /// The bytes to append to an executable to bundle this program with it.
+    fn payload(&self) -> Vec<u8> {
+        let json = json!({"name": self.name, "options": self.options}).to_string();
+        let mut payload = self.source.clone();
+        payload.extend_from_slice(json.as_bytes());
+        payload.extend_from_slice(&(self.source.len() as u64).to_le_bytes());
+        payload.extend_from_slice(&(json.len() as u64).to_le_bytes());
+        payload.extend_from_slice(MAGIC);
+        payload
+    }
+
+    /// Read the bundle at the end of the executable at `path`, if there is one, along with the
+    /// length of the executable without it.
+    fn read_from(path: &Path) -> io::Result<Option<(Bundle, u64)>> {
+        let mut file = File::open(path)?;
+        let len = file.metadata()?.len();
+        let Some(end) = len.checked_sub(TRAILER_LEN as u64) else {
+            return Ok(None);
+        };
+        let mut trailer = [0; TRAILER_LEN];
+        file.seek(SeekFrom::Start(end))?;
+        file.read_exact(&mut trailer)?;
+        if trailer[16..] != MAGIC[..] {
+            return Ok(None);
+        }
+        let length = |bytes: &[u8]| u64::from_le_bytes(bytes.try_into().expect("8 bytes"));
+        let (source_len, json_len) = (length(&trailer[..8]), length(&trailer[8..16]));
+        let invalid = || io::Error::new(io::ErrorKind::InvalidData, "the bundle is damaged");
+        let start = source_len
+            .checked_add(json_len)
+            .and_then(|payload| end.checked_sub(payload))
+            .ok_or_else(invalid)?;
+        file.seek(SeekFrom::Start(start))?;
+        let mut source = vec![0; usize::try_from(source_len).map_err(|_| invalid())?];
+        file.read_exact(&mut source)?;
+        let mut json = vec![0; usize::try_from(json_len).map_err(|_| invalid())?];
+        file.read_exact(&mut json)?;
+        let settings: Value = serde_json::from_slice(&json).map_err(|_| invalid())?;
+        let name = settings["name"].as_str().ok_or_else(invalid)?;
+        let options = settings["options"].as_array().ok_or_else(invalid)?;
+        let bundle = Bundle {
+            name: name.to_owned(),
+            source,
+            options: options
+                .iter()
+                .map(|option| option.as_str().map(str::to_owned).ok_or_else(invalid))
+                .collect::<io::Result<_>>()?,
+        };
+        Ok(Some((bundle, start)))
+    }
+
+    /// The program bundled into the running executable, if it is a bundle.
+    ///
+    /// # Errors
+    /// Fails if the executable can't be read, or ends with a damaged bundle.
+    pub fn embedded() -> io::Result<Option<Bundle>> {
+        Self::embedded_in(env::current_exe())
+    }
+
+    /// The program bundled into the executable at `exe`, where finding it may have failed. On
+    /// platforms like WASI, where the running executable can't be found, there is no bundle.
+    fn embedded_in(exe: io::Result<PathBuf>) -> io::Result<Option<Bundle>> {
+        let exe = match exe {
+            Ok(exe) => exe,
+            Err(err)
+                if matches!(
+                    err.kind(),
+                    io::ErrorKind::Unsupported | io::ErrorKind::NotFound
+                ) =>
+            {
+                return Ok(None);
+            }
+            Err(err) => return Err(err),
+        };
+        Ok(Self::read_from(&exe)?.map(|(bundle, _)| bundle))
+    }
+
+    /// The command line that runs the bundled program, given the arguments the bundle was run
+    /// with, less its own name.
+    ///
+    /// # Errors
+    /// The arguments are passed on as with `--args`, which splits them at whitespace, so fails if
+    /// any of them is empty, holds whitespace, or isn't UTF-8.
+    pub fn command_line(
+        &self,
+        args: impl IntoIterator<Item = OsString>,
+    ) -> Result<Vec<OsString>, String> {
+        let mut command_line: Vec<OsString> = vec!["bft".into(), "run".into()];
+        command_line.extend(self.options.iter().map(OsString::from));
+        let args = args
+            .into_iter()
+            .map(|arg| match arg.into_string() {
+                Ok(arg) if !arg.is_empty() && !arg.contains(char::is_whitespace) => Ok(arg),
+                Ok(arg) => Err(format!(
+                    "The argument {arg:?} can't be passed on, as arguments can't be empty or \
+                     hold whitespace"
+                )),
+                Err(arg) => Err(format!(
+                    "The argument '{}' can't be passed on, as it isn't UTF-8",
+                    arg.to_string_lossy()
+                )),
+            })
+            .collect::<Result<Vec<_>, _>>()?;
+        if !args.is_empty() {
+            command_line.push("--args".into());
+            command_line.push(args.join(" ").into());
+        }
+        command_line.push("--".into());
+        command_line.push(self.name.clone().into());
+        Ok(command_line)
+    }
+}
+
+/// Write an executable to `output` that runs `bundle`, made from the running executable.
+///
+/// # Errors
+/// Fails if the running executable can't be read, or the bundle can't be written.
+pub fn write(bundle: &Bundle, output: &Path) -> Result<(), Box<dyn Error>> {
+    let exe = env::current_exe()?;
+    let mut executable = fs::read(&exe)?;
+    // Bundling from a bundle replaces its program rather than adding another.
+    if let Some((_, len)) = Bundle::read_from(&exe)? {
+        executable.truncate(usize::try_from(len)?);
+    }
+    executable.extend(bundle.payload());
+    fs::write(output, executable)?;
+    #[cfg(unix)]
+    {
+        use std::os::unix::fs::PermissionsExt;
+        fs::set_permissions(output, fs::Permissions::from_mode(0o755))?;
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn round_trip() {
+        let path = env::temp_dir().join(format!("bft-bundle-{}", std::process::id()));
+        let bundle = Bundle {
+            name: "hello.b".to_owned(),
+            source: b"++++[>++++<-]>.".to_vec(),
+            options: vec!["--cells".to_owned(), "8".to_owned()],
+        };
+        fs::write(&path, b"not a bundle").unwrap();
+        assert_eq!(Bundle::read_from(&path).unwrap(), None);
+
+        let mut executable = b"executable".to_vec();
+        executable.extend(bundle.payload());
+        fs::write(&path, executable).unwrap();
+        assert_eq!(Bundle::read_from(&path).unwrap(), Some((bundle, 10)));
+
+        let mut damaged = b"x".to_vec();
+        damaged.extend_from_slice(&100u64.to_le_bytes());
+        damaged.extend_from_slice(&0u64.to_le_bytes());
+        damaged.extend_from_slice(MAGIC);
+        fs::write(&path, damaged).unwrap();
+        assert!(Bundle::read_from(&path).is_err());
+        fs::remove_file(&path).unwrap();
+    }
+
+    #[test]
+    fn no_executable() {
+        let unsupported = io::Error::from(io::ErrorKind::Unsupported);
+        assert_eq!(Bundle::embedded_in(Err(unsupported)).unwrap(), None);
+        let missing = io::Error::from(io::ErrorKind::NotFound);
+        assert_eq!(Bundle::embedded_in(Err(missing)).unwrap(), None);
+        let denied = io::Error::from(io::ErrorKind::PermissionDenied);
+        assert!(Bundle::embedded_in(Err(denied)).is_err());
+    }
+
+    #[test]
+    fn command_lines() {
+        let bundle = Bundle {
+            name: "hello.b".to_owned(),
+            source: Vec::new(),
+            options: vec!["--cells".to_owned(), "8".to_owned()],
+        };
+        assert_eq!(
+            bundle.command_line([]).unwrap(),
+            ["bft", "run", "--cells", "8", "--", "hello.b"]
+        );
+        assert_eq!(
+            bundle.command_line(["a".into(), "b".into()]).unwrap(),
+            ["bft", "run", "--cells", "8", "--args", "a b", "--", "hello.b"]
+        );
+        assert!(bundle.command_line(["a b".into()]).is_err());
+        assert!(bundle.command_line(["".into()]).is_err());
+        #[cfg(unix)]
+        {
+            use std::os::unix::ffi::OsStringExt;
+            assert!(bundle
+                .command_line([OsString::from_vec(vec![0xff])])
+                .is_err());
+        }
+    }
+}
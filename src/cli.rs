@@ -1,22 +1,912 @@
 #![warn(missing_docs)]
 
-use clap::Parser;
-use std::num::NonZeroUsize;
+use bft_types::{Extension, Location};
+
+use crate::input::InputNewlines;
+use crate::lint::{Level, LintConfig};
+use crate::output_format::OutputFormat;
+use crate::{disasm, exit_code, highlight};
+use clap::builder::{PossibleValuesParser, TypedValueParser};
+use clap::{Args, ColorChoice, Parser, Subcommand, ValueEnum};
+use std::num::{NonZeroU32, NonZeroU64, NonZeroUsize};
 use std::path::PathBuf;
 
 /// A Brainf*ck interpreter.
 #[derive(Debug, Parser)]
-#[command(author, version, about, name = "bft")]
+#[command(
+    author,
+    version,
+    about,
+    name = "bft",
+    args_conflicts_with_subcommands = true,
+    subcommand_negates_reqs = true,
+    after_long_help = exit_code::HELP
+)]
 pub struct Opt {
+    /// Alternative modes of operation.
+    #[command(subcommand)]
+    pub command: Option<Command>,
+
+    /// When to use colors in diagnostics. `auto` uses them when writing to a terminal, unless
+    /// `NO_COLOR` is set.
+    #[arg(long, value_enum, default_value_t = ColorChoice::Auto, global = true, env = "BFT_COLOR")]
+    pub color: ColorChoice,
+
+    /// How to print diagnostics: as text for people, or as JSON with one diagnostic on each line.
+    #[arg(
+        long,
+        value_enum,
+        default_value_t = DiagnosticFormat::Human,
+        global = true,
+        env = "BFT_DIAGNOSTICS"
+    )]
+    pub diagnostics: DiagnosticFormat,
+
+    /// Running a program without a subcommand is the same as `bft run`.
+    #[command(flatten)]
+    pub run: RunArgs,
+}
+
+/// Options for running a program.
+#[derive(Clone, Debug, Args)]
+#[allow(clippy::struct_excessive_bools)]
+pub struct RunArgs {
     /// The Brainf*ck program to run.
     #[clap(required(true), value_parser)]
-    pub program: PathBuf,
+    pub program: Option<PathBuf>,
 
     /// Number of cells for the programs tape.
-    #[arg(short, long)]
+    #[arg(short, long, env = "BFT_CELLS")]
     pub cells: Option<NonZeroUsize>,
 
     /// Allow the program tape to be automatically extended.
-    #[arg(short, long, default_value_t = false)]
+    #[arg(short, long, default_value_t = false, env = "BFT_EXTENSIBLE")]
     pub extensible: bool,
+
+    /// Size the tape from the head range found by `bft stats`, letting it grow if the range
+    /// can't be bounded.
+    #[arg(long, conflicts_with = "cells", env = "BFT_AUTO_CELLS")]
+    pub auto_cells: bool,
+
+    /// Run tight inner loops without checking the head at each instruction, where one check
+    /// when the loop starts proves that it stays on the tape. Loops where that can't be proven
+    /// are run with the usual checks.
+    #[arg(
+        long,
+        conflicts_with_all = ["trace", "log_io", "trace_output_positions", "debug_script", "net"]
+    )]
+    pub fast_unchecked: bool,
+
+    /// Report which loops `--fast-unchecked` compiled, and how often each ran, to stderr once
+    /// the program stops.
+    #[arg(long, requires = "fast_unchecked")]
+    pub dump_fused: bool,
+
+    /// Refuse to run programs with loops nested more than this many deep.
+    #[arg(long, value_name = "DEPTH", env = "BFT_MAX_NESTING")]
+    pub max_nesting: Option<NonZeroUsize>,
+
+    /// The language the program is written in.
+    #[arg(long, value_enum, default_value_t = Dialect::Brainfuck, env = "BFT_DIALECT")]
+    pub dialect: Dialect,
+
+    /// Read the program using the instruction tokens defined in this TOML mapping file, instead
+    /// of the standard Brainf*ck characters.
+    #[arg(
+        long,
+        value_name = "FILE",
+        conflicts_with = "dialect",
+        env = "BFT_ALPHABET"
+    )]
+    pub alphabet: Option<PathBuf>,
+
+    /// Opt-in language extensions to enable, separated by commas.
+    #[arg(long, value_delimiter = ',', value_parser = extension_parser(), env = "BFT_EXTENSIONS")]
+    pub extensions: Vec<Extension>,
+
+    /// Number of tapes available to programs using the multitape extension.
+    #[arg(long, default_value = "2", env = "BFT_TAPES")]
+    pub tapes: NonZeroUsize,
+
+    /// Seed for the random bytes written by `?`, to make runs reproducible.
+    #[arg(long, env = "BFT_SEED")]
+    pub seed: Option<u64>,
+
+    /// Make threaded programs reproducible, by running each thread N instructions at a time in
+    /// the order they were started, and seeding `?` with 0 unless a seed is given.
+    #[arg(
+        long,
+        value_name = "N",
+        num_args = 0..=1,
+        require_equals = true,
+        default_missing_value = "1"
+    )]
+    pub deterministic: Option<NonZeroU32>,
+
+    /// Let the threads started by the brainfork extension share their tapes, rather than each
+    /// working on its own copy. Each instruction happens all at once, and its writes are seen by
+    /// every thread straight away.
+    #[arg(long)]
+    pub shared_tapes: bool,
+
+    /// Run the program with conservative limits on the instructions it executes, its memory, and
+    /// its input and output, without any extensions, for programs that can't be trusted.
+    #[arg(long, conflicts_with = "extensions", env = "BFT_SANDBOX")]
+    pub sandbox: bool,
+
+    /// Pass these whitespace-separated arguments to the program on its tape. Cell 0 holds the
+    /// number of bytes they take, followed by each argument ending in a NUL byte.
+    #[arg(long, value_name = "ARGS")]
+    pub args: Option<String>,
+
+    /// Copy the value of this environment variable to the program's tape, after any arguments.
+    /// The values are laid out like the arguments, in the order they are given.
+    #[arg(long, value_name = "NAME")]
+    pub env: Vec<String>,
+
+    /// Stop the program with an error if it writes more than this many bytes.
+    #[arg(long, value_name = "N", env = "BFT_MAX_OUTPUT_BYTES")]
+    pub max_output_bytes: Option<usize>,
+
+    /// The line endings used by the program's input, which are translated to the `\n` that
+    /// programs expect.
+    #[arg(
+        long,
+        value_enum,
+        default_value_t = InputNewlines::Lf,
+        env = "BFT_INPUT_NEWLINES"
+    )]
+    pub input_newlines: InputNewlines,
+
+    /// Read the terminal a key at a time, without waiting for Enter or echoing the keys, for
+    /// interactive programs. Ctrl-D ends the input on Unix, and Ctrl-Z on Windows.
+    #[arg(long, conflicts_with_all = ["debug_script", "net"], env = "BFT_RAW_INPUT")]
+    pub raw_input: bool,
+
+    /// Write each byte the program reads back to its output, so that transcripts show both sides
+    /// of an interactive session. With a file other than `-`, the bytes are written there
+    /// instead.
+    #[arg(
+        long,
+        value_name = "FILE",
+        num_args = 0..=1,
+        require_equals = true,
+        default_missing_value = "-",
+        conflicts_with_all = ["debug_script", "net"]
+    )]
+    pub echo_input: Option<PathBuf>,
+
+    /// How to show the program's output: as it is, as a hex dump, with unprintable bytes
+    /// escaped, or decoded as UTF-8 text.
+    #[arg(
+        long,
+        value_enum,
+        default_value_t = OutputFormat::Raw,
+        conflicts_with = "net",
+        env = "BFT_OUTPUT_FORMAT"
+    )]
+    pub output_format: OutputFormat,
+
+    /// Write a JSON manifest of everything needed to reproduce the run to this file: a
+    /// fingerprint of the program, every option, the seed, where the input came from, the version
+    /// of bft and how the run ended.
+    #[arg(long, value_name = "FILE", conflicts_with = "debug_script")]
+    pub manifest: Option<PathBuf>,
+
+    /// Print the time the run took, the number of instructions executed and the instructions
+    /// executed each second to stderr once the program stops.
+    #[arg(long, conflicts_with = "debug_script")]
+    pub time: bool,
+
+    /// Save a checkpoint every this many million instructions, to `PROGRAM.autosave.0` and
+    /// `PROGRAM.autosave.1` in turn, so that a long run can be resumed if it's stopped.
+    #[arg(
+        long,
+        value_name = "MILLIONS",
+        conflicts_with_all = [
+            "debug_script",
+            "trace",
+            "log_io",
+            "trace_output_positions",
+            "net",
+            "fast_unchecked"
+        ]
+    )]
+    pub autosave: Option<NonZeroU64>,
+
+    /// Carry on from the newest checkpoint saved by `--autosave`, given the same input again.
+    #[arg(long, requires = "autosave")]
+    pub resume: bool,
+
+    /// Run the program under the debugger, executing the debugger commands in this file.
+    #[arg(long, value_name = "FILE")]
+    pub debug_script: Option<PathBuf>,
+
+    /// The program's source, when it's bundled into the executable rather than read from
+    /// `program`.
+    #[arg(skip)]
+    pub source: Option<Vec<u8>>,
+
+    /// Record every executed instruction to this file, for use with `bft replay-trace`.
+    #[arg(long, value_name = "FILE", conflicts_with = "debug_script")]
+    pub trace: Option<PathBuf>,
+
+    /// Log each byte the program reads or writes to this file, with the time, the byte in hex
+    /// and as a character, and the location of the instruction.
+    #[arg(long, value_name = "FILE", conflicts_with_all = ["debug_script", "trace"])]
+    pub log_io: Option<PathBuf>,
+
+    /// Write the source location and step of the `.` that wrote each byte of output to this
+    /// file, as JSON, to find which instruction wrote a wrong byte.
+    #[arg(
+        long,
+        value_name = "FILE",
+        conflicts_with_all = ["debug_script", "trace", "log_io", "net"]
+    )]
+    pub trace_output_positions: Option<PathBuf>,
+
+    /// Profile the run, then print the program's source to stderr with the number of
+    /// instructions executed on each line, and their share of all those executed, in the margin.
+    #[arg(
+        long,
+        conflicts_with_all = [
+            "debug_script",
+            "trace",
+            "log_io",
+            "trace_output_positions",
+            "net",
+            "autosave",
+            "fast_unchecked"
+        ]
+    )]
+    pub annotate: bool,
+
+    /// Connect the program's input and output to a TCP connection, either waiting for a client
+    /// with `listen ADDRESS`, or with `connect ADDRESS`. An address like `:7000` gives just the
+    /// port.
+    #[arg(
+        long,
+        num_args = 2,
+        value_names = ["MODE", "ADDRESS"],
+        conflicts_with_all = ["debug_script", "trace", "log_io"]
+    )]
+    pub net: Option<Vec<String>>,
+}
+
+impl RunArgs {
+    /// The first option given that only programs run on a tape of bytes can use, so not
+    /// Boolfuck programs, if any.
+    pub fn byte_tape_option(&self) -> Option<&'static str> {
+        [
+            ("--trace", self.trace.is_some()),
+            ("--log-io", self.log_io.is_some()),
+            (
+                "--trace-output-positions",
+                self.trace_output_positions.is_some(),
+            ),
+            ("--annotate", self.annotate),
+            ("--autosave", self.autosave.is_some()),
+            ("--net", self.net.is_some()),
+            ("--debug-script", self.debug_script.is_some()),
+        ]
+        .into_iter()
+        .find_map(|(name, given)| given.then_some(name))
+    }
+}
+
+/// Formats for diagnostics.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, ValueEnum)]
+pub enum DiagnosticFormat {
+    /// Text, with colors if they're enabled.
+    Human,
+
+    /// A JSON object on each line, with the `file`, `span`, `severity`, `message`, lint `code`
+    /// and `note` of each diagnostic.
+    Json,
+}
+
+/// Languages that programs can be written in.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, ValueEnum)]
+pub enum Dialect {
+    /// Standard Brainf*ck, with a tape of bytes.
+    Brainfuck,
+
+    /// Boolfuck, with a tape of bits. Input and output are packed into bytes, least significant
+    /// bit first.
+    Boolfuck,
+
+    /// Ook!, which spells each Brainf*ck instruction as a pair of `Ook.`, `Ook?` or `Ook!` words.
+    Ook,
+}
+
+/// The levels lints are reported at, overriding `bft.toml`. The name `warnings` refers to every
+/// lint that would otherwise be a warning, and lints named individually take precedence over it.
+#[derive(Clone, Debug, Default, Args)]
+pub struct LintLevels {
+    /// Don't report this lint.
+    #[arg(short = 'A', long, value_name = "LINT")]
+    pub allow: Vec<String>,
+
+    /// Report this lint as a warning.
+    #[arg(short = 'W', long, value_name = "LINT")]
+    pub warn: Vec<String>,
+
+    /// Treat this lint as an error, failing if it fires. With `warnings`, every lint that would
+    /// otherwise be a warning is denied, except those named individually.
+    #[arg(short = 'D', long, value_name = "LINT")]
+    pub deny: Vec<String>,
+}
+
+impl LintLevels {
+    /// `config` with these levels applied.
+    ///
+    /// # Errors
+    /// Fails if any of the lints doesn't exist.
+    pub fn apply(&self, config: &LintConfig) -> Result<LintConfig, String> {
+        let mut config = config.clone();
+        let levels = [
+            (Level::Allow, &self.allow),
+            (Level::Warn, &self.warn),
+            (Level::Deny, &self.deny),
+        ];
+        config.set_levels(
+            levels
+                .iter()
+                .flat_map(|(level, names)| names.iter().map(|name| (name.as_str(), *level))),
+        )?;
+        Ok(config)
+    }
+}
+
+/// Options for comparing the behaviour of two programs.
+#[derive(Clone, Debug, Args)]
+pub struct EquivArgs {
+    /// The first program.
+    pub a: PathBuf,
+
+    /// The second program.
+    pub b: PathBuf,
+
+    /// Number of inputs to generate, after trying the empty input and any given with `--input`.
+    #[arg(long, default_value_t = 1000)]
+    pub inputs: usize,
+
+    /// Maximum number of instructions each program may execute for each input.
+    #[arg(long, default_value_t = 1_000_000)]
+    pub fuel: u64,
+
+    /// Also try the contents of this file as an input. May be given more than once.
+    #[arg(long = "input", value_name = "FILE")]
+    pub input_files: Vec<PathBuf>,
+
+    /// Seed for generating inputs, so that a run can be repeated.
+    #[arg(long, default_value_t = 0)]
+    pub seed: u64,
+}
+
+/// Options for timing a program.
+#[derive(Clone, Debug, Args)]
+pub struct BenchArgs {
+    /// The program to time.
+    pub program: PathBuf,
+
+    /// File to feed to the program as input on each run.
+    #[arg(long, value_name = "FILE")]
+    pub input: Option<PathBuf>,
+
+    /// Number of times to run the program.
+    #[arg(long, default_value = "10")]
+    pub runs: NonZeroUsize,
+
+    /// Number of cells on the program's tape.
+    #[arg(long, default_value = "30000")]
+    pub cells: NonZeroUsize,
+
+    /// Maximum number of instructions the program may execute on each run.
+    #[arg(long, default_value_t = 100_000_000)]
+    pub max_steps: u64,
+}
+
+/// Modes of operation.
+#[derive(Debug, Subcommand)]
+pub enum Command {
+    /// Run a program. This is what `bft PROGRAM` does without a subcommand.
+    Run(RunArgs),
+
+    /// Run a program under the debugger, executing the debugger commands in a script.
+    Debug {
+        /// The file of debugger commands to execute.
+        #[arg(
+            long,
+            value_name = "FILE",
+            conflicts_with_all = ["debug_script", "trace", "log_io", "net", "time"]
+        )]
+        script: PathBuf,
+
+        /// How to run the program.
+        #[command(flatten)]
+        run: RunArgs,
+    },
+
+    /// Run a Debug Adapter Protocol server over stdin and stdout.
+    Dap,
+
+    /// Run a Language Server Protocol server over stdin and stdout.
+    Lsp,
+
+    /// Convert a program from one dialect to another, keeping its comments where possible.
+    Translate {
+        /// The program to convert.
+        program: PathBuf,
+
+        /// The dialect the program is written in.
+        #[arg(long, value_enum, default_value_t = Dialect::Brainfuck)]
+        from: Dialect,
+
+        /// Read the program using the tokens in this TOML mapping file.
+        #[arg(long, value_name = "FILE", conflicts_with = "from")]
+        from_alphabet: Option<PathBuf>,
+
+        /// The dialect to convert the program to.
+        #[arg(long, value_enum, default_value_t = Dialect::Brainfuck)]
+        to: Dialect,
+
+        /// Write the program using the tokens in this TOML mapping file.
+        #[arg(long, value_name = "FILE", conflicts_with = "to")]
+        to_alphabet: Option<PathBuf>,
+
+        /// Write the converted program to this file instead of stdout.
+        #[arg(short, long, value_name = "FILE")]
+        output: Option<PathBuf>,
+    },
+
+    /// Serve a web playground for running programs, with an HTTP API behind it.
+    Serve {
+        /// The address to listen on.
+        #[arg(long, default_value = "127.0.0.1:8000")]
+        addr: String,
+
+        /// Number of cells on the tape of each program.
+        #[arg(long, default_value = "30000")]
+        cells: NonZeroUsize,
+
+        /// Maximum number of instructions each program may execute.
+        #[arg(long, default_value_t = 10_000_000)]
+        max_steps: u64,
+
+        /// Maximum number of bytes each program may write.
+        #[arg(long, default_value_t = 1 << 20)]
+        max_output: usize,
+
+        /// Maximum size of a request, in bytes.
+        #[arg(long, default_value_t = 1 << 20)]
+        max_request: usize,
+
+        /// Maximum number of seconds each program may run for.
+        #[arg(long, value_name = "SECONDS", default_value_t = 10)]
+        timeout: u64,
+    },
+
+    /// Run programs sent over a Unix socket, without starting a process for each one.
+    Daemon {
+        /// Path of the socket to listen on.
+        #[arg(long, value_name = "PATH")]
+        socket: PathBuf,
+
+        /// Number of cells on the tape of each program.
+        #[arg(long, default_value = "30000")]
+        cells: NonZeroUsize,
+
+        /// Maximum number of instructions each program may execute.
+        #[arg(long, default_value_t = 100_000_000)]
+        max_steps: u64,
+
+        /// Maximum number of bytes each program may write.
+        #[arg(long, default_value_t = 1 << 20)]
+        max_output: usize,
+
+        /// Maximum size of a request, in bytes.
+        #[arg(long, default_value_t = 1 << 20)]
+        max_request: usize,
+    },
+
+    /// Run programs as a pipeline, feeding the output of each program to the next one.
+    Pipe {
+        /// The programs to run, in order. The first reads stdin and the last writes stdout.
+        #[arg(required = true)]
+        programs: Vec<PathBuf>,
+
+        /// Number of cells for each program's tape.
+        #[arg(short, long)]
+        cells: Option<NonZeroUsize>,
+
+        /// Allow each program's tape to be automatically extended.
+        #[arg(short, long)]
+        extensible: bool,
+
+        /// Maximum number of instructions each program may execute.
+        #[arg(long)]
+        max_steps: Option<u64>,
+    },
+
+    /// Check that bft works, by running a few built-in programs whose output is known. The
+    /// options from `bft.toml` and the `BFT_` environment variables are used, so that the
+    /// semantics they configure are checked too.
+    Selftest,
+
+    /// Run a corpus of test programs, checking each one's output.
+    Test {
+        /// Directory of `.b` programs, with the input for each in a `.in` file and the expected
+        /// output in a `.out` file alongside it.
+        #[arg(long, value_name = "DIR")]
+        corpus: PathBuf,
+
+        /// Maximum number of instructions each program may execute.
+        #[arg(long, default_value_t = 100_000_000)]
+        max_steps: u64,
+
+        /// Write each program's current output to its `.out` file, instead of checking it.
+        #[arg(long)]
+        bless: bool,
+    },
+
+    /// Time a program over several runs, reporting the fastest, mean and slowest, and the
+    /// instructions executed each second.
+    Bench(BenchArgs),
+
+    /// Run every program in a directory concurrently, reporting how each one went.
+    RunAll {
+        /// Directory of `.b` programs, with the input for each in a `.in` file alongside it.
+        dir: PathBuf,
+
+        /// Number of programs to run at once. Defaults to the number of CPUs.
+        #[arg(short, long)]
+        jobs: Option<NonZeroUsize>,
+
+        /// Number of cells on the tape of each program.
+        #[arg(long, default_value = "30000")]
+        cells: NonZeroUsize,
+
+        /// Maximum number of instructions each program may execute.
+        #[arg(long, default_value_t = 100_000_000)]
+        max_steps: u64,
+
+        /// Also write the results to this file, as JSON.
+        #[arg(long, value_name = "FILE")]
+        report: Option<PathBuf>,
+    },
+
+    /// Run a program with both bft and a reference interpreter, and compare their output.
+    DiffRun {
+        /// The reference interpreter, which is run with the path of the program as its argument.
+        #[arg(long, value_name = "INTERPRETER")]
+        reference: PathBuf,
+
+        /// The Brainf*ck program to run.
+        program: PathBuf,
+
+        /// File to feed to both interpreters as input.
+        #[arg(long, value_name = "FILE")]
+        input: Option<PathBuf>,
+
+        /// Maximum number of instructions for bft to execute.
+        #[arg(long, default_value_t = 100_000_000)]
+        max_steps: u64,
+    },
+
+    /// Compare two programs instruction by instruction, ignoring comments and layout, and report
+    /// the runs of instructions that were inserted, removed or changed.
+    Diff {
+        /// The original program.
+        old: PathBuf,
+
+        /// The changed program.
+        new: PathBuf,
+    },
+
+    /// Look for an input that two programs behave differently on, by running both on the same
+    /// inputs. Finding none doesn't prove that they're equivalent.
+    Equiv(EquivArgs),
+
+    /// Format programs in a canonical layout, rewriting them in place.
+    Fmt {
+        /// The programs to format. With none given, a program is read from stdin and the
+        /// formatted version written to stdout.
+        files: Vec<PathBuf>,
+
+        /// Maximum length of lines of instructions.
+        #[arg(long, default_value_t = 80)]
+        width: usize,
+
+        /// Report the programs that aren't formatted, instead of changing them.
+        #[arg(long)]
+        check: bool,
+    },
+
+    /// Compile a program written in bfasm, a small structured language, to Brainf*ck.
+    Asm {
+        /// The bfasm program to compile.
+        program: PathBuf,
+
+        /// Write the Brainf*ck program to this file instead of stdout.
+        #[arg(short, long, value_name = "FILE")]
+        output: Option<PathBuf>,
+
+        /// Write a JSON source map, giving the bfasm line for each span of the output, to this
+        /// file.
+        #[arg(long, value_name = "FILE")]
+        source_map: Option<PathBuf>,
+    },
+
+    /// Generate Brainf*ck programs.
+    Gen {
+        /// What to generate.
+        #[command(subcommand)]
+        generator: Generator,
+
+        /// Write the program to this file instead of stdout.
+        #[arg(short, long, value_name = "FILE", global = true)]
+        output: Option<PathBuf>,
+    },
+
+    /// Strip comments and redundant instructions from a program.
+    Minify {
+        /// The program to shrink.
+        program: PathBuf,
+
+        /// Write the shrunk program to this file instead of stdout.
+        #[arg(short, long, value_name = "FILE")]
+        output: Option<PathBuf>,
+    },
+
+    /// Check that programs are valid and run the lints, without running the programs.
+    Check {
+        /// The programs to check.
+        #[arg(required = true)]
+        files: Vec<PathBuf>,
+
+        /// Print the diagnostics as a JSON array.
+        #[arg(long)]
+        json: bool,
+
+        /// Print the diagnostics as a SARIF log, for code scanning tools.
+        #[arg(long, conflicts_with = "json")]
+        sarif: bool,
+
+        /// Levels for the lints.
+        #[command(flatten)]
+        levels: LintLevels,
+    },
+
+    /// Check programs for suspicious code.
+    Lint {
+        /// The programs to check.
+        #[arg(required_unless_present = "list")]
+        files: Vec<PathBuf>,
+
+        /// List the available lints instead of checking programs.
+        #[arg(long)]
+        list: bool,
+
+        /// Print the diagnostics as a SARIF log, for code scanning tools.
+        #[arg(long, conflicts_with = "list")]
+        sarif: bool,
+
+        /// Levels for the lints.
+        #[command(flatten)]
+        levels: LintLevels,
+    },
+
+    /// List the instructions in a program.
+    Disasm {
+        /// The program to list.
+        program: PathBuf,
+
+        /// How to list the instructions.
+        #[arg(long, value_enum, default_value_t = disasm::Format::Text)]
+        format: disasm::Format,
+
+        /// Only list instructions on these source lines, given as `START-END`, `START-` or a
+        /// single line.
+        #[arg(long, value_name = "RANGE")]
+        lines: Option<disasm::LineRange>,
+    },
+
+    /// Cut the innermost loop around a location out of a program, as a program of its own, so
+    /// that it can be run and tested apart from the rest.
+    Slice {
+        /// The program to take the loop from.
+        program: PathBuf,
+
+        /// Where in the program the loop is, as LINE:COL. Anywhere from its `[` to its `]` will
+        /// do.
+        #[arg(value_name = "LINE:COL")]
+        at: Location,
+
+        /// Write the loop to this file instead of stdout.
+        #[arg(short, long, value_name = "FILE")]
+        output: Option<PathBuf>,
+    },
+
+    /// Work with snapshots of runs, which are the checkpoints saved by `--autosave` or by the
+    /// debugger's `snapshot` command.
+    Snapshot {
+        /// What to do with the snapshots.
+        #[command(subcommand)]
+        command: SnapshotCommand,
+    },
+
+    /// Bundle a program with the interpreter into a single executable that runs it, so that it
+    /// can be handed out as a program of its own. The bundle passes its own arguments to the
+    /// program as with `--args`, so each must be non-empty UTF-8 without whitespace, and ignores
+    /// bft's configuration and environment variables.
+    #[command(visible_alias = "compile")]
+    Bundle {
+        /// The program to bundle.
+        program: PathBuf,
+
+        /// Write the executable to this file.
+        #[arg(short, long, value_name = "FILE")]
+        output: PathBuf,
+
+        /// Options to run the program with, as given to `bft run`, after `--`.
+        #[arg(last = true, value_name = "OPTIONS")]
+        options: Vec<String>,
+    },
+
+    /// Print a program as readable pseudo-code.
+    Decompile {
+        /// The program to decompile.
+        program: PathBuf,
+    },
+
+    /// Report static metrics about programs, like instruction counts and loop nesting.
+    Stats {
+        /// The programs to measure.
+        #[arg(required = true)]
+        files: Vec<PathBuf>,
+
+        /// Print the metrics as a JSON array, with an object for each program.
+        #[arg(long)]
+        json: bool,
+
+        /// Also list the well-known idioms found in each program, like clear and copy loops,
+        /// with their locations.
+        #[arg(long)]
+        idioms: bool,
+    },
+
+    /// Print a program with syntax highlighting.
+    Highlight {
+        /// The program to highlight.
+        program: PathBuf,
+
+        /// How to render the highlighting.
+        #[arg(long, value_enum, default_value_t = highlight::Format::Ansi)]
+        format: highlight::Format,
+    },
+
+    /// Print a shell completion script for bft.
+    Completions {
+        /// The shell to generate completions for.
+        #[arg(value_enum)]
+        shell: clap_complete::Shell,
+    },
+
+    /// Print a man page for bft, in roff format.
+    Manpage,
+
+    /// Inspect an execution trace recorded with `--trace`.
+    ReplayTrace {
+        /// The trace file to load.
+        trace: PathBuf,
+
+        /// Reconstruct the state after this many instructions have executed. Defaults to the
+        /// end of the trace.
+        #[arg(long)]
+        step: Option<usize>,
+
+        /// First cell to print. Defaults to the head position.
+        #[arg(long)]
+        start: Option<usize>,
+
+        /// Number of cells to print.
+        #[arg(long, default_value_t = 10)]
+        count: usize,
+
+        /// Find the first instruction that wrote to this cell.
+        #[arg(long, value_name = "CELL")]
+        first_write: Option<usize>,
+    },
+}
+
+/// What can be done with snapshots.
+#[derive(Debug, Subcommand)]
+pub enum SnapshotCommand {
+    /// Report which cells changed, and how the heads moved, between two snapshots.
+    Diff {
+        /// The earlier snapshot.
+        before: PathBuf,
+
+        /// The later snapshot.
+        after: PathBuf,
+    },
+}
+
+/// Kinds of program that can be generated.
+#[derive(Debug, Subcommand)]
+pub enum Generator {
+    /// A program that prints some text.
+    Text {
+        /// The text to print.
+        text: String,
+    },
+
+    /// A program that computes an integer expression, like `(3*7+2)%5`, leaving the result in
+    /// the first cell. Arithmetic wraps around, like the cells do.
+    Expr {
+        /// The expression to compute.
+        expr: String,
+
+        /// Print the result in decimal.
+        #[arg(long)]
+        print: bool,
+    },
+}
+
+fn extension_parser() -> impl TypedValueParser<Value = Extension> {
+    PossibleValuesParser::new(Extension::ALL.map(|ext| ext.name())).try_map(|name| name.parse())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn deterministic_takes_its_value_after_equals() {
+        let opt = Opt::try_parse_from(["bft", "--deterministic", "prog.b"]).unwrap();
+        assert_eq!(opt.run.deterministic, NonZeroU32::new(1));
+        assert_eq!(opt.run.program, Some(PathBuf::from("prog.b")));
+
+        let opt = Opt::try_parse_from(["bft", "run", "--deterministic", "prog.b"]).unwrap();
+        let Some(Command::Run(run)) = opt.command else {
+            panic!("expected a run");
+        };
+        assert_eq!(run.deterministic, NonZeroU32::new(1));
+
+        let opt = Opt::try_parse_from(["bft", "--deterministic=3", "prog.b"]).unwrap();
+        assert_eq!(opt.run.deterministic, NonZeroU32::new(3));
+    }
+
+    #[test]
+    fn byte_tape_options() {
+        let opt = Opt::try_parse_from(["bft", "prog.b"]).unwrap();
+        assert_eq!(opt.run.byte_tape_option(), None);
+        let opt = Opt::try_parse_from(["bft", "--annotate", "prog.b"]).unwrap();
+        assert_eq!(opt.run.byte_tape_option(), Some("--annotate"));
+        let opt = Opt::try_parse_from(["bft", "--net", "listen", ":7000", "prog.b"]).unwrap();
+        assert_eq!(opt.run.byte_tape_option(), Some("--net"));
+    }
+
+    #[test]
+    fn byte_tape_options_with_a_byte_tape_dialect() {
+        for dialect in ["brainfuck", "ook"] {
+            let opt = Opt::try_parse_from(["bft", "--dialect", dialect, "--trace", "t", "prog.b"])
+                .unwrap();
+            assert_eq!(opt.run.trace, Some(PathBuf::from("t")));
+        }
+    }
+
+    #[test]
+    fn fast_unchecked_conflicts_with_net() {
+        let args = [
+            "bft",
+            "--fast-unchecked",
+            "--net",
+            "listen",
+            ":7000",
+            "prog.b",
+        ];
+        assert!(Opt::try_parse_from(args).is_err());
+    }
 }
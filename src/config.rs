@@ -0,0 +1,240 @@
+//! Loading default options from a `bft.toml` configuration file.
+//!
+//! The configuration is read from the first `bft.toml` found in the current directory or one of
+//! its parents, or else from `$XDG_CONFIG_HOME/bft/bft.toml`. Any option given on the command
+//! line, or through its `BFT_` environment variable, overrides the configuration file:
+//!
+//! ```toml
+//! cells = 30000
+//! extensible = true
+//! dialect = "brainfuck"
+//! extensions = ["pbrain", "random"]
+//! tapes = 2
+//! seed = 42
+//! max_nesting = 10000
+//!
+//! [lints]
+//! warnings = "deny"
+//! empty-loop = "warn"
+//! cancelling-instructions = "allow"
+//! ```
+//!
+//! In `[lints]`, `warnings` sets the level of every lint that would otherwise be a warning, and
+//! lints named individually take precedence over it.
+
+use std::env;
+use std::error::Error;
+use std::num::NonZeroUsize;
+use std::path::{Path, PathBuf};
+
+use clap::parser::ValueSource;
+use clap::{ArgMatches, ValueEnum};
+
+use bft_types::Extension;
+
+use crate::cli::{Command, Dialect, Opt};
+use crate::lint::LintConfig;
+
+/// Name of the configuration file.
+const FILE_NAME: &str = "bft.toml";
+
+/// Default options loaded from a configuration file.
+#[derive(Debug, Default)]
+pub struct Config {
+    cells: Option<NonZeroUsize>,
+    extensible: Option<bool>,
+    dialect: Option<Dialect>,
+    extensions: Option<Vec<Extension>>,
+    tapes: Option<NonZeroUsize>,
+    seed: Option<u64>,
+    max_nesting: Option<NonZeroUsize>,
+
+    /// The level each lint is reported at.
+    pub lints: LintConfig,
+}
+
+/// Read a positive integer setting.
+fn positive(key: &str, value: &toml::Value) -> Result<NonZeroUsize, String> {
+    value
+        .as_integer()
+        .and_then(|n| usize::try_from(n).ok())
+        .and_then(NonZeroUsize::new)
+        .ok_or_else(|| format!("'{key}' must be a positive integer"))
+}
+
+/// Read a string setting.
+fn string<'a>(key: &str, value: &'a toml::Value) -> Result<&'a str, String> {
+    value
+        .as_str()
+        .ok_or_else(|| format!("'{key}' must be a string"))
+}
+
+impl Config {
+    /// Parse the contents of a configuration file.
+    ///
+    /// # Errors
+    /// Fails if the text isn't valid TOML, or any setting is unknown or has an invalid value.
+    pub fn parse(text: &str) -> Result<Self, Box<dyn Error>> {
+        let table: toml::Table = text.parse()?;
+        let mut config = Config::default();
+        for (key, value) in &table {
+            match key.as_str() {
+                "cells" => config.cells = Some(positive(key, value)?),
+                "tapes" => config.tapes = Some(positive(key, value)?),
+                "max_nesting" => config.max_nesting = Some(positive(key, value)?),
+                "extensible" => {
+                    config.extensible = Some(
+                        value
+                            .as_bool()
+                            .ok_or("'extensible' must be true or false")?,
+                    );
+                }
+                "dialect" => config.dialect = Some(Dialect::from_str(string(key, value)?, false)?),
+                "extensions" => {
+                    let names = value.as_array().ok_or("'extensions' must be a list")?;
+                    config.extensions = Some(
+                        names
+                            .iter()
+                            .map(|name| string(key, name)?.parse())
+                            .collect::<Result<_, _>>()?,
+                    );
+                }
+                "seed" => {
+                    let seed = value.as_integer().and_then(|n| u64::try_from(n).ok());
+                    config.seed = Some(seed.ok_or("'seed' must be a non-negative integer")?);
+                }
+                "lints" => {
+                    let levels = value.as_table().ok_or("'lints' must be a table")?;
+                    let levels = levels
+                        .iter()
+                        .map(|(name, level)| Ok((name.as_str(), string(name, level)?.parse()?)))
+                        .collect::<Result<Vec<_>, String>>()?;
+                    config.lints.set_levels(levels)?;
+                }
+                _ => return Err(format!("Unknown setting '{key}'").into()),
+            }
+        }
+        Ok(config)
+    }
+
+    /// Find the configuration file to use, if there is one.
+    fn find() -> Option<PathBuf> {
+        let local = env::current_dir().ok().and_then(|dir| {
+            dir.ancestors()
+                .map(|dir| dir.join(FILE_NAME))
+                .find(|path| path.is_file())
+        });
+        local.or_else(|| {
+            let config_home = env::var_os("XDG_CONFIG_HOME")
+                .map(PathBuf::from)
+                .or_else(|| env::var_os("HOME").map(|home| Path::new(&home).join(".config")))?;
+            Some(config_home.join("bft").join(FILE_NAME)).filter(|path| path.is_file())
+        })
+    }
+
+    /// Load the configuration file, or the default configuration if there isn't one.
+    ///
+    /// # Errors
+    /// Fails if the configuration file can't be read or parsed.
+    pub fn load() -> Result<Self, Box<dyn Error>> {
+        let Some(path) = Self::find() else {
+            return Ok(Config::default());
+        };
+        let text = std::fs::read_to_string(&path)?;
+        Self::parse(&text).map_err(|err| format!("{}: {err}", path.display()).into())
+    }
+
+    /// Fill in the options for running a program in `options` that weren't given on the command
+    /// line or in the environment, as recorded in `matches`.
+    pub fn apply(&self, options: &mut Opt, matches: &ArgMatches) {
+        let (options, matches) = match (&mut options.command, matches.subcommand()) {
+            (Some(Command::Run(args) | Command::Debug { run: args, .. }), Some((_, matches))) => {
+                (args, matches)
+            }
+            _ => (&mut options.run, matches),
+        };
+        let unset = |id| {
+            matches!(
+                matches.value_source(id),
+                None | Some(ValueSource::DefaultValue)
+            )
+        };
+        if unset("cells") && self.cells.is_some() {
+            options.cells = self.cells;
+        }
+        if let Some(extensible) = self.extensible.filter(|_| unset("extensible")) {
+            options.extensible = extensible;
+        }
+        if let Some(dialect) = self.dialect.filter(|_| unset("dialect")) {
+            options.dialect = dialect;
+        }
+        if let Some(extensions) = self.extensions.as_ref().filter(|_| unset("extensions")) {
+            options.extensions.clone_from(extensions);
+        }
+        if let Some(tapes) = self.tapes.filter(|_| unset("tapes")) {
+            options.tapes = tapes;
+        }
+        if unset("seed") && self.seed.is_some() {
+            options.seed = self.seed;
+        }
+        if unset("max_nesting") && self.max_nesting.is_some() {
+            options.max_nesting = self.max_nesting;
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::cli::RunArgs;
+    use crate::lint::Level;
+    use clap::{CommandFactory, FromArgMatches};
+
+    fn options(config: &str, args: &[&str]) -> RunArgs {
+        let matches = Opt::command().get_matches_from(args);
+        let mut options = Opt::from_arg_matches(&matches).unwrap();
+        Config::parse(config).unwrap().apply(&mut options, &matches);
+        match options.command {
+            Some(Command::Run(args)) => args,
+            _ => options.run,
+        }
+    }
+
+    #[test]
+    fn parsing() {
+        let config = Config::parse(
+            "cells = 10\nextensions = [\"pbrain\"]\ndialect = \"ook\"\n[lints]\nempty-loop = \"deny\"\nwarnings = \"allow\"",
+        )
+        .unwrap();
+        assert_eq!(config.cells, NonZeroUsize::new(10));
+        assert_eq!(config.extensions, Some(vec![Extension::Pbrain]));
+        assert_eq!(config.dialect, Some(Dialect::Ook));
+        assert_eq!(config.lints.level("empty-loop"), Level::Deny);
+        assert_eq!(config.lints.level("cancelling-instructions"), Level::Allow);
+
+        let err = |text| Config::parse(text).unwrap_err().to_string();
+        assert_eq!(err("cells = 0"), "'cells' must be a positive integer");
+        assert_eq!(err("colour = true"), "Unknown setting 'colour'");
+        assert_eq!(err("[lints]\nnope = \"deny\""), "Unknown lint 'nope'");
+    }
+
+    #[test]
+    fn command_line_overrides() {
+        let config = "cells = 10\ntapes = 3\nextensible = true\nseed = 7\nmax_nesting = 4";
+        let opt = options(config, &["bft", "a.b"]);
+        assert_eq!(opt.cells, NonZeroUsize::new(10));
+        assert_eq!(opt.tapes, NonZeroUsize::new(3).unwrap());
+        assert!(opt.extensible);
+        assert_eq!(opt.seed, Some(7));
+        assert_eq!(opt.max_nesting, NonZeroUsize::new(4));
+
+        let opt = options(config, &["bft", "a.b", "--cells", "5", "--tapes", "2"]);
+        assert_eq!(opt.cells, NonZeroUsize::new(5));
+        assert_eq!(opt.tapes, NonZeroUsize::new(2).unwrap());
+
+        let opt = options(config, &["bft", "run", "a.b", "--cells", "5"]);
+        assert_eq!(opt.program, Some(PathBuf::from("a.b")));
+        assert_eq!(opt.cells, NonZeroUsize::new(5));
+        assert_eq!(opt.tapes, NonZeroUsize::new(3).unwrap());
+    }
+}
@@ -0,0 +1,202 @@
+//! Running a directory of test programs and checking their output.
+//!
+//! Each `NAME.b` program in the directory, or any directory below it, is run with `NAME.in` as
+//! its input, if that exists. When there is a `NAME.out` file, the program's output must match it
+//! exactly; otherwise the program only has to run without an error. When blessing, the `.out`
+//! files are written from the programs' current output instead of being checked.
+
+use std::error::Error;
+use std::fs;
+use std::io;
+use std::io::Write;
+use std::path::{Path, PathBuf};
+
+use bft_interp::{StepOutcome, BFVM};
+use bft_types::BFprogram;
+
+/// Find every `.b` file in `dir`, and the directories below it, in a stable order.
+pub fn find_programs(dir: &Path) -> io::Result<Vec<PathBuf>> {
+    let mut entries = fs::read_dir(dir)?
+        .map(|entry| entry.map(|e| e.path()))
+        .collect::<io::Result<Vec<_>>>()?;
+    entries.sort();
+    let mut programs = Vec::new();
+    for path in entries {
+        if path.is_dir() {
+            programs.extend(find_programs(&path)?);
+        } else if path.extension().is_some_and(|ext| ext == "b") {
+            programs.push(path);
+        }
+    }
+    Ok(programs)
+}
+
+/// Describe where `actual` first differs from `expected`.
+fn describe_difference(expected: &[u8], actual: &[u8]) -> String {
+    let idx = expected
+        .iter()
+        .zip(actual)
+        .position(|(e, a)| e != a)
+        .unwrap_or_else(|| expected.len().min(actual.len()));
+    let line = expected[..idx].split(|c| *c == b'\n').count();
+    let line_start = expected[..idx]
+        .iter()
+        .rposition(|c| *c == b'\n')
+        .map_or(0, |pos| pos + 1);
+    let line_of = |data: &[u8]| {
+        let end = data[line_start..]
+            .iter()
+            .position(|c| *c == b'\n')
+            .map_or(data.len(), |pos| line_start + pos);
+        data[line_start..end].escape_ascii().to_string()
+    };
+    format!(
+        "output differs at byte {idx} (line {line})\n  expected: \"{}\"\n  actual:   \"{}\"",
+        line_of(expected),
+        line_of(actual)
+    )
+}
+
+/// Run the program at `path`, returning its output, or a description of the problem if it fails.
+fn run_program(path: &Path, max_steps: u64) -> Result<Vec<u8>, String> {
+    let mut program = BFprogram::from_file(path).map_err(|err| err.to_string())?;
+    program.validate_brackets().map_err(|err| err.to_string())?;
+    let input = fs::read(path.with_extension("in")).unwrap_or_default();
+    let mut input = input.as_slice();
+
+    let mut vm: BFVM<u8> = BFVM::new(None, false);
+    let mut output = Vec::new();
+    let mut steps = 0;
+    while vm
+        .step(&program, &mut input, &mut output)
+        .map_err(|err| err.to_string())?
+        == StepOutcome::Running
+    {
+        steps += 1;
+        if steps >= max_steps {
+            return Err(format!("step limit of {max_steps} exceeded"));
+        }
+    }
+    Ok(output)
+}
+
+/// The result of running one program in the corpus.
+enum Outcome {
+    Pass,
+    Blessed,
+    Fail(String),
+}
+
+/// Run the program at `path` and check its output, or write its output to the `.out` file if
+/// `bless` is set and it differs.
+fn run_case(path: &Path, max_steps: u64, bless: bool) -> io::Result<Outcome> {
+    let output = match run_program(path, max_steps) {
+        Ok(output) => output,
+        Err(problem) => return Ok(Outcome::Fail(problem)),
+    };
+    let expected_path = path.with_extension("out");
+    match fs::read(&expected_path).ok() {
+        Some(expected) if expected == output => Ok(Outcome::Pass),
+        _ if bless => {
+            fs::write(expected_path, output)?;
+            Ok(Outcome::Blessed)
+        }
+        Some(expected) => Ok(Outcome::Fail(describe_difference(&expected, &output))),
+        None => Ok(Outcome::Pass),
+    }
+}
+
+/// Run every program in the corpus at `dir`, reporting the results to `out`. Each program may
+/// execute at most `max_steps` instructions. With `bless`, the expected output of each program
+/// that runs successfully is replaced with its actual output.
+///
+/// Returns true if every program passed.
+///
+/// # Errors
+/// Fails if the corpus can't be read, an expected output file can't be written, or the report
+/// can't be written.
+pub fn run_corpus<W: Write>(
+    dir: &Path,
+    max_steps: u64,
+    bless: bool,
+    out: &mut W,
+) -> Result<bool, Box<dyn Error>> {
+    let programs = find_programs(dir)?;
+    let mut failed = 0;
+    for path in &programs {
+        let name = path.strip_prefix(dir).unwrap_or(path).display();
+        match run_case(path, max_steps, bless)? {
+            Outcome::Pass => writeln!(out, "PASS {name}")?,
+            Outcome::Blessed => writeln!(out, "BLESS {name}")?,
+            Outcome::Fail(problem) => {
+                failed += 1;
+                writeln!(out, "FAIL {name}")?;
+                for line in problem.lines() {
+                    writeln!(out, "  {line}")?;
+                }
+            }
+        }
+    }
+    writeln!(out, "{} passed, {} failed", programs.len() - failed, failed)?;
+    Ok(failed == 0)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn passing_corpus() {
+        let mut out = Vec::new();
+        assert!(run_corpus(Path::new("data/corpus"), 1_000_000, false, &mut out).unwrap());
+        assert_eq!(
+            String::from_utf8(out).unwrap(),
+            "PASS cat.b\nPASS eof.b\nPASS hello.b\nPASS nested/a.b\n4 passed, 0 failed\n"
+        );
+    }
+
+    #[test]
+    fn step_limit() {
+        let mut out = Vec::new();
+        assert!(!run_corpus(Path::new("data/corpus/nested"), 10, false, &mut out).unwrap());
+        assert_eq!(
+            String::from_utf8(out).unwrap(),
+            "FAIL a.b\n  step limit of 10 exceeded\n0 passed, 1 failed\n"
+        );
+    }
+
+    #[test]
+    fn blessing() {
+        let dir = std::env::temp_dir().join(format!("bft-bless-{}", std::process::id()));
+        fs::create_dir_all(&dir).unwrap();
+        fs::write(dir.join("a.b"), "++++++++[>++++++++<-]>+.").unwrap();
+        fs::write(dir.join("a.out"), "B").unwrap();
+        fs::write(dir.join("b.b"), "+[]").unwrap();
+
+        let mut out = Vec::new();
+        assert!(!run_corpus(&dir, 1000, true, &mut out).unwrap());
+        assert_eq!(
+            String::from_utf8(out).unwrap(),
+            "BLESS a.b\nFAIL b.b\n  step limit of 1000 exceeded\n1 passed, 1 failed\n"
+        );
+        assert_eq!(fs::read(dir.join("a.out")).unwrap(), b"A");
+        assert!(!dir.join("b.out").exists());
+
+        let mut out = Vec::new();
+        run_corpus(&dir, 1000, true, &mut out).unwrap();
+        assert!(String::from_utf8(out).unwrap().starts_with("PASS a.b\n"));
+        fs::remove_dir_all(dir).unwrap();
+    }
+
+    #[test]
+    fn differences() {
+        assert_eq!(
+            describe_difference(b"ab\ncd\n", b"ab\nce\n"),
+            "output differs at byte 4 (line 2)\n  expected: \"cd\"\n  actual:   \"ce\""
+        );
+        assert_eq!(
+            describe_difference(b"ab", b"a"),
+            "output differs at byte 1 (line 1)\n  expected: \"ab\"\n  actual:   \"a\""
+        );
+    }
+}
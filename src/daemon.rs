@@ -0,0 +1,379 @@
+//! A daemon running programs sent over a Unix socket, for callers that run so many programs that
+//! starting a process for each one is too slow.
+//!
+//! Requests and responses are frames: a 4 byte big-endian length, followed by that many bytes of
+//! JSON. A request has the program's `code`, and optionally its `input`, the number of `cells` on
+//! its tape and the `max_steps` it may execute, which can only lower the daemon's own limits. The
+//! response has the program's `output`, any `error`, the number of `steps` it took and its
+//! `exit_status`. A request of `{"metrics": true}` gets the daemon's metrics instead, in the
+//! Prometheus text format.
+//!
+//! A connection can send any number of requests, each answered in turn. Parsed programs are
+//! cached, so running the same program again skips parsing and matching its brackets.
+
+// Without Unix sockets there's nothing to handle requests from.
+#![cfg_attr(not(unix), allow(dead_code, unused_imports))]
+
+use std::collections::HashMap;
+use std::io;
+use std::io::{Read, Write};
+use std::num::NonZeroUsize;
+use std::path::Path;
+use std::sync::{Arc, Mutex, PoisonError};
+use std::time::Instant;
+
+use serde_json::{json, Value};
+
+use bft_interp::{Limit, Uniform, VMError, BFVM};
+use bft_types::BFprogram;
+
+use crate::metrics::{ErrorClass, Metrics};
+
+/// Name used for submitted programs in error messages.
+const SOURCE_NAME: &str = "request";
+
+/// How many parsed programs to keep.
+const CACHE_CAPACITY: usize = 256;
+
+/// Limits on the programs run by the daemon.
+#[derive(Clone, Copy, Debug)]
+pub struct Limits {
+    /// Number of cells on the tape, which can't grow.
+    pub cells: NonZeroUsize,
+
+    /// Maximum number of instructions a program may execute.
+    pub max_steps: u64,
+
+    /// Maximum number of bytes a program may write.
+    pub max_output: usize,
+
+    /// Maximum size of a request, in bytes.
+    pub max_request: usize,
+}
+
+/// State shared by every connection to the daemon.
+#[derive(Debug, Default)]
+struct Daemon {
+    /// Programs that have been parsed, by their code.
+    cache: Mutex<HashMap<String, Arc<BFprogram>>>,
+    metrics: Metrics,
+}
+
+impl Daemon {
+    /// Parse `code`, or fetch it from the cache if it's been parsed before.
+    fn program(&self, code: &str) -> Result<Arc<BFprogram>, String> {
+        let mut cache = self.cache.lock().unwrap_or_else(PoisonError::into_inner);
+        if let Some(program) = cache.get(code) {
+            return Ok(Arc::clone(program));
+        }
+        let mut program = BFprogram::new(SOURCE_NAME, code.as_bytes());
+        program.validate_brackets().map_err(|err| err.to_string())?;
+        if cache.len() >= CACHE_CAPACITY {
+            if let Some(evicted) = cache.keys().next().cloned() {
+                cache.remove(&evicted);
+            }
+        }
+        let program = Arc::new(program);
+        cache.insert(code.to_string(), Arc::clone(&program));
+        Ok(program)
+    }
+
+    /// Run the program in `request`, returning the response to send.
+    fn run(&self, request: &Value, limits: &Limits) -> Value {
+        let Some(code) = request["code"].as_str() else {
+            self.metrics.record_error(ErrorClass::BadRequest);
+            return json!({ "error": "The request needs the program's \"code\"" });
+        };
+        let program = match self.program(code) {
+            Ok(program) => program,
+            Err(error) => {
+                self.metrics.record_error(ErrorClass::Brackets);
+                return json!({ "output": "", "error": error, "steps": 0 });
+            }
+        };
+        let cells = request["cells"]
+            .as_u64()
+            .and_then(|cells| NonZeroUsize::new(usize::try_from(cells).ok()?))
+            .map_or(limits.cells, |cells| cells.min(limits.cells));
+        let max_steps = request["max_steps"]
+            .as_u64()
+            .map_or(limits.max_steps, |steps| steps.min(limits.max_steps));
+        let mut vm: BFVM<u8> = BFVM::new(Some(cells), false)
+            .with_fuel(max_steps, Uniform)
+            .with_max_output(limits.max_output);
+        let mut input = request["input"].as_str().unwrap_or_default().as_bytes();
+        let mut output = Vec::new();
+
+        let started = Instant::now();
+        let result = vm.run(&program, &mut input, &mut output);
+        let steps = max_steps - vm.fuel().unwrap_or_default();
+        let error = result.err().map(|err| {
+            let class = match err {
+                VMError::OutOfFuel(..) => ErrorClass::StepLimit,
+                VMError::LimitExceeded(_, _, Limit::Output) => ErrorClass::OutputLimit,
+                VMError::IOError(..) => ErrorClass::Io,
+                _ => ErrorClass::Runtime,
+            };
+            (class, err.to_string())
+        });
+        self.metrics.record_run(
+            steps,
+            started.elapsed(),
+            error.as_ref().map(|(class, _)| *class),
+        );
+        json!({
+            "output": String::from_utf8_lossy(&output),
+            "error": error.map(|(_, message)| message),
+            "steps": steps,
+            "exit_status": vm.exit_status(),
+        })
+    }
+
+    /// Respond to the body of a request frame.
+    fn respond(&self, body: &[u8], limits: &Limits) -> Value {
+        match serde_json::from_slice::<Value>(body) {
+            Ok(request) if request["metrics"] == true => {
+                json!({ "metrics": self.metrics.render() })
+            }
+            Ok(request) => self.run(&request, limits),
+            Err(err) => {
+                self.metrics.record_error(ErrorClass::BadRequest);
+                json!({ "error": err.to_string() })
+            }
+        }
+    }
+}
+
+/// Read a frame, returning `None` if the connection is closed before it starts.
+fn read_frame<R: Read>(input: &mut R, max_len: usize) -> io::Result<Option<Vec<u8>>> {
+    let mut len = [0; 4];
+    match input.read_exact(&mut len) {
+        Ok(()) => {}
+        Err(err) if err.kind() == io::ErrorKind::UnexpectedEof => return Ok(None),
+        Err(err) => return Err(err),
+    }
+    let len = usize::try_from(u32::from_be_bytes(len)).unwrap_or(usize::MAX);
+    if len > max_len {
+        return Err(io::Error::new(
+            io::ErrorKind::InvalidData,
+            "Request is too large",
+        ));
+    }
+    let mut body = vec![0; len];
+    input.read_exact(&mut body)?;
+    Ok(Some(body))
+}
+
+fn write_frame<W: Write>(output: &mut W, body: &[u8]) -> io::Result<()> {
+    let len = u32::try_from(body.len())
+        .map_err(|_| io::Error::new(io::ErrorKind::InvalidData, "Response is too large"))?;
+    output.write_all(&len.to_be_bytes())?;
+    output.write_all(body)?;
+    output.flush()
+}
+
+/// Answer requests from `stream` until it's closed.
+fn handle_connection<S: Read + Write>(
+    mut stream: S,
+    daemon: &Daemon,
+    limits: &Limits,
+) -> io::Result<()> {
+    while let Some(body) = read_frame(&mut stream, limits.max_request)? {
+        let response = daemon.respond(&body, limits);
+        write_frame(&mut stream, response.to_string().as_bytes())?;
+    }
+    Ok(())
+}
+
+/// Listen on the Unix socket at `path`, handling each connection on its own thread, until the
+/// process is stopped. A stale socket left at `path` by an earlier daemon is replaced, but
+/// anything else already there is left alone.
+///
+/// # Errors
+/// Fails if something other than a socket is at `path`, or the daemon can't listen on it.
+#[cfg(unix)]
+pub fn daemon(path: &Path, limits: Limits) -> io::Result<()> {
+    use std::os::unix::fs::FileTypeExt;
+    use std::os::unix::net::{UnixListener, UnixStream};
+
+    match std::fs::symlink_metadata(path) {
+        Ok(metadata) if !metadata.file_type().is_socket() => {
+            return Err(io::Error::new(
+                io::ErrorKind::AlreadyExists,
+                format!("{} already exists and is not a socket", path.display()),
+            ));
+        }
+        Ok(_) if UnixStream::connect(path).is_err() => std::fs::remove_file(path)?,
+        Ok(_) => {}
+        Err(err) if err.kind() == io::ErrorKind::NotFound => {}
+        Err(err) => return Err(err),
+    }
+    let listener = UnixListener::bind(path)?;
+    eprintln!("Listening on {}", path.display());
+    let daemon = Arc::new(Daemon::default());
+    for stream in listener.incoming() {
+        let Ok(stream) = stream else {
+            continue;
+        };
+        let daemon = Arc::clone(&daemon);
+        std::thread::spawn(move || {
+            if let Err(err) = handle_connection(stream, &daemon, &limits) {
+                eprintln!("bft: {err}");
+            }
+        });
+    }
+    Ok(())
+}
+
+/// Unix sockets aren't available on this platform.
+///
+/// # Errors
+/// Always fails.
+#[cfg(not(unix))]
+pub fn daemon(_path: &Path, _limits: Limits) -> io::Result<()> {
+    Err(io::Error::new(
+        io::ErrorKind::Unsupported,
+        "bft daemon needs Unix sockets, which aren't available on this platform",
+    ))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const LIMITS: Limits = Limits {
+        cells: NonZeroUsize::new(16).unwrap(),
+        max_steps: 1000,
+        max_output: 8,
+        max_request: 1024,
+    };
+
+    fn frame(body: &Value) -> Vec<u8> {
+        let mut frame = Vec::new();
+        write_frame(&mut frame, body.to_string().as_bytes()).unwrap();
+        frame
+    }
+
+    /// Send `requests` on one connection, returning the responses.
+    fn exchange(daemon: &Daemon, requests: &[Value]) -> Vec<Value> {
+        let input: Vec<u8> = requests.iter().flat_map(frame).collect();
+        let mut output = Vec::new();
+        handle_connection(
+            ReadWrite {
+                input: &input[..],
+                output: &mut output,
+            },
+            daemon,
+            &LIMITS,
+        )
+        .unwrap();
+        let mut output = &output[..];
+        let mut responses = Vec::new();
+        while let Some(body) = read_frame(&mut output, usize::MAX).unwrap() {
+            responses.push(serde_json::from_slice(&body).unwrap());
+        }
+        responses
+    }
+
+    struct ReadWrite<R, W> {
+        input: R,
+        output: W,
+    }
+
+    impl<R: Read, W> Read for ReadWrite<R, W> {
+        fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+            self.input.read(buf)
+        }
+    }
+
+    impl<R, W: Write> Write for ReadWrite<R, W> {
+        fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+            self.output.write(buf)
+        }
+
+        fn flush(&mut self) -> io::Result<()> {
+            self.output.flush()
+        }
+    }
+
+    #[test]
+    fn running() {
+        let daemon = Daemon::default();
+        let responses = exchange(
+            &daemon,
+            &[
+                json!({"code": ",.+.", "input": "a"}),
+                json!({"code": ",.+.", "input": "x"}),
+                json!({"code": "+["}),
+                json!({"code": "+[]", "max_steps": 10}),
+                json!({"code": "+[.]"}),
+                json!({"metrics": true}),
+            ],
+        );
+        assert_eq!(responses[0]["output"], "ab");
+        assert_eq!(responses[0]["steps"], 4);
+        assert_eq!(responses[1]["output"], "xy");
+        assert_eq!(
+            responses[2]["error"],
+            "Unmatched bracket '[' at [request:1:2]"
+        );
+        assert_eq!(responses[3]["steps"], 10);
+        assert!(responses[3]["error"]
+            .as_str()
+            .unwrap()
+            .starts_with("Ran out of fuel"));
+        assert_eq!(responses[4]["output"], "\u{1}".repeat(8));
+        let metrics = responses[5]["metrics"].as_str().unwrap();
+        assert!(metrics.contains("bft_programs_run_total 4\n"));
+        assert!(metrics.contains("bft_errors_total{class=\"output_limit\"} 1\n"));
+
+        // The program run twice was only parsed once.
+        assert_eq!(daemon.cache.lock().unwrap().len(), 3);
+    }
+
+    #[test]
+    fn bad_requests() {
+        let daemon = Daemon::default();
+        let responses = exchange(&daemon, &[json!({"input": ""}), json!(null)]);
+        assert!(responses[0]["error"].as_str().unwrap().contains("code"));
+
+        let mut input = &[0, 0, 8, 0][..];
+        assert!(read_frame(&mut input, LIMITS.max_request).is_err());
+        let mut input = &b"\0\0\0\x02{"[..];
+        assert!(read_frame(&mut input, LIMITS.max_request).is_err());
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn socket() {
+        use std::os::unix::net::UnixStream;
+
+        let path = std::env::temp_dir().join(format!("bft-daemon-{}.sock", std::process::id()));
+        let listening = path.clone();
+        std::thread::spawn(move || daemon(&listening, LIMITS));
+        let mut stream = loop {
+            if let Ok(stream) = UnixStream::connect(&path) {
+                break stream;
+            }
+            std::thread::yield_now();
+        };
+        stream
+            .write_all(&frame(&json!({"code": "++++++++[>++++++++<-]>+."})))
+            .unwrap();
+        let body = read_frame(&mut stream, usize::MAX).unwrap().unwrap();
+        let response: Value = serde_json::from_slice(&body).unwrap();
+        assert_eq!(response["output"], "A");
+        let _ = std::fs::remove_file(&path);
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn leaves_other_files_alone() {
+        let path = std::env::temp_dir().join(format!("bft-daemon-{}.txt", std::process::id()));
+        std::fs::write(&path, "keep").unwrap();
+        let err = daemon(&path, LIMITS).unwrap_err();
+        assert_eq!(err.kind(), io::ErrorKind::AlreadyExists);
+        assert_eq!(std::fs::read_to_string(&path).unwrap(), "keep");
+        let _ = std::fs::remove_file(&path);
+    }
+}
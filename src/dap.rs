@@ -0,0 +1,752 @@
+//! A [Debug Adapter Protocol](https://microsoft.github.io/debug-adapter-protocol/) server, so
+//! that editors can debug Brainf*ck programs with their native debugging UI.
+//!
+//! Requests are read on a thread of their own, so that a program that's been told to continue
+//! runs a slice at a time, and can still be paused or disconnected from between slices.
+
+use std::io;
+use std::io::{BufRead, Cursor, Write};
+use std::num::NonZeroUsize;
+use std::path::PathBuf;
+use std::sync::mpsc::{channel, TryRecvError};
+use std::thread;
+
+use serde_json::{json, Value};
+
+use bft_interp::{BftError, VMError, BFVM};
+use bft_types::{Alphabet, BFprogram, Extension};
+
+use crate::debugger::{Debugger, Stop};
+use crate::exit_code;
+
+/// DAP requires a thread to stop, but Brainf*ck programs only ever have the one.
+const THREAD_ID: u64 = 1;
+
+/// Variable reference for the VM's registers.
+const REGISTERS_REF: u64 = 1;
+
+/// Variable reference for the cells of the tape.
+const TAPE_REF: u64 = 2;
+
+/// Number of instructions a continued program runs before the server checks for requests.
+const SLICE: u64 = 100_000;
+
+/// Read a single message framed with a `Content-Length` header. Returns `None` once the input is
+/// exhausted.
+pub fn read_message<R: BufRead>(input: &mut R) -> io::Result<Option<Value>> {
+    let mut content_length = None;
+    loop {
+        let mut line = String::new();
+        if input.read_line(&mut line)? == 0 {
+            return Ok(None);
+        }
+        let line = line.trim_end();
+        if line.is_empty() {
+            break;
+        }
+        if let Some((name, value)) = line.split_once(':') {
+            if name.eq_ignore_ascii_case("Content-Length") {
+                content_length =
+                    Some(value.trim().parse::<usize>().map_err(|err| {
+                        io::Error::new(io::ErrorKind::InvalidData, err.to_string())
+                    })?);
+            }
+        }
+    }
+    let length = content_length.ok_or_else(|| {
+        io::Error::new(io::ErrorKind::InvalidData, "Missing Content-Length header")
+    })?;
+    let mut body = vec![0; length];
+    input.read_exact(&mut body)?;
+    serde_json::from_slice(&body)
+        .map(Some)
+        .map_err(|err| io::Error::new(io::ErrorKind::InvalidData, err))
+}
+
+/// Write a single message framed with a `Content-Length` header.
+pub fn write_message<W: Write>(output: &mut W, message: &Value) -> io::Result<()> {
+    let body = message.to_string();
+    write!(output, "Content-Length: {}\r\n\r\n{}", body.len(), body)?;
+    output.flush()
+}
+
+/// The state of a single debugging session.
+struct Server<W> {
+    output: W,
+    seq: u64,
+    debugger: Option<Debugger>,
+    stop_on_entry: bool,
+    configured: bool,
+    pending_breakpoints: Vec<usize>,
+    /// Whether the program has been told to continue, and hasn't stopped since.
+    running: bool,
+}
+
+impl<W: Write> Server<W> {
+    fn send(&mut self, mut message: Value) -> io::Result<()> {
+        self.seq += 1;
+        message["seq"] = json!(self.seq);
+        write_message(&mut self.output, &message)
+    }
+
+    fn respond(&mut self, request: &Value, result: Result<Value, String>) -> io::Result<()> {
+        let mut response = json!({
+            "type": "response",
+            "request_seq": request["seq"],
+            "command": request["command"],
+            "success": result.is_ok(),
+        });
+        match result {
+            Ok(body) => response["body"] = body,
+            Err(message) => response["message"] = json!(message),
+        }
+        self.send(response)
+    }
+
+    fn event(&mut self, event: &str, body: Value) -> io::Result<()> {
+        let mut message = json!({"type": "event", "event": event});
+        message["body"] = body;
+        self.send(message)
+    }
+
+    /// Handle a single request. Returns false once the client has disconnected.
+    fn handle(&mut self, request: &Value) -> io::Result<bool> {
+        let args = &request["arguments"];
+        match request["command"].as_str().unwrap_or_default() {
+            "initialize" => {
+                self.respond(
+                    request,
+                    Ok(json!({
+                        "supportsConfigurationDoneRequest": true,
+                        "supportsSingleThreadExecutionRequests": false,
+                    })),
+                )?;
+                self.event("initialized", json!({}))?;
+            }
+            "launch" => self.launch(request)?,
+            "setBreakpoints" => self.set_breakpoints(request)?,
+            "configurationDone" => {
+                self.configured = true;
+                self.respond(request, Ok(json!({})))?;
+                if self.debugger.is_some() {
+                    self.start()?;
+                }
+            }
+            "threads" => self.respond(
+                request,
+                Ok(json!({"threads": [{"id": THREAD_ID, "name": "main"}]})),
+            )?,
+            "stackTrace" => {
+                let body = self.debugger.as_ref().map(stack_trace);
+                self.respond(request, body.ok_or_else(not_launched))?;
+            }
+            "scopes" => {
+                let tape_len = self
+                    .debugger
+                    .as_ref()
+                    .map_or(0, |dbg| dbg.vm().tape().len());
+                self.respond(
+                    request,
+                    Ok(json!({"scopes": [
+                        {
+                            "name": "Registers",
+                            "variablesReference": REGISTERS_REF,
+                            "expensive": false,
+                        },
+                        {
+                            "name": "Tape",
+                            "variablesReference": TAPE_REF,
+                            "indexedVariables": tape_len,
+                            "expensive": false,
+                        },
+                    ]})),
+                )?;
+            }
+            "variables" => {
+                let body = self.debugger.as_ref().map(|dbg| variables(dbg, args));
+                self.respond(request, body.ok_or_else(not_launched))?;
+            }
+            "continue" => {
+                self.respond(request, Ok(json!({"allThreadsContinued": true})))?;
+                self.running = self.debugger.is_some();
+            }
+            "pause" => {
+                self.respond(request, Ok(json!({})))?;
+                if std::mem::take(&mut self.running) {
+                    self.event(
+                        "stopped",
+                        json!({"reason": "pause", "threadId": THREAD_ID, "allThreadsStopped": true}),
+                    )?;
+                }
+            }
+            "next" => {
+                self.respond(request, Ok(json!({})))?;
+                self.execute(Debugger::step_over)?;
+            }
+            "stepIn" => {
+                self.respond(request, Ok(json!({})))?;
+                self.execute(Debugger::step_in)?;
+            }
+            "stepOut" => {
+                self.respond(request, Ok(json!({})))?;
+                self.execute(Debugger::step_out)?;
+            }
+            "disconnect" | "terminate" => {
+                self.respond(request, Ok(json!({})))?;
+                return Ok(false);
+            }
+            command => {
+                self.respond(request, Err(format!("Unsupported command '{command}'")))?;
+            }
+        }
+        Ok(true)
+    }
+
+    fn launch(&mut self, request: &Value) -> io::Result<()> {
+        match launch(&request["arguments"]) {
+            Ok((mut debugger, stop_on_entry)) => {
+                debugger.set_line_breakpoints(&self.pending_breakpoints);
+                self.debugger = Some(debugger);
+                self.stop_on_entry = stop_on_entry;
+                self.respond(request, Ok(json!({})))?;
+                if self.configured {
+                    self.start()?;
+                }
+                Ok(())
+            }
+            Err(message) => self.respond(request, Err(message)),
+        }
+    }
+
+    fn set_breakpoints(&mut self, request: &Value) -> io::Result<()> {
+        let lines: Vec<usize> = request["arguments"]["breakpoints"]
+            .as_array()
+            .map(|bps| {
+                bps.iter()
+                    .filter_map(|bp| bp["line"].as_u64())
+                    .filter_map(|line| usize::try_from(line).ok())
+                    .collect()
+            })
+            .unwrap_or_default();
+        let breakpoints: Vec<Value> = if let Some(dbg) = self.debugger.as_mut() {
+            dbg.set_line_breakpoints(&lines)
+                .into_iter()
+                .map(|line| match line {
+                    Some(line) => json!({"verified": true, "line": line}),
+                    None => json!({"verified": false}),
+                })
+                .collect()
+        } else {
+            // The program hasn't been loaded yet, so we can't check the lines.
+            let breakpoints = lines
+                .iter()
+                .map(|line| json!({"verified": true, "line": line}))
+                .collect();
+            self.pending_breakpoints = lines;
+            breakpoints
+        };
+        self.respond(request, Ok(json!({ "breakpoints": breakpoints })))
+    }
+
+    /// Begin execution once the program is loaded and the client has finished configuring.
+    fn start(&mut self) -> io::Result<()> {
+        if self.stop_on_entry {
+            if let Some(dbg) = self.debugger.as_mut() {
+                dbg.stop_on_entry();
+            }
+            self.event(
+                "stopped",
+                json!({"reason": "entry", "threadId": THREAD_ID, "allThreadsStopped": true}),
+            )
+        } else {
+            self.running = true;
+            Ok(())
+        }
+    }
+
+    /// Run the next slice of a continued program, reporting to the client if it stops.
+    fn run_slice(&mut self) -> io::Result<()> {
+        let Some(dbg) = self.debugger.as_mut() else {
+            self.running = false;
+            return Ok(());
+        };
+        match dbg.resume_for(SLICE) {
+            Ok(Stop::Step) => self.send_output(),
+            result => {
+                self.running = false;
+                self.report(result)
+            }
+        }
+    }
+
+    /// Run one of the debugger's execution commands and report the result to the client.
+    fn execute(
+        &mut self,
+        command: impl FnOnce(&mut Debugger) -> Result<Stop, VMError>,
+    ) -> io::Result<()> {
+        self.running = false;
+        let Some(dbg) = self.debugger.as_mut() else {
+            return Ok(());
+        };
+        let result = command(dbg);
+        self.report(result)
+    }
+
+    /// Send the program's output since it was last sent to the client.
+    fn send_output(&mut self) -> io::Result<()> {
+        let Some(dbg) = self.debugger.as_mut() else {
+            return Ok(());
+        };
+        let output = dbg.take_output();
+        if output.is_empty() {
+            return Ok(());
+        }
+        self.event(
+            "output",
+            json!({"category": "stdout", "output": String::from_utf8_lossy(&output)}),
+        )
+    }
+
+    /// Report why the program stopped to the client, with any output it wrote first.
+    fn report(&mut self, result: Result<Stop, VMError>) -> io::Result<()> {
+        self.send_output()?;
+        match result {
+            Ok(Stop::Step) => self.event(
+                "stopped",
+                json!({"reason": "step", "threadId": THREAD_ID, "allThreadsStopped": true}),
+            ),
+            Ok(Stop::Breakpoint) => self.event(
+                "stopped",
+                json!({"reason": "breakpoint", "threadId": THREAD_ID, "allThreadsStopped": true}),
+            ),
+            Ok(Stop::Finished) => {
+                let status = self
+                    .debugger
+                    .as_ref()
+                    .and_then(|dbg| dbg.vm().exit_status())
+                    .unwrap_or(0);
+                self.exit(status)
+            }
+            Err(err) => {
+                self.event(
+                    "output",
+                    json!({"category": "stderr", "output": format!("{err}\n")}),
+                )?;
+                self.exit(exit_code::for_error(&BftError::Runtime(err)))
+            }
+        }
+    }
+
+    fn exit(&mut self, code: u8) -> io::Result<()> {
+        self.event("exited", json!({ "exitCode": code }))?;
+        self.event("terminated", json!({}))
+    }
+}
+
+fn not_launched() -> String {
+    String::from("No program has been launched")
+}
+
+/// The alphabet for the `dialect` and `extensions` in the arguments of a `launch` request,
+/// along with the extensions.
+fn launch_alphabet(args: &Value) -> Result<(Alphabet, Vec<Extension>), String> {
+    let mut alphabet = match args["dialect"].as_str().unwrap_or("brainfuck") {
+        "brainfuck" => Alphabet::default(),
+        "ook" => Alphabet::ook(),
+        "boolfuck" => return Err(String::from("Boolfuck programs can't be debugged")),
+        dialect => return Err(format!("Unknown dialect '{dialect}'")),
+    };
+    let extensions = args["extensions"]
+        .as_array()
+        .map(Vec::as_slice)
+        .unwrap_or_default()
+        .iter()
+        .map(|name| {
+            name.as_str()
+                .ok_or_else(|| String::from("Extensions must be given by name"))?
+                .parse::<Extension>()
+        })
+        .collect::<Result<Vec<_>, _>>()?;
+    for extension in &extensions {
+        alphabet
+            .add_extension(*extension)
+            .map_err(|err| err.to_string())?;
+    }
+    Ok((alphabet, extensions))
+}
+
+/// Load the program described by the arguments of a `launch` request.
+fn launch(args: &Value) -> Result<(Debugger, bool), String> {
+    let path = args["program"]
+        .as_str()
+        .ok_or_else(|| String::from("Missing 'program' in launch arguments"))?;
+    let (alphabet, extensions) = launch_alphabet(args)?;
+    let mut program = BFprogram::from_file_with_alphabet(path, &alphabet)
+        .map_err(|err| format!("{path}: {err}"))?;
+    program.validate_brackets().map_err(|err| err.to_string())?;
+    program
+        .validate_extensions(&extensions)
+        .map_err(|err| err.to_string())?;
+    let cells = args["cells"]
+        .as_u64()
+        .and_then(|c| usize::try_from(c).ok())
+        .and_then(NonZeroUsize::new);
+    let extensible = args["extensible"].as_bool().unwrap_or(false);
+    let mut vm = BFVM::new(cells, extensible);
+    if extensions.contains(&Extension::Multitape) {
+        let tapes = args["tapes"]
+            .as_u64()
+            .and_then(|t| usize::try_from(t).ok())
+            .and_then(NonZeroUsize::new)
+            .unwrap_or(NonZeroUsize::new(2).unwrap_or(NonZeroUsize::MIN));
+        vm = vm.with_tapes(tapes);
+    }
+    let input = args["input"]
+        .as_str()
+        .unwrap_or_default()
+        .as_bytes()
+        .to_vec();
+    let debugger = Debugger::new(program, vm, Box::new(Cursor::new(input)));
+    Ok((debugger, args["stopOnEntry"].as_bool().unwrap_or(false)))
+}
+
+fn stack_trace(dbg: &Debugger) -> Value {
+    let path = dbg.program().source();
+    let path = std::fs::canonicalize(path).unwrap_or_else(|_| PathBuf::from(path));
+    let (name, line, column) = dbg.current_instruction().map_or_else(
+        || (String::from("end of program"), 0, 0),
+        |inst| {
+            (
+                inst.instruction().to_string(),
+                inst.line_number(),
+                inst.char_number(),
+            )
+        },
+    );
+    json!({
+        "stackFrames": [{
+            "id": 0,
+            "name": name,
+            "source": {"path": path},
+            "line": line,
+            "column": column,
+        }],
+        "totalFrames": 1,
+    })
+}
+
+fn variables(dbg: &Debugger, args: &Value) -> Value {
+    let vm = dbg.vm();
+    let variables: Vec<Value> = match args["variablesReference"].as_u64() {
+        Some(REGISTERS_REF) => vec![
+            json!({"name": "pc", "value": vm.pc().to_string(), "variablesReference": 0}),
+            json!({"name": "head", "value": vm.head().to_string(), "variablesReference": 0}),
+            json!({
+                "name": "cell",
+                "value": vm.tape()[vm.head()].to_string(),
+                "variablesReference": 0,
+            }),
+        ],
+        Some(TAPE_REF) => {
+            let tape = vm.tape();
+            let start = args["start"]
+                .as_u64()
+                .and_then(|s| usize::try_from(s).ok())
+                .unwrap_or(0)
+                .min(tape.len());
+            let count = args["count"]
+                .as_u64()
+                .and_then(|c| usize::try_from(c).ok())
+                .unwrap_or(tape.len());
+            tape[start..]
+                .iter()
+                .take(count)
+                .enumerate()
+                .map(|(offset, cell)| {
+                    json!({
+                        "name": format!("[{}]", start + offset),
+                        "value": cell.to_string(),
+                        "variablesReference": 0,
+                    })
+                })
+                .collect()
+        }
+        _ => Vec::new(),
+    };
+    json!({ "variables": variables })
+}
+
+/// Serve a single debugging session, reading requests from `input` and writing responses and
+/// events to `output`, until the client disconnects.
+///
+/// # Errors
+/// Returns an error if reading or writing a message fails.
+pub fn serve<R: BufRead + Send + 'static, W: Write>(mut input: R, output: W) -> io::Result<()> {
+    let (sender, requests) = channel();
+    // The reader isn't joined, as it may be waiting for input that never comes once the client
+    // has disconnected.
+    thread::spawn(move || loop {
+        let message = read_message(&mut input);
+        let last = !matches!(message, Ok(Some(_)));
+        if sender.send(message).is_err() || last {
+            break;
+        }
+    });
+    let mut server = Server {
+        output,
+        seq: 0,
+        debugger: None,
+        stop_on_entry: false,
+        configured: false,
+        pending_breakpoints: Vec::new(),
+        running: false,
+    };
+    loop {
+        if server.running {
+            server.run_slice()?;
+        }
+        let message = if server.running {
+            match requests.try_recv() {
+                Ok(message) => message,
+                Err(TryRecvError::Empty) => continue,
+                Err(TryRecvError::Disconnected) => Ok(None),
+            }
+        } else {
+            requests.recv().unwrap_or(Ok(None))
+        };
+        let Some(request) = message? else {
+            break;
+        };
+        if !server.handle(&request)? {
+            break;
+        }
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn session(requests: &[Value]) -> Vec<Value> {
+        let mut input = Vec::new();
+        for (seq, request) in requests.iter().enumerate() {
+            let mut request = request.clone();
+            request["seq"] = json!(seq + 1);
+            request["type"] = json!("request");
+            write_message(&mut input, &request).unwrap();
+        }
+        let mut output = Vec::new();
+        serve(Cursor::new(input), &mut output).unwrap();
+
+        let mut output = Cursor::new(output);
+        let mut messages = Vec::new();
+        while let Some(message) = read_message(&mut output).unwrap() {
+            messages.push(message);
+        }
+        messages
+    }
+
+    /// Write `code` to a temporary file for a session to launch.
+    fn program(name: &str, code: &str) -> PathBuf {
+        let path = std::env::temp_dir().join(format!("bft-dap-{name}-{}.b", std::process::id()));
+        std::fs::write(&path, code).unwrap();
+        path
+    }
+
+    fn events(messages: &[Value]) -> Vec<&str> {
+        messages
+            .iter()
+            .filter_map(|m| m["event"].as_str())
+            .collect()
+    }
+
+    #[test]
+    fn message_framing() {
+        let mut buf = Vec::new();
+        write_message(&mut buf, &json!({"a": 1})).unwrap();
+        assert_eq!(buf, b"Content-Length: 7\r\n\r\n{\"a\":1}");
+        let message = read_message(&mut Cursor::new(buf)).unwrap();
+        assert_eq!(message, Some(json!({"a": 1})));
+    }
+
+    #[test]
+    fn missing_content_length() {
+        let err = read_message(&mut Cursor::new(b"Foo: 1\r\n\r\n{}")).unwrap_err();
+        assert_eq!(err.kind(), io::ErrorKind::InvalidData);
+    }
+
+    #[test]
+    fn breakpoint_session() {
+        let messages = session(&[
+            json!({"command": "initialize", "arguments": {}}),
+            json!({"command": "launch", "arguments": {"program": "data/session1.txt"}}),
+            json!({
+                "command": "setBreakpoints",
+                "arguments": {"source": {"path": "data/session1.txt"}, "breakpoints": [{"line": 12}]},
+            }),
+            json!({"command": "configurationDone"}),
+            json!({"command": "stackTrace", "arguments": {"threadId": 1}}),
+            json!({"command": "variables", "arguments": {"variablesReference": REGISTERS_REF}}),
+            json!({"command": "variables", "arguments": {"variablesReference": TAPE_REF, "start": 0, "count": 2}}),
+            json!({"command": "stepIn", "arguments": {"threadId": 1}}),
+            json!({"command": "disconnect"}),
+        ]);
+
+        assert_eq!(events(&messages), vec!["initialized", "stopped", "stopped"]);
+        let stopped: Vec<&Value> = messages
+            .iter()
+            .filter(|m| m["event"] == "stopped")
+            .collect();
+        assert_eq!(stopped[0]["body"]["reason"], "breakpoint");
+        assert_eq!(stopped[1]["body"]["reason"], "step");
+
+        let breakpoints = messages
+            .iter()
+            .find(|m| m["command"] == "setBreakpoints")
+            .unwrap();
+        assert_eq!(breakpoints["body"]["breakpoints"][0]["line"], 12);
+
+        let trace = messages
+            .iter()
+            .find(|m| m["command"] == "stackTrace")
+            .unwrap();
+        assert_eq!(trace["body"]["stackFrames"][0]["line"], 12);
+        assert_eq!(trace["body"]["stackFrames"][0]["column"], 7);
+
+        let variables: Vec<&Value> = messages
+            .iter()
+            .filter(|m| m["command"] == "variables")
+            .collect();
+        assert_eq!(variables[0]["body"]["variables"][1]["name"], "head");
+        assert_eq!(variables[1]["body"]["variables"][0]["name"], "[0]");
+        assert_eq!(
+            variables[1]["body"]["variables"].as_array().unwrap().len(),
+            2
+        );
+    }
+
+    #[test]
+    fn stop_on_entry_with_a_breakpoint_there() {
+        let messages = session(&[
+            json!({"command": "initialize", "arguments": {}}),
+            json!({"command": "launch", "arguments": {"program": "data/session1.txt", "stopOnEntry": true, "extensible": true}}),
+            json!({
+                "command": "setBreakpoints",
+                "arguments": {"source": {"path": "data/session1.txt"}, "breakpoints": [{"line": 1}]},
+            }),
+            json!({"command": "configurationDone"}),
+            json!({"command": "continue", "arguments": {"threadId": 1}}),
+            json!({"command": "disconnect"}),
+        ]);
+        assert_eq!(
+            events(&messages),
+            vec!["initialized", "stopped", "output", "exited", "terminated"]
+        );
+        let stopped = messages.iter().find(|m| m["event"] == "stopped").unwrap();
+        assert_eq!(stopped["body"]["reason"], "entry");
+    }
+
+    #[test]
+    fn run_to_completion() {
+        let messages = session(&[
+            json!({"command": "initialize", "arguments": {}}),
+            json!({"command": "configurationDone"}),
+            json!({"command": "launch", "arguments": {"program": "data/session1.txt", "extensible": true}}),
+            json!({"command": "disconnect"}),
+        ]);
+        assert_eq!(
+            events(&messages),
+            vec!["initialized", "output", "exited", "terminated"]
+        );
+        let output = messages.iter().find(|m| m["event"] == "output").unwrap();
+        assert_eq!(output["body"]["category"], "stdout");
+        assert_eq!(output["body"]["output"], "hello world");
+    }
+
+    #[test]
+    fn launch_errors() {
+        let messages = session(&[
+            json!({"command": "launch", "arguments": {}}),
+            json!({"command": "launch", "arguments": {"program": "no/such/file.b"}}),
+            json!({"command": "stackTrace"}),
+            json!({"command": "bogus"}),
+        ]);
+        assert!(messages.iter().all(|m| m["success"] == false));
+        assert_eq!(
+            messages[0]["message"],
+            "Missing 'program' in launch arguments"
+        );
+        assert_eq!(messages[3]["message"], "Unsupported command 'bogus'");
+    }
+
+    #[test]
+    fn pausing_an_endless_loop() {
+        let path = program("endless", "+[]");
+        let messages = session(&[
+            json!({"command": "initialize", "arguments": {}}),
+            json!({"command": "launch", "arguments": {"program": path}}),
+            json!({"command": "configurationDone"}),
+            json!({"command": "pause", "arguments": {"threadId": 1}}),
+            json!({"command": "continue", "arguments": {"threadId": 1}}),
+            json!({"command": "disconnect"}),
+        ]);
+        std::fs::remove_file(&path).unwrap();
+        assert_eq!(events(&messages), vec!["initialized", "stopped"]);
+        let stopped = messages.iter().find(|m| m["event"] == "stopped").unwrap();
+        assert_eq!(stopped["body"]["reason"], "pause");
+        assert_eq!(messages.last().unwrap()["command"], "disconnect");
+    }
+
+    #[test]
+    fn exit_status() {
+        let path = program("halt", "+++@");
+        let messages = session(&[
+            json!({"command": "initialize", "arguments": {}}),
+            json!({"command": "configurationDone"}),
+            json!({"command": "launch", "arguments": {"program": path, "extensions": ["halt"]}}),
+            json!({"command": "disconnect"}),
+        ]);
+        let exited = messages.iter().find(|m| m["event"] == "exited").unwrap();
+        assert_eq!(exited["body"]["exitCode"], 3);
+
+        let messages = session(&[
+            json!({"command": "initialize", "arguments": {}}),
+            json!({"command": "configurationDone"}),
+            json!({"command": "launch", "arguments": {"program": path}}),
+            json!({"command": "disconnect"}),
+        ]);
+        let exited = messages.iter().find(|m| m["event"] == "exited").unwrap();
+        assert_eq!(exited["body"]["exitCode"], 0);
+
+        let messages = session(&[
+            json!({"command": "initialize", "arguments": {}}),
+            json!({"command": "launch", "arguments": {"program": path, "extensions": ["bogus"]}}),
+            json!({"command": "launch", "arguments": {"program": path, "dialect": "boolfuck"}}),
+        ]);
+        std::fs::remove_file(&path).unwrap();
+        let launches: Vec<&Value> = messages
+            .iter()
+            .filter(|m| m["command"] == "launch")
+            .collect();
+        assert_eq!(launches[0]["message"], "Unknown extension 'bogus'");
+        assert_eq!(
+            launches[1]["message"],
+            "Boolfuck programs can't be debugged"
+        );
+    }
+
+    #[test]
+    fn runtime_errors() {
+        let path = program("underflow", "<");
+        let messages = session(&[
+            json!({"command": "initialize", "arguments": {}}),
+            json!({"command": "configurationDone"}),
+            json!({"command": "launch", "arguments": {"program": path}}),
+            json!({"command": "disconnect"}),
+        ]);
+        std::fs::remove_file(&path).unwrap();
+        let exited = messages.iter().find(|m| m["event"] == "exited").unwrap();
+        assert_eq!(exited["body"]["exitCode"], exit_code::RUNTIME);
+    }
+}
@@ -0,0 +1,507 @@
+//! Non-interactive debugger sessions driven by a script of debugger commands.
+//!
+//! Scripts contain one command per line, with `#` starting a comment:
+//!
+//! ```text
+//! break LINE              Stop at the first instruction on or after LINE
+//! run                     Run until a breakpoint is hit, or the program finishes
+//! step [N]                Execute N instructions (default 1)
+//! next                    Step over the loop starting at the current instruction
+//! finish                  Run until the current loop exits
+//! dump [START [COUNT]]    Print COUNT cells (default 10) starting from START (default head)
+//! assert cell N == V      Fail unless cell N holds V
+//! assert head == V        Fail unless the head is at V
+//! assert output == "TEXT" Fail unless the program has output exactly TEXT so far
+//! assert finished         Fail unless the program has run to completion
+//! snapshot FILE           Save the state of the run to FILE, for `bft snapshot diff`
+//! watch CELL              Record each change to CELL from now on
+//! history cell CELL       Print each change to the watched CELL, and the instruction that made it
+//! ```
+
+use std::error::Error;
+use std::fmt;
+use std::fmt::{Display, Formatter};
+use std::io;
+use std::io::Write;
+use std::path::PathBuf;
+
+use bft_interp::{BftError, VMError};
+
+use crate::debugger::{Debugger, Stop};
+
+/// Number of cells printed by `dump` when no count is given.
+const DEFAULT_DUMP_COUNT: usize = 10;
+
+/// A single command from a debugger script.
+#[derive(Debug, PartialEq)]
+enum Command {
+    Break(usize),
+    Run,
+    Step(usize),
+    Next,
+    Finish,
+    Dump(Option<usize>, usize),
+    AssertCell(usize, u8),
+    AssertHead(usize),
+    AssertOutput(Vec<u8>),
+    AssertFinished,
+    Snapshot(PathBuf),
+    Watch(usize),
+    History(usize),
+}
+
+/// Errors while running a debugger script. Each carries the line of the script that failed.
+#[derive(Debug)]
+pub enum ScriptError {
+    /// The script line couldn't be understood.
+    Parse(usize, String),
+
+    /// An `assert` command didn't hold.
+    AssertionFailed(usize, String),
+
+    /// The program failed while running.
+    Runtime(usize, VMError),
+
+    /// The debugger's report couldn't be written.
+    Io(io::Error),
+}
+
+impl Display for ScriptError {
+    fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::Parse(line, message) => write!(f, "debug script line {line}: {message}"),
+            Self::AssertionFailed(line, message) => {
+                write!(f, "debug script line {line}: assertion failed: {message}")
+            }
+            Self::Runtime(line, err) => write!(f, "debug script line {line}: {err}"),
+            Self::Io(err) => write!(f, "{err}"),
+        }
+    }
+}
+
+impl Error for ScriptError {
+    fn source(&self) -> Option<&(dyn Error + 'static)> {
+        match self {
+            Self::Runtime(_, err) => Some(err),
+            Self::Io(err) => Some(err),
+            _ => None,
+        }
+    }
+}
+
+impl From<io::Error> for ScriptError {
+    fn from(err: io::Error) -> Self {
+        Self::Io(err)
+    }
+}
+
+/// Failures of the program, or of reading and writing, keep their own kind so that they're
+/// reported, and exit, as they would without the debugger.
+impl From<ScriptError> for BftError {
+    fn from(err: ScriptError) -> Self {
+        match err {
+            ScriptError::Runtime(_, err) => Self::Runtime(err),
+            ScriptError::Io(err) => Self::Io(err),
+            err => Self::Other(Box::new(err)),
+        }
+    }
+}
+
+fn parse_number<T: std::str::FromStr>(word: Option<&str>, what: &str) -> Result<T, String> {
+    let word = word.ok_or_else(|| format!("missing {what}"))?;
+    word.parse()
+        .map_err(|_| format!("'{word}' is not a valid {what}"))
+}
+
+/// Parse a single line of a script, returning `None` for blank lines and comments.
+fn parse_line(line: &str) -> Result<Option<Command>, String> {
+    let line = line.split_once('#').map_or(line, |(code, _)| code).trim();
+    let mut words = line.split_whitespace();
+    let Some(command) = words.next() else {
+        return Ok(None);
+    };
+    let command = match command {
+        "break" => Command::Break(parse_number(words.next(), "line number")?),
+        "run" | "continue" => Command::Run,
+        "step" => Command::Step(
+            words
+                .next()
+                .map_or(Ok(1), |n| parse_number(Some(n), "step count"))?,
+        ),
+        "next" => Command::Next,
+        "finish" => Command::Finish,
+        "dump" => {
+            let start = words
+                .next()
+                .map(|n| parse_number(Some(n), "cell index"))
+                .transpose()?;
+            let count = words
+                .next()
+                .map_or(Ok(DEFAULT_DUMP_COUNT), |n| parse_number(Some(n), "count"))?;
+            Command::Dump(start, count)
+        }
+        "assert" => return parse_assert(line["assert".len()..].trim()).map(Some),
+        "snapshot" => Command::Snapshot(PathBuf::from(
+            words.next().ok_or_else(|| String::from("missing file"))?,
+        )),
+        "watch" => Command::Watch(parse_number(words.next(), "cell index")?),
+        "history" => match words.next() {
+            Some("cell") => Command::History(parse_number(words.next(), "cell index")?),
+            _ => return Err(String::from("expected 'cell' after 'history'")),
+        },
+        _ => return Err(format!("unknown command '{command}'")),
+    };
+    match words.next() {
+        Some(word) => Err(format!("unexpected '{word}'")),
+        None => Ok(Some(command)),
+    }
+}
+
+fn parse_assert(condition: &str) -> Result<Command, String> {
+    if condition == "finished" {
+        return Ok(Command::AssertFinished);
+    }
+    let (lhs, rhs) = condition
+        .split_once("==")
+        .ok_or_else(|| format!("expected '==' in assertion '{condition}'"))?;
+    let mut lhs = lhs.split_whitespace();
+    let rhs = rhs.trim();
+    let command = match lhs.next() {
+        Some("cell") => Command::AssertCell(
+            parse_number(lhs.next(), "cell index")?,
+            parse_number(Some(rhs), "cell value")?,
+        ),
+        Some("head") => Command::AssertHead(parse_number(Some(rhs), "head position")?),
+        Some("output") => {
+            let text = rhs
+                .strip_prefix('"')
+                .and_then(|r| r.strip_suffix('"'))
+                .ok_or_else(|| String::from("expected quoted output"))?;
+            Command::AssertOutput(unescape(text)?)
+        }
+        _ => return Err(format!("can't assert '{condition}'")),
+    };
+    match lhs.next() {
+        Some(word) => Err(format!("unexpected '{word}'")),
+        None => Ok(command),
+    }
+}
+
+/// Handle the `\n`, `\t`, `\\`, `\"` and `\xNN` escapes in quoted strings.
+fn unescape(text: &str) -> Result<Vec<u8>, String> {
+    let mut result = Vec::new();
+    let mut chars = text.chars();
+    while let Some(c) = chars.next() {
+        if c != '\\' {
+            let mut buf = [0; 4];
+            result.extend_from_slice(c.encode_utf8(&mut buf).as_bytes());
+            continue;
+        }
+        match chars.next() {
+            Some('n') => result.push(b'\n'),
+            Some('t') => result.push(b'\t'),
+            Some('\\') => result.push(b'\\'),
+            Some('"') => result.push(b'"'),
+            Some('x') => {
+                let hex: String = chars.by_ref().take(2).collect();
+                let byte = u8::from_str_radix(&hex, 16)
+                    .map_err(|_| format!("invalid escape '\\x{hex}'"))?;
+                result.push(byte);
+            }
+            Some(c) => return Err(format!("unknown escape '\\{c}'")),
+            None => return Err(String::from("unterminated escape")),
+        }
+    }
+    Ok(result)
+}
+
+/// Check an `assert` command, describing the failure if it doesn't hold.
+fn check_assertion(command: &Command, dbg: &Debugger, all_output: &[u8]) -> Option<String> {
+    match command {
+        Command::AssertCell(idx, expected) => {
+            let actual = dbg.vm().tape().get(*idx).copied();
+            (actual != Some(*expected)).then(|| {
+                let actual = actual.map_or(String::from("off the tape"), |a| a.to_string());
+                format!("cell {idx} is {actual}, expected {expected}")
+            })
+        }
+        Command::AssertHead(expected) => (dbg.vm().head() != *expected)
+            .then(|| format!("head is at {}, expected {}", dbg.vm().head(), expected)),
+        Command::AssertOutput(expected) => (all_output != expected).then(|| {
+            format!(
+                "output is {:?}, expected {:?}",
+                String::from_utf8_lossy(all_output),
+                String::from_utf8_lossy(expected)
+            )
+        }),
+        Command::AssertFinished => {
+            (!dbg.is_finished()).then(|| String::from("program has not finished"))
+        }
+        _ => None,
+    }
+}
+
+/// Write the changes made to the watched `cell` to `report`.
+fn write_history<R: Write>(dbg: &Debugger, cell: usize, report: &mut R) -> io::Result<()> {
+    let Some(writes) = dbg.history(cell) else {
+        return writeln!(report, "cell {cell} is not being watched");
+    };
+    match writes.len() {
+        1 => writeln!(report, "cell {cell}: 1 change")?,
+        count => writeln!(report, "cell {cell}: {count} changes")?,
+    }
+    for write in writes {
+        writeln!(
+            report,
+            "step {} at {}: {} -> {}",
+            write.step,
+            dbg.program().instructions()[write.pc].location(),
+            write.old,
+            write.new
+        )?;
+    }
+    Ok(())
+}
+
+/// Run the debugger commands in `script` against `dbg`. The program's output is passed through
+/// to `output`, and a transcript of the session is written to `report`.
+///
+/// # Errors
+/// Stops at the first line that can't be parsed, fails an assertion, or causes a runtime error.
+pub fn run_script<O: Write, R: Write>(
+    dbg: &mut Debugger,
+    script: &str,
+    output: &mut O,
+    report: &mut R,
+) -> Result<(), ScriptError> {
+    let mut all_output = Vec::new();
+    for (idx, line) in script.lines().enumerate() {
+        let line_number = idx + 1;
+        let Some(command) = parse_line(line).map_err(|msg| ScriptError::Parse(line_number, msg))?
+        else {
+            continue;
+        };
+
+        let stop = match command {
+            Command::Break(line) => {
+                match dbg.add_line_breakpoint(line) {
+                    Some(actual) => writeln!(report, "breakpoint set on line {actual}")?,
+                    None => writeln!(report, "no instructions on or after line {line}")?,
+                }
+                None
+            }
+            Command::Run => Some(dbg.resume()),
+            Command::Step(n) => {
+                let mut result = Ok(Stop::Step);
+                for _ in 0..n {
+                    result = dbg.step_in();
+                    if !matches!(result, Ok(Stop::Step)) {
+                        break;
+                    }
+                }
+                Some(result)
+            }
+            Command::Next => Some(dbg.step_over()),
+            Command::Finish => Some(dbg.step_out()),
+            Command::Dump(start, count) => {
+                let vm = dbg.vm();
+                let start = start.unwrap_or(vm.head());
+                for (offset, cell) in vm.tape().iter().skip(start).take(count).enumerate() {
+                    let marker = if start + offset == vm.head() {
+                        " <- head"
+                    } else {
+                        ""
+                    };
+                    writeln!(report, "cell {} = {}{}", start + offset, cell, marker)?;
+                }
+                None
+            }
+            Command::Snapshot(path) => {
+                std::fs::write(&path, dbg.vm().checkpoint(dbg.program()))?;
+                writeln!(report, "snapshot saved to {}", path.display())?;
+                None
+            }
+            Command::Watch(cell) => {
+                dbg.watch(cell);
+                writeln!(report, "watching cell {cell}")?;
+                None
+            }
+            Command::History(cell) => {
+                write_history(dbg, cell, report)?;
+                None
+            }
+            Command::AssertCell(..)
+            | Command::AssertHead(_)
+            | Command::AssertOutput(_)
+            | Command::AssertFinished => {
+                if let Some(failure) = check_assertion(&command, dbg, &all_output) {
+                    return Err(ScriptError::AssertionFailed(line_number, failure));
+                }
+                None
+            }
+        };
+
+        let program_output = dbg.take_output();
+        output.write_all(&program_output)?;
+        all_output.extend(program_output);
+
+        match stop
+            .transpose()
+            .map_err(|err| ScriptError::Runtime(line_number, err))?
+        {
+            Some(Stop::Finished) => writeln!(report, "program finished")?,
+            Some(stop) => {
+                let reason = if stop == Stop::Breakpoint {
+                    "breakpoint"
+                } else {
+                    "step"
+                };
+                let location = dbg
+                    .current_instruction()
+                    .map(bft_types::InputInstruction::location)
+                    .unwrap_or_default();
+                writeln!(report, "stopped at {location} ({reason})")?;
+            }
+            None => {}
+        }
+    }
+    output.flush()?;
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use bft_interp::BFVM;
+    use bft_types::BFprogram;
+
+    fn run(code: &str, script: &str) -> (Result<(), ScriptError>, String, String) {
+        let mut program = BFprogram::new("mod.test", code.as_bytes());
+        program.validate_brackets().unwrap();
+        let mut dbg = Debugger::new(program, BFVM::new(None, false), Box::new(io::empty()));
+        let mut output = Vec::new();
+        let mut report = Vec::new();
+        let result = run_script(&mut dbg, script, &mut output, &mut report);
+        (
+            result,
+            String::from_utf8(output).unwrap(),
+            String::from_utf8(report).unwrap(),
+        )
+    }
+
+    #[test]
+    fn parsing() {
+        assert_eq!(parse_line("  # nothing here"), Ok(None));
+        assert_eq!(parse_line("break 12"), Ok(Some(Command::Break(12))));
+        assert_eq!(parse_line("step"), Ok(Some(Command::Step(1))));
+        assert_eq!(parse_line("step 5 # five"), Ok(Some(Command::Step(5))));
+        assert_eq!(parse_line("dump"), Ok(Some(Command::Dump(None, 10))));
+        assert_eq!(parse_line("dump 3 2"), Ok(Some(Command::Dump(Some(3), 2))));
+        assert_eq!(
+            parse_line("assert cell 3 == 65"),
+            Ok(Some(Command::AssertCell(3, 65)))
+        );
+        assert_eq!(
+            parse_line(r#"assert output == "hi\n""#),
+            Ok(Some(Command::AssertOutput(Vec::from("hi\n"))))
+        );
+        assert_eq!(
+            parse_line("jump 3"),
+            Err(String::from("unknown command 'jump'"))
+        );
+        assert_eq!(
+            parse_line("step x"),
+            Err(String::from("'x' is not a valid step count"))
+        );
+        assert_eq!(parse_line("run now"), Err(String::from("unexpected 'now'")));
+        assert_eq!(
+            parse_line("snapshot a.bfsnap"),
+            Ok(Some(Command::Snapshot(PathBuf::from("a.bfsnap"))))
+        );
+        assert_eq!(parse_line("snapshot"), Err(String::from("missing file")));
+        assert_eq!(parse_line("watch 7"), Ok(Some(Command::Watch(7))));
+        assert_eq!(parse_line("history cell 7"), Ok(Some(Command::History(7))));
+        assert_eq!(
+            parse_line("history 7"),
+            Err(String::from("expected 'cell' after 'history'"))
+        );
+        assert_eq!(
+            parse_line(r#"assert output == "\xg0""#),
+            Err(String::from("invalid escape '\\xg0'"))
+        );
+    }
+
+    #[test]
+    fn passing_script() {
+        let (result, output, report) = run(
+            "+++.\n>++[<+>-]\n<.",
+            "break 2\nrun\nassert cell 0 == 3\ndump 0 2\nrun\nassert output == \"\\x03\\x05\"",
+        );
+        assert!(result.is_ok());
+        assert_eq!(output, "\x03\x05");
+        assert_eq!(
+            report,
+            "breakpoint set on line 2\nstopped at 2:1 (breakpoint)\ncell 0 = 3 <- head\ncell 1 = 0\nprogram finished\n"
+        );
+
+        let (result, _, _) = run(
+            "+++.>++[<+>-]<.",
+            "run\nassert finished\nassert cell 0 == 5",
+        );
+        assert!(result.is_ok());
+    }
+
+    #[test]
+    fn watching_cells() {
+        let (result, _, report) = run(
+            "++\n[-]",
+            "watch 0\nbreak 2\nrun\nhistory cell 0\nrun\nhistory cell 0\nhistory cell 1",
+        );
+        assert!(result.is_ok());
+        assert_eq!(
+            report,
+            "watching cell 0\n\
+             breakpoint set on line 2\n\
+             stopped at 2:1 (breakpoint)\n\
+             cell 0: 2 changes\n\
+             step 1 at 1:1: 0 -> 1\n\
+             step 2 at 1:2: 1 -> 2\n\
+             program finished\n\
+             cell 0: 4 changes\n\
+             step 1 at 1:1: 0 -> 1\n\
+             step 2 at 1:2: 1 -> 2\n\
+             step 4 at 2:2: 2 -> 1\n\
+             step 6 at 2:2: 1 -> 0\n\
+             cell 1 is not being watched\n"
+        );
+    }
+
+    #[test]
+    fn failing_assertion() {
+        let (result, _, _) = run("++", "step\nassert cell 0 == 2");
+        assert_eq!(
+            result.unwrap_err().to_string(),
+            "debug script line 2: assertion failed: cell 0 is 1, expected 2"
+        );
+    }
+
+    #[test]
+    fn runtime_error() {
+        let (result, _, _) = run("<", "run");
+        assert!(matches!(result, Err(ScriptError::Runtime(1, _))));
+    }
+
+    #[test]
+    fn exit_codes() {
+        use crate::exit_code;
+
+        let exit_code =
+            |code, script| exit_code::for_error(&run(code, script).0.unwrap_err().into());
+        assert_eq!(exit_code("<", "run"), exit_code::RUNTIME);
+        assert_eq!(exit_code("+", "jump 3"), exit_code::FAILURE);
+        assert_eq!(
+            exit_code("+", "run\nassert cell 0 == 2"),
+            exit_code::FAILURE
+        );
+    }
+}
@@ -0,0 +1,369 @@
+//! A debugger built on top of the VM's step API.
+
+use std::collections::{BTreeMap, BTreeSet};
+use std::io::Read;
+
+use bft_interp::{StepOutcome, VMError, VmEvent, BFVM};
+use bft_types::{BFprogram, InputInstruction, Instruction};
+
+/// Why the debugger handed control back to its user.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Stop {
+    /// The requested step completed.
+    Step,
+
+    /// Execution reached an instruction with a breakpoint.
+    Breakpoint,
+
+    /// The program ran to completion.
+    Finished,
+}
+
+/// A change to a watched cell, made by a single instruction.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct CellWrite {
+    /// The number of instructions executed up to and including the one that made the change.
+    pub step: u64,
+
+    /// The index of the instruction that made the change.
+    pub pc: usize,
+
+    /// The value of the cell before the change.
+    pub old: u8,
+
+    /// The value of the cell after the change.
+    pub new: u8,
+}
+
+/// A program being run under the control of a debugger.
+pub struct Debugger {
+    program: BFprogram,
+    vm: BFVM<u8>,
+    input: Box<dyn Read>,
+    output: Vec<u8>,
+    breakpoints: BTreeSet<usize>,
+    history: BTreeMap<usize, Vec<CellWrite>>,
+    finished: bool,
+    started: bool,
+}
+
+impl Debugger {
+    /// Prepare to debug `program` on `vm`, with `input` feeding the program's `,` instructions.
+    pub fn new(program: BFprogram, vm: BFVM<u8>, input: Box<dyn Read>) -> Self {
+        let finished = program.instructions().is_empty();
+        Debugger {
+            program,
+            vm,
+            input,
+            output: Vec::new(),
+            breakpoints: BTreeSet::new(),
+            history: BTreeMap::new(),
+            finished,
+            started: false,
+        }
+    }
+
+    /// The program being debugged.
+    pub fn program(&self) -> &BFprogram {
+        &self.program
+    }
+
+    /// The VM running the program.
+    pub fn vm(&self) -> &BFVM<u8> {
+        &self.vm
+    }
+
+    /// The instruction that will be executed next, if any.
+    pub fn current_instruction(&self) -> Option<&InputInstruction> {
+        self.program.instructions().get(self.vm.pc())
+    }
+
+    /// Remove and return everything the program has output so far.
+    pub fn take_output(&mut self) -> Vec<u8> {
+        std::mem::take(&mut self.output)
+    }
+
+    /// Replace all breakpoints with ones on the given source lines.
+    ///
+    /// A breakpoint is placed on the first instruction at or after the start of each line. The
+    /// line that the breakpoint actually ended up on is returned for each requested line, or
+    /// `None` if there are no instructions at or after that line.
+    pub fn set_line_breakpoints(&mut self, lines: &[usize]) -> Vec<Option<usize>> {
+        self.breakpoints.clear();
+        lines
+            .iter()
+            .map(|&line| self.add_line_breakpoint(line))
+            .collect()
+    }
+
+    /// Add a breakpoint on the first instruction at or after the start of `line`, returning the
+    /// line the breakpoint ended up on.
+    pub fn add_line_breakpoint(&mut self, line: usize) -> Option<usize> {
+        let (idx, inst) = self
+            .program
+            .instructions()
+            .iter()
+            .enumerate()
+            .find(|(_, inst)| inst.line_number() >= line)?;
+        self.breakpoints.insert(idx);
+        Some(inst.line_number())
+    }
+
+    /// Start recording each change to `cell` on the selected tape, from now on.
+    pub fn watch(&mut self, cell: usize) {
+        self.history.entry(cell).or_default();
+    }
+
+    /// The changes made to `cell` since it was first watched, in order, or `None` if it isn't
+    /// being watched.
+    pub fn history(&self, cell: usize) -> Option<&[CellWrite]> {
+        self.history.get(&cell).map(Vec::as_slice)
+    }
+
+    /// The values of the watched cells, in order of cell. Cells beyond the end of a tape that
+    /// can grow count as zero.
+    fn watched_values(&self) -> Vec<u8> {
+        self.history
+            .keys()
+            .map(|cell| self.vm.tape().get(*cell).copied().unwrap_or(0))
+            .collect()
+    }
+
+    /// Record the changes to the watched cells, which held `before` when the instruction at `pc`
+    /// was about to run with tape `tape_index` selected. Steps that ran another thread, or
+    /// selected another tape, change which cells are visible rather than writing to them, so they
+    /// aren't recorded.
+    fn record_writes(&mut self, before: &[u8], pc: usize, tape_index: usize) {
+        if self.vm.last_executed() != Some(pc) || self.vm.tape_index() != tape_index {
+            return;
+        }
+        let step = self.vm.executed();
+        for ((cell, writes), old) in self.history.iter_mut().zip(before) {
+            let new = self.vm.tape().get(*cell).copied().unwrap_or(0);
+            if new != *old {
+                writes.push(CellWrite {
+                    step,
+                    pc,
+                    old: *old,
+                    new,
+                });
+            }
+        }
+    }
+
+    /// True once the program has run to completion.
+    pub fn is_finished(&self) -> bool {
+        self.finished
+    }
+
+    /// Execute a single instruction.
+    pub fn step_in(&mut self) -> Result<Stop, VMError> {
+        self.run_while(|_| false)
+    }
+
+    /// Execute a single instruction, treating a whole loop as one instruction when stopped at the
+    /// start of one.
+    pub fn step_over(&mut self) -> Result<Stop, VMError> {
+        let pc = self.vm.pc();
+        match self
+            .current_instruction()
+            .map(InputInstruction::instruction)
+        {
+            Some(Instruction::BeginLoop) => match self.program.matching_bracket(pc) {
+                Some(end) => self.run_while(|pc| pc != end + 1),
+                None => self.step_in(),
+            },
+            _ => self.step_in(),
+        }
+    }
+
+    /// Run until the innermost loop containing the current instruction exits.
+    pub fn step_out(&mut self) -> Result<Stop, VMError> {
+        let mut depth = 0usize;
+        let end = self.program.instructions()[self.vm.pc()..]
+            .iter()
+            .enumerate()
+            .find_map(|(offset, inst)| {
+                match inst.instruction() {
+                    Instruction::BeginLoop => depth += 1,
+                    Instruction::EndLoop if depth == 0 => return Some(self.vm.pc() + offset),
+                    Instruction::EndLoop => depth -= 1,
+                    _ => {}
+                }
+                None
+            });
+        match end {
+            Some(end) => self.run_while(|pc| pc != end + 1),
+            None => self.run_while(|_| true),
+        }
+    }
+
+    /// Run until a breakpoint is hit, or the program finishes.
+    pub fn resume(&mut self) -> Result<Stop, VMError> {
+        self.resume_for(u64::MAX)
+    }
+
+    /// Run until a breakpoint is hit, the program finishes, or `max_steps` instructions have
+    /// been executed, when [`Stop::Step`] is returned. Resuming again carries on as if the run
+    /// hadn't been split.
+    pub fn resume_for(&mut self, max_steps: u64) -> Result<Stop, VMError> {
+        // Every other breakpoint is checked after the step that reaches it, so one on the first
+        // instruction is checked before anything runs.
+        let pc = self.vm.pc();
+        if !self.finished && !self.started && self.breakpoints.contains(&pc) {
+            self.started = true;
+            self.vm.notify(VmEvent::BreakpointHit(pc));
+            return Ok(Stop::Breakpoint);
+        }
+        let mut remaining = max_steps;
+        self.run_while(|_| {
+            remaining = remaining.saturating_sub(1);
+            remaining > 0
+        })
+    }
+
+    /// Note that execution has stopped before the first instruction without running anything, so
+    /// that resuming from there doesn't stop again at a breakpoint on that instruction.
+    pub fn stop_on_entry(&mut self) {
+        self.started = true;
+    }
+
+    /// Keep executing instructions while `keep_going` returns true for the new program counter.
+    /// Execution always stops at a breakpoint, or when the program finishes.
+    fn run_while(&mut self, mut keep_going: impl FnMut(usize) -> bool) -> Result<Stop, VMError> {
+        if self.finished {
+            return Ok(Stop::Finished);
+        }
+        self.started = true;
+        loop {
+            let before = self.watched_values();
+            let (pc, tape_index) = (self.vm.pc(), self.vm.tape_index());
+            let outcome = self
+                .vm
+                .step(&self.program, &mut self.input, &mut self.output)?;
+            self.record_writes(&before, pc, tape_index);
+            if outcome != StepOutcome::Running {
+                self.finished = true;
+                return Ok(Stop::Finished);
+            }
+            let pc = self.vm.pc();
+            if self.breakpoints.contains(&pc) {
+                self.vm.notify(VmEvent::BreakpointHit(pc));
+                return Ok(Stop::Breakpoint);
+            }
+            if !keep_going(pc) {
+                return Ok(Stop::Step);
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn debugger(code: &str) -> Debugger {
+        let mut program = BFprogram::new("mod.test", code.as_bytes());
+        program.validate_brackets().unwrap();
+        Debugger::new(program, BFVM::new(None, false), Box::new(std::io::empty()))
+    }
+
+    #[test]
+    fn line_breakpoints() {
+        let mut dbg = debugger("++\n\n+.\n+");
+        let events = dbg.vm.subscribe();
+        assert_eq!(
+            dbg.set_line_breakpoints(&[2, 4, 5]),
+            vec![Some(3), Some(4), None]
+        );
+        assert_eq!(dbg.resume().unwrap(), Stop::Breakpoint);
+        assert_eq!(dbg.vm().pc(), 2);
+        assert_eq!(events.try_recv(), Ok(VmEvent::BreakpointHit(2)));
+        assert_eq!(dbg.resume().unwrap(), Stop::Breakpoint);
+        assert_eq!(dbg.take_output(), vec![3]);
+        assert_eq!(dbg.resume().unwrap(), Stop::Finished);
+        assert_eq!(dbg.step_in().unwrap(), Stop::Finished);
+    }
+
+    #[test]
+    fn breakpoint_on_the_first_instruction() {
+        let mut dbg = debugger("+\n+\n+");
+        assert_eq!(dbg.set_line_breakpoints(&[1]), vec![Some(1)]);
+        assert_eq!(dbg.resume().unwrap(), Stop::Breakpoint);
+        assert_eq!(dbg.vm().pc(), 0);
+        assert_eq!(dbg.vm().executed(), 0);
+        assert_eq!(dbg.resume().unwrap(), Stop::Finished);
+        assert_eq!(dbg.vm().tape()[0], 3);
+
+        let mut dbg = debugger("+\n+\n+");
+        dbg.set_line_breakpoints(&[1]);
+        assert_eq!(dbg.step_in().unwrap(), Stop::Step);
+        assert_eq!(dbg.vm().executed(), 1);
+        assert_eq!(dbg.resume().unwrap(), Stop::Finished);
+    }
+
+    #[test]
+    fn resuming_in_slices() {
+        let mut dbg = debugger("+++[-]\n+");
+        dbg.set_line_breakpoints(&[2]);
+        assert_eq!(dbg.resume_for(4).unwrap(), Stop::Step);
+        assert_eq!(dbg.vm().executed(), 4);
+        while dbg.resume_for(2).unwrap() == Stop::Step {}
+        assert_eq!(dbg.vm().pc(), 6);
+        assert_eq!(dbg.resume_for(2).unwrap(), Stop::Finished);
+    }
+
+    #[test]
+    fn stepping_over_and_out_of_loops() {
+        let mut dbg = debugger("+++[>+[-]<-]+");
+        for _ in 0..3 {
+            assert_eq!(dbg.step_in().unwrap(), Stop::Step);
+        }
+        assert_eq!(dbg.step_over().unwrap(), Stop::Step);
+        assert_eq!(dbg.vm().pc(), 12);
+        assert_eq!(dbg.vm().tape()[..2], [0, 0]);
+
+        let mut dbg = debugger("+++[>+[-]<-]+");
+        for _ in 0..7 {
+            dbg.step_in().unwrap();
+        }
+        assert_eq!(dbg.vm().pc(), 7);
+        assert_eq!(dbg.step_out().unwrap(), Stop::Step);
+        assert_eq!(dbg.vm().pc(), 9);
+        assert_eq!(dbg.step_out().unwrap(), Stop::Step);
+        assert_eq!(dbg.vm().pc(), 12);
+        assert_eq!(dbg.step_out().unwrap(), Stop::Finished);
+    }
+
+    #[test]
+    fn cell_history() {
+        let mut dbg = debugger("+>++[<+>-]<[-]");
+        dbg.watch(0);
+        assert_eq!(dbg.history(0), Some(&[][..]));
+        assert_eq!(dbg.history(1), None);
+        dbg.step_in().unwrap();
+        dbg.watch(1);
+        assert_eq!(dbg.resume().unwrap(), Stop::Finished);
+        let write = |step, pc, old, new| CellWrite { step, pc, old, new };
+        assert_eq!(
+            dbg.history(0).unwrap(),
+            [
+                write(1, 0, 0, 1),
+                write(7, 6, 1, 2),
+                write(12, 6, 2, 3),
+                write(18, 12, 3, 2),
+                write(20, 12, 2, 1),
+                write(22, 12, 1, 0),
+            ]
+        );
+        assert_eq!(
+            dbg.history(1).unwrap(),
+            [
+                write(3, 2, 0, 1),
+                write(4, 3, 1, 2),
+                write(9, 8, 2, 1),
+                write(14, 8, 1, 0),
+            ]
+        );
+    }
+}
@@ -0,0 +1,232 @@
+//! Lifting programs into readable pseudo-code.
+//!
+//! The head position is written as `p`. Loops that leave the head where they found it are
+//! balanced, and the cells used inside them are written relative to the head position before the
+//! loop. Unbalanced loops move `p` explicitly.
+
+use std::collections::HashMap;
+use std::fmt::Write;
+
+use bft_types::{BFprogram, InputInstruction, Instruction};
+
+/// Indentation stops growing past this many levels of nesting, so that the output for deeply
+/// nested programs doesn't grow with the square of their length.
+const MAX_INDENT: usize = 32;
+
+/// How a loop is written.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+enum LoopKind {
+    /// `[-]` or `[+]`, written as setting the cell to zero.
+    Clear,
+
+    /// A loop that leaves the head where it found it, as does every loop within it.
+    Balanced,
+
+    /// Any other loop.
+    Unbalanced,
+}
+
+/// The index of the matching `]` and the kind of each loop in `instructions`, by the index of
+/// its `[`. This is worked out in a single pass, however deeply the loops are nested.
+fn loop_kinds(instructions: &[InputInstruction]) -> HashMap<usize, (usize, LoopKind)> {
+    let mut kinds = HashMap::new();
+    // For each open loop, where it starts, where the head was when it was entered, and
+    // whether it's balanced so far.
+    let mut open: Vec<(usize, i64, bool)> = Vec::new();
+    let mut offset = 0i64;
+    for (idx, inst) in instructions.iter().enumerate() {
+        match inst.instruction() {
+            Instruction::MoveLeft => offset -= 1,
+            Instruction::MoveRight => offset += 1,
+            Instruction::BeginLoop => open.push((idx, offset, true)),
+            Instruction::EndLoop => {
+                let Some((start, entered, balanced)) = open.pop() else {
+                    continue;
+                };
+                let balanced = balanced && offset == entered;
+                if let (false, Some((_, _, outer))) = (balanced, open.last_mut()) {
+                    *outer = false;
+                }
+                let kind = match &instructions[start + 1..idx] {
+                    [body]
+                        if matches!(
+                            body.instruction(),
+                            Instruction::Increment | Instruction::Decrement
+                        ) =>
+                    {
+                        LoopKind::Clear
+                    }
+                    _ if balanced => LoopKind::Balanced,
+                    _ => LoopKind::Unbalanced,
+                };
+                kinds.insert(start, (idx, kind));
+            }
+            _ => {}
+        }
+    }
+    kinds
+}
+
+/// The cell at `offset` from the head.
+fn cell(offset: i64) -> String {
+    match offset {
+        0 => String::from("cell[p]"),
+        offset if offset > 0 => format!("cell[p+{offset}]"),
+        offset => format!("cell[p-{}]", -offset),
+    }
+}
+
+/// Writes pseudo-code, merging runs of additions to the same cell.
+#[derive(Default)]
+struct Decompiler {
+    out: String,
+    indent: usize,
+    /// An addition that hasn't been written yet, as the offset of the cell and the amount.
+    pending: Option<(i64, i64)>,
+}
+
+impl Decompiler {
+    fn line(&mut self, text: &str) {
+        self.flush();
+        let _ = writeln!(self.out, "{:1$}{text}", "", self.indent.min(MAX_INDENT) * 4);
+    }
+
+    fn flush(&mut self) {
+        match self.pending.take() {
+            Some((offset, amount)) if amount > 0 => {
+                self.line(&format!("{} += {amount};", cell(offset)));
+            }
+            Some((offset, amount)) if amount < 0 => {
+                self.line(&format!("{} -= {};", cell(offset), -amount));
+            }
+            _ => {}
+        }
+    }
+
+    fn add(&mut self, offset: i64, amount: i64) {
+        match &mut self.pending {
+            Some((pending, total)) if *pending == offset => *total += amount,
+            _ => {
+                self.flush();
+                self.pending = Some((offset, amount));
+            }
+        }
+    }
+
+    fn moved(&mut self, offset: i64) {
+        match offset {
+            0 => {}
+            offset if offset > 0 => self.line(&format!("p += {offset};")),
+            offset => self.line(&format!("p -= {};", -offset)),
+        }
+    }
+}
+
+/// Write `program` as pseudo-code. The brackets in `program` should already have been validated.
+///
+/// The program is written in a single pass, keeping the loops that are open on a stack rather
+/// than recursing into them, so that deeply nested programs can't exhaust the call stack.
+#[must_use]
+pub fn decompile(program: &BFprogram) -> String {
+    let instructions = program.instructions();
+    let kinds = loop_kinds(instructions);
+    let mut decompiler = Decompiler::default();
+    // The position of the head relative to `p`.
+    let mut offset = 0i64;
+    // For each loop being written, the offset to go back to after it if it's balanced, or
+    // `None` if the head moves `p` explicitly.
+    let mut open: Vec<Option<i64>> = Vec::new();
+    let mut idx = 0;
+    while let Some(inst) = instructions.get(idx) {
+        match inst.instruction() {
+            Instruction::Increment => decompiler.add(offset, 1),
+            Instruction::Decrement => decompiler.add(offset, -1),
+            Instruction::MoveLeft => offset -= 1,
+            Instruction::MoveRight => offset += 1,
+            Instruction::Output => decompiler.line(&format!("output({});", cell(offset))),
+            Instruction::Input => decompiler.line(&format!("{} = input();", cell(offset))),
+            Instruction::BeginLoop => match kinds.get(&idx) {
+                Some((end, LoopKind::Clear)) => {
+                    decompiler.line(&format!("{} = 0;", cell(offset)));
+                    idx = *end;
+                }
+                Some((_, LoopKind::Balanced)) => {
+                    decompiler.line(&format!("while {} != 0 {{", cell(offset)));
+                    decompiler.indent += 1;
+                    open.push(Some(offset));
+                }
+                _ => {
+                    decompiler.moved(offset);
+                    offset = 0;
+                    decompiler.line("while cell[p] != 0 {");
+                    decompiler.indent += 1;
+                    open.push(None);
+                }
+            },
+            Instruction::EndLoop => {
+                decompiler.flush();
+                match open.pop() {
+                    Some(Some(entered)) => offset = entered,
+                    Some(None) => {
+                        decompiler.moved(offset);
+                        offset = 0;
+                    }
+                    None => {}
+                }
+                decompiler.indent = decompiler.indent.saturating_sub(1);
+                decompiler.line("}");
+            }
+            inst => decompiler.line(&format!("// {inst} at {}", cell(offset))),
+        }
+        idx += 1;
+    }
+    decompiler.flush();
+    decompiler.out
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn lift(code: &str) -> String {
+        let mut program = BFprogram::new("decompile.test", code.as_bytes());
+        program.validate_brackets().unwrap();
+        decompile(&program)
+    }
+
+    #[test]
+    fn balanced_loops() {
+        assert_eq!(
+            lift("++>+++[<[-]>->+++<]>."),
+            "cell[p] += 2;\n\
+             cell[p+1] += 3;\n\
+             while cell[p+1] != 0 {\n    \
+                 cell[p] = 0;\n    \
+                 cell[p+1] -= 1;\n    \
+                 cell[p+2] += 3;\n\
+             }\n\
+             output(cell[p+2]);\n"
+        );
+    }
+
+    #[test]
+    fn unbalanced_loops() {
+        assert_eq!(
+            lift(">>[>],+-"),
+            "p += 2;\n\
+             while cell[p] != 0 {\n    \
+                 p += 1;\n\
+             }\n\
+             cell[p] = input();\n"
+        );
+    }
+
+    #[test]
+    fn deep_nesting() {
+        let depth = 200_000;
+        let code = "+[>".repeat(depth) + "+" + &"<-]".repeat(depth);
+        let out = lift(&code);
+        assert_eq!(out.lines().count(), 4 * depth + 1);
+        assert!(out.lines().all(|line| line.len() <= MAX_INDENT * 4 + 40));
+    }
+}
@@ -0,0 +1,444 @@
+//! Problems found in programs, from bracket matching, the lints and running them, and rendering
+//! them for people to read.
+
+use std::env;
+use std::fmt;
+use std::fmt::{Display, Formatter, Write as _};
+use std::path::{Path, PathBuf};
+
+use clap::ColorChoice;
+use serde_json::{json, Value};
+
+use bft_interp::{BftError, VMError};
+use bft_types::{BFprogram, Extension, InputInstruction};
+
+use crate::cli::DiagnosticFormat;
+use crate::lint::{run_lints, Level, LintConfig};
+
+/// How serious a diagnostic is.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Severity {
+    /// The program is broken, or breaks a denied lint.
+    Error,
+
+    /// The program is suspicious.
+    Warning,
+
+    /// More information about another diagnostic.
+    Note,
+}
+
+impl Display for Severity {
+    fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::Error => write!(f, "error"),
+            Self::Warning => write!(f, "warning"),
+            Self::Note => write!(f, "note"),
+        }
+    }
+}
+
+/// A problem found in a program.
+#[derive(Debug, PartialEq)]
+pub struct Diagnostic {
+    /// The file the problem is in.
+    pub file: PathBuf,
+
+    /// Line of the instruction with the problem.
+    pub line: usize,
+
+    /// Column of the instruction with the problem.
+    pub column: usize,
+
+    /// Number of bytes of the token the instruction with the problem was written as.
+    pub len: usize,
+
+    /// How serious the problem is.
+    pub severity: Severity,
+
+    /// Description of the problem.
+    pub message: String,
+
+    /// The lint that found the problem, if it was found by one.
+    pub code: Option<&'static str>,
+
+    /// A hint about how to fix the problem.
+    pub note: Option<String>,
+}
+
+impl Display for Diagnostic {
+    fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
+        write!(f, "{}", Renderer::PLAIN.render(self))
+    }
+}
+
+impl Diagnostic {
+    /// Describe `error` as a diagnostic, if it's an error in a program with a known location.
+    pub fn from_error(error: &BftError) -> Option<Self> {
+        let diagnostic = |file: &Path, (line, column), note| Diagnostic {
+            file: file.to_path_buf(),
+            line,
+            column,
+            len: 1,
+            severity: Severity::Error,
+            message: error.to_string(),
+            code: None,
+            note,
+        };
+        match error {
+            BftError::Brackets(err) => Some(diagnostic(err.source_name(), err.location(), None)),
+            BftError::Extension(err) => {
+                let note = format!("enable it with `--extensions {}`", err.extension());
+                Some(diagnostic(err.source_name(), err.location(), Some(note)))
+            }
+            BftError::Runtime(err) => {
+                let inst = err.instruction()?;
+                let note = matches!(err, VMError::InvalidHeadPosition(..)).then(|| {
+                    "make the tape longer with `--cells`, or let it grow with `--extensible`"
+                        .to_string()
+                });
+                let location = (inst.line_number(), inst.char_number());
+                Some(Diagnostic {
+                    len: inst.token_len(),
+                    ..diagnostic(err.source_name(), location, note)
+                })
+            }
+            _ => None,
+        }
+    }
+
+    /// The diagnostic as a JSON object. The span covers the token of the instruction with the
+    /// problem, ending just after it.
+    pub fn to_json(&self) -> Value {
+        json!({
+            "file": self.file,
+            "line": self.line,
+            "column": self.column,
+            "span": {
+                "start": { "line": self.line, "column": self.column },
+                "end": { "line": self.line, "column": self.column + self.len },
+            },
+            "severity": self.severity.to_string(),
+            "message": self.message,
+            "code": self.code,
+            "note": self.note,
+        })
+    }
+}
+
+/// Check the program in `data` for mismatched brackets and, if they match, run the lints on it.
+pub fn check_source(source_name: &Path, data: &[u8], config: &LintConfig) -> Vec<Diagnostic> {
+    let mut program = BFprogram::new(source_name, data);
+    if let Err(err) = program.validate_brackets() {
+        let (line, column) = err.location();
+        return vec![Diagnostic {
+            file: source_name.to_path_buf(),
+            line,
+            column,
+            len: 1,
+            severity: Severity::Error,
+            message: err.to_string(),
+            code: None,
+            note: None,
+        }];
+    }
+    lint_diagnostics(source_name, &program, config)
+}
+
+/// Match every bracket of `program` that matches, for editors, which work on programs while
+/// they're being written. Returns a diagnostic for each bracket that doesn't match and each
+/// instruction from an extension that isn't `enabled`. If there are no such problems, the lints
+/// are run instead.
+pub fn diagnose(
+    source_name: &Path,
+    program: &mut BFprogram,
+    enabled: &[Extension],
+    config: &LintConfig,
+) -> Vec<Diagnostic> {
+    let brackets = program.match_brackets_partially();
+    let instructions = program.instructions();
+    let len_at = |offset| {
+        instructions
+            .binary_search_by_key(&offset, InputInstruction::offset)
+            .map_or(1, |idx| instructions[idx].token_len())
+    };
+    let brackets = brackets
+        .into_iter()
+        .map(|err| (err.offset(), BftError::from(err)));
+    let extensions = program
+        .disallowed_instructions(enabled)
+        .into_iter()
+        .map(|err| (err.offset(), BftError::from(err)));
+    let mut diagnostics: Vec<_> = brackets
+        .chain(extensions)
+        .filter_map(|(offset, err)| {
+            Some(Diagnostic {
+                len: len_at(offset),
+                ..Diagnostic::from_error(&err)?
+            })
+        })
+        .collect();
+    if diagnostics.is_empty() {
+        diagnostics = lint_diagnostics(source_name, program, config);
+    } else {
+        diagnostics.sort_by_key(|diagnostic| (diagnostic.line, diagnostic.column));
+    }
+    diagnostics
+}
+
+/// The warnings from running the lints on `program`, as diagnostics.
+fn lint_diagnostics(
+    source_name: &Path,
+    program: &BFprogram,
+    config: &LintConfig,
+) -> Vec<Diagnostic> {
+    run_lints(program, config)
+        .into_iter()
+        .map(|warning| Diagnostic {
+            file: source_name.to_path_buf(),
+            line: warning.line,
+            column: warning.column,
+            len: warning.len,
+            severity: if warning.level == Level::Deny {
+                Severity::Error
+            } else {
+                Severity::Warning
+            },
+            message: warning.message,
+            code: Some(warning.lint),
+            note: None,
+        })
+        .collect()
+}
+
+/// Renders diagnostics as text, with or without colors, or as JSON.
+#[derive(Clone, Copy, Debug)]
+pub struct Renderer {
+    color: bool,
+    json: bool,
+}
+
+impl Renderer {
+    /// Renders text without colors.
+    const PLAIN: Renderer = Renderer {
+        color: false,
+        json: false,
+    };
+
+    /// A renderer for output to a stream, using colors when `choice` asks for them. With
+    /// [`ColorChoice::Auto`], colors are used if the stream `is_terminal` and `NO_COLOR` isn't
+    /// set.
+    pub fn new(choice: ColorChoice, is_terminal: bool) -> Self {
+        let color = match choice {
+            ColorChoice::Always => true,
+            ColorChoice::Never => false,
+            ColorChoice::Auto => {
+                is_terminal && env::var_os("NO_COLOR").is_none_or(|value| value.is_empty())
+            }
+        };
+        Renderer { color, json: false }
+    }
+
+    /// Render diagnostics in `format`, which for JSON is a single line for each diagnostic.
+    pub fn with_format(self, format: DiagnosticFormat) -> Self {
+        Renderer {
+            json: format == DiagnosticFormat::Json,
+            ..self
+        }
+    }
+
+    /// Whether diagnostics are rendered as JSON, for machines rather than people.
+    pub fn is_json(self) -> bool {
+        self.json
+    }
+
+    /// Wrap `text` in the ANSI escape codes given by `style`, if colors are being used.
+    fn paint(self, style: &str, text: impl Display) -> String {
+        if self.color {
+            format!("\x1b[{style}m{text}\x1b[0m")
+        } else {
+            text.to_string()
+        }
+    }
+
+    /// The label for `severity`, like `error:`.
+    pub fn label(self, severity: Severity) -> String {
+        let style = match severity {
+            Severity::Error => "1;31",
+            Severity::Warning => "1;33",
+            Severity::Note => "1;36",
+        };
+        self.paint(style, format_args!("{severity}:"))
+    }
+
+    /// Render `diagnostic`, followed by its note on another line if it has one.
+    pub fn render(self, diagnostic: &Diagnostic) -> String {
+        if self.json {
+            return diagnostic.to_json().to_string();
+        }
+        let location = format!(
+            "{}:{}:{}:",
+            diagnostic.file.display(),
+            diagnostic.line,
+            diagnostic.column
+        );
+        let mut out = format!(
+            "{} {} {}",
+            self.paint("1", location),
+            self.label(diagnostic.severity),
+            diagnostic.message
+        );
+        if let Some(code) = diagnostic.code {
+            let _ = write!(out, " [{code}]");
+        }
+        if let Some(note) = &diagnostic.note {
+            let _ = write!(out, "\n  {} {note}", self.label(Severity::Note));
+        }
+        out
+    }
+
+    /// Render an `error` stopping bft. Errors in a program are shown at their location,
+    /// and others are labelled as coming from bft.
+    pub fn render_error(self, error: &BftError) -> String {
+        match Diagnostic::from_error(error) {
+            Some(diagnostic) => self.render(&diagnostic),
+            None if self.json => json!({
+                "file": null,
+                "span": null,
+                "severity": Severity::Error.to_string(),
+                "message": error.to_string(),
+                "code": null,
+                "note": null,
+            })
+            .to_string(),
+            None => format!(
+                "{}: {} {error}",
+                env!("CARGO_PKG_NAME"),
+                self.label(Severity::Error)
+            ),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use bft_types::Alphabet;
+
+    fn parse_partially(
+        source_name: &Path,
+        data: &[u8],
+        alphabet: &Alphabet,
+        enabled: &[Extension],
+        config: &LintConfig,
+    ) -> (BFprogram, Vec<Diagnostic>) {
+        let mut program = BFprogram::with_alphabet(source_name, data, alphabet);
+        let diagnostics = diagnose(source_name, &mut program, enabled, config);
+        (program, diagnostics)
+    }
+
+    #[test]
+    fn checking() {
+        let config = LintConfig::default();
+        let diagnostics = check_source(Path::new("a.b"), b"+]", &config);
+        assert_eq!(
+            diagnostics[0].to_string(),
+            "a.b:1:2: error: Unexpected closing bracket ']' at [a.b:1:2]"
+        );
+        let diagnostics = check_source(Path::new("a.b"), b"+-", &config);
+        assert_eq!(
+            diagnostics[0].to_string(),
+            "a.b:1:1: warning: these two instructions cancel each other out [cancelling-instructions]"
+        );
+        assert_eq!(diagnostics[0].to_json()["severity"], "warning");
+        assert!(check_source(Path::new("a.b"), b"+[-]", &config).is_empty());
+    }
+
+    #[test]
+    fn partial_parsing() {
+        let config = LintConfig::default();
+        let mut alphabet = Alphabet::default();
+        alphabet.add_extension(Extension::Random).unwrap();
+        let (program, diagnostics) =
+            parse_partially(Path::new("a.b"), b"]?+[>[-]", &alphabet, &[], &config);
+        let messages: Vec<_> = diagnostics.iter().map(ToString::to_string).collect();
+        assert_eq!(
+            messages,
+            [
+                "a.b:1:1: error: Unexpected closing bracket ']' at [a.b:1:1]",
+                "a.b:1:2: error: Instruction from the 'random' extension, which isn't enabled, at [a.b:1:2]\n  note: enable it with `--extensions random`",
+                "a.b:1:4: error: Unmatched bracket '[' at [a.b:1:4]",
+            ]
+        );
+        assert_eq!(program.instructions().len(), 8);
+        assert_eq!(program.matching_bracket(5), Some(7));
+
+        let (_, diagnostics) = parse_partially(
+            Path::new("a.b"),
+            b"?+-",
+            &alphabet,
+            &[Extension::Random],
+            &config,
+        );
+        assert_eq!(diagnostics[0].code, Some("cancelling-instructions"));
+
+        // Spans cover the whole of each token, as it was written.
+        let ook = b"Ook. Ook.  Ook!  Ook!";
+        let (_, diagnostics) =
+            parse_partially(Path::new("a.b"), ook, &Alphabet::ook(), &[], &config);
+        assert_eq!(diagnostics[0].len, 9);
+        assert_eq!(
+            diagnostics[0].to_json()["span"]["end"],
+            json!({"line": 1, "column": 10})
+        );
+        let (_, diagnostics) = parse_partially(
+            Path::new("a.b"),
+            b"Ook! Ook?",
+            &Alphabet::ook(),
+            &[],
+            &config,
+        );
+        assert_eq!(diagnostics[0].len, 9);
+    }
+
+    #[test]
+    fn rendering() {
+        let config = LintConfig::default();
+        let diagnostic = &check_source(Path::new("a.b"), b"+-", &config)[0];
+        let plain = Renderer::new(ColorChoice::Never, true);
+        assert_eq!(plain.render(diagnostic), diagnostic.to_string());
+        assert_eq!(
+            Renderer::new(ColorChoice::Always, false).render(diagnostic),
+            "\x1b[1ma.b:1:1:\x1b[0m \x1b[1;33mwarning:\x1b[0m these two instructions cancel each other out [cancelling-instructions]"
+        );
+        assert!(!Renderer::new(ColorChoice::Auto, false).color);
+
+        let mut program = BFprogram::new("a.b", &b"\n+[>+]"[..]);
+        program.validate_brackets().unwrap();
+        let err = bft_interp::BFVM::<u8>::new(std::num::NonZeroUsize::new(2), false)
+            .run(&program, &mut &b""[..], &mut Vec::new())
+            .unwrap_err();
+        assert_eq!(
+            plain.render_error(&err.into()),
+            "a.b:2:3: error: Head moved to invalid position from 1 at [a.b:2:3]\n  note: make the tape longer with `--cells`, or let it grow with `--extensible`"
+        );
+        let err = std::io::Error::other("no such file");
+        assert_eq!(plain.render_error(&err.into()), "bft: error: no such file");
+    }
+
+    #[test]
+    fn json() {
+        let renderer = Renderer::new(ColorChoice::Always, true).with_format(DiagnosticFormat::Json);
+        let diagnostic = &check_source(Path::new("a.b"), b"\n ]", &LintConfig::default())[0];
+        let value: Value = serde_json::from_str(&renderer.render(diagnostic)).unwrap();
+        assert_eq!(value["span"]["start"], json!({"line": 2, "column": 2}));
+        assert_eq!(value["span"]["end"], json!({"line": 2, "column": 3}));
+        assert_eq!(value["severity"], "error");
+        assert_eq!(value["code"], Value::Null);
+
+        let err = std::io::Error::other("no such file");
+        let value: Value = serde_json::from_str(&renderer.render_error(&err.into())).unwrap();
+        assert_eq!(value["message"], "no such file");
+        assert_eq!(value["file"], Value::Null);
+    }
+}
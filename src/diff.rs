@@ -0,0 +1,221 @@
+//! Comparing two programs instruction by instruction, ignoring their comments and layout.
+//!
+//! The instructions are compared with Myers' algorithm, which finds the fewest insertions and
+//! removals that turn one program into the other. Each run of differences is reported as a
+//! hunk, with the locations it covers in both programs.
+
+use std::fmt::Write;
+use std::ops::Range;
+
+use bft_types::{BFprogram, InputInstruction, Instruction};
+
+use crate::disasm::symbol;
+
+/// A run of instructions that differ between the old and the new program, as ranges of
+/// instruction indices. One of the ranges may be empty, for pure insertions and removals.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Hunk {
+    pub old: Range<usize>,
+    pub new: Range<usize>,
+}
+
+/// A step in turning one sequence into another.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+enum Edit {
+    Keep,
+    Remove,
+    Insert,
+}
+
+/// The fewest edits turning `old` into `new`, found with Myers' algorithm.
+fn edit_script(old: &[Instruction], new: &[Instruction]) -> Vec<Edit> {
+    let max = old.len() + new.len();
+    // `furthest[max + k]` is the furthest `x` reached on diagonal `k = x - y`.
+    let mut furthest = vec![0; 2 * max + 2];
+    let mut trace = Vec::new();
+    'search: for d in 0..=max {
+        trace.push(furthest.clone());
+        for k in (max - d..=max + d).step_by(2) {
+            let mut x = if k == max - d || (k != max + d && furthest[k - 1] < furthest[k + 1]) {
+                furthest[k + 1]
+            } else {
+                furthest[k - 1] + 1
+            };
+            let mut y = x + max - k;
+            while x < old.len() && y < new.len() && old[x] == new[y] {
+                x += 1;
+                y += 1;
+            }
+            furthest[k] = x;
+            if x >= old.len() && y >= new.len() {
+                break 'search;
+            }
+        }
+    }
+
+    let mut edits = Vec::new();
+    let (mut x, mut y) = (old.len(), new.len());
+    for (d, furthest) in trace.iter().enumerate().skip(1).rev() {
+        let k = x + max - y;
+        let prev_k = if k == max - d || (k != max + d && furthest[k - 1] < furthest[k + 1]) {
+            k + 1
+        } else {
+            k - 1
+        };
+        let prev_x = furthest[prev_k];
+        let prev_y = prev_x + max - prev_k;
+        while x > prev_x && y > prev_y {
+            edits.push(Edit::Keep);
+            x -= 1;
+            y -= 1;
+        }
+        edits.push(if x == prev_x {
+            Edit::Insert
+        } else {
+            Edit::Remove
+        });
+        (x, y) = (prev_x, prev_y);
+    }
+    edits.extend((0..x).map(|_| Edit::Keep));
+    edits.reverse();
+    edits
+}
+
+/// The runs of instructions that differ between `old` and `new`.
+#[must_use]
+pub fn hunks(old: &[Instruction], new: &[Instruction]) -> Vec<Hunk> {
+    // Edits usually leave most of a program alone, so the common start and end are skipped
+    // before searching.
+    let prefix = old.iter().zip(new).take_while(|(a, b)| a == b).count();
+    let suffix = old[prefix..]
+        .iter()
+        .rev()
+        .zip(new[prefix..].iter().rev())
+        .take_while(|(a, b)| a == b)
+        .count();
+    let edits = edit_script(
+        &old[prefix..old.len() - suffix],
+        &new[prefix..new.len() - suffix],
+    );
+
+    let mut hunks: Vec<Hunk> = Vec::new();
+    let (mut i, mut j) = (prefix, prefix);
+    let mut in_hunk = false;
+    for edit in edits {
+        if edit != Edit::Keep && !in_hunk {
+            hunks.push(Hunk {
+                old: i..i,
+                new: j..j,
+            });
+        }
+        in_hunk = edit != Edit::Keep;
+        match edit {
+            Edit::Keep => {
+                i += 1;
+                j += 1;
+            }
+            Edit::Remove => i += 1,
+            Edit::Insert => j += 1,
+        }
+        if let Some(hunk) = hunks.last_mut().filter(|_| in_hunk) {
+            hunk.old.end = i;
+            hunk.new.end = j;
+        }
+    }
+    hunks
+}
+
+/// Where `range` of `instructions` is in the source, as `LINE:COL-LINE:COL`, or where it would
+/// be for an empty range.
+fn span(instructions: &[InputInstruction], range: &Range<usize>) -> String {
+    match (instructions.get(range.start), range.is_empty()) {
+        (Some(first), false) => {
+            let last = instructions[range.end - 1];
+            format!("{}-{}", first.location(), last.location())
+        }
+        (Some(next), true) => format!("before {}", next.location()),
+        (None, _) => String::from("at the end"),
+    }
+}
+
+/// The instructions in `range`, written with their usual symbols.
+fn code(instructions: &[InputInstruction], range: &Range<usize>) -> String {
+    instructions[range.clone()]
+        .iter()
+        .map(|inst| symbol(*inst.instruction()))
+        .collect()
+}
+
+/// Compare the instructions in `old` and `new`, returning the differences as text, which is
+/// empty if there are none.
+#[must_use]
+pub fn diff(old: &BFprogram, new: &BFprogram) -> String {
+    let (a, b) = (old.instructions(), new.instructions());
+    let insts = |program: &[InputInstruction]| -> Vec<Instruction> {
+        program.iter().map(|inst| *inst.instruction()).collect()
+    };
+    let mut out = String::new();
+    for hunk in hunks(&insts(a), &insts(b)) {
+        let kind = match (hunk.old.is_empty(), hunk.new.is_empty()) {
+            (false, false) => "changed",
+            (false, true) => "removed",
+            _ => "inserted",
+        };
+        let _ = writeln!(
+            out,
+            "{kind}: {} {}, {} {}",
+            old.source().display(),
+            span(a, &hunk.old),
+            new.source().display(),
+            span(b, &hunk.new)
+        );
+        if !hunk.old.is_empty() {
+            let _ = writeln!(out, "- {}", code(a, &hunk.old));
+        }
+        if !hunk.new.is_empty() {
+            let _ = writeln!(out, "+ {}", code(b, &hunk.new));
+        }
+    }
+    out
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn insts(code: &str) -> Vec<Instruction> {
+        BFprogram::new("mod.test", code.as_bytes())
+            .instructions()
+            .iter()
+            .map(|inst| *inst.instruction())
+            .collect()
+    }
+
+    fn hunks_of(old: &str, new: &str) -> Vec<(Range<usize>, Range<usize>)> {
+        hunks(&insts(old), &insts(new))
+            .into_iter()
+            .map(|hunk| (hunk.old, hunk.new))
+            .collect()
+    }
+
+    #[test]
+    fn finding_hunks() {
+        assert_eq!(hunks_of("+[-]>.", "add one +[-] >."), []);
+        assert_eq!(hunks_of("+[-]>.", "+[-]>>."), [(5..5, 5..6)]);
+        assert_eq!(hunks_of("+[-]>.", "+>."), [(1..4, 1..1)]);
+        assert_eq!(hunks_of("+[-]>.", "-[-]<."), [(0..1, 0..1), (4..5, 4..5)]);
+        assert_eq!(hunks_of("", "+."), [(0..0, 0..2)]);
+        assert_eq!(hunks_of(",[.,]", "++,[-.,]"), [(0..0, 0..2), (2..2, 4..5)]);
+    }
+
+    #[test]
+    fn reporting() {
+        let old = BFprogram::new("old.b", b"+++ add\n[->+<]\n.");
+        let new = BFprogram::new("new.b", b"++\n[->+<]\n>.");
+        assert_eq!(
+            diff(&old, &new),
+            "removed: old.b 1:3-1:3, new.b before 2:1\n- +\n\
+             inserted: old.b before 3:1, new.b 3:1-3:1\n+ >\n"
+        );
+    }
+}
@@ -0,0 +1,176 @@
+//! Comparing our output with that of a reference interpreter.
+
+use std::error::Error;
+use std::io;
+use std::io::Write;
+use std::path::Path;
+use std::process::{Command, Stdio};
+
+use bft_interp::{StepOutcome, BFVM};
+use bft_types::BFprogram;
+
+/// The output of a run of our VM, along with the index of the instruction that wrote each byte.
+struct Recorded {
+    output: Vec<u8>,
+    sources: Vec<usize>,
+    hit_limit: bool,
+}
+
+/// Run `program` on our VM with `input`, executing at most `max_steps` instructions.
+fn run_ours(
+    program: &BFprogram,
+    mut input: &[u8],
+    max_steps: u64,
+) -> Result<Recorded, Box<dyn Error>> {
+    let mut vm: BFVM<u8> = BFVM::new(None, false);
+    let mut recorded = Recorded {
+        output: Vec::new(),
+        sources: Vec::new(),
+        hit_limit: false,
+    };
+    let mut steps = 0;
+    loop {
+        let outcome = vm.step(program, &mut input, &mut recorded.output)?;
+        if let Some(pc) = vm.last_executed() {
+            recorded.sources.resize(recorded.output.len(), pc);
+        }
+        if outcome == StepOutcome::Finished {
+            return Ok(recorded);
+        }
+        steps += 1;
+        if steps >= max_steps {
+            recorded.hit_limit = true;
+            return Ok(recorded);
+        }
+    }
+}
+
+/// Run `reference`, passing it the path of the program and feeding it `input`.
+fn run_reference(reference: &Path, program: &Path, input: &[u8]) -> io::Result<Vec<u8>> {
+    let mut child = Command::new(reference)
+        .arg(program)
+        .stdin(Stdio::piped())
+        .stdout(Stdio::piped())
+        .spawn()?;
+    let mut stdin = child.stdin.take();
+    std::thread::scope(|s| {
+        // Feed the input from another thread, so a reference that writes a lot of output before
+        // reading its input can't deadlock us.
+        s.spawn(|| {
+            if let Some(stdin) = &mut stdin {
+                // The reference may exit without reading all of its input.
+                let _ = stdin.write_all(input);
+            }
+            drop(stdin.take());
+        });
+        Ok(child.wait_with_output()?.stdout)
+    })
+}
+
+/// Compare our output with the reference's, writing a report to `out`. Returns true if they
+/// match.
+fn report<W: Write>(
+    program: &BFprogram,
+    ours: &Recorded,
+    theirs: &[u8],
+    max_steps: u64,
+    out: &mut W,
+) -> io::Result<bool> {
+    if ours.hit_limit {
+        writeln!(out, "bft stopped after the step limit of {max_steps}")?;
+    }
+    let Some(idx) = ours
+        .output
+        .iter()
+        .zip(theirs)
+        .position(|(a, b)| a != b)
+        .or_else(|| {
+            (ours.output.len() != theirs.len()).then(|| ours.output.len().min(theirs.len()))
+        })
+    else {
+        writeln!(out, "outputs match ({} bytes)", ours.output.len())?;
+        return Ok(true);
+    };
+    let show = |data: &[u8]| {
+        data.get(idx)
+            .map_or(String::from("nothing"), |byte| format!("0x{byte:02x}"))
+    };
+    writeln!(
+        out,
+        "outputs diverge at byte {idx}: reference wrote {}, bft wrote {}",
+        show(theirs),
+        show(&ours.output)
+    )?;
+    let source = ours.sources.get(idx).or(ours.sources.last());
+    if let Some(inst) = source.and_then(|pc| program.instructions().get(*pc)) {
+        let what = if idx < ours.output.len() {
+            "written by"
+        } else {
+            "last output from"
+        };
+        writeln!(
+            out,
+            "  {} the instruction at {}:{}",
+            what,
+            program.source().display(),
+            inst.location()
+        )?;
+    }
+    Ok(false)
+}
+
+/// Run the program at `path` with both our VM and the `reference` interpreter, and report the
+/// first place their outputs differ to `out`. Returns true if the outputs match.
+///
+/// # Errors
+/// Fails if the program can't be loaded or fails on our VM, if the reference can't be run, or if
+/// the report can't be written.
+pub fn diff_run<W: Write>(
+    reference: &Path,
+    path: &Path,
+    input: &[u8],
+    max_steps: u64,
+    out: &mut W,
+) -> Result<bool, Box<dyn Error>> {
+    let mut program = BFprogram::from_file(path)?;
+    program.validate_brackets()?;
+    let ours = run_ours(&program, input, max_steps)?;
+    let theirs = run_reference(reference, path, input)
+        .map_err(|err| format!("Couldn't run {}: {}", reference.display(), err))?;
+    Ok(report(&program, &ours, &theirs, max_steps, out)?)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn compare(code: &str, theirs: &[u8]) -> String {
+        let mut program = BFprogram::new("mod.test", code.as_bytes());
+        program.validate_brackets().unwrap();
+        let ours = run_ours(&program, b"", 1000).unwrap();
+        let mut out = Vec::new();
+        report(&program, &ours, theirs, 1000, &mut out).unwrap();
+        String::from_utf8(out).unwrap()
+    }
+
+    #[test]
+    fn matching_output() {
+        assert_eq!(compare("+.+.", &[1, 2]), "outputs match (2 bytes)\n");
+    }
+
+    #[test]
+    fn diverging_output() {
+        assert_eq!(
+            compare("+.\n+.", &[1, 3]),
+            "outputs diverge at byte 1: reference wrote 0x03, bft wrote 0x02\n  written by the instruction at mod.test:2:2\n"
+        );
+        assert_eq!(
+            compare("+.", &[1, 3]),
+            "outputs diverge at byte 1: reference wrote 0x03, bft wrote nothing\n  last output from the instruction at mod.test:1:2\n"
+        );
+        assert_eq!(
+            compare("+[]", b""),
+            "bft stopped after the step limit of 1000\noutputs match (0 bytes)\n"
+        );
+    }
+}
@@ -0,0 +1,318 @@
+//! Listing the instructions in a program.
+
+use std::fmt::Write;
+use std::str::FromStr;
+
+use serde_json::{json, Value};
+
+use bft_types::{Alphabet, BFprogram, Instruction};
+
+/// Output formats for disassembly.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, clap::ValueEnum)]
+pub enum Format {
+    /// One line per instruction, giving its index, location, symbol and description.
+    Text,
+
+    /// Runs of moves and arithmetic merged into single operations, with loops as jumps.
+    Ir,
+
+    /// A JSON array with an object for each instruction.
+    Json,
+}
+
+/// An inclusive range of source lines, like `3-7`, `3-` or `3`.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct LineRange {
+    start: usize,
+    end: Option<usize>,
+}
+
+impl LineRange {
+    fn contains(self, line: usize) -> bool {
+        line >= self.start && self.end.is_none_or(|end| line <= end)
+    }
+}
+
+impl FromStr for LineRange {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let parse = |n: &str| {
+            n.trim()
+                .parse::<usize>()
+                .map_err(|_| format!("Invalid line number '{n}'"))
+        };
+        let (start, end) = match s.split_once('-') {
+            Some((start, "")) => (parse(start)?, None),
+            Some((start, end)) => (parse(start)?, Some(parse(end)?)),
+            None => (parse(s)?, Some(parse(s)?)),
+        };
+        if end.is_some_and(|end| end < start) {
+            return Err(format!("Line range '{s}' ends before it starts"));
+        }
+        Ok(LineRange { start, end })
+    }
+}
+
+/// The character used for `inst` in the standard alphabet, or by the extension it comes from.
+pub fn symbol(inst: Instruction) -> char {
+    let token = match inst.extension() {
+        Some(extension) => extension
+            .tokens()
+            .iter()
+            .find(|(_, i)| *i == inst)
+            .map(|(c, _)| *c),
+        None => Alphabet::default().token_for(inst).map(|t| t[0]),
+    };
+    token.map_or('?', char::from)
+}
+
+/// A short name for `inst`, used in the IR listing.
+fn mnemonic(inst: Instruction) -> &'static str {
+    match inst {
+        Instruction::MoveLeft | Instruction::MoveRight => "move",
+        Instruction::Increment | Instruction::Decrement => "add",
+        Instruction::Input => "in",
+        Instruction::Output => "out",
+        Instruction::BeginLoop => "jz",
+        Instruction::EndLoop => "jnz",
+        Instruction::BeginProcedure => "proc",
+        Instruction::EndProcedure => "ret",
+        Instruction::CallProcedure => "call",
+        Instruction::Fork => "fork",
+        Instruction::EndProgram => "end",
+        Instruction::Store => "store",
+        Instruction::Retrieve => "retrieve",
+        Instruction::ShiftRight => "shr",
+        Instruction::ShiftLeft => "shl",
+        Instruction::Not => "not",
+        Instruction::Xor => "xor",
+        Instruction::And => "and",
+        Instruction::Or => "or",
+        Instruction::Random => "rand",
+        Instruction::NextTape => "nexttape",
+        Instruction::PreviousTape => "prevtape",
+        Instruction::Halt => "halt",
+    }
+}
+
+/// How far `inst` moves the head, or changes the current cell.
+fn amount(inst: Instruction) -> i64 {
+    match inst {
+        Instruction::MoveLeft | Instruction::Decrement => -1,
+        _ => 1,
+    }
+}
+
+/// An operation in the IR listing.
+struct Op {
+    /// Index of the first instruction the operation was built from.
+    first: usize,
+    inst: Instruction,
+    /// The total for merged runs, or the index of the other half of a jump.
+    arg: Option<i64>,
+}
+
+/// Merge runs of moves and arithmetic in `program`, and number the jumps by operation.
+fn lower(program: &BFprogram) -> Vec<Op> {
+    let mut ops: Vec<Op> = Vec::new();
+    let mut loops = Vec::new();
+    for (idx, inst) in program.instructions().iter().enumerate() {
+        let inst = *inst.instruction();
+        match inst {
+            Instruction::MoveLeft
+            | Instruction::MoveRight
+            | Instruction::Increment
+            | Instruction::Decrement => {
+                if let Some(last) = ops
+                    .last_mut()
+                    .filter(|last| mnemonic(last.inst) == mnemonic(inst))
+                {
+                    last.arg = last.arg.map(|total| total + amount(inst));
+                } else {
+                    ops.push(Op {
+                        first: idx,
+                        inst,
+                        arg: Some(amount(inst)),
+                    });
+                }
+            }
+            Instruction::BeginLoop => {
+                loops.push(ops.len());
+                ops.push(Op {
+                    first: idx,
+                    inst,
+                    arg: None,
+                });
+            }
+            Instruction::EndLoop => {
+                let begin = loops.pop();
+                if let Some(begin) = begin {
+                    ops[begin].arg = i64::try_from(ops.len()).ok();
+                }
+                ops.push(Op {
+                    first: idx,
+                    inst,
+                    arg: begin.and_then(|begin| i64::try_from(begin).ok()),
+                });
+            }
+            _ => ops.push(Op {
+                first: idx,
+                inst,
+                arg: None,
+            }),
+        }
+    }
+    ops
+}
+
+/// List the instructions in `program` in the given format, keeping only those on lines in
+/// `lines`, if it is given. The brackets in `program` should already have been validated.
+#[must_use]
+pub fn disassemble(program: &BFprogram, format: Format, lines: Option<LineRange>) -> String {
+    let instructions = program.instructions();
+    let shown =
+        |idx: usize| lines.is_none_or(|lines| lines.contains(instructions[idx].line_number()));
+    let mut out = String::new();
+    match format {
+        Format::Text => {
+            for (idx, inst) in instructions
+                .iter()
+                .enumerate()
+                .filter(|(idx, _)| shown(*idx))
+            {
+                let _ = write!(
+                    out,
+                    "{idx:>6}  {:<9} {}  {}",
+                    inst.location(),
+                    symbol(*inst.instruction()),
+                    inst.instruction()
+                );
+                if let Some(other) = program.matching_bracket(idx) {
+                    let _ = write!(out, " (matches {other})");
+                }
+                out.push('\n');
+            }
+        }
+        Format::Ir => {
+            for (n, op) in lower(program)
+                .iter()
+                .enumerate()
+                .filter(|(_, op)| shown(op.first))
+            {
+                let _ = write!(
+                    out,
+                    "{n:>6}  {:<9} {}",
+                    instructions[op.first].location(),
+                    mnemonic(op.inst)
+                );
+                match (op.inst, op.arg) {
+                    (Instruction::BeginLoop | Instruction::EndLoop, Some(other)) => {
+                        let _ = write!(out, " {other}");
+                    }
+                    (_, Some(total)) => {
+                        let _ = write!(out, " {total:+}");
+                    }
+                    (_, None) => {}
+                }
+                out.push('\n');
+            }
+        }
+        Format::Json => {
+            let listing: Vec<Value> = instructions
+                .iter()
+                .enumerate()
+                .filter(|(idx, _)| shown(*idx))
+                .map(|(idx, inst)| {
+                    json!({
+                        "index": idx,
+                        "line": inst.line_number(),
+                        "column": inst.char_number(),
+                        "symbol": symbol(*inst.instruction()).to_string(),
+                        "description": inst.instruction().to_string(),
+                        "matches": program.matching_bracket(idx),
+                    })
+                })
+                .collect();
+            let _ = writeln!(out, "{}", Value::from(listing));
+        }
+    }
+    out
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn program(code: &str) -> BFprogram {
+        let mut program = BFprogram::new("disasm.test", code.as_bytes());
+        program.validate_brackets().unwrap();
+        program
+    }
+
+    #[test]
+    fn line_ranges() {
+        assert_eq!(
+            "3-7".parse(),
+            Ok(LineRange {
+                start: 3,
+                end: Some(7)
+            })
+        );
+        assert_eq!(
+            "3-".parse(),
+            Ok(LineRange {
+                start: 3,
+                end: None
+            })
+        );
+        assert_eq!(
+            "3".parse(),
+            Ok(LineRange {
+                start: 3,
+                end: Some(3)
+            })
+        );
+        assert!("7-3".parse::<LineRange>().is_err());
+        assert!("x".parse::<LineRange>().is_err());
+    }
+
+    #[test]
+    fn text_listing() {
+        assert_eq!(
+            disassemble(&program("+[-]"), Format::Text, None),
+            "     0  1:1       +  Increment current location\n     \
+             1  1:2       [  Start looping (matches 3)\n     \
+             2  1:3       -  Decrement current location\n     \
+             3  1:4       ]  Finish looping (matches 1)\n"
+        );
+        assert_eq!(
+            disassemble(&program("+\n-\n."), Format::Text, "2".parse().ok()),
+            "     1  2:1       -  Decrement current location\n"
+        );
+    }
+
+    #[test]
+    fn ir_listing() {
+        assert_eq!(
+            disassemble(&program("+++[->>+<<]."), Format::Ir, None),
+            "     0  1:1       add +3\n     \
+             1  1:4       jz 6\n     \
+             2  1:5       add -1\n     \
+             3  1:6       move +2\n     \
+             4  1:8       add +1\n     \
+             5  1:9       move -2\n     \
+             6  1:11      jnz 1\n     \
+             7  1:12      out\n"
+        );
+    }
+
+    #[test]
+    fn json_listing() {
+        let listing: Value =
+            serde_json::from_str(&disassemble(&program("[.]"), Format::Json, None)).unwrap();
+        assert_eq!(listing[1]["symbol"], ".");
+        assert_eq!(listing[2]["matches"], 0);
+        assert_eq!(listing[1]["matches"], Value::Null);
+    }
+}
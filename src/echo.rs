@@ -0,0 +1,102 @@
+//! Echoing the input a program reads, so that transcripts of interactive sessions show both sides
+//! of the conversation.
+//!
+//! The bytes can be echoed to the program's own output, where they are shown in the same
+//! [`OutputFormat`](crate::output_format::OutputFormat) as the rest of it, or to a file of their
+//! own.
+
+use std::cell::RefCell;
+use std::fs::File;
+use std::io;
+use std::io::{Read, Write};
+use std::path::Path;
+use std::rc::Rc;
+
+/// Input that writes each byte read from it to `echo`.
+pub struct Echoed<R, W> {
+    inner: R,
+    echo: W,
+}
+
+impl<R: Read, W: Write> Echoed<R, W> {
+    /// Read from `inner`, echoing to `echo`.
+    pub fn new(inner: R, echo: W) -> Self {
+        Echoed { inner, echo }
+    }
+}
+
+impl<R: Read, W: Write> Read for Echoed<R, W> {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        let count = self.inner.read(buf)?;
+        self.echo.write_all(&buf[..count])?;
+        Ok(count)
+    }
+}
+
+/// Output written to from more than one place, so that echoed input and the program's output are
+/// kept in order.
+pub struct Shared<W>(Rc<RefCell<W>>);
+
+impl<W> Clone for Shared<W> {
+    fn clone(&self) -> Self {
+        Shared(Rc::clone(&self.0))
+    }
+}
+
+impl<W: Write> Write for Shared<W> {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        self.0.borrow_mut().write(buf)
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        self.0.borrow_mut().flush()
+    }
+}
+
+/// Connect `input` and `output` so that the input is echoed to `echo`, if it's given: to `output`
+/// if it's `-`, and to the file it names otherwise.
+///
+/// # Errors
+/// Fails if the file to echo to can't be created.
+pub fn connect<R, W>(
+    input: R,
+    output: W,
+    echo: Option<&Path>,
+) -> io::Result<(Box<dyn Read>, Box<dyn Write>)>
+where
+    R: Read + 'static,
+    W: Write + 'static,
+{
+    Ok(match echo {
+        None => (Box::new(input), Box::new(output)),
+        Some(path) if path == Path::new("-") => {
+            let output = Shared(Rc::new(RefCell::new(output)));
+            (
+                Box::new(Echoed::new(input, output.clone())),
+                Box::new(output),
+            )
+        }
+        Some(path) => (
+            Box::new(Echoed::new(input, File::create(path)?)),
+            Box::new(output),
+        ),
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use bft_interp::BFVM;
+    use bft_types::BFprogram;
+
+    #[test]
+    fn echoing_to_output() {
+        let mut program = BFprogram::new("a.b", b"+++++[>++++++++++<-]>.,.,.");
+        program.validate_brackets().unwrap();
+        let output = Shared(Rc::new(RefCell::new(Vec::new())));
+        let mut input = Echoed::new(&b"ab"[..], output.clone());
+        let mut vm: BFVM<u8> = BFVM::new(None, false);
+        vm.run(&program, &mut input, &mut output.clone()).unwrap();
+        assert_eq!(*output.0.borrow(), b"2aabb");
+    }
+}
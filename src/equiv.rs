@@ -0,0 +1,264 @@
+//! Checking whether two programs behave the same, by running both on the same inputs under a
+//! budget of instructions.
+//!
+//! This can only show that programs differ, never that they're equivalent. The inputs tried are
+//! the empty input, any given by the user, and then generated ones: short strings of digits,
+//! letters and newlines, which most programs read, mixed with arbitrary bytes.
+
+use std::error::Error;
+use std::fmt;
+use std::io;
+use std::io::Write;
+use std::mem::{discriminant, Discriminant};
+
+use bft_interp::{StepOutcome, VMError, BFVM};
+use bft_types::BFprogram;
+
+/// Longest input generated.
+const MAX_INPUT_LEN: usize = 16;
+
+/// Bytes that generated text is made from.
+const TEXT: &[u8] = b"0123456789abcxyzABC \n";
+
+/// How a run ended.
+#[derive(Clone, Debug, Eq)]
+enum Ending {
+    Finished,
+    OutOfFuel,
+    /// The kind of error the run failed with, and its message for the report.
+    Failed(Discriminant<VMError>, String),
+}
+
+/// Runs that failed are the same if they failed in the same way, wherever in the program that
+/// happened.
+impl PartialEq for Ending {
+    fn eq(&self, other: &Self) -> bool {
+        match (self, other) {
+            (Self::Finished, Self::Finished) | (Self::OutOfFuel, Self::OutOfFuel) => true,
+            (Self::Failed(a, _), Self::Failed(b, _)) => a == b,
+            _ => false,
+        }
+    }
+}
+
+/// The output of a run of a program, and how it ended.
+#[derive(Clone, Debug, PartialEq, Eq)]
+struct Run {
+    output: Vec<u8>,
+    ending: Ending,
+}
+
+impl fmt::Display for Run {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "wrote \"{}\" and ", self.output.escape_ascii())?;
+        match &self.ending {
+            Ending::Finished => write!(f, "finished"),
+            Ending::OutOfFuel => write!(f, "ran out of fuel"),
+            Ending::Failed(_, error) => write!(f, "failed: {error}"),
+        }
+    }
+}
+
+/// Run `program` with `input`, executing at most `fuel` instructions.
+fn run(program: &BFprogram, mut input: &[u8], fuel: u64) -> Run {
+    let mut vm: BFVM<u8> = BFVM::new(None, false);
+    let mut output = Vec::new();
+    let mut steps = 0;
+    let ending = loop {
+        match vm.step(program, &mut input, &mut output) {
+            Ok(StepOutcome::Running) => {}
+            Ok(_) => break Ending::Finished,
+            Err(err) => break Ending::Failed(discriminant(&err), err.to_string()),
+        }
+        steps += 1;
+        if steps >= fuel {
+            break Ending::OutOfFuel;
+        }
+    };
+    Run { output, ending }
+}
+
+/// Whether `a` and `b` show that the programs differ. A run that ran out of fuel might have gone
+/// on to write more, so it only differs from another run if what it wrote so far doesn't fit.
+fn distinguishes(a: &Run, b: &Run) -> bool {
+    match (&a.ending, &b.ending) {
+        (Ending::OutOfFuel, Ending::OutOfFuel) => {
+            !a.output.starts_with(&b.output) && !b.output.starts_with(&a.output)
+        }
+        (Ending::OutOfFuel, _) => !b.output.starts_with(&a.output),
+        (_, Ending::OutOfFuel) => !a.output.starts_with(&b.output),
+        _ => a != b,
+    }
+}
+
+/// A generator of inputs, using `SplitMix64` so that the same seed always gives the same inputs.
+struct Inputs(u64);
+
+impl Inputs {
+    fn next_u64(&mut self) -> u64 {
+        self.0 = self.0.wrapping_add(0x9e37_79b9_7f4a_7c15);
+        let mut z = self.0;
+        z = (z ^ (z >> 30)).wrapping_mul(0xbf58_476d_1ce4_e5b9);
+        z = (z ^ (z >> 27)).wrapping_mul(0x94d0_49bb_1331_11eb);
+        z ^ (z >> 31)
+    }
+
+    /// A number below `n`.
+    fn below(&mut self, n: usize) -> usize {
+        usize::try_from(self.next_u64() % n as u64).unwrap_or_default()
+    }
+
+    /// The next input: text for even-numbered inputs, and arbitrary bytes for odd ones.
+    fn generate(&mut self, n: usize) -> Vec<u8> {
+        let len = self.below(MAX_INPUT_LEN + 1);
+        (0..len)
+            .map(|_| {
+                if n.is_multiple_of(2) {
+                    TEXT[self.below(TEXT.len())]
+                } else {
+                    self.next_u64().to_le_bytes()[0]
+                }
+            })
+            .collect()
+    }
+}
+
+/// What to run the programs on.
+pub struct Options {
+    /// Inputs given by the user.
+    pub inputs: Vec<Vec<u8>>,
+
+    /// Number of inputs to generate.
+    pub generated: usize,
+
+    /// Seed for generating inputs.
+    pub seed: u64,
+
+    /// Most instructions each program may execute for each input.
+    pub fuel: u64,
+}
+
+/// Run `a` and `b` on the inputs from `options`, reporting the first input they behave
+/// differently on to `out`. Returns true if no input showed a difference.
+///
+/// # Errors
+/// Fails if the report can't be written.
+pub fn check<W: Write>(
+    a: &BFprogram,
+    b: &BFprogram,
+    options: &Options,
+    out: &mut W,
+) -> Result<bool, Box<dyn Error>> {
+    let mut generator = Inputs(options.seed);
+    let inputs = std::iter::once(Vec::new())
+        .chain(options.inputs.iter().cloned())
+        .chain((0..options.generated).map(|n| generator.generate(n)));
+    let mut tried = 0;
+    let mut out_of_fuel = 0;
+    for input in inputs {
+        let (run_a, run_b) = (run(a, &input, options.fuel), run(b, &input, options.fuel));
+        tried += 1;
+        if distinguishes(&run_a, &run_b) {
+            report_difference(a, b, &input, &run_a, &run_b, out)?;
+            return Ok(false);
+        }
+        if run_a.ending == Ending::OutOfFuel || run_b.ending == Ending::OutOfFuel {
+            out_of_fuel += 1;
+        }
+    }
+    write!(out, "no difference found on {tried} inputs")?;
+    if out_of_fuel > 0 {
+        write!(
+            out,
+            " ({out_of_fuel} ran out of fuel, so were only compared in part)"
+        )?;
+    }
+    writeln!(out)?;
+    Ok(true)
+}
+
+fn report_difference<W: Write>(
+    a: &BFprogram,
+    b: &BFprogram,
+    input: &[u8],
+    run_a: &Run,
+    run_b: &Run,
+    out: &mut W,
+) -> io::Result<()> {
+    writeln!(out, "programs differ on input \"{}\"", input.escape_ascii())?;
+    writeln!(out, "  {} {run_a}", a.source().display())?;
+    writeln!(out, "  {} {run_b}", b.source().display())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn program(code: &str) -> BFprogram {
+        let mut program = BFprogram::new("mod.test", code.as_bytes());
+        program.validate_brackets().unwrap();
+        program
+    }
+
+    fn check_programs(a: &str, b: &str) -> (bool, String) {
+        let options = Options {
+            inputs: vec![b"zz".to_vec()],
+            generated: 50,
+            seed: 1,
+            fuel: 10_000,
+        };
+        let mut out = Vec::new();
+        let same = check(&program(a), &program(b), &options, &mut out).unwrap();
+        (same, String::from_utf8(out).unwrap())
+    }
+
+    #[test]
+    fn equivalent_programs() {
+        assert_eq!(
+            check_programs(",.,.", "echo a byte ,. and another ,."),
+            (true, String::from("no difference found on 52 inputs\n"))
+        );
+        let (same, out) = check_programs("+[]", "+[>+<]");
+        assert!(same);
+        assert!(out.ends_with("(52 ran out of fuel, so were only compared in part)\n"));
+    }
+
+    #[test]
+    fn distinguishing_input() {
+        assert_eq!(
+            check_programs(",.", ",+-."),
+            (true, String::from("no difference found on 52 inputs\n"))
+        );
+        let (same, out) = check_programs(",.", ",[-].");
+        assert!(!same);
+        assert_eq!(
+            out,
+            "programs differ on input \"zz\"\n  mod.test wrote \"z\" and finished\n  \
+             mod.test wrote \"\\x00\" and finished\n"
+        );
+    }
+
+    #[test]
+    fn failing_in_the_same_way() {
+        let (same, _) = check_programs("+.<", "+.\n<");
+        assert!(same);
+        let (same, out) = check_programs("+.<", "+.");
+        assert!(!same);
+        assert!(out.contains("failed: Head moved to invalid position"));
+    }
+
+    #[test]
+    fn fuel() {
+        let finished = Run {
+            output: b"ab".to_vec(),
+            ending: Ending::Finished,
+        };
+        let partial = |output: &[u8]| Run {
+            output: output.to_vec(),
+            ending: Ending::OutOfFuel,
+        };
+        assert!(!distinguishes(&finished, &partial(b"a")));
+        assert!(distinguishes(&finished, &partial(b"abc")));
+        assert!(distinguishes(&partial(b"b"), &partial(b"ab")));
+    }
+}
@@ -0,0 +1,76 @@
+//! The exit codes used by bft, so that scripts can tell what went wrong.
+//!
+//! A program that finishes with the `halt` extension exits with the status it gives instead,
+//! which may overlap with these.
+
+use bft_interp::{BftError, VMError};
+
+/// The command line couldn't be parsed, or a check made by a subcommand failed, like a test
+/// program's output not matching.
+pub const FAILURE: u8 = 1;
+
+/// A program couldn't be parsed or is invalid, like having unmatched brackets.
+pub const INVALID: u8 = 2;
+
+/// A program failed while running, like moving the head off the tape.
+pub const RUNTIME: u8 = 3;
+
+/// A program was stopped for going over one of its limits.
+pub const LIMIT: u8 = 4;
+
+/// Reading or writing a file, or a program's input or output, failed.
+pub const IO: u8 = 5;
+
+/// A description of the exit codes, for the help.
+pub const HELP: &str = "\
+Exit codes:
+  0  Success
+  1  Usage error, or a failed check such as a test whose output doesn't match
+  2  Invalid program, such as one with unmatched brackets
+  3  Runtime error, such as moving the head off the tape
+  4  Limit exceeded, such as running out of fuel or writing too much output
+  5  I/O error";
+
+/// The exit code for stopping with `error`.
+pub fn for_error(error: &BftError) -> u8 {
+    match error {
+        _ if error.is_invalid_program() => INVALID,
+        BftError::Runtime(
+            VMError::OutOfFuel(..) | VMError::LimitExceeded(..) | VMError::Cancelled(_),
+        ) => LIMIT,
+        BftError::Runtime(VMError::IOError(..)) | BftError::Io(_) => IO,
+        BftError::Runtime(_) => RUNTIME,
+        _ => FAILURE,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use bft_interp::BFVM;
+    use bft_types::BFprogram;
+    use std::io;
+    use std::num::NonZeroUsize;
+
+    fn run(code: &str) -> BftError {
+        let mut program = BFprogram::new("a.b", code.as_bytes());
+        program.validate_brackets().unwrap();
+        BFVM::<u8>::new(NonZeroUsize::new(4), false)
+            .with_max_output(1)
+            .run(&program, &mut &b""[..], &mut Vec::new())
+            .unwrap_err()
+            .into()
+    }
+
+    #[test]
+    fn classifying() {
+        let err = BFprogram::new("a.b", &b"["[..])
+            .validate_brackets()
+            .unwrap_err();
+        assert_eq!(for_error(&err.into()), INVALID);
+        assert_eq!(for_error(&run("<")), RUNTIME);
+        assert_eq!(for_error(&run("..")), LIMIT);
+        assert_eq!(for_error(&io::Error::other("disk full").into()), IO);
+        assert_eq!(for_error(&"Unknown setting 'colour'".into()), FAILURE);
+    }
+}
@@ -0,0 +1,201 @@
+//! Formatting programs in a canonical layout.
+//!
+//! Loops and procedures are written with their opening and closing brackets on lines of their
+//! own, and their bodies indented by two spaces for each level of nesting, up to a limit so that
+//! the output for deeply nested programs stays in proportion to them. Other instructions are
+//! packed onto lines up to the requested width. Instructions keep the spelling they were
+//! written with, in dialects with several tokens for one instruction, though the whitespace
+//! within multi-word tokens is reduced to single spaces. Comments are kept: one that shared a line with
+//! an instruction stays at the end of that line, while others get lines of their own. A single
+//! blank line is kept wherever the original had one or more.
+
+use std::error::Error;
+use std::path::Path;
+
+use bft_types::{Alphabet, BFprogram, Instruction, Token};
+
+const INDENT: &str = "  ";
+
+/// Nesting deeper than this isn't indented any further.
+const MAX_INDENT: usize = 32;
+
+/// Builds the formatted program one line at a time.
+struct Layout {
+    lines: Vec<String>,
+    line: String,
+    /// True if more instructions may be added to the end of `line`.
+    open: bool,
+    depth: usize,
+    width: usize,
+}
+
+impl Layout {
+    fn finish_line(&mut self) {
+        if !self.line.is_empty() {
+            self.lines.push(std::mem::take(&mut self.line));
+        }
+        self.open = false;
+    }
+
+    fn start_line(&mut self, text: &str) {
+        self.finish_line();
+        self.line = INDENT.repeat(self.depth.min(MAX_INDENT)) + text;
+    }
+
+    fn blank_line(&mut self) {
+        self.finish_line();
+        if self.lines.last().is_some_and(|line| !line.is_empty()) {
+            self.lines.push(String::new());
+        }
+    }
+
+    fn instruction(&mut self, inst: Instruction, token: &str) {
+        match inst {
+            Instruction::BeginLoop | Instruction::BeginProcedure => {
+                self.start_line(token);
+                self.depth += 1;
+            }
+            Instruction::EndLoop | Instruction::EndProcedure => {
+                self.depth = self.depth.saturating_sub(1);
+                self.start_line(token);
+            }
+            _ => {
+                if !self.open || self.line.len() + token.len() > self.width {
+                    self.start_line("");
+                }
+                self.line.push_str(token);
+                self.open = true;
+            }
+        }
+    }
+
+    fn comment(&mut self, text: &str) {
+        let mut pieces = text.split('\n');
+        let first = pieces.next().unwrap_or_default().trim();
+        if !first.is_empty() {
+            if self.line.is_empty() {
+                self.start_line(first);
+            } else {
+                self.line.push(' ');
+                self.line.push_str(first);
+            }
+            self.open = false;
+        }
+        let rest: Vec<&str> = pieces.map(str::trim).collect();
+        for (idx, piece) in rest.iter().enumerate() {
+            if !piece.is_empty() {
+                self.start_line(piece);
+                self.open = false;
+            } else if idx + 1 < rest.len() {
+                self.blank_line();
+            }
+        }
+    }
+}
+
+/// Format the program in `data`, written using the tokens in `alphabet`, packing instructions
+/// onto lines of at most `width` characters.
+///
+/// # Errors
+/// Fails if the program's brackets don't match, as the nesting can't be worked out.
+pub fn format(
+    source_name: &Path,
+    data: &[u8],
+    alphabet: &Alphabet,
+    width: usize,
+) -> Result<String, Box<dyn Error>> {
+    BFprogram::with_alphabet(source_name, data, alphabet).validate_brackets()?;
+    let mut layout = Layout {
+        lines: Vec::new(),
+        line: String::new(),
+        open: false,
+        depth: 0,
+        width,
+    };
+    for token in alphabet.tokenize(data) {
+        match token {
+            Token::Instruction(inst, written) => {
+                let written = String::from_utf8_lossy(written);
+                let words: Vec<&str> = written.split_ascii_whitespace().collect();
+                layout.instruction(inst, &words.join(" "));
+            }
+            Token::Comment(text) => layout.comment(&String::from_utf8_lossy(text)),
+        }
+    }
+    layout.finish_line();
+    while layout.lines.last().is_some_and(String::is_empty) {
+        layout.lines.pop();
+    }
+    let mut out = layout.lines.join("\n");
+    out.push('\n');
+    Ok(out)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn fmt(code: &str, width: usize) -> String {
+        format(
+            Path::new("mod.test"),
+            code.as_bytes(),
+            &Alphabet::default(),
+            width,
+        )
+        .unwrap()
+    }
+
+    #[test]
+    fn indenting_loops() {
+        assert_eq!(
+            fmt("++[>+[-]<-]>.", 80),
+            "++\n[\n  >+\n  [\n    -\n  ]\n  <-\n]\n>.\n"
+        );
+    }
+
+    #[test]
+    fn wrapping() {
+        assert_eq!(fmt("++++++++++", 4), "++++\n++++\n++\n");
+        assert_eq!(fmt("[++++++]", 6), "[\n  ++++\n  ++\n]\n");
+    }
+
+    #[test]
+    fn comments() {
+        let code = "Start here\n+++ set the counter\n\n\n[ loop\n  -\n]done";
+        let formatted = fmt(code, 80);
+        assert_eq!(
+            formatted,
+            "Start here\n+++ set the counter\n\n[ loop\n  -\n] done\n"
+        );
+        assert_eq!(fmt(&formatted, 80), formatted);
+    }
+
+    #[test]
+    fn deep_nesting() {
+        let depth = 200_000;
+        let code = "[".repeat(depth) + &"]".repeat(depth);
+        let formatted = fmt(&code, 80);
+        assert_eq!(formatted.lines().count(), 2 * depth);
+        assert!(formatted
+            .lines()
+            .all(|line| line.len() <= MAX_INDENT * 2 + 1));
+        assert_eq!(fmt(&formatted, 80), formatted);
+    }
+
+    #[test]
+    fn spelling() {
+        let alphabet = Alphabet::new([
+            (Instruction::Increment, "inc"),
+            (Instruction::Increment, "up"),
+            (Instruction::Output, "print  it"),
+        ])
+        .unwrap();
+        let formatted = format(Path::new("mod.test"), b"up inc print\n it", &alphabet, 80);
+        assert_eq!(formatted.unwrap(), "upincprint it\n");
+    }
+
+    #[test]
+    fn mismatched_brackets() {
+        assert!(format(Path::new("mod.test"), b"[", &Alphabet::default(), 80).is_err());
+    }
+}
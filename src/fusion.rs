@@ -0,0 +1,101 @@
+//! Reporting which loops `--fast-unchecked` compiled and how often they ran, for `--dump-fused`.
+//!
+//! Each compiled loop is listed in program order, with how many times it ran compiled, the
+//! iterations it ran, and how many times it had to fall back to running one instruction at a
+//! time. Loops that compile to the same thing are then counted together under their shape, the
+//! shortest loop that does the same, with the shapes that ran the most iterations first:
+//!
+//! ```text
+//! fused loops: 2 compiled, 2 ran
+//! 1:10 [->++>+++<<]: 4 runs, 12 iterations, 0 fallbacks
+//! 1:5 [->+<]: 1 run, 4 iterations, 0 fallbacks
+//! shapes:
+//! [->++>+++<<]: 1 loop, 12 iterations
+//! [->+<]: 1 loop, 4 iterations
+//! ```
+
+use std::collections::BTreeMap;
+use std::io;
+use std::io::Write;
+
+use bft_interp::FusedLoop;
+use bft_types::BFprogram;
+
+/// `count` followed by `noun`, made plural if needed.
+fn counted(count: u64, noun: &str) -> String {
+    if count == 1 {
+        format!("1 {noun}")
+    } else {
+        format!("{count} {noun}s")
+    }
+}
+
+/// Write a report on the loops in `stats`, which were compiled from `program`, to `out`.
+///
+/// # Errors
+/// Fails if the report can't be written.
+pub fn report<W: Write>(
+    program: &BFprogram,
+    stats: &BTreeMap<usize, FusedLoop>,
+    out: &mut W,
+) -> io::Result<()> {
+    let ran = stats.values().filter(|fused| fused.runs > 0).count();
+    writeln!(out, "fused loops: {} compiled, {ran} ran", stats.len())?;
+    let mut shapes: BTreeMap<String, (u64, u64)> = BTreeMap::new();
+    for (start, fused) in stats {
+        let shape = fused.shape();
+        writeln!(
+            out,
+            "{} {shape}: {}, {}, {}",
+            program.instructions()[*start].location(),
+            counted(fused.runs, "run"),
+            counted(fused.iterations, "iteration"),
+            counted(fused.fallbacks, "fallback")
+        )?;
+        let (loops, iterations) = shapes.entry(shape).or_default();
+        *loops += 1;
+        *iterations += fused.iterations;
+    }
+    let mut shapes: Vec<_> = shapes.into_iter().collect();
+    shapes.sort_by(|(_, (_, a)), (_, (_, b))| b.cmp(a));
+    writeln!(out, "shapes:")?;
+    for (shape, (loops, iterations)) in shapes {
+        writeln!(
+            out,
+            "{shape}: {}, {}",
+            counted(loops, "loop"),
+            counted(iterations, "iteration")
+        )?;
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use bft_interp::BFVM;
+    use std::num::NonZeroUsize;
+
+    #[test]
+    fn reporting() {
+        let mut program = BFprogram::new("a.b", b"++++[>+<-]>[>++>+++<<-]<+[->>>>+<<<<]");
+        program.validate_brackets().unwrap();
+        // The last loop's cells are off the end of the tape, so it has to grow it.
+        let mut vm: BFVM<u8> = BFVM::new(NonZeroUsize::new(4), true).with_fusion_stats();
+        vm.run_fast_unchecked(&program, &mut io::empty(), &mut io::sink())
+            .unwrap();
+        let mut out = Vec::new();
+        report(&program, vm.fusion_stats().unwrap(), &mut out).unwrap();
+        assert_eq!(
+            String::from_utf8(out).unwrap(),
+            "fused loops: 3 compiled, 2 ran\n\
+             1:5 [->+<]: 1 run, 4 iterations, 0 fallbacks\n\
+             1:12 [->++>+++<<]: 1 run, 4 iterations, 0 fallbacks\n\
+             1:26 [->>>>+<<<<]: 0 runs, 0 iterations, 1 fallback\n\
+             shapes:\n\
+             [->++>+++<<]: 1 loop, 4 iterations\n\
+             [->+<]: 1 loop, 4 iterations\n\
+             [->>>>+<<<<]: 1 loop, 0 iterations\n"
+        );
+    }
+}
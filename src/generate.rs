@@ -0,0 +1,486 @@
+//! Generating Brainf*ck programs.
+
+/// The longest line written by the generators.
+const WIDTH: usize = 80;
+
+/// The deepest an expression's operators and brackets can be nested, which keeps parsing and
+/// generating code for it from running out of stack.
+const MAX_EXPR_DEPTH: usize = 256;
+
+/// The shortest code that adds `delta` to the current cell, wrapping around, using the cell to
+/// its right, which must be zero, as a loop counter. The head is left where it started.
+fn add(delta: u8) -> String {
+    let (n, up, down) = if delta <= 128 {
+        (usize::from(delta), "+", "-")
+    } else {
+        (usize::from(delta.wrapping_neg()), "-", "+")
+    };
+    let mut best = up.repeat(n);
+    for a in 2..=n {
+        // Add `a * b` with a loop, then adjust by the remainder, either up from below or down
+        // from above.
+        for b in [n / a, n.div_ceil(a)] {
+            let product = a * b;
+            let adjust = if product <= n {
+                up.repeat(n - product)
+            } else {
+                down.repeat(product - n)
+            };
+            let code = format!(">{}[<{}>-]<{adjust}", "+".repeat(a), up.repeat(b));
+            if code.len() < best.len() {
+                best = code;
+            }
+        }
+    }
+    best
+}
+
+/// Split `pieces` of code over lines of at most [`WIDTH`] characters, without splitting any
+/// piece that fits on a line.
+fn wrap(pieces: impl IntoIterator<Item = String>) -> String {
+    let mut out = String::new();
+    let mut line_len = 0;
+    for piece in pieces {
+        if line_len > 0 && line_len + piece.len() > WIDTH {
+            out.push('\n');
+            line_len = 0;
+        }
+        line_len += piece.len();
+        out.push_str(&piece);
+    }
+    out.push('\n');
+    out
+}
+
+/// A program that prints `text`, using two cells.
+#[must_use]
+pub fn text(text: &[u8]) -> String {
+    let mut current = 0u8;
+    wrap(text.iter().map(|c| {
+        let code = add(c.wrapping_sub(current)) + ".";
+        current = *c;
+        code
+    }))
+}
+
+/// A parsed arithmetic expression.
+enum Expr {
+    Number(u8),
+    Negate(Box<Expr>),
+    Binary(u8, Box<Expr>, Box<Expr>),
+}
+
+impl Expr {
+    /// The value of the expression, with the same wrapping byte arithmetic the generated program
+    /// uses.
+    fn value(&self) -> Result<u8, String> {
+        match self {
+            Expr::Number(n) => Ok(*n),
+            Expr::Negate(e) => Ok(e.value()?.wrapping_neg()),
+            Expr::Binary(op, l, r) => {
+                let (l, r) = (l.value()?, r.value()?);
+                match op {
+                    b'+' => Ok(l.wrapping_add(r)),
+                    b'-' => Ok(l.wrapping_sub(r)),
+                    b'*' => Ok(l.wrapping_mul(r)),
+                    _ if r == 0 => Err(String::from("division by zero")),
+                    b'/' => Ok(l / r),
+                    _ => Ok(l % r),
+                }
+            }
+        }
+    }
+}
+
+/// A recursive descent parser for arithmetic expressions. Each parsed expression comes with its
+/// depth, which is kept within [`MAX_EXPR_DEPTH`].
+struct ExprParser<'a> {
+    text: &'a [u8],
+    pos: usize,
+    nesting: usize,
+}
+
+impl ExprParser<'_> {
+    /// The next character that isn't whitespace, without consuming it.
+    fn peek(&mut self) -> Option<u8> {
+        while self.text.get(self.pos).is_some_and(u8::is_ascii_whitespace) {
+            self.pos += 1;
+        }
+        self.text.get(self.pos).copied()
+    }
+
+    fn unexpected(&mut self) -> String {
+        match self.peek() {
+            Some(c) => format!("unexpected '{}' at column {}", char::from(c), self.pos + 1),
+            None => String::from("unexpected end of expression"),
+        }
+    }
+
+    /// Check that an expression of `depth` isn't nested too deeply.
+    fn check_depth(depth: usize) -> Result<usize, String> {
+        if depth > MAX_EXPR_DEPTH {
+            return Err(format!(
+                "expression is nested more than {MAX_EXPR_DEPTH} deep"
+            ));
+        }
+        Ok(depth)
+    }
+
+    /// Parse with `parse` inside a bracket or negation, failing before the nesting gets too deep
+    /// to parse.
+    fn nested(
+        &mut self,
+        parse: fn(&mut Self) -> Result<(Expr, usize), String>,
+    ) -> Result<(Expr, usize), String> {
+        self.nesting += 1;
+        Self::check_depth(self.nesting)?;
+        let parsed = parse(self);
+        self.nesting -= 1;
+        parsed
+    }
+
+    /// Parse binary operators in `ops`, whose operands are parsed by `operand`.
+    fn binary(
+        &mut self,
+        ops: &[u8],
+        operand: fn(&mut Self) -> Result<(Expr, usize), String>,
+    ) -> Result<(Expr, usize), String> {
+        let (mut expr, mut depth) = operand(self)?;
+        while let Some(op) = self.peek().filter(|c| ops.contains(c)) {
+            self.pos += 1;
+            let (right, right_depth) = operand(self)?;
+            depth = Self::check_depth(depth.max(right_depth) + 1)?;
+            expr = Expr::Binary(op, Box::new(expr), Box::new(right));
+        }
+        Ok((expr, depth))
+    }
+
+    fn sum(&mut self) -> Result<(Expr, usize), String> {
+        self.binary(b"+-", Self::product)
+    }
+
+    fn product(&mut self) -> Result<(Expr, usize), String> {
+        self.binary(b"*/%", Self::unary)
+    }
+
+    fn unary(&mut self) -> Result<(Expr, usize), String> {
+        match self.peek() {
+            Some(b'-') => {
+                self.pos += 1;
+                let (expr, depth) = self.nested(Self::unary)?;
+                Ok((Expr::Negate(Box::new(expr)), Self::check_depth(depth + 1)?))
+            }
+            Some(b'(') => {
+                self.pos += 1;
+                let parsed = self.nested(Self::sum)?;
+                if self.peek() != Some(b')') {
+                    return Err(self.unexpected());
+                }
+                self.pos += 1;
+                Ok(parsed)
+            }
+            Some(c) if c.is_ascii_digit() => {
+                let mut n = 0u8;
+                while let Some(digit) = self.text.get(self.pos).filter(|c| c.is_ascii_digit()) {
+                    n = n.wrapping_mul(10).wrapping_add(digit - b'0');
+                    self.pos += 1;
+                }
+                Ok((Expr::Number(n), 1))
+            }
+            _ => Err(self.unexpected()),
+        }
+    }
+}
+
+/// Emits code for operations on particular cells, keeping track of where the head is.
+#[derive(Default)]
+struct Codegen {
+    pieces: Vec<String>,
+    head: usize,
+}
+
+impl Codegen {
+    fn move_to(&mut self, cell: usize) {
+        let piece = if cell < self.head {
+            "<".repeat(self.head - cell)
+        } else {
+            ">".repeat(cell - self.head)
+        };
+        self.pieces.push(piece);
+        self.head = cell;
+    }
+
+    fn emit(&mut self, code: &str) {
+        self.pieces.push(String::from(code));
+    }
+
+    /// Add `value` to `cell`. The cell to its right must be zero.
+    fn add(&mut self, cell: usize, value: u8) {
+        self.move_to(cell);
+        self.pieces.push(add(value));
+    }
+
+    fn clear(&mut self, cell: usize) {
+        self.move_to(cell);
+        self.emit("[-]");
+    }
+
+    /// Run `body` until `cell` is zero. The body must leave the head where it found it.
+    fn looped(&mut self, cell: usize, body: impl FnOnce(&mut Self)) {
+        self.move_to(cell);
+        self.emit("[");
+        body(self);
+        self.move_to(cell);
+        self.emit("]");
+    }
+
+    /// Move the value in `from` into each of `to`, adding or subtracting it as given, and leave
+    /// `from` zero.
+    fn drain(&mut self, from: usize, to: &[(usize, bool)]) {
+        self.looped(from, |gen| {
+            gen.emit("-");
+            for (cell, add) in to {
+                gen.move_to(*cell);
+                gen.emit(if *add { "+" } else { "-" });
+            }
+        });
+    }
+
+    /// Copy `from` to `to`, using `spare`, which must be zero.
+    fn copy(&mut self, from: usize, to: usize, spare: usize) {
+        self.drain(from, &[(to, true), (spare, true)]);
+        self.drain(spare, &[(from, true)]);
+    }
+
+    /// Divide `cell` by `cell + 2`, with `cell + 1` and the three cells after the divisor zero.
+    /// Leaves `cell` zero, and the remainder and quotient in `cell + 3` and `cell + 4`, with
+    /// junk in the cells between.
+    fn divmod(&mut self, cell: usize) {
+        self.move_to(cell);
+        self.emit("[->+>-[>+>>]>[+[-<+>]>+>>]<<<<<<]");
+    }
+
+    /// Combine the values in `a` and `a + 1` with `op`, leaving the result in `a` and every cell
+    /// after it zero.
+    fn binary(&mut self, op: u8, a: usize) {
+        let b = a + 1;
+        match op {
+            b'+' => self.drain(b, &[(a, true)]),
+            b'-' => self.drain(b, &[(a, false)]),
+            b'*' => {
+                let (count, spare) = (a + 2, a + 3);
+                self.drain(a, &[(count, true)]);
+                self.looped(count, |gen| {
+                    gen.emit("-");
+                    gen.copy(b, a, spare);
+                    gen.move_to(count);
+                });
+                self.clear(b);
+            }
+            _ => {
+                self.drain(b, &[(a + 2, true)]);
+                self.divmod(a);
+                let (remainder, quotient) = (a + 3, a + 4);
+                let (result, junk) = if op == b'/' {
+                    (quotient, remainder)
+                } else {
+                    (remainder, quotient)
+                };
+                for cell in [a + 1, a + 2, junk] {
+                    self.clear(cell);
+                }
+                self.drain(result, &[(a, true)]);
+            }
+        }
+    }
+
+    /// Compute `expr` into `cell`, using the cells after it, which must be zero.
+    fn expr(&mut self, expr: &Expr, cell: usize) {
+        match expr {
+            Expr::Number(n) => self.add(cell, *n),
+            Expr::Negate(e) => {
+                self.expr(e, cell + 1);
+                self.drain(cell + 1, &[(cell, false)]);
+            }
+            Expr::Binary(op, l, r) => {
+                self.expr(l, cell);
+                self.expr(r, cell + 1);
+                self.binary(*op, cell);
+            }
+        }
+    }
+
+    /// Print the value in cell 0 in decimal, leaving it unchanged, using the cells after it,
+    /// which must be zero.
+    fn print_decimal(&mut self) {
+        // Split the value into its digits, with the units in cell 4, tens in cell 8 and hundreds
+        // in cell 9.
+        self.copy(0, 1, 2);
+        self.add(3, 10);
+        self.divmod(1);
+        self.clear(2);
+        self.clear(3);
+        self.add(7, 10);
+        self.divmod(5);
+        self.clear(6);
+        self.clear(7);
+        let (units, tens, hundreds, flag, temp) = (4, 8, 9, 10, 11);
+
+        // Print the hundreds if there are any, flagging that the tens must be printed.
+        self.copy(hundreds, temp, temp + 1);
+        self.looped(temp, |gen| {
+            gen.add(hundreds, b'0');
+            gen.emit(".");
+            gen.move_to(flag);
+            gen.emit("+");
+            gen.clear(temp);
+        });
+        self.clear(hundreds);
+        self.copy(tens, temp, temp + 1);
+        self.looped(temp, |gen| {
+            gen.clear(flag);
+            gen.emit("+");
+            gen.clear(temp);
+        });
+        self.looped(flag, |gen| {
+            gen.add(tens, b'0');
+            gen.emit(".");
+            gen.clear(flag);
+        });
+        self.add(units, b'0');
+        self.emit(".");
+        self.clear(units);
+        self.clear(tens);
+        self.move_to(0);
+    }
+}
+
+/// A program that computes the integer expression in `text`, leaving the result in cell 0, and
+/// printing it in decimal if `print` is set. Expressions can use `+`, `-`, `*`, `/`, `%` and
+/// brackets, and the arithmetic is on bytes, wrapping around like the cells do.
+///
+/// # Errors
+/// Fails if the expression can't be parsed, is nested too deeply, or divides by zero.
+pub fn expr(text: &str, print: bool) -> Result<String, String> {
+    let mut parser = ExprParser {
+        text: text.as_bytes(),
+        pos: 0,
+        nesting: 0,
+    };
+    let (expr, _) = parser.sum()?;
+    if parser.peek().is_some() {
+        return Err(parser.unexpected());
+    }
+    expr.value()?;
+    let mut gen = Codegen::default();
+    gen.expr(&expr, 0);
+    gen.move_to(0);
+    if print {
+        gen.print_decimal();
+    }
+    Ok(wrap(
+        gen.pieces.into_iter().filter(|piece| !piece.is_empty()),
+    ))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use std::io;
+
+    use bft_interp::BFVM;
+    use bft_types::BFprogram;
+
+    fn run(code: &str) -> Vec<u8> {
+        run_vm(code).1
+    }
+
+    fn run_vm(code: &str) -> (BFVM<u8>, Vec<u8>) {
+        let mut program = BFprogram::new("generate.test", code.as_bytes());
+        program.validate_brackets().unwrap();
+        let mut vm: BFVM<u8> = BFVM::new(None, true);
+        let mut output = Vec::new();
+        vm.run(&program, &mut io::empty(), &mut output).unwrap();
+        (vm, output)
+    }
+
+    #[test]
+    fn adding() {
+        assert_eq!(add(3), "+++");
+        assert_eq!(add(254), "--");
+        assert_eq!(add(72), ">++++++++[<+++++++++>-]<");
+        assert_eq!(add(0), "");
+    }
+
+    #[test]
+    fn printing_text() {
+        let text = b"Hello, World!\n\x00\xff";
+        let code = super::text(text);
+        assert_eq!(run(&code), text);
+        assert!(code.len() < 250, "{} bytes", code.len());
+        assert!(code.lines().all(|line| line.len() <= WIDTH));
+    }
+
+    #[test]
+    fn expressions() {
+        for (text, value) in [
+            ("(3*7+2)%5", 3),
+            ("200 + 100", 44),
+            ("-(2 - 7) * 4 / 3", 6),
+            ("255 / 16 % 10", 5),
+            ("7", 7),
+        ] {
+            let (vm, output) = run_vm(&expr(text, false).unwrap());
+            assert_eq!(vm.tape()[0], value, "{text}");
+            assert!(vm.tape()[1..].iter().all(|c| *c == 0), "{text}");
+            assert!(output.is_empty());
+        }
+    }
+
+    #[test]
+    fn printing_expressions() {
+        for (text, printed) in [("0", "0"), ("9", "9"), ("10*10+5", "105"), ("250+5", "255")] {
+            let (vm, output) = run_vm(&expr(text, true).unwrap());
+            assert_eq!(String::from_utf8(output).unwrap(), printed);
+            assert_eq!(vm.tape()[0].to_string(), printed);
+            assert!(vm.tape()[1..].iter().all(|c| *c == 0), "{text}");
+        }
+        assert_eq!(run(&expr("5*10-45", true).unwrap()), b"5");
+    }
+
+    #[test]
+    fn invalid_expressions() {
+        assert_eq!(
+            expr("1 +", false),
+            Err(String::from("unexpected end of expression"))
+        );
+        assert_eq!(
+            expr("(1", false),
+            Err(String::from("unexpected end of expression"))
+        );
+        assert_eq!(
+            expr("1 ) 2", false),
+            Err(String::from("unexpected ')' at column 3"))
+        );
+        assert_eq!(
+            expr("4 / (2 - 2)", false),
+            Err(String::from("division by zero"))
+        );
+    }
+
+    #[test]
+    fn deeply_nested_expressions() {
+        let too_deep = Err(format!(
+            "expression is nested more than {MAX_EXPR_DEPTH} deep"
+        ));
+        let brackets = format!("{}1{}", "(".repeat(20000), ")".repeat(20000));
+        assert_eq!(expr(&brackets, false), too_deep);
+        assert_eq!(expr(&format!("{}1", "-".repeat(20000)), false), too_deep);
+        assert_eq!(expr(&vec!["1"; 20000].join("+"), false), too_deep);
+        assert_eq!(expr(&vec!["2"; 20000].join("*"), false), too_deep);
+
+        let (vm, _) = run_vm(&expr(&vec!["1"; 200].join("+"), false).unwrap());
+        assert_eq!(vm.tape()[0], 200);
+    }
+}
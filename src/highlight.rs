@@ -0,0 +1,168 @@
+//! Rendering programs with syntax highlighting.
+
+use std::fmt::Write;
+
+use bft_types::{Alphabet, Instruction, Token};
+
+/// The number of colours matching brackets cycle through.
+const BRACKET_COLOURS: usize = 6;
+
+/// Output formats for highlighted source.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, clap::ValueEnum)]
+pub enum Format {
+    /// HTML, with a `<style>` block followed by a `<pre>` block.
+    Html,
+
+    /// Text with ANSI colour escapes, for terminals.
+    Ansi,
+}
+
+/// The kinds of text that are coloured differently.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+enum Class {
+    Move,
+    Arithmetic,
+    Io,
+    Extension,
+    /// A bracket, with its nesting depth.
+    Bracket(usize),
+    Comment,
+}
+
+impl Class {
+    fn of(inst: Instruction, depth: usize) -> Self {
+        match inst {
+            Instruction::MoveLeft | Instruction::MoveRight => Class::Move,
+            Instruction::Increment | Instruction::Decrement => Class::Arithmetic,
+            Instruction::Input | Instruction::Output => Class::Io,
+            Instruction::BeginLoop | Instruction::EndLoop => Class::Bracket(depth),
+            _ => Class::Extension,
+        }
+    }
+
+    fn css_class(self) -> String {
+        match self {
+            Class::Move => String::from("bf-move"),
+            Class::Arithmetic => String::from("bf-arith"),
+            Class::Io => String::from("bf-io"),
+            Class::Extension => String::from("bf-ext"),
+            Class::Bracket(depth) => format!("bf-bracket-{}", depth % BRACKET_COLOURS),
+            Class::Comment => String::from("bf-comment"),
+        }
+    }
+
+    fn ansi_code(self) -> &'static str {
+        const RAINBOW: [&str; BRACKET_COLOURS] = ["31", "33", "32", "36", "34", "35"];
+        match self {
+            Class::Move => "36",
+            Class::Arithmetic => "32",
+            Class::Io => "33",
+            Class::Extension => "35",
+            Class::Bracket(depth) => RAINBOW[depth % BRACKET_COLOURS],
+            Class::Comment => "2",
+        }
+    }
+}
+
+const STYLE: &str = "<style>
+pre.bft { background: #1e1e1e; color: #d4d4d4; }
+.bf-move { color: #4ec9b0; }
+.bf-arith { color: #6a9955; }
+.bf-io { color: #dcdcaa; }
+.bf-ext { color: #c586c0; }
+.bf-comment { color: #808080; }
+.bf-bracket-0 { color: #f44747; }
+.bf-bracket-1 { color: #d7ba7d; }
+.bf-bracket-2 { color: #b5cea8; }
+.bf-bracket-3 { color: #9cdcfe; }
+.bf-bracket-4 { color: #569cd6; }
+.bf-bracket-5 { color: #c586c0; }
+</style>
+";
+
+fn escape_html(text: &str) -> String {
+    text.replace('&', "&amp;")
+        .replace('<', "&lt;")
+        .replace('>', "&gt;")
+}
+
+/// Render the program in `data`, written with the tokens in `alphabet`, in `format`.
+///
+/// Instructions are coloured by what they do, with brackets coloured by their nesting depth so
+/// matching pairs share a colour, and comments dimmed.
+#[must_use]
+pub fn highlight(data: &[u8], alphabet: &Alphabet, format: Format) -> String {
+    let mut out = String::new();
+    if format == Format::Html {
+        out.push_str(STYLE);
+        out.push_str("<pre class=\"bft\">");
+    }
+    // Runs of text of the same class, as the class and the end of the run.
+    let mut runs: Vec<(Class, usize)> = Vec::new();
+    let mut depth = 0usize;
+    let mut pos = 0;
+    for token in alphabet.tokenize(data) {
+        let class = match token {
+            Token::Instruction(inst, text) => {
+                if inst == Instruction::EndLoop {
+                    depth = depth.saturating_sub(1);
+                }
+                let class = Class::of(inst, depth);
+                if inst == Instruction::BeginLoop {
+                    depth += 1;
+                }
+                pos += text.len();
+                class
+            }
+            Token::Comment(text) => {
+                pos += text.len();
+                Class::Comment
+            }
+        };
+        match runs.last_mut() {
+            Some((last, end)) if *last == class => *end = pos,
+            _ => runs.push((class, pos)),
+        }
+    }
+    let mut start = 0;
+    for (class, end) in runs {
+        let text = String::from_utf8_lossy(&data[start..end]);
+        start = end;
+        match format {
+            Format::Html => write!(
+                out,
+                "<span class=\"{}\">{}</span>",
+                class.css_class(),
+                escape_html(&text)
+            ),
+            Format::Ansi => write!(out, "\x1b[{}m{}\x1b[0m", class.ansi_code(), text),
+        }
+        .expect("Writing to a String can't fail.");
+    }
+    if format == Format::Html {
+        out.push_str("</pre>\n");
+    }
+    out
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn ansi() {
+        assert_eq!(
+            highlight(b"++[>.]x", &Alphabet::default(), Format::Ansi),
+            "\x1b[32m++\x1b[0m\x1b[31m[\x1b[0m\x1b[36m>\x1b[0m\x1b[33m.\x1b[0m\x1b[31m]\x1b[0m\x1b[2mx\x1b[0m"
+        );
+    }
+
+    #[test]
+    fn html() {
+        let html = highlight(b"[[<]] a&b", &Alphabet::default(), Format::Html);
+        assert!(html.starts_with("<style>"));
+        assert!(html.ends_with(
+            "<pre class=\"bft\"><span class=\"bf-bracket-0\">[</span><span class=\"bf-bracket-1\">[</span><span class=\"bf-move\">&lt;</span><span class=\"bf-bracket-1\">]</span><span class=\"bf-bracket-0\">]</span><span class=\"bf-comment\"> a&amp;b</span></pre>\n"
+        ));
+    }
+}
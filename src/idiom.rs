@@ -0,0 +1,277 @@
+//! Recognizing well-known idioms in programs, to help with reading unfamiliar code.
+
+use std::collections::BTreeMap;
+use std::fmt;
+use std::fmt::{Display, Formatter};
+
+use serde_json::{json, Value};
+
+use bft_types::{BFprogram, InputInstruction, Instruction};
+
+use crate::disasm::symbol;
+
+/// The usual loop for division with remainder. With the head on `n`, followed by a divisor `d`
+/// and three zero cells, it leaves `0 d-n%d n%d n/d`.
+const DIVMOD: &str = "[->-[>+>>]>[+[-<+>]>+>>]<<<<<]";
+
+/// A well-known idiom.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Idiom {
+    /// `[-]`, which sets the current cell to zero.
+    Clear,
+
+    /// A loop like `[->+<]`, which adds the current cell to others, clearing it.
+    Copy,
+
+    /// A loop like `[->+++<]`, which adds multiples of the current cell to others, clearing it.
+    Multiply,
+
+    /// A loop like `[>]`, which moves the head to the next zero cell.
+    ScanToZero,
+
+    /// The usual division with remainder loop.
+    DivMod,
+
+    /// Division with remainder followed by output, which prints a cell as a decimal number.
+    PrintDecimal,
+}
+
+impl Idiom {
+    /// The name used for the idiom in JSON.
+    #[must_use]
+    pub fn name(self) -> &'static str {
+        match self {
+            Self::Clear => "clear",
+            Self::Copy => "copy",
+            Self::Multiply => "multiply",
+            Self::ScanToZero => "scan-to-zero",
+            Self::DivMod => "divmod",
+            Self::PrintDecimal => "print-decimal",
+        }
+    }
+}
+
+impl Display for Idiom {
+    fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::Clear => write!(f, "clear loop: sets the cell to zero"),
+            Self::Copy => write!(f, "copy loop: adds the cell to other cells, clearing it"),
+            Self::Multiply => write!(
+                f,
+                "multiplication: adds multiples of the cell to other cells, clearing it"
+            ),
+            Self::ScanToZero => write!(f, "scan: moves the head to the nearest zero cell"),
+            Self::DivMod => write!(f, "division: divides the cell, keeping the remainder"),
+            Self::PrintDecimal => write!(f, "decimal print: prints the cell as a number"),
+        }
+    }
+}
+
+/// An idiom found in a program, and the instructions it spans.
+#[derive(Clone, Debug, PartialEq)]
+pub struct Found {
+    pub idiom: Idiom,
+
+    /// The first instruction of the idiom.
+    pub start: InputInstruction,
+
+    /// The last instruction of the idiom.
+    pub end: InputInstruction,
+}
+
+impl Found {
+    /// The idiom and where it is, as a JSON object.
+    #[must_use]
+    pub fn to_json(&self) -> Value {
+        json!({
+            "idiom": self.idiom.name(),
+            "start": {"line": self.start.line_number(), "column": self.start.char_number()},
+            "end": {"line": self.end.line_number(), "column": self.end.char_number()},
+        })
+    }
+}
+
+impl Display for Found {
+    fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "{}-{}  {}",
+            self.start.location(),
+            self.end.location(),
+            self.idiom
+        )
+    }
+}
+
+/// The idiom made by the loop with body `body`, if it's one of the simple loops. Only the start
+/// of the body, up to the first instruction that can't be part of a simple loop, is looked at,
+/// so that finding the idioms takes linear time however deeply the loops are nested.
+fn simple_loop(body: &[InputInstruction]) -> Option<Idiom> {
+    let first = *body.first()?.instruction();
+    match first {
+        Instruction::Increment | Instruction::Decrement if body.len() == 1 => {
+            return Some(Idiom::Clear);
+        }
+        Instruction::MoveLeft | Instruction::MoveRight
+            if body.iter().all(|inst| *inst.instruction() == first) =>
+        {
+            return Some(Idiom::ScanToZero);
+        }
+        _ => {}
+    }
+    // What each iteration adds to each cell, relative to the current one.
+    let mut added: BTreeMap<i64, i64> = BTreeMap::new();
+    let mut offset = 0i64;
+    for inst in body {
+        match inst.instruction() {
+            Instruction::MoveLeft => offset -= 1,
+            Instruction::MoveRight => offset += 1,
+            Instruction::Increment => *added.entry(offset).or_default() += 1,
+            Instruction::Decrement => *added.entry(offset).or_default() -= 1,
+            _ => return None,
+        }
+    }
+    added.retain(|_, n| *n != 0);
+    if offset != 0 || added.remove(&0) != Some(-1) || added.is_empty() {
+        return None;
+    }
+    Some(if added.values().all(|n| *n == 1) {
+        Idiom::Copy
+    } else {
+        Idiom::Multiply
+    })
+}
+
+/// For each `]` in `code`, the index of the first `.` after it that comes before the loop it's
+/// in ends, if there is one. This is found in a single pass from the end of the code.
+fn outputs_after(code: &[char]) -> Vec<Option<usize>> {
+    let mut outputs = vec![None; code.len()];
+    // The first output found so far in each loop being passed through, from the outside in.
+    let mut nearest = vec![None];
+    for (idx, c) in code.iter().enumerate().rev() {
+        match c {
+            '.' => {
+                if let Some(nearest) = nearest.last_mut() {
+                    *nearest = Some(idx);
+                }
+            }
+            ']' => {
+                outputs[idx] = nearest.last().copied().flatten();
+                nearest.push(None);
+            }
+            '[' if nearest.len() > 1 => {
+                let inner = nearest.pop().flatten();
+                if let Some(outer) = nearest.last_mut() {
+                    *outer = inner.or(*outer);
+                }
+            }
+            _ => {}
+        }
+    }
+    outputs
+}
+
+/// Find the idioms in `program`, in the order they start. Idioms can be nested inside others.
+#[must_use]
+pub fn find(program: &BFprogram) -> Vec<Found> {
+    let instructions = program.instructions();
+    let code: Vec<char> = instructions
+        .iter()
+        .map(|inst| symbol(*inst.instruction()))
+        .collect();
+    let divmod: Vec<char> = DIVMOD.chars().collect();
+    let outputs = outputs_after(&code);
+    let mut found = Vec::new();
+    let mut open = Vec::new();
+    for (idx, inst) in instructions.iter().enumerate() {
+        match inst.instruction() {
+            Instruction::BeginLoop => open.push(idx),
+            Instruction::EndLoop => {
+                let Some(start) = open.pop() else {
+                    continue;
+                };
+                if let Some(idiom) = simple_loop(&instructions[start + 1..idx]) {
+                    found.push(Found {
+                        idiom,
+                        start: instructions[start],
+                        end: *inst,
+                    });
+                }
+                if !code[start..].starts_with(&divmod) {
+                    continue;
+                }
+                // The output has to come before the loop the division is in ends.
+                let output = outputs[idx];
+                found.push(Found {
+                    idiom: output.map_or(Idiom::DivMod, |_| Idiom::PrintDecimal),
+                    start: instructions[start],
+                    end: instructions[output.unwrap_or(idx)],
+                });
+            }
+            _ => {}
+        }
+    }
+    found.sort_by_key(|found| found.start.offset());
+    found
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn idioms(code: &str) -> Vec<String> {
+        find(&BFprogram::new("mod.test", code.as_bytes()))
+            .iter()
+            .map(|found| format!("{} {}", found.start.location(), found.idiom.name()))
+            .collect()
+    }
+
+    #[test]
+    fn simple_loops() {
+        assert_eq!(idioms("+[-]>[+]"), ["1:2 clear", "1:6 clear"]);
+        assert_eq!(idioms("[->+<][->>+<+<]"), ["1:1 copy", "1:7 copy"]);
+        assert_eq!(idioms("[->+++<][>-<-]"), ["1:1 multiply", "1:9 multiply"]);
+        assert_eq!(idioms("[>][<<]"), ["1:1 scan-to-zero", "1:4 scan-to-zero"]);
+        assert!(idioms("[->+<<][>+<][-.]").is_empty());
+        // Only the innermost loop is simple enough to be recognized.
+        assert_eq!(idioms("++[>++[->+<]<-]"), ["1:7 copy"]);
+    }
+
+    #[test]
+    fn decimal_printing() {
+        assert_eq!(idioms(DIVMOD), ["1:1 divmod", "1:14 copy"]);
+        // Print a number below 10 with two divisions, turning the digits into characters.
+        let print = format!("++++++>++++++++++<{DIVMOD}>>[-]>[-]++++++[-<++++++++>]<.");
+        let found = find(&BFprogram::new("mod.test", print.as_bytes()));
+        let found: Vec<_> = found
+            .iter()
+            .filter(|found| found.idiom == Idiom::PrintDecimal)
+            .collect();
+        assert_eq!(found.len(), 1);
+        assert_eq!(
+            found[0].to_string(),
+            format!(
+                "1:19-1:{}  decimal print: prints the cell as a number",
+                print.len()
+            )
+        );
+        assert_eq!(
+            idioms(&format!("[{DIVMOD}]>.")),
+            ["1:2 divmod", "1:15 copy"]
+        );
+        assert_eq!(
+            idioms(&format!("{DIVMOD}[-]>[>.]")),
+            ["1:1 print-decimal", "1:14 copy", "1:31 clear"]
+        );
+    }
+
+    #[test]
+    fn deep_nesting() {
+        let depth = 200_000;
+        let code = "+[>".repeat(depth) + &format!("+{DIVMOD}") + &"<]".repeat(depth) + ".";
+        let found = find(&BFprogram::new("mod.test", code.as_bytes()));
+        // The output comes after the loops around the division end.
+        assert_eq!(found.len(), 2);
+        assert_eq!(found[0].idiom, Idiom::DivMod);
+    }
+}
@@ -0,0 +1,98 @@
+//! Adapting the input given to programs before they read it.
+
+use std::io;
+use std::io::Read;
+
+/// The line endings used by a program's input, which are translated to the `\n` (10) that
+/// programs expect.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, clap::ValueEnum)]
+pub enum InputNewlines {
+    /// Lines end with `\n`, as programs expect, so nothing is translated.
+    #[default]
+    Lf,
+
+    /// Lines end with `\r\n`, as on Windows. Each `\r\n` is read as `\n`, and a `\r` on its own
+    /// is left alone.
+    Crlf,
+
+    /// Lines end with `\r`, as on classic Mac OS. Each `\r` is read as `\n`.
+    Cr,
+}
+
+/// Input with its line endings translated to `\n`.
+pub struct Translated<R> {
+    inner: R,
+    newlines: InputNewlines,
+
+    /// A byte read while looking for the `\n` after a `\r`, which is the next byte to give.
+    peeked: Option<u8>,
+}
+
+impl<R: Read> Translated<R> {
+    /// Read from `inner`, which has line endings like `newlines`.
+    pub fn new(inner: R, newlines: InputNewlines) -> Self {
+        Translated {
+            inner,
+            newlines,
+            peeked: None,
+        }
+    }
+
+    fn next_byte(&mut self) -> io::Result<Option<u8>> {
+        if let Some(byte) = self.peeked.take() {
+            return Ok(Some(byte));
+        }
+        let mut buf = [0];
+        Ok(match self.inner.read(&mut buf)? {
+            0 => None,
+            _ => Some(buf[0]),
+        })
+    }
+}
+
+impl<R: Read> Read for Translated<R> {
+    /// Read a single byte, as the VM does, so that a `\r` only waits for the byte after it.
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        if self.newlines == InputNewlines::Lf || buf.is_empty() {
+            return self.inner.read(buf);
+        }
+        let Some(mut byte) = self.next_byte()? else {
+            return Ok(0);
+        };
+        if byte == b'\r' {
+            match self.newlines {
+                InputNewlines::Cr => byte = b'\n',
+                InputNewlines::Crlf => match self.next_byte()? {
+                    Some(b'\n') => byte = b'\n',
+                    next => self.peeked = next,
+                },
+                InputNewlines::Lf => {}
+            }
+        }
+        buf[0] = byte;
+        Ok(1)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn translate(newlines: InputNewlines, data: &[u8]) -> Vec<u8> {
+        let mut out = Vec::new();
+        Translated::new(data, newlines)
+            .read_to_end(&mut out)
+            .unwrap();
+        out
+    }
+
+    #[test]
+    fn translating() {
+        assert_eq!(translate(InputNewlines::Lf, b"a\r\nb\r"), b"a\r\nb\r");
+        assert_eq!(
+            translate(InputNewlines::Crlf, b"a\r\nb\rc\r\r\n\r"),
+            b"a\nb\rc\r\n\r"
+        );
+        assert_eq!(translate(InputNewlines::Cr, b"a\rb\r\n"), b"a\nb\n\n");
+    }
+}
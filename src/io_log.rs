@@ -0,0 +1,129 @@
+//! Logging every byte a program reads and writes, for programs that speak binary protocols
+//! whose output can't be read directly.
+//!
+//! Each `,` and `.` executed adds a line to the log:
+//!
+//! ```text
+//! SECONDS DIRECTION BYTE PRINTABLE LINE:COLUMN
+//! ```
+//!
+//! where `SECONDS` is the time since the program started, `DIRECTION` is `in` or `out`, `BYTE` is
+//! the byte in hex and `PRINTABLE` is the byte as an escaped character. When a read finds the end
+//! of the input, `BYTE` and `PRINTABLE` are both `EOF`.
+
+use std::error::Error;
+use std::io;
+use std::io::{Read, Write};
+use std::time::Instant;
+
+use bft_interp::{StepOutcome, BFVM};
+use bft_types::{BFprogram, Instruction};
+
+/// Input that remembers the last byte read from it.
+struct Remembering<R> {
+    inner: R,
+    last: Option<u8>,
+}
+
+impl<R: Read> Read for Remembering<R> {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        let count = self.inner.read(buf)?;
+        self.last = buf[..count].last().copied();
+        Ok(count)
+    }
+}
+
+/// Write a log line for `byte`, or the end of the input if it's `None`.
+fn write_entry<L: Write>(
+    log: &mut L,
+    started: Instant,
+    direction: &str,
+    byte: Option<u8>,
+    location: &str,
+) -> io::Result<()> {
+    let seconds = started.elapsed().as_secs_f64();
+    match byte {
+        Some(byte) => writeln!(
+            log,
+            "{seconds:.6} {direction:<3} 0x{byte:02x} {:<4} {location}",
+            byte.escape_ascii().to_string()
+        ),
+        None => writeln!(log, "{seconds:.6} {direction:<3} EOF  EOF  {location}"),
+    }
+}
+
+/// Run `program` to completion, logging each byte it reads and writes to `log`.
+///
+/// # Errors
+/// Fails if the program fails, or if writing the log fails.
+pub fn run_logged<R: Read, W: Write, L: Write>(
+    program: &BFprogram,
+    vm: &mut BFVM<u8>,
+    input: &mut R,
+    output: &mut W,
+    log: &mut L,
+) -> Result<(), Box<dyn Error>> {
+    let started = Instant::now();
+    let mut input = Remembering {
+        inner: input,
+        last: None,
+    };
+    let mut outcome = StepOutcome::Running;
+    while outcome == StepOutcome::Running {
+        outcome = vm.step(program, &mut input, output)?;
+        let Some(pc) = vm.last_executed() else {
+            break;
+        };
+        let inst = &program.instructions()[pc];
+        match inst.instruction() {
+            Instruction::Input => {
+                write_entry(log, started, "in", input.last.take(), &inst.location())?;
+            }
+            Instruction::Output => {
+                let byte = vm.tape()[vm.head()];
+                write_entry(log, started, "out", Some(byte), &inst.location())?;
+            }
+            _ => {}
+        }
+    }
+    // Output closed by whatever is reading it stops the program quietly, as with `BFVM::run`.
+    match output.flush() {
+        Err(err) if err.kind() != io::ErrorKind::BrokenPipe => return Err(err.into()),
+        _ => {}
+    }
+    log.flush()?;
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn logging() {
+        let mut program = BFprogram::new("a.b", b"+++++++++[>+<-]>+.\n,.,.,");
+        program.validate_brackets().unwrap();
+        let mut vm = BFVM::new(None, false);
+        let mut output = Vec::new();
+        let mut log = Vec::new();
+        run_logged(&program, &mut vm, &mut &b"A\0"[..], &mut output, &mut log).unwrap();
+        assert_eq!(output, b"\nA\0");
+
+        let log = String::from_utf8(log).unwrap();
+        let entries: Vec<_> = log
+            .lines()
+            .map(|line| line.split_once(' ').unwrap().1)
+            .collect();
+        assert_eq!(
+            entries,
+            [
+                "out 0x0a \\n   1:18",
+                "in  0x41 A    2:1",
+                "out 0x41 A    2:2",
+                "in  0x00 \\x00 2:3",
+                "out 0x00 \\x00 2:4",
+                "in  EOF  EOF  2:5",
+            ]
+        );
+    }
+}
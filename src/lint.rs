@@ -0,0 +1,556 @@
+//! Static checks for suspicious code in programs.
+
+use std::collections::{HashMap, HashSet};
+use std::fmt;
+use std::fmt::{Display, Formatter};
+use std::str::FromStr;
+
+use bft_types::{BFprogram, InputInstruction, Instruction};
+
+use crate::value_range::ValueRanges;
+
+/// How seriously to treat a lint when it fires.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, PartialOrd, Ord)]
+pub enum Level {
+    /// Don't report the lint.
+    Allow,
+
+    /// Report the lint as a warning.
+    Warn,
+
+    /// Report the lint as an error.
+    Deny,
+}
+
+impl Display for Level {
+    fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::Allow => write!(f, "allowed"),
+            Self::Warn => write!(f, "warning"),
+            Self::Deny => write!(f, "error"),
+        }
+    }
+}
+
+impl FromStr for Level {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "allow" => Ok(Self::Allow),
+            "warn" => Ok(Self::Warn),
+            "deny" => Ok(Self::Deny),
+            _ => Err(format!("Unknown lint level '{s}'")),
+        }
+    }
+}
+
+/// A check that looks for a particular problem in a program.
+pub struct Lint {
+    /// The name used to refer to the lint on the command line.
+    pub name: &'static str,
+
+    /// A short explanation of what the lint looks for.
+    pub description: &'static str,
+
+    /// Finds the index of each instruction where the lint fires, with a message describing the
+    /// problem.
+    check: fn(&BFprogram) -> Vec<(usize, String)>,
+
+    /// The level the lint is reported at, unless it's configured otherwise.
+    pub default: Level,
+}
+
+/// Every lint that is available.
+pub const LINTS: [Lint; 6] = [
+    Lint {
+        name: "cancelling-instructions",
+        description: "adjacent instructions that undo each other, like `+-` or `<>`",
+        check: cancelling_instructions,
+        default: Level::Warn,
+    },
+    Lint {
+        name: "cell-overflow",
+        description: "`+` on a cell that is always 255, or `-` on one that is always 0",
+        check: cell_overflow,
+        default: Level::Allow,
+    },
+    Lint {
+        name: "empty-loop",
+        description: "`[]`, which loops forever unless the current cell is zero",
+        check: empty_loop,
+        default: Level::Warn,
+    },
+    Lint {
+        name: "infinite-loop",
+        description: "`[]` where the current cell is known not to be zero, which never ends",
+        check: infinite_loop,
+        default: Level::Warn,
+    },
+    Lint {
+        name: "input-loop-counter",
+        description: "a loop that counts down from a byte read by `,`, which is a character code",
+        check: input_loop_counter,
+        default: Level::Warn,
+    },
+    Lint {
+        name: "loop-never-runs",
+        description: "a loop that starts where the current cell is known to be zero",
+        check: loop_never_runs,
+        default: Level::Warn,
+    },
+];
+
+fn cancelling_instructions(program: &BFprogram) -> Vec<(usize, String)> {
+    program
+        .instructions()
+        .windows(2)
+        .enumerate()
+        .filter(|(_, pair)| {
+            matches!(
+                (pair[0].instruction(), pair[1].instruction()),
+                (Instruction::Increment, Instruction::Decrement)
+                    | (Instruction::Decrement, Instruction::Increment)
+                    | (Instruction::MoveLeft, Instruction::MoveRight)
+                    | (Instruction::MoveRight, Instruction::MoveLeft)
+            )
+        })
+        .map(|(idx, _)| {
+            (
+                idx,
+                String::from("these two instructions cancel each other out"),
+            )
+        })
+        .collect()
+}
+
+/// Whether the instruction at `idx` of `instructions` starts an empty loop, `[]`.
+fn is_empty_loop(instructions: &[InputInstruction], idx: usize) -> bool {
+    *instructions[idx].instruction() == Instruction::BeginLoop
+        && instructions
+            .get(idx + 1)
+            .is_some_and(|next| *next.instruction() == Instruction::EndLoop)
+}
+
+/// The value of the current cell before the instruction at `idx`, if it's known without running
+/// the program. It's known after a run of `+` and `-` that follows a point where the cell is
+/// zero: the start of the program, or the end of a loop. The value is given modulo 256, which
+/// is zero exactly when it's zero for cells of any width.
+fn known_value(instructions: &[InputInstruction], idx: usize) -> Option<u8> {
+    let mut value = 0u8;
+    for inst in instructions[..idx].iter().rev() {
+        match inst.instruction() {
+            Instruction::Increment => value = value.wrapping_add(1),
+            Instruction::Decrement => value = value.wrapping_sub(1),
+            Instruction::EndLoop => return Some(value),
+            _ => return None,
+        }
+    }
+    Some(value)
+}
+
+fn empty_loop(program: &BFprogram) -> Vec<(usize, String)> {
+    let instructions = program.instructions();
+    (0..instructions.len())
+        .filter(|idx| {
+            // Loops that certainly never end are reported by `infinite-loop` instead.
+            is_empty_loop(instructions, *idx)
+                && known_value(instructions, *idx).is_none_or(|value| value == 0)
+        })
+        .map(|idx| {
+            (
+                idx,
+                String::from("empty loop never ends if the current cell isn't zero"),
+            )
+        })
+        .collect()
+}
+
+fn infinite_loop(program: &BFprogram) -> Vec<(usize, String)> {
+    let instructions = program.instructions();
+    (0..instructions.len())
+        .filter_map(|idx| {
+            let value = known_value(instructions, idx).filter(|value| *value != 0)?;
+            is_empty_loop(instructions, idx).then(|| {
+                (
+                    idx,
+                    format!("the current cell is {value} here, so this empty loop never ends"),
+                )
+            })
+        })
+        .collect()
+}
+
+fn cell_overflow(program: &BFprogram) -> Vec<(usize, String)> {
+    let ranges = ValueRanges::of(program);
+    program
+        .instructions()
+        .iter()
+        .enumerate()
+        .filter_map(|(idx, inst)| {
+            let value = ranges.before(idx)?.known()?;
+            let wrapped = match (inst.instruction(), value) {
+                (Instruction::Increment, u8::MAX) => 0,
+                (Instruction::Decrement, 0) => u8::MAX,
+                _ => return None,
+            };
+            Some((
+                idx,
+                format!(
+                    "the current cell is always {value} here, so this wraps around to {wrapped}"
+                ),
+            ))
+        })
+        .collect()
+}
+
+/// What is known about a loop that is being passed through, while finding the loops that count
+/// down.
+#[derive(Default)]
+struct Counter {
+    /// Where the head was when the loop started.
+    offset: i64,
+    decrements: bool,
+    reads: bool,
+    /// Whether the body, or a loop in it, doesn't move the head back to where it started.
+    unbalanced: bool,
+}
+
+/// The indices of the loops in `instructions` that count down the cell they start on: their
+/// body moves the head back to where it started, and decrements that cell without reading
+/// input into it. This takes a single pass, however deeply the loops are nested.
+fn counting_loops(instructions: &[InputInstruction]) -> HashSet<usize> {
+    let mut counting = HashSet::new();
+    let mut offset = 0i64;
+    // The loops being passed through, with the index of their `[`.
+    let mut open: Vec<(usize, Counter)> = Vec::new();
+    // The loops being passed through that started at each offset, innermost last. Each decrement
+    // or read is marked on the innermost one, and passed outwards as loops end.
+    let mut at_offset: HashMap<i64, Vec<usize>> = HashMap::new();
+    for (idx, inst) in instructions.iter().enumerate() {
+        match inst.instruction() {
+            Instruction::MoveLeft => offset -= 1,
+            Instruction::MoveRight => offset += 1,
+            Instruction::BeginLoop => {
+                at_offset.entry(offset).or_default().push(open.len());
+                open.push((
+                    idx,
+                    Counter {
+                        offset,
+                        ..Counter::default()
+                    },
+                ));
+            }
+            Instruction::EndLoop => {
+                let Some((start, mut counter)) = open.pop() else {
+                    continue;
+                };
+                counter.unbalanced |= offset != counter.offset;
+                let same = at_offset.get_mut(&counter.offset);
+                if let Some(same) = same {
+                    same.pop();
+                    if let Some(outer) = same.last() {
+                        let outer = &mut open[*outer].1;
+                        outer.decrements |= counter.decrements;
+                        outer.reads |= counter.reads;
+                    }
+                }
+                if let Some((_, outer)) = open.last_mut() {
+                    outer.unbalanced |= counter.unbalanced;
+                }
+                if counter.decrements && !counter.reads && !counter.unbalanced {
+                    counting.insert(start);
+                }
+            }
+            Instruction::Decrement | Instruction::Input => {
+                let innermost = at_offset.get(&offset).and_then(|same| same.last());
+                if let Some(innermost) = innermost {
+                    let counter = &mut open[*innermost].1;
+                    if *inst.instruction() == Instruction::Decrement {
+                        counter.decrements = true;
+                    } else {
+                        counter.reads = true;
+                    }
+                }
+            }
+            _ => {}
+        }
+    }
+    counting
+}
+
+fn input_loop_counter(program: &BFprogram) -> Vec<(usize, String)> {
+    let ranges = ValueRanges::of(program);
+    let instructions = program.instructions();
+    let counting = counting_loops(instructions);
+    (0..instructions.len())
+        .filter(|idx| {
+            *instructions[*idx].instruction() == Instruction::BeginLoop
+                && ranges.before(*idx).is_some_and(|value| value.from_input)
+                && counting.contains(idx)
+        })
+        .map(|idx| {
+            (
+                idx,
+                String::from(
+                    "this loop counts down from a byte read by `,`, which is its character code \
+                     rather than the number it shows",
+                ),
+            )
+        })
+        .collect()
+}
+
+fn loop_never_runs(program: &BFprogram) -> Vec<(usize, String)> {
+    let instructions = program.instructions();
+    instructions
+        .iter()
+        .enumerate()
+        .filter(|(idx, inst)| {
+            // The tape starts as zeros, and a loop only ends when the current cell is zero.
+            *inst.instruction() == Instruction::BeginLoop
+                && idx
+                    .checked_sub(1)
+                    .is_none_or(|prev| *instructions[prev].instruction() == Instruction::EndLoop)
+        })
+        .map(|(idx, _)| {
+            (
+                idx,
+                String::from("the current cell is always zero here, so this loop never runs"),
+            )
+        })
+        .collect()
+}
+
+/// A lint that fired on a program.
+#[derive(Debug, PartialEq)]
+pub struct Warning {
+    /// The name of the lint.
+    pub lint: &'static str,
+
+    /// How seriously the lint is treated.
+    pub level: Level,
+
+    /// Line of the instruction the lint fired on.
+    pub line: usize,
+
+    /// Column of the instruction the lint fired on.
+    pub column: usize,
+
+    /// Number of bytes of the token the instruction the lint fired on was written as.
+    pub len: usize,
+
+    /// Description of the problem.
+    pub message: String,
+}
+
+impl Display for Warning {
+    fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "{}:{}: {}: {} [{}]",
+            self.line, self.column, self.level, self.message, self.lint
+        )
+    }
+}
+
+/// The level each lint is reported at.
+#[derive(Clone, Debug)]
+pub struct LintConfig {
+    levels: Vec<(&'static str, Level)>,
+}
+
+impl Default for LintConfig {
+    /// Every lint is at its default level.
+    fn default() -> Self {
+        LintConfig {
+            levels: LINTS.iter().map(|lint| (lint.name, lint.default)).collect(),
+        }
+    }
+}
+
+impl LintConfig {
+    /// Set the level for the lint called `name`. The name `warnings` refers to every lint that
+    /// is currently a warning.
+    ///
+    /// # Errors
+    /// Fails if there is no lint called `name`.
+    pub fn set_level(&mut self, name: &str, level: Level) -> Result<(), String> {
+        if name == "warnings" {
+            for (_, l) in self.levels.iter_mut().filter(|(_, l)| *l == Level::Warn) {
+                *l = level;
+            }
+            return Ok(());
+        }
+        let (_, l) = self
+            .levels
+            .iter_mut()
+            .find(|(lint, _)| *lint == name)
+            .ok_or_else(|| format!("Unknown lint '{name}'"))?;
+        *l = level;
+        Ok(())
+    }
+
+    /// Set the level of each lint in `levels`. Settings for `warnings` are applied first, so that
+    /// lints named individually take precedence over it.
+    ///
+    /// # Errors
+    /// Fails if any of the lints doesn't exist.
+    pub fn set_levels<'a, I>(&mut self, levels: I) -> Result<(), String>
+    where
+        I: IntoIterator<Item = (&'a str, Level)>,
+    {
+        let (warnings, lints): (Vec<_>, Vec<_>) = levels
+            .into_iter()
+            .partition(|(name, _)| *name == "warnings");
+        for (name, level) in warnings.into_iter().chain(lints) {
+            self.set_level(name, level)?;
+        }
+        Ok(())
+    }
+
+    /// The level `lint` is reported at.
+    #[must_use]
+    pub fn level(&self, lint: &str) -> Level {
+        self.levels
+            .iter()
+            .find(|(name, _)| *name == lint)
+            .map_or(Level::Allow, |(_, level)| *level)
+    }
+}
+
+/// Run every lint that isn't allowed on `program`, returning the warnings in source order.
+#[must_use]
+pub fn run_lints(program: &BFprogram, config: &LintConfig) -> Vec<Warning> {
+    let mut warnings: Vec<(usize, Warning)> = LINTS
+        .iter()
+        .filter(|lint| config.level(lint.name) != Level::Allow)
+        .flat_map(|lint| {
+            (lint.check)(program).into_iter().map(|(idx, message)| {
+                let inst = &program.instructions()[idx];
+                (
+                    idx,
+                    Warning {
+                        lint: lint.name,
+                        level: config.level(lint.name),
+                        line: inst.line_number(),
+                        column: inst.char_number(),
+                        len: inst.token_len(),
+                        message,
+                    },
+                )
+            })
+        })
+        .collect();
+    warnings.sort_by_key(|(idx, _)| *idx);
+    warnings.into_iter().map(|(_, warning)| warning).collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn lint(code: &str, config: &LintConfig) -> Vec<String> {
+        run_lints(&BFprogram::new("mod.test", code.as_bytes()), config)
+            .iter()
+            .map(ToString::to_string)
+            .collect()
+    }
+
+    #[test]
+    fn lints_fire() {
+        assert_eq!(
+            lint("[.]+-\n>[]", &LintConfig::default()),
+            [
+                "1:1: warning: the current cell is always zero here, so this loop never runs [loop-never-runs]",
+                "1:4: warning: these two instructions cancel each other out [cancelling-instructions]",
+                "2:2: warning: empty loop never ends if the current cell isn't zero [empty-loop]",
+            ]
+        );
+        assert!(lint("+[->+<]>.", &LintConfig::default()).is_empty());
+    }
+
+    #[test]
+    fn infinite_loops() {
+        let config = LintConfig::default();
+        assert_eq!(
+            lint("++[]", &config),
+            ["1:3: warning: the current cell is 2 here, so this empty loop never ends [infinite-loop]"]
+        );
+        assert_eq!(
+            lint("+[-]-[]", &config),
+            ["1:6: warning: the current cell is 255 here, so this empty loop never ends [infinite-loop]"]
+        );
+        // Input, or a move, means the cell's value isn't known.
+        assert_eq!(
+            lint(",+[]", &config),
+            ["1:3: warning: empty loop never ends if the current cell isn't zero [empty-loop]"]
+        );
+        assert_eq!(lint(">+[]", &config).len(), 1);
+        assert_eq!(lint("+-[]", &config).len(), 2);
+    }
+
+    #[test]
+    fn value_lints() {
+        // Wrapping around is how cells are defined to behave, so it's only reported when asked.
+        assert!(lint("-.", &LintConfig::default()).is_empty());
+        let mut config = LintConfig::default();
+        config.set_level("cell-overflow", Level::Warn).unwrap();
+        assert_eq!(
+            lint("-.", &config),
+            ["1:1: warning: the current cell is always 0 here, so this wraps around to 255 [cell-overflow]"]
+        );
+        assert_eq!(
+            lint("+[-]>-.", &config),
+            ["1:6: warning: the current cell is always 0 here, so this wraps around to 255 [cell-overflow]"]
+        );
+        assert!(lint("+[->-<]>.", &config).is_empty());
+        assert_eq!(
+            lint(",[->+<]>.", &config),
+            ["1:2: warning: this loop counts down from a byte read by `,`, which is its character code rather than the number it shows [input-loop-counter]"]
+        );
+        assert!(lint(",[.,]", &config).is_empty());
+        assert!(lint(",------[->+<]>.", &config).is_empty());
+        assert_eq!(lint(",[>[-],<-]", &config).len(), 1);
+        assert!(lint(",[>[-]<,-]", &config).is_empty());
+        assert!(lint(",[>[>]<-]", &config).is_empty());
+    }
+
+    #[test]
+    fn deep_nesting() {
+        let depth = 200_000;
+        let code = "+[>".repeat(depth) + &"<]".repeat(depth);
+        let mut config = LintConfig::default();
+        config.set_level("warnings", Level::Warn).unwrap();
+        lint(&code, &config);
+    }
+
+    #[test]
+    fn levels() {
+        let mut config = LintConfig::default();
+        config.set_level("empty-loop", Level::Allow).unwrap();
+        config.set_level("warnings", Level::Deny).unwrap();
+        assert_eq!(config.level("empty-loop"), Level::Allow);
+        assert_eq!(config.level("loop-never-runs"), Level::Deny);
+        assert_eq!(
+            lint(",[]-+", &config),
+            ["1:4: error: these two instructions cancel each other out [cancelling-instructions]"]
+        );
+        assert_eq!(
+            config.set_level("nope", Level::Warn),
+            Err(String::from("Unknown lint 'nope'"))
+        );
+
+        let mut config = LintConfig::default();
+        config
+            .set_levels([
+                ("empty-loop", Level::Allow),
+                ("cancelling-instructions", Level::Warn),
+                ("warnings", Level::Deny),
+            ])
+            .unwrap();
+        assert_eq!(config.level("empty-loop"), Level::Allow);
+        assert_eq!(config.level("cancelling-instructions"), Level::Warn);
+        assert_eq!(config.level("loop-never-runs"), Level::Deny);
+    }
+}
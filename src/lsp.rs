@@ -0,0 +1,565 @@
+//! A [Language Server Protocol](https://microsoft.github.io/language-server-protocol/) server,
+//! giving editors diagnostics, bracket matching and formatting for Brainf*ck programs.
+//!
+//! Documents are parsed as far as possible even when they're broken, so the brackets that do
+//! match can still be navigated while others are being written. Clients send each edit rather
+//! than the whole document, and only the edited text is parsed again.
+
+use std::collections::HashMap;
+use std::io;
+use std::io::{BufRead, Write};
+use std::path::Path;
+
+use serde_json::{json, Value};
+
+use bft_types::{Alphabet, BFprogram, Instruction};
+
+use crate::dap::{read_message, write_message};
+use crate::diagnostic::{diagnose, Severity};
+use crate::formatter;
+use crate::lint::LintConfig;
+
+/// Line width used when formatting documents.
+const FORMAT_WIDTH: usize = 80;
+
+/// JSON-RPC error code for requests the server doesn't support.
+const METHOD_NOT_FOUND: i64 = -32601;
+
+/// JSON-RPC error code for requests that couldn't be carried out.
+const REQUEST_FAILED: i64 = -32803;
+
+/// The text of the 1-based `line` of `text`, if there is one.
+fn line_text(text: &str, line: usize) -> Option<&str> {
+    text.split('\n').nth(line.checked_sub(1)?)
+}
+
+/// The LSP character offset, counted in UTF-16 code units, of the 1-based byte `column` on the
+/// 1-based `line` of `text`.
+fn character(text: &str, line: usize, column: usize) -> usize {
+    let line = line_text(text, line).unwrap_or_default();
+    let offset = column - 1;
+    let units: usize = line
+        .char_indices()
+        .take_while(|(i, _)| *i < offset)
+        .map(|(_, c)| c.len_utf16())
+        .sum();
+    units + offset.saturating_sub(line.len())
+}
+
+/// The 1-based byte column on the 1-based `line` of `text` at the LSP character offset
+/// `character`, counted in UTF-16 code units, if it's at the start of a character.
+fn byte_column(text: &str, line: usize, character: usize) -> Option<usize> {
+    let line = line_text(text, line)?;
+    let mut units = 0;
+    for (i, c) in line.char_indices() {
+        if units >= character {
+            return (units == character).then_some(i + 1);
+        }
+        units += c.len_utf16();
+    }
+    (units == character).then_some(line.len() + 1)
+}
+
+/// An LSP range covering `len` bytes of `text` from a 1-based line and byte column.
+fn span(text: &str, line: usize, column: usize, len: usize) -> Value {
+    json!({
+        "start": {"line": line - 1, "character": character(text, line, column)},
+        "end": {"line": line - 1, "character": character(text, line, column + len)},
+    })
+}
+
+/// The byte offset in `text` of an LSP position. Characters past the end of a line count as the
+/// end of it, as the protocol asks.
+fn offset(text: &str, position: &Value) -> Option<usize> {
+    let line = usize::try_from(position["line"].as_u64()?).ok()?;
+    let character = usize::try_from(position["character"].as_u64()?).ok()?;
+    let start: usize = text.split_inclusive('\n').take(line).map(str::len).sum();
+    let line = text[start..].split('\n').next().unwrap_or_default();
+    let mut units = 0;
+    for (i, c) in line.char_indices() {
+        if units >= character {
+            return Some(start + i);
+        }
+        units += c.len_utf16();
+    }
+    Some(start + line.len())
+}
+
+/// An open document, along with the program parsed from it as far as possible, which is kept up
+/// to date as the document is edited.
+struct Document {
+    text: String,
+    program: BFprogram,
+}
+
+impl Document {
+    fn new(uri: &str, text: &str) -> Self {
+        Document {
+            text: text.to_string(),
+            program: BFprogram::with_alphabet(uri, text.as_bytes(), &Alphabet::default()),
+        }
+    }
+
+    /// Apply one of the changes from a `didChange` notification. A change without a range
+    /// replaces the whole document.
+    fn edit(&mut self, uri: &str, change: &Value) {
+        let Some(new_text) = change["text"].as_str() else {
+            return;
+        };
+        if change.get("range").is_none() {
+            *self = Document::new(uri, new_text);
+            return;
+        }
+        let range = &change["range"];
+        let (Some(start), Some(end)) = (
+            offset(&self.text, &range["start"]),
+            offset(&self.text, &range["end"]),
+        ) else {
+            return;
+        };
+        if start > end {
+            return;
+        }
+        self.program
+            .apply_edit(self.text.as_bytes(), start..end, new_text.as_bytes());
+        self.text.replace_range(start..end, new_text);
+    }
+
+    /// Match the program's brackets as far as possible, returning the diagnostics for it.
+    fn diagnostics(&mut self, uri: &str) -> Vec<Value> {
+        let diagnostics = diagnose(
+            Path::new(uri),
+            &mut self.program,
+            &[],
+            &LintConfig::default(),
+        );
+        diagnostics
+            .into_iter()
+            .map(|diagnostic| {
+                json!({
+                    "range": span(&self.text, diagnostic.line, diagnostic.column, diagnostic.len),
+                    "severity": if diagnostic.severity == Severity::Error { 1 } else { 2 },
+                    "source": "bft",
+                    "code": diagnostic.code,
+                    "message": diagnostic.message,
+                })
+            })
+            .collect()
+    }
+}
+
+/// Find the index of the instruction at an LSP position in `text`, which `program` was parsed
+/// from.
+fn instruction_at(program: &BFprogram, text: &str, position: &Value) -> Option<usize> {
+    let line = usize::try_from(position["line"].as_u64()?).ok()? + 1;
+    let character = usize::try_from(position["character"].as_u64()?).ok()?;
+    let column = byte_column(text, line, character)?;
+    program
+        .instructions()
+        .iter()
+        .position(|inst| inst.line_number() == line && inst.char_number() == column)
+}
+
+/// How many loops contain the instruction at `idx`.
+fn loop_depth(program: &BFprogram, idx: usize) -> usize {
+    let mut depth = 0usize;
+    for inst in &program.instructions()[..idx] {
+        match inst.instruction() {
+            Instruction::BeginLoop => depth += 1,
+            Instruction::EndLoop => depth = depth.saturating_sub(1),
+            _ => {}
+        }
+    }
+    depth
+}
+
+/// The state of the server: the open documents, and where to send messages.
+struct Server<W> {
+    output: W,
+    documents: HashMap<String, Document>,
+}
+
+impl<W: Write> Server<W> {
+    fn respond(&mut self, request: &Value, result: Result<Value, (i64, String)>) -> io::Result<()> {
+        let mut response = json!({"jsonrpc": "2.0", "id": request["id"]});
+        match result {
+            Ok(result) => response["result"] = result,
+            Err((code, message)) => response["error"] = json!({"code": code, "message": message}),
+        }
+        write_message(&mut self.output, &response)
+    }
+
+    fn notify(&mut self, method: &str, params: &Value) -> io::Result<()> {
+        write_message(
+            &mut self.output,
+            &json!({"jsonrpc": "2.0", "method": method, "params": params}),
+        )
+    }
+
+    fn publish_diagnostics(&mut self, uri: &str) -> io::Result<()> {
+        let diagnostics = self
+            .documents
+            .get_mut(uri)
+            .map_or_else(Vec::new, |document| document.diagnostics(uri));
+        self.notify(
+            "textDocument/publishDiagnostics",
+            &json!({"uri": uri, "diagnostics": diagnostics}),
+        )
+    }
+
+    /// The text of the document named in `params`, and the program parsed from it as far as
+    /// possible.
+    fn program(&self, params: &Value) -> Option<(&str, &BFprogram)> {
+        let uri = params["textDocument"]["uri"].as_str()?;
+        let document = self.documents.get(uri)?;
+        Some((&document.text, &document.program))
+    }
+
+    fn hover(&self, params: &Value) -> Value {
+        let Some((text, program)) = self.program(params) else {
+            return Value::Null;
+        };
+        let Some(idx) = instruction_at(program, text, &params["position"]) else {
+            return Value::Null;
+        };
+        let inst = &program.instructions()[idx];
+        let matching = program
+            .matching_bracket(idx)
+            .map(|other| {
+                let other = &program.instructions()[other];
+                format!(
+                    ", matches the bracket at line {}, column {}",
+                    other.line_number(),
+                    other.char_number()
+                )
+            })
+            .unwrap_or_default();
+        let value = format!(
+            "{}, loop depth {}{}",
+            inst.instruction(),
+            loop_depth(program, idx),
+            matching
+        );
+        json!({
+            "contents": {"kind": "plaintext", "value": value},
+            "range": span(text, inst.line_number(), inst.char_number(), inst.token_len()),
+        })
+    }
+
+    fn definition(&self, params: &Value) -> Value {
+        let Some((text, program)) = self.program(params) else {
+            return Value::Null;
+        };
+        instruction_at(program, text, &params["position"])
+            .and_then(|idx| program.matching_bracket(idx))
+            .map_or(Value::Null, |other| {
+                let other = &program.instructions()[other];
+                json!({
+                    "uri": params["textDocument"]["uri"],
+                    "range": span(
+                        text,
+                        other.line_number(),
+                        other.char_number(),
+                        other.token_len(),
+                    ),
+                })
+            })
+    }
+
+    fn formatting(&self, params: &Value) -> Result<Value, (i64, String)> {
+        let uri = params["textDocument"]["uri"].as_str().unwrap_or_default();
+        let text = &self
+            .documents
+            .get(uri)
+            .ok_or_else(|| (REQUEST_FAILED, format!("Unknown document '{uri}'")))?
+            .text;
+        let formatted = formatter::format(
+            Path::new(uri),
+            text.as_bytes(),
+            &Alphabet::default(),
+            FORMAT_WIDTH,
+        )
+        .map_err(|err| (REQUEST_FAILED, err.to_string()))?;
+        let lines = text.split('\n').count();
+        Ok(json!([{
+            "range": {"start": {"line": 0, "character": 0}, "end": {"line": lines, "character": 0}},
+            "newText": formatted,
+        }]))
+    }
+
+    /// Handle a single message. Returns false once the client has asked the server to exit.
+    fn handle(&mut self, message: &Value) -> io::Result<bool> {
+        let params = &message["params"];
+        let uri = params["textDocument"]["uri"]
+            .as_str()
+            .unwrap_or_default()
+            .to_string();
+        match message["method"].as_str().unwrap_or_default() {
+            "initialize" => self.respond(
+                message,
+                Ok(json!({
+                    "capabilities": {
+                        "textDocumentSync": 2,
+                        "hoverProvider": true,
+                        "definitionProvider": true,
+                        "documentFormattingProvider": true,
+                    },
+                    "serverInfo": {"name": "bft", "version": env!("CARGO_PKG_VERSION")},
+                })),
+            )?,
+            "textDocument/didOpen" => {
+                let text = params["textDocument"]["text"].as_str().unwrap_or_default();
+                self.documents
+                    .insert(uri.clone(), Document::new(&uri, text));
+                self.publish_diagnostics(&uri)?;
+            }
+            "textDocument/didChange" => {
+                if let (Some(document), Some(changes)) = (
+                    self.documents.get_mut(&uri),
+                    params["contentChanges"].as_array(),
+                ) {
+                    for change in changes {
+                        document.edit(&uri, change);
+                    }
+                }
+                self.publish_diagnostics(&uri)?;
+            }
+            "textDocument/didClose" => {
+                self.documents.remove(&uri);
+                self.publish_diagnostics(&uri)?;
+            }
+            "textDocument/hover" => {
+                let result = self.hover(params);
+                self.respond(message, Ok(result))?;
+            }
+            "textDocument/definition" => {
+                let result = self.definition(params);
+                self.respond(message, Ok(result))?;
+            }
+            "textDocument/formatting" => {
+                let result = self.formatting(params);
+                self.respond(message, result)?;
+            }
+            "shutdown" => self.respond(message, Ok(Value::Null))?,
+            "exit" => return Ok(false),
+            method => {
+                // Notifications that we don't understand are ignored, but requests get an error.
+                if message.get("id").is_some() {
+                    self.respond(
+                        message,
+                        Err((METHOD_NOT_FOUND, format!("Unsupported method '{method}'"))),
+                    )?;
+                }
+            }
+        }
+        Ok(true)
+    }
+}
+
+/// Serve LSP requests read from `input`, writing responses and notifications to `output`, until
+/// the client asks the server to exit.
+///
+/// # Errors
+/// Fails if reading a request or writing a response fails.
+pub fn serve<R: BufRead, W: Write>(mut input: R, output: W) -> io::Result<()> {
+    let mut server = Server {
+        output,
+        documents: HashMap::new(),
+    };
+    while let Some(message) = read_message(&mut input)? {
+        if !server.handle(&message)? {
+            break;
+        }
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::io::Cursor;
+
+    fn session(messages: &[Value]) -> Vec<Value> {
+        let mut input = Vec::new();
+        for message in messages {
+            write_message(&mut input, message).unwrap();
+        }
+        let mut output = Vec::new();
+        serve(Cursor::new(input), &mut output).unwrap();
+        let mut output = Cursor::new(output);
+        let mut responses = Vec::new();
+        while let Some(response) = read_message(&mut output).unwrap() {
+            responses.push(response);
+        }
+        responses
+    }
+
+    fn open(text: &str) -> Value {
+        json!({"method": "textDocument/didOpen", "params": {"textDocument": {"uri": "file:///a.b", "text": text}}})
+    }
+
+    fn at(method: &str, id: u64, line: u64, character: u64) -> Value {
+        json!({"id": id, "method": method, "params": {
+            "textDocument": {"uri": "file:///a.b"},
+            "position": {"line": line, "character": character},
+        }})
+    }
+
+    #[test]
+    fn diagnostics() {
+        let responses = session(&[
+            json!({"id": 1, "method": "initialize", "params": {}}),
+            open("+[-]]"),
+            json!({"method": "textDocument/didChange", "params": {
+                "textDocument": {"uri": "file:///a.b"},
+                "contentChanges": [{"text": "+-"}],
+            }}),
+        ]);
+        assert_eq!(
+            responses[0]["result"]["capabilities"]["hoverProvider"],
+            true
+        );
+        let diagnostics = &responses[1]["params"]["diagnostics"];
+        assert_eq!(diagnostics[0]["severity"], 1);
+        assert_eq!(
+            diagnostics[0]["range"]["start"],
+            json!({"line": 0, "character": 4})
+        );
+        let diagnostics = &responses[2]["params"]["diagnostics"];
+        assert_eq!(diagnostics[0]["code"], "cancelling-instructions");
+        assert_eq!(diagnostics[0]["severity"], 2);
+    }
+
+    #[test]
+    fn incremental_changes() {
+        let change = |range: Value, text: &str| {
+            json!({"method": "textDocument/didChange", "params": {
+                "textDocument": {"uri": "file:///a.b"},
+                "contentChanges": [{"range": range, "text": text}],
+            }})
+        };
+        let range = |start: (u64, u64), end: (u64, u64)| {
+            json!({
+                "start": {"line": start.0, "character": start.1},
+                "end": {"line": end.0, "character": end.1},
+            })
+        };
+        let responses = session(&[
+            json!({"id": 1, "method": "initialize", "params": {}}),
+            open("é +[-]"),
+            // Add a line inside the loop, then close it off with a bracket too many.
+            change(range((0, 4), (0, 4)), "\n>>"),
+            change(range((1, 2), (1, 99)), "]]"),
+            at("textDocument/definition", 2, 0, 3),
+            at("textDocument/hover", 3, 1, 0),
+            // Replacing everything starts again.
+            json!({"method": "textDocument/didChange", "params": {
+                "textDocument": {"uri": "file:///a.b"},
+                "contentChanges": [{"text": "+-"}],
+            }}),
+        ]);
+        assert_eq!(
+            responses[0]["result"]["capabilities"]["textDocumentSync"],
+            2
+        );
+        assert_eq!(responses[2]["params"]["diagnostics"], json!([]));
+        let diagnostics = &responses[3]["params"]["diagnostics"];
+        assert_eq!(diagnostics.as_array().map(Vec::len), Some(1));
+        assert_eq!(
+            diagnostics[0]["range"]["start"],
+            json!({"line": 1, "character": 3})
+        );
+        assert_eq!(
+            responses[4]["result"]["range"]["start"],
+            json!({"line": 1, "character": 2})
+        );
+        assert_eq!(
+            responses[5]["result"]["contents"]["value"],
+            "Move right one location, loop depth 1"
+        );
+        assert_eq!(
+            responses[6]["params"]["diagnostics"][0]["code"],
+            "cancelling-instructions"
+        );
+    }
+
+    #[test]
+    fn brackets() {
+        let responses = session(&[
+            open("+[>\n[-]<]"),
+            at("textDocument/hover", 1, 1, 0),
+            at("textDocument/definition", 2, 0, 1),
+            at("textDocument/definition", 3, 0, 0),
+        ]);
+        assert_eq!(
+            responses[1]["result"]["contents"]["value"],
+            "Start looping, loop depth 1, matches the bracket at line 2, column 3"
+        );
+        assert_eq!(
+            responses[2]["result"]["range"]["start"],
+            json!({"line": 1, "character": 4})
+        );
+        assert_eq!(responses[3]["result"], Value::Null);
+    }
+
+    #[test]
+    fn broken_documents() {
+        let responses = session(&[
+            open("]+[>\n[-]<"),
+            at("textDocument/definition", 1, 1, 0),
+            at("textDocument/hover", 2, 0, 2),
+        ]);
+        let diagnostics = &responses[0]["params"]["diagnostics"];
+        assert_eq!(diagnostics.as_array().map(Vec::len), Some(2));
+        assert_eq!(
+            diagnostics[1]["range"]["start"],
+            json!({"line": 0, "character": 2})
+        );
+        assert_eq!(
+            responses[1]["result"]["range"]["start"],
+            json!({"line": 1, "character": 2})
+        );
+        assert_eq!(
+            responses[2]["result"]["contents"]["value"],
+            "Start looping, loop depth 0"
+        );
+    }
+
+    #[test]
+    fn non_ascii_comments() {
+        // The comment takes 6 bytes, but only 3 UTF-16 code units.
+        let responses = session(&[
+            open("é😀 +[-]]"),
+            at("textDocument/hover", 1, 0, 5),
+            at("textDocument/definition", 2, 0, 5),
+            at("textDocument/hover", 3, 0, 2),
+        ]);
+        assert_eq!(
+            responses[0]["params"]["diagnostics"][0]["range"],
+            json!({"start": {"line": 0, "character": 8}, "end": {"line": 0, "character": 9}})
+        );
+        assert_eq!(
+            responses[1]["result"]["range"],
+            json!({"start": {"line": 0, "character": 5}, "end": {"line": 0, "character": 6}})
+        );
+        assert_eq!(
+            responses[2]["result"]["range"]["start"],
+            json!({"line": 0, "character": 7})
+        );
+        assert_eq!(responses[3]["result"], Value::Null);
+    }
+
+    #[test]
+    fn formatting() {
+        let responses = session(&[
+            open("+[-]"),
+            json!({"id": 1, "method": "textDocument/formatting", "params": {"textDocument": {"uri": "file:///a.b"}}}),
+            json!({"id": 2, "method": "shutdown"}),
+            json!({"method": "exit"}),
+            json!({"id": 3, "method": "shutdown"}),
+        ]);
+        assert_eq!(responses[1]["result"][0]["newText"], "+\n[\n  -\n]\n");
+        assert_eq!(responses[2]["result"], Value::Null);
+        assert_eq!(responses.len(), 3);
+    }
+}
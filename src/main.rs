@@ -1,30 +1,994 @@
 //! Main entry point to our Brainf*ck interpreter.
 #![warn(missing_docs)]
 
-use clap::Parser;
+use clap::{CommandFactory, FromArgMatches, Parser};
+use std::fs::File;
+use std::io;
+use std::io::{IsTerminal, Read, Write};
+use std::num::NonZeroUsize;
+use std::path::{Path, PathBuf};
 use std::process::ExitCode;
+use std::time::{Duration, Instant};
 
-use bft_interp::BFVM;
-use bft_types::BFprogram;
+use bft_interp::bits::{BitReader, BitWriter};
+use bft_interp::{BftError, CellKind, SandboxLimits, BFVM};
+use bft_types::{Alphabet, BFprogram, Extension, Location};
 
+mod alphabet;
+mod autosave;
+mod bench;
+mod bfasm;
+mod bundle;
 mod cli;
+mod config;
+mod corpus;
+mod daemon;
+mod dap;
+mod debug_script;
+mod debugger;
+mod decompile;
+mod diagnostic;
+mod diff;
+mod diff_run;
+mod disasm;
+mod echo;
+mod equiv;
+mod exit_code;
+mod formatter;
+mod fusion;
+mod generate;
+mod highlight;
+mod idiom;
+mod input;
+mod io_log;
+mod lint;
+mod lsp;
+mod manifest;
+mod metrics;
+mod minify;
+mod net;
+mod output_format;
+mod output_positions;
+mod pipe;
+mod prefill;
+mod profile;
+mod raw_input;
+mod run_all;
+mod sarif;
+mod selftest;
+mod serve;
+mod slice;
+mod snapshot;
+mod stats;
+mod trace;
+mod translate;
+mod value_range;
 
-fn run_bft(options: &cli::Opt) -> Result<(), Box<dyn std::error::Error>> {
-    let mut src = BFprogram::from_file(options.program.clone())?;
-    src.validate_brackets()?;
-    let vm: BFVM<u8> = BFVM::new(None, false);
-    vm.interpret(&src);
+/// The tokens used by `dialect`, or those in the mapping file at `alphabet` if one is given.
+fn dialect_alphabet(
+    dialect: cli::Dialect,
+    alphabet: Option<&Path>,
+) -> Result<Alphabet, Box<dyn std::error::Error>> {
+    match (alphabet, dialect) {
+        (Some(alphabet), _) => alphabet::load_alphabet(alphabet),
+        (None, cli::Dialect::Brainfuck) => Ok(Alphabet::default()),
+        (None, cli::Dialect::Boolfuck) => Ok(Alphabet::boolfuck()),
+        (None, cli::Dialect::Ook) => Ok(Alphabet::ook()),
+    }
+}
+
+/// Read the program at `path`, using the dialect, alphabet and extensions selected in `options`.
+fn load_program(
+    options: &cli::RunArgs,
+    path: &Path,
+) -> Result<BFprogram, Box<dyn std::error::Error>> {
+    let plain = options.dialect == cli::Dialect::Brainfuck
+        && options.alphabet.is_none()
+        && options.extensions.is_empty();
+    if plain && options.source.is_none() {
+        return Ok(BFprogram::from_file(path)?);
+    }
+    let mut alphabet = dialect_alphabet(options.dialect, options.alphabet.as_deref())?;
+    for extension in &options.extensions {
+        alphabet.add_extension(*extension)?;
+    }
+    Ok(match &options.source {
+        Some(source) => BFprogram::with_alphabet(path, source, &alphabet),
+        None => BFprogram::from_file_with_alphabet(path, &alphabet)?,
+    })
+}
+
+/// Read the Brainf*ck program at `path`, checking that its brackets match.
+fn load_checked(path: &Path) -> Result<BFprogram, Box<dyn std::error::Error>> {
+    let mut program = BFprogram::from_file(path)?;
+    program.validate_brackets()?;
+    Ok(program)
+}
+
+/// Build a VM configured by `options`, failing if the arguments or environment variables for the
+/// program don't fit on its tape.
+fn new_vm<C: CellKind + Default + Clone>(options: &cli::RunArgs) -> Result<BFVM<C>, BftError> {
+    let mut vm = BFVM::new(options.cells, options.extensible);
+    let cells = prefill::cells(options.args.as_deref(), &options.env)?;
+    if !cells.is_empty() {
+        vm = vm.with_cells(0, &cells);
+    }
+    if options.extensions.contains(&Extension::Multitape) {
+        vm = vm.with_tapes(options.tapes);
+    }
+    if options.sandbox {
+        vm = vm.with_sandbox(SandboxLimits::conservative());
+    }
+    if let Some(bytes) = options.max_output_bytes {
+        vm = vm.with_max_output(bytes);
+    }
+    if options.dump_fused {
+        vm = vm.with_fusion_stats();
+    }
+    if let Some(instructions) = options.deterministic {
+        vm = vm.with_time_slice(instructions);
+    }
+    if options.shared_tapes {
+        vm = vm.with_shared_tapes();
+    }
+    Ok(match options.seed {
+        Some(seed) => vm.with_seed(seed),
+        None => vm,
+    })
+}
+
+/// The exit code for a command that has `succeeded` or not.
+fn status(succeeded: bool) -> ExitCode {
+    if succeeded {
+        ExitCode::SUCCESS
+    } else {
+        ExitCode::from(exit_code::FAILURE)
+    }
+}
+
+/// The exit code for checking programs that are `valid` or not.
+fn validity(valid: bool) -> ExitCode {
+    if valid {
+        ExitCode::SUCCESS
+    } else {
+        ExitCode::from(exit_code::INVALID)
+    }
+}
+
+/// Write `data` to the file at `path`, or to stdout if no path is given.
+fn write_output(path: Option<&Path>, data: &[u8]) -> io::Result<()> {
+    match path {
+        Some(path) => std::fs::write(path, data),
+        None => io::stdout().write_all(data),
+    }
+}
+
+/// Translate the program at `path` between the dialects given by `from` and `to`, each of which
+/// may be overridden by an alphabet mapping file.
+fn translate_file(
+    path: &Path,
+    from: (cli::Dialect, Option<&Path>),
+    to: (cli::Dialect, Option<&Path>),
+    output: Option<&Path>,
+) -> Result<(), Box<dyn std::error::Error>> {
+    if (from.0 == cli::Dialect::Boolfuck) != (to.0 == cli::Dialect::Boolfuck) {
+        return Err("Boolfuck programs can only be translated to and from Boolfuck".into());
+    }
+    let from = dialect_alphabet(from.0, from.1)?;
+    let to = dialect_alphabet(to.0, to.1)?;
+    let translated = translate::translate(&std::fs::read(path)?, &from, &to)?;
+    Ok(write_output(output, &translated)?)
+}
+
+/// Format each of `files`, or stdin if there are none. With `check`, report the files that
+/// aren't formatted instead of rewriting them.
+fn format_files(
+    files: &[PathBuf],
+    width: usize,
+    check: bool,
+) -> Result<ExitCode, Box<dyn std::error::Error>> {
+    let alphabet = Alphabet::default();
+    if files.is_empty() {
+        let mut data = Vec::new();
+        io::stdin().read_to_end(&mut data)?;
+        let formatted = formatter::format(Path::new("<stdin>"), &data, &alphabet, width)?;
+        if check {
+            return Ok(status(formatted.as_bytes() == data));
+        }
+        io::stdout().write_all(formatted.as_bytes())?;
+        return Ok(ExitCode::SUCCESS);
+    }
+    let mut unformatted = false;
+    for file in files {
+        let data = std::fs::read(file)?;
+        let formatted = formatter::format(file, &data, &alphabet, width)?;
+        if formatted.as_bytes() != data {
+            if check {
+                println!("{} is not formatted", file.display());
+                unformatted = true;
+            } else {
+                std::fs::write(file, formatted)?;
+            }
+        }
+    }
+    Ok(status(!unformatted))
+}
+
+/// Check that each of `files` is valid, and run the lints on them, printing `OK` if there are no
+/// problems. Fails if any errors are found.
+fn check_files(
+    files: &[PathBuf],
+    json: bool,
+    sarif: bool,
+    config: &lint::LintConfig,
+    renderer: diagnostic::Renderer,
+) -> Result<ExitCode, Box<dyn std::error::Error>> {
+    let mut diagnostics = Vec::new();
+    for file in files {
+        diagnostics.extend(diagnostic::check_source(
+            file,
+            &std::fs::read(file)?,
+            config,
+        ));
+    }
+    if json {
+        let diagnostics: Vec<_> = diagnostics
+            .iter()
+            .map(diagnostic::Diagnostic::to_json)
+            .collect();
+        println!("{}", serde_json::Value::from(diagnostics));
+    } else if sarif {
+        println!("{:#}", sarif::log(&diagnostics));
+    } else if diagnostics.is_empty() && !renderer.is_json() {
+        println!("OK");
+    } else {
+        for diagnostic in &diagnostics {
+            println!("{}", renderer.render(diagnostic));
+        }
+    }
+    Ok(validity(diagnostics.iter().all(|diagnostic| {
+        diagnostic.severity != diagnostic::Severity::Error
+    })))
+}
+
+/// Check each of `files` with the lints at the levels in `config`. The diagnostics are printed as
+/// they're found, or as a SARIF log at the end. Fails if any denied lint fires.
+fn lint_files(
+    files: &[PathBuf],
+    list: bool,
+    sarif: bool,
+    config: &lint::LintConfig,
+    renderer: diagnostic::Renderer,
+) -> Result<ExitCode, Box<dyn std::error::Error>> {
+    if list {
+        for lint in &lint::LINTS {
+            println!("{}: {}", lint.name, lint.description);
+        }
+        return Ok(ExitCode::SUCCESS);
+    }
+    let mut denied = false;
+    let mut found = Vec::new();
+    for file in files {
+        for diagnostic in diagnostic::check_source(file, &std::fs::read(file)?, config) {
+            denied |= diagnostic.severity == diagnostic::Severity::Error;
+            if sarif {
+                found.push(diagnostic);
+            } else {
+                println!("{}", renderer.render(&diagnostic));
+            }
+        }
+    }
+    if sarif {
+        println!("{:#}", sarif::log(&found));
+    }
+    Ok(validity(!denied))
+}
+
+/// Print metrics for each of `files`, and the idioms found in them if asked for, as text or as
+/// a JSON array.
+fn report_stats(
+    files: &[PathBuf],
+    json: bool,
+    idioms: bool,
+) -> Result<ExitCode, Box<dyn std::error::Error>> {
+    let mut reports = Vec::new();
+    for file in files {
+        let data = std::fs::read(file)?;
+        let stats = stats::Stats::of(file, &data);
+        let found = if idioms {
+            idiom::find(&BFprogram::new(file, &data))
+        } else {
+            Vec::new()
+        };
+        if json {
+            let mut report = stats.to_json();
+            report["file"] = serde_json::json!(file);
+            if idioms {
+                report["idioms"] = found.iter().map(idiom::Found::to_json).collect();
+            }
+            reports.push(report);
+        } else {
+            print!("{}:\n{stats}", file.display());
+            if idioms {
+                println!("idioms: {}", found.len());
+                for found in &found {
+                    println!("  {found}");
+                }
+            }
+            println!();
+        }
+    }
+    if json {
+        println!("{}", serde_json::Value::from(reports));
+    }
+    Ok(ExitCode::SUCCESS)
+}
+
+/// Print the differences between the programs at `old` and `new`, returning whether there were
+/// none.
+fn report_diff(old: &Path, new: &Path) -> Result<bool, Box<dyn std::error::Error>> {
+    let changes = diff::diff(&BFprogram::from_file(old)?, &BFprogram::from_file(new)?);
+    print!("{changes}");
+    Ok(changes.is_empty())
+}
 
+/// Look for an input that the programs in `args` behave differently on, returning whether none
+/// was found.
+fn check_equiv(args: &cli::EquivArgs) -> Result<bool, Box<dyn std::error::Error>> {
+    let options = equiv::Options {
+        inputs: args
+            .input_files
+            .iter()
+            .map(std::fs::read)
+            .collect::<Result<_, _>>()?,
+        generated: args.inputs,
+        seed: args.seed,
+        fuel: args.fuel,
+    };
+    equiv::check(
+        &load_checked(&args.a)?,
+        &load_checked(&args.b)?,
+        &options,
+        &mut io::stdout().lock(),
+    )
+}
+
+/// Time the program in `args` over several runs, writing the results to stdout.
+fn bench_program(args: &cli::BenchArgs) -> Result<ExitCode, Box<dyn std::error::Error>> {
+    let input = match &args.input {
+        Some(path) => std::fs::read(path)?,
+        None => Vec::new(),
+    };
+    let options = bench::Options {
+        runs: args.runs,
+        cells: args.cells,
+        max_steps: args.max_steps,
+    };
+    bench::bench(
+        &load_checked(&args.program)?,
+        &input,
+        &options,
+        &mut io::stdout().lock(),
+    )?;
+    Ok(ExitCode::SUCCESS)
+}
+
+/// Compile the bfasm program at `path`, writing the Brainf*ck to `output`, or stdout if that's
+/// not given, and its source map to `source_map` if that is.
+fn assemble(
+    path: &Path,
+    output: Option<&Path>,
+    source_map: Option<&Path>,
+) -> Result<ExitCode, Box<dyn std::error::Error>> {
+    let compiled = bfasm::compile(&std::fs::read_to_string(path)?)?;
+    write_output(output, compiled.code.as_bytes())?;
+    if let Some(path) = source_map {
+        std::fs::write(path, compiled.source_map_json().to_string())?;
+    }
+    Ok(ExitCode::SUCCESS)
+}
+
+/// Do what `command` asks with snapshots of runs.
+fn snapshot_command(
+    command: &cli::SnapshotCommand,
+) -> Result<ExitCode, Box<dyn std::error::Error>> {
+    match command {
+        cli::SnapshotCommand::Diff { before, after } => {
+            snapshot::report(&snapshot::diff(before, after)?, &mut io::stdout().lock())?;
+        }
+    }
+    Ok(ExitCode::SUCCESS)
+}
+
+/// Bundle the program at `program` into an executable at `output` that runs it with `options`,
+/// checking the options and the program first so that mistakes show up now rather than when the
+/// bundle is run.
+fn bundle_program(
+    program: &Path,
+    output: &Path,
+    options: &[String],
+) -> Result<ExitCode, Box<dyn std::error::Error>> {
+    let name = program
+        .file_name()
+        .ok_or("the program to bundle must be a file")?
+        .to_string_lossy()
+        .into_owned();
+    let bundle = bundle::Bundle {
+        source: std::fs::read(program)?,
+        options: options.to_vec(),
+        name,
+    };
+    let opt = match cli::Opt::try_parse_from(bundle.command_line([])?) {
+        Ok(opt) => opt,
+        Err(error) => return Ok(usage(&error)),
+    };
+    let Some(cli::Command::Run(run)) = opt.command else {
+        unreachable!("a bundle's command line is a run");
+    };
+    if run.manifest.is_some() || run.autosave.is_some() || run.debug_script.is_some() {
+        return Err("--manifest, --autosave and --debug-script can't be bundled".into());
+    }
+    let run = cli::RunArgs {
+        source: Some(bundle.source.clone()),
+        ..run
+    };
+    let mut src = load_program(&run, program)?;
+    src.validate_brackets_with_max_depth(run.max_nesting.map_or(usize::MAX, NonZeroUsize::get))?;
+    src.validate_extensions(&run.extensions)?;
+    bundle::write(&bundle, output)?;
+    Ok(ExitCode::SUCCESS)
+}
+
+/// Cut the loop around `at` out of the program at `path`, writing it to `output`, or stdout if
+/// that's not given.
+fn slice_loop(
+    path: &Path,
+    at: Location,
+    output: Option<&Path>,
+) -> Result<ExitCode, Box<dyn std::error::Error>> {
+    let data = std::fs::read(path)?;
+    let mut program = BFprogram::new(path, &data);
+    program.validate_brackets()?;
+    let sliced = slice::slice(&program, &data, at)
+        .ok_or_else(|| format!("No loop contains {at} in {}", path.display()))?;
+    write_output(output, sliced.as_bytes())?;
+    Ok(ExitCode::SUCCESS)
+}
+
+/// Run one of the subcommands that work on program source without running it, rendering
+/// diagnostics with `renderer`.
+fn run_source_tool(
+    command: &cli::Command,
+    config: &config::Config,
+    renderer: diagnostic::Renderer,
+) -> Result<ExitCode, Box<dyn std::error::Error>> {
+    match command {
+        cli::Command::Bench(args) => bench_program(args),
+        cli::Command::Translate {
+            program,
+            from,
+            from_alphabet,
+            to,
+            to_alphabet,
+            output,
+        } => {
+            let from = (*from, from_alphabet.as_deref());
+            let to = (*to, to_alphabet.as_deref());
+            translate_file(program, from, to, output.as_deref())?;
+            Ok(ExitCode::SUCCESS)
+        }
+        cli::Command::Fmt {
+            files,
+            width,
+            check,
+        } => format_files(files, *width, *check),
+        cli::Command::Asm {
+            program,
+            output,
+            source_map,
+        } => assemble(program, output.as_deref(), source_map.as_deref()),
+        cli::Command::Gen { generator, output } => {
+            let code = match generator {
+                cli::Generator::Text { text } => generate::text(text.as_bytes()),
+                cli::Generator::Expr { expr, print } => generate::expr(expr, *print)?,
+            };
+            write_output(output.as_deref(), code.as_bytes())?;
+            Ok(ExitCode::SUCCESS)
+        }
+        cli::Command::Minify { program, output } => {
+            let minified = minify::minify(&BFprogram::from_file(program)?);
+            write_output(output.as_deref(), minified.as_bytes())?;
+            Ok(ExitCode::SUCCESS)
+        }
+        cli::Command::Check {
+            files,
+            json,
+            sarif,
+            levels,
+        } => check_files(
+            files,
+            *json,
+            *sarif,
+            &levels.apply(&config.lints)?,
+            renderer,
+        ),
+        cli::Command::Lint {
+            files,
+            list,
+            sarif,
+            levels,
+        } => lint_files(
+            files,
+            *list,
+            *sarif,
+            &levels.apply(&config.lints)?,
+            renderer,
+        ),
+        cli::Command::Disasm {
+            program,
+            format,
+            lines,
+        } => {
+            let mut program = BFprogram::from_file(program)?;
+            program.validate_brackets()?;
+            print!("{}", disasm::disassemble(&program, *format, *lines));
+            Ok(ExitCode::SUCCESS)
+        }
+        cli::Command::Slice {
+            program,
+            at,
+            output,
+        } => slice_loop(program, *at, output.as_deref()),
+        cli::Command::Snapshot { command } => snapshot_command(command),
+        cli::Command::Decompile { program } => {
+            let mut program = BFprogram::from_file(program)?;
+            program.validate_brackets()?;
+            print!("{}", decompile::decompile(&program));
+            Ok(ExitCode::SUCCESS)
+        }
+        cli::Command::Stats {
+            files,
+            json,
+            idioms,
+        } => report_stats(files, *json, *idioms),
+        cli::Command::Diff { old, new } => Ok(status(report_diff(old, new)?)),
+        cli::Command::Highlight { program, format } => {
+            let data = std::fs::read(program)?;
+            print!(
+                "{}",
+                highlight::highlight(&data, &Alphabet::default(), *format)
+            );
+            Ok(ExitCode::SUCCESS)
+        }
+        _ => unreachable!("{command:?} doesn't work on program source"),
+    }
+}
+
+/// Run one of the subcommands that serve requests until the process is stopped.
+fn run_server(command: &cli::Command) -> io::Result<()> {
+    match command {
+        cli::Command::Dap => dap::serve(io::BufReader::new(io::stdin()), io::stdout().lock()),
+        cli::Command::Lsp => lsp::serve(io::stdin().lock(), io::stdout().lock()),
+        cli::Command::Serve {
+            addr,
+            cells,
+            max_steps,
+            max_output,
+            max_request,
+            timeout,
+        } => serve::serve(
+            addr,
+            serve::Limits {
+                cells: *cells,
+                max_steps: *max_steps,
+                max_output: *max_output,
+                max_request: *max_request,
+                max_time: std::time::Duration::from_secs(*timeout),
+            },
+        ),
+        cli::Command::Daemon {
+            socket,
+            cells,
+            max_steps,
+            max_output,
+            max_request,
+        } => daemon::daemon(
+            socket,
+            daemon::Limits {
+                cells: *cells,
+                max_steps: *max_steps,
+                max_output: *max_output,
+                max_request: *max_request,
+            },
+        ),
+        _ => unreachable!("{command:?} isn't a server"),
+    }
+}
+
+/// Run one of the subcommands.
+fn run_command(
+    command: &cli::Command,
+    config: &config::Config,
+    renderer: diagnostic::Renderer,
+) -> Result<ExitCode, Box<dyn std::error::Error>> {
+    match command {
+        cli::Command::Dap
+        | cli::Command::Lsp
+        | cli::Command::Serve { .. }
+        | cli::Command::Daemon { .. } => {
+            run_server(command)?;
+            Ok(ExitCode::SUCCESS)
+        }
+        cli::Command::Pipe {
+            programs,
+            cells,
+            extensible,
+            max_steps,
+        } => {
+            let programs = programs
+                .iter()
+                .map(|path| load_checked(path))
+                .collect::<Result<Vec<_>, _>>()?;
+            let limits = pipe::Limits {
+                cells: *cells,
+                extensible: *extensible,
+                max_steps: *max_steps,
+            };
+            let status = pipe::run_pipeline(&programs, limits, io::stdin(), io::stdout())?;
+            Ok(ExitCode::from(status))
+        }
+        cli::Command::Test {
+            corpus,
+            max_steps,
+            bless,
+        } => {
+            let passed = corpus::run_corpus(corpus, *max_steps, *bless, &mut io::stdout().lock())?;
+            Ok(status(passed))
+        }
+        cli::Command::RunAll {
+            dir,
+            jobs,
+            cells,
+            max_steps,
+            report,
+        } => {
+            let limits = run_all::Limits {
+                cells: *cells,
+                max_steps: *max_steps,
+            };
+            let passed = run_all::run_all(
+                dir,
+                *jobs,
+                limits,
+                report.as_deref(),
+                &mut io::stdout().lock(),
+            )?;
+            Ok(status(passed))
+        }
+        cli::Command::DiffRun {
+            reference,
+            program,
+            input,
+            max_steps,
+        } => {
+            let input = match input {
+                Some(path) => std::fs::read(path)?,
+                None => Vec::new(),
+            };
+            let matched = diff_run::diff_run(
+                reference,
+                program,
+                &input,
+                *max_steps,
+                &mut io::stdout().lock(),
+            )?;
+            Ok(status(matched))
+        }
+        cli::Command::Equiv(args) => Ok(status(check_equiv(args)?)),
+        cli::Command::ReplayTrace {
+            trace,
+            step,
+            start,
+            count,
+            first_write,
+        } => {
+            let trace = trace::Trace::load(io::BufReader::new(File::open(trace)?))?;
+            let mut out = io::stdout().lock();
+            if let Some(cell) = first_write {
+                trace::report_first_write(&trace, *cell, &mut out)?;
+            } else {
+                trace::report(&trace, *step, *start, *count, &mut out)?;
+            }
+            Ok(ExitCode::SUCCESS)
+        }
+        cli::Command::Completions { shell } => {
+            clap_complete::generate(*shell, &mut cli::Opt::command(), "bft", &mut io::stdout());
+            Ok(ExitCode::SUCCESS)
+        }
+        cli::Command::Manpage => {
+            clap_mangen::Man::new(cli::Opt::command()).render(&mut io::stdout())?;
+            Ok(ExitCode::SUCCESS)
+        }
+        _ => run_source_tool(command, config, renderer),
+    }
+}
+
+fn run_bft(options: &cli::Opt, config: &config::Config) -> Result<ExitCode, BftError> {
+    match &options.command {
+        Some(cli::Command::Run(args)) => run_program(args),
+        Some(cli::Command::Debug { script, run }) => run_program(&cli::RunArgs {
+            debug_script: Some(script.clone()),
+            ..run.clone()
+        }),
+        Some(cli::Command::Bundle {
+            program,
+            output,
+            options: run_options,
+        }) => Ok(bundle_program(program, output, run_options)?),
+        Some(cli::Command::Selftest) => {
+            let passed = selftest::run_selftest(|| new_vm(&options.run), &mut io::stdout().lock())?;
+            Ok(status(passed))
+        }
+        Some(command) => Ok(run_command(
+            command,
+            config,
+            diagnostic::Renderer::new(options.color, io::stdout().is_terminal())
+                .with_format(options.diagnostics),
+        )?),
+        None => run_program(&options.run),
+    }
+}
+
+/// `options` with the tape sized for `program`, if they ask for that. The tape holds every cell
+/// the head can reach, and the arguments and environment variables, or grows if the cells the
+/// head can reach can't be bounded.
+fn auto_cells(options: &cli::RunArgs, program: &BFprogram) -> Result<cli::RunArgs, BftError> {
+    if !options.auto_cells {
+        return Ok(options.clone());
+    }
+    let prefilled = prefill::cells(options.args.as_deref(), &options.env)?.len();
+    Ok(match stats::head_range(program.instructions()) {
+        Some(range) => cli::RunArgs {
+            cells: NonZeroUsize::new(
+                usize::try_from(*range.end() + 1)
+                    .unwrap_or(usize::MAX)
+                    .max(prefilled),
+            ),
+            ..options.clone()
+        },
+        None => cli::RunArgs {
+            extensible: true,
+            ..options.clone()
+        },
+    })
+}
+
+/// Run the program given in `options`.
+fn run_program(options: &cli::RunArgs) -> Result<ExitCode, BftError> {
+    let Some(program) = &options.program else {
+        return Ok(ExitCode::SUCCESS);
+    };
+    // The dialect may come from a config file, after the command line's conflicts are checked.
+    if options.dialect == cli::Dialect::Boolfuck {
+        if let Some(option) = options.byte_tape_option() {
+            return Err(format!("{option} can't be used with Boolfuck programs").into());
+        }
+    }
+    let mut src = load_program(options, program)?;
+    src.validate_brackets_with_max_depth(
+        options.max_nesting.map_or(usize::MAX, NonZeroUsize::get),
+    )?;
+    src.validate_extensions(&options.extensions)?;
+    // Deterministic runs use a fixed seed, and a manifest always records a seed, so one is chosen
+    // if none was given.
+    let options = &cli::RunArgs {
+        seed: options
+            .seed
+            .or_else(|| options.deterministic.map(|_| 0))
+            .or_else(|| options.manifest.as_ref().map(|_| manifest::random_seed())),
+        ..options.clone()
+    };
+    let options = &auto_cells(options, &src)?;
+    if options.dialect == cli::Dialect::Boolfuck {
+        let mut vm: BFVM<bool> = new_vm(options)?;
+        let started = Instant::now();
+        let (_raw_mode, stdin) = stdin(options)?;
+        let stdout = output_format::Formatted::new(io::stdout().lock(), options.output_format);
+        let (stdin, stdout) = echo::connect(stdin, stdout, options.echo_input.as_deref())?;
+        let (mut input, mut output) = (BitReader::new(stdin), BitWriter::new(stdout));
+        let result = if options.fast_unchecked {
+            vm.run_fast_unchecked(&src, &mut input, &mut output)
+        } else {
+            vm.run(&src, &mut input, &mut output)
+        };
+        return finish_run(options, &src, &vm, started, result.map_err(BftError::from));
+    }
+    let mut vm: BFVM<u8> = new_vm(options)?;
+    if let Some(script) = &options.debug_script {
+        let script = std::fs::read_to_string(script)?;
+        let mut dbg = debugger::Debugger::new(src, vm, Box::new(io::stdin()));
+        debug_script::run_script(
+            &mut dbg,
+            &script,
+            &mut io::stdout().lock(),
+            &mut io::stderr().lock(),
+        )?;
+        return Ok(ExitCode::from(dbg.vm().exit_status().unwrap_or(0)));
+    }
+    let started = Instant::now();
+    let result = run_vm(&src, &mut vm, options);
+    finish_run(options, &src, &vm, started, result)
+}
+
+/// Report on the run of `src` on `vm` that began at `started` and ended with `result`, as
+/// `options` asks, returning the exit code for it.
+fn finish_run<C>(
+    options: &cli::RunArgs,
+    src: &BFprogram,
+    vm: &BFVM<C>,
+    started: Instant,
+    result: Result<(), BftError>,
+) -> Result<ExitCode, BftError> {
+    let elapsed = started.elapsed();
+    if options.time {
+        report_time(vm.executed(), elapsed);
+    }
+    if let Some(stats) = vm.fusion_stats() {
+        fusion::report(src, stats, &mut io::stderr().lock())?;
+    }
+    let exit_code = match &result {
+        Ok(()) => vm.exit_status().unwrap_or(0),
+        Err(err) => exit_code::for_error(err),
+    };
+    let written = options.manifest.as_deref().map(|path| {
+        let summary = manifest::Summary {
+            error: result.as_ref().err(),
+            exit_code,
+            output_closed: vm.output_closed(),
+            executed: vm.executed(),
+            input_read: vm.input_read(),
+            output_written: vm.output_written(),
+            elapsed,
+        };
+        manifest::write(path, options, &summary)
+    });
+    result?;
+    written.transpose()?;
+    Ok(ExitCode::from(exit_code))
+}
+
+/// Run `src` on `vm`, with its input and output connected as `options` asks.
+fn run_vm(src: &BFprogram, vm: &mut BFVM<u8>, options: &cli::RunArgs) -> Result<(), BftError> {
+    let (_raw_mode, stdin) = stdin(options)?;
+    let stdout = output_format::Formatted::new(io::stdout().lock(), options.output_format);
+    let (mut stdin, mut stdout) = echo::connect(stdin, stdout, options.echo_input.as_deref())?;
+    if let Some(net) = &options.net {
+        let stream = net::Net::parse(net)?.open()?;
+        let mut input = input::Translated::new(io::BufReader::new(&stream), options.input_newlines);
+        vm.run(src, &mut input, &mut &stream)?;
+        if !vm.output_closed() {
+            stream.shutdown(std::net::Shutdown::Write)?;
+        }
+    } else if let Some(trace) = &options.trace {
+        let mut trace = io::BufWriter::new(File::create(trace)?);
+        trace::run_traced(src, vm, &mut stdin, &mut stdout, &mut trace)?;
+    } else if let Some(log) = &options.log_io {
+        let mut log = io::BufWriter::new(File::create(log)?);
+        io_log::run_logged(src, vm, &mut stdin, &mut stdout, &mut log)?;
+    } else if let Some(positions) = &options.trace_output_positions {
+        let mut positions = io::BufWriter::new(File::create(positions)?);
+        output_positions::run_recorded(src, vm, &mut stdin, &mut stdout, &mut positions)?;
+    } else if let (true, Some(program)) = (options.annotate, &options.program) {
+        let (counts, result) = profile::run_profiled(src, vm, &mut stdin, &mut stdout);
+        let source = match &options.source {
+            Some(source) => source.clone(),
+            None => std::fs::read(program)?,
+        };
+        profile::annotate(src, &source, &counts, &mut io::stderr().lock())?;
+        result?;
+    } else if let (Some(millions), Some(program)) = (options.autosave, &options.program) {
+        let every = millions.saturating_mul(autosave::MILLION);
+        let paths = autosave::paths(program);
+        if options.resume && !autosave::resume(src, vm, &paths) {
+            eprintln!("No checkpoint to resume from, so starting from the beginning");
+        }
+        autosave::run_autosaved(src, vm, &mut stdin, &mut stdout, every, &paths)?;
+    } else if options.fast_unchecked {
+        vm.run_fast_unchecked(src, &mut stdin, &mut stdout)?;
+    } else {
+        vm.run(src, &mut stdin, &mut stdout)?;
+    }
     Ok(())
 }
 
-fn main() -> ExitCode {
-    let opt = cli::Opt::parse();
-    if let Err(error) = run_bft(&opt) {
-        const BIN_NAME: &str = env!("CARGO_PKG_NAME");
-        eprintln!("{BIN_NAME}: {error}");
-        ExitCode::from(1)
+/// The program's standard input, adapted as `options` asks. With `--raw-input`, the terminal
+/// stays in raw mode until the returned mode is dropped.
+fn stdin(options: &cli::RunArgs) -> io::Result<(Option<raw_input::RawMode>, impl Read)> {
+    let raw_mode = if options.raw_input {
+        raw_input::RawMode::enable()?
+    } else {
+        None
+    };
+    let keys = raw_input::Keys::new(io::stdin().lock(), raw_mode.is_some());
+    Ok((
+        raw_mode,
+        input::Translated::new(keys, options.input_newlines),
+    ))
+}
+
+/// Print how long a run took, and how many instructions it executed, to stderr.
+fn report_time(executed: u64, elapsed: Duration) {
+    let per_second = u128::from(executed) * 1_000_000_000 / elapsed.as_nanos().max(1);
+    eprintln!("bft: {elapsed:.3?} elapsed, {executed} instructions, {per_second} instructions/s");
+}
+
+/// Print a command line parsing `error`, or the help or version it was asked for, returning the
+/// exit code to use.
+fn usage(error: &clap::Error) -> ExitCode {
+    let _ = error.print();
+    if error.use_stderr() {
+        ExitCode::from(exit_code::FAILURE)
     } else {
-        ExitCode::from(0)
+        ExitCode::SUCCESS
+    }
+}
+
+fn main() -> ExitCode {
+    let bundle = match bundle::Bundle::embedded() {
+        Ok(bundle) => bundle,
+        Err(error) => {
+            eprintln!("bft: error: can't read the bundled program: {error}");
+            return ExitCode::from(exit_code::FAILURE);
+        }
+    };
+    let args = match &bundle {
+        Some(bundle) => {
+            // A bundle runs the same wherever it goes, so bft's environment variables don't
+            // change what it does.
+            for (name, _) in std::env::vars_os() {
+                if name.to_string_lossy().starts_with("BFT_") {
+                    std::env::remove_var(name);
+                }
+            }
+            match bundle.command_line(std::env::args_os().skip(1)) {
+                Ok(args) => args,
+                Err(error) => {
+                    eprintln!("bft: error: {error}");
+                    return ExitCode::from(exit_code::FAILURE);
+                }
+            }
+        }
+        None => std::env::args_os().collect(),
+    };
+    let matches = match cli::Opt::command().try_get_matches_from(args) {
+        Ok(matches) => matches,
+        Err(error) => return usage(&error),
+    };
+    let mut opt = match cli::Opt::from_arg_matches(&matches) {
+        Ok(opt) => opt,
+        Err(error) => return usage(&error),
+    };
+    let result = match bundle {
+        Some(bundle) => {
+            if let Some(cli::Command::Run(run)) = &mut opt.command {
+                run.source = Some(bundle.source);
+            }
+            run_bft(&opt, &config::Config::default())
+        }
+        None => config::Config::load()
+            .map_err(BftError::from)
+            .and_then(|config| {
+                config.apply(&mut opt, &matches);
+                run_bft(&opt, &config)
+            }),
+    };
+    match result {
+        Ok(code) => code,
+        Err(error) => {
+            let renderer = diagnostic::Renderer::new(opt.color, io::stderr().is_terminal())
+                .with_format(opt.diagnostics);
+            eprintln!("{}", renderer.render_error(&error));
+            ExitCode::from(exit_code::for_error(&error))
+        }
     }
 }
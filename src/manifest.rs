@@ -0,0 +1,212 @@
+//! Manifests recording everything needed to reproduce a run, for bug reports and for keeping an
+//! audit trail when grading.
+//!
+//! A manifest is a JSON object with these members:
+//!
+//! - `bft`: the version of bft, and the operating system and architecture it was built for.
+//! - `command_line`: the arguments bft was started with.
+//! - `program`: the program's path, its size in bytes, and a fingerprint of its contents.
+//! - `options`: every option for the run, after `bft.toml` and the environment were applied. The
+//!   seed is always given, as one is chosen for runs that didn't ask for one.
+//! - `input`: where the program's input came from.
+//! - `result`: how the run ended, its exit code, and the instructions, bytes and time it took.
+
+use std::env;
+use std::fs;
+use std::io;
+use std::io::IsTerminal;
+use std::path::Path;
+use std::time::Duration;
+
+use clap::ValueEnum;
+use serde_json::{json, Value};
+
+use bft_interp::BftError;
+use bft_types::Extension;
+
+use crate::cli::RunArgs;
+
+/// How a run ended.
+pub struct Summary<'a> {
+    /// The error the run stopped with, if it failed.
+    pub error: Option<&'a BftError>,
+
+    /// The exit code bft gave.
+    pub exit_code: u8,
+
+    /// Whether the program was stopped because its output was closed.
+    pub output_closed: bool,
+
+    /// Number of instructions executed.
+    pub executed: u64,
+
+    /// Number of bytes read.
+    pub input_read: usize,
+
+    /// Number of bytes written.
+    pub output_written: usize,
+
+    /// How long the run took.
+    pub elapsed: Duration,
+}
+
+/// The 64-bit FNV-1a hash of `data`, which is enough to tell versions of a program apart.
+fn fnv1a(data: &[u8]) -> u64 {
+    data.iter().fold(0xcbf2_9ce4_8422_2325, |hash, byte| {
+        (hash ^ u64::from(*byte)).wrapping_mul(0x0000_0100_0000_01b3)
+    })
+}
+
+/// A seed for runs that weren't given one, so that the manifest can record it.
+pub fn random_seed() -> u64 {
+    use std::hash::{BuildHasher, Hasher};
+
+    // Each `RandomState` is keyed randomly, so hashing nothing gives a random number.
+    std::collections::hash_map::RandomState::new()
+        .build_hasher()
+        .finish()
+}
+
+/// The name of `value` on the command line.
+fn name<T: ValueEnum>(value: &T) -> Option<String> {
+    value
+        .to_possible_value()
+        .map(|value| value.get_name().to_owned())
+}
+
+/// The options for the run, in `options`.
+fn options(options: &RunArgs) -> Value {
+    let env: serde_json::Map<_, _> = options
+        .env
+        .iter()
+        .map(|name| {
+            let value = env::var_os(name).map(|value| value.to_string_lossy().into_owned());
+            (name.clone(), json!(value))
+        })
+        .collect();
+    json!({
+        "cells": options.cells,
+        "extensible": options.extensible,
+        "auto_cells": options.auto_cells,
+        "fast_unchecked": options.fast_unchecked,
+        "dump_fused": options.dump_fused,
+        "max_nesting": options.max_nesting,
+        "dialect": name(&options.dialect),
+        "alphabet": options.alphabet,
+        "extensions": options.extensions.iter().map(Extension::name).collect::<Vec<_>>(),
+        "tapes": options.tapes,
+        "seed": options.seed,
+        "deterministic": options.deterministic,
+        "shared_tapes": options.shared_tapes,
+        "sandbox": options.sandbox,
+        "args": options.args,
+        "env": env,
+        "max_output_bytes": options.max_output_bytes,
+        "input_newlines": name(&options.input_newlines),
+        "raw_input": options.raw_input,
+        "echo_input": options.echo_input,
+        "output_format": name(&options.output_format),
+        "time": options.time,
+        "trace": options.trace,
+        "autosave": options.autosave,
+        "resume": options.resume,
+        "log_io": options.log_io,
+        "trace_output_positions": options.trace_output_positions,
+        "annotate": options.annotate,
+        "net": options.net,
+    })
+}
+
+/// Where the program's input came from.
+fn input(options: &RunArgs) -> Value {
+    match options.net.as_deref() {
+        Some([mode, address]) => json!({ "source": "net", "mode": mode, "address": address }),
+        _ => json!({ "source": "stdin", "terminal": io::stdin().is_terminal() }),
+    }
+}
+
+/// The manifest for running the program at `path`, whose contents are `program`, with
+/// `options`, which ended as `summary` says.
+fn manifest(path: &Path, program: &[u8], options: &RunArgs, summary: &Summary) -> Value {
+    let status = match summary.error {
+        Some(_) => "error",
+        None if summary.output_closed => "broken_pipe",
+        None => "finished",
+    };
+    json!({
+        "bft": {
+            "version": env!("CARGO_PKG_VERSION"),
+            "os": env::consts::OS,
+            "arch": env::consts::ARCH,
+        },
+        "command_line": env::args_os()
+            .map(|arg| arg.to_string_lossy().into_owned())
+            .collect::<Vec<_>>(),
+        "program": {
+            "path": path,
+            "bytes": program.len(),
+            "fingerprint": format!("fnv1a64:{:016x}", fnv1a(program)),
+        },
+        "options": self::options(options),
+        "input": input(options),
+        "result": {
+            "status": status,
+            "error": summary.error.map(ToString::to_string),
+            "exit_code": summary.exit_code,
+            "instructions": summary.executed,
+            "input_bytes": summary.input_read,
+            "output_bytes": summary.output_written,
+            "elapsed_seconds": summary.elapsed.as_secs_f64(),
+        },
+    })
+}
+
+/// Write the manifest for running the program in `options`, which ended as `summary` says, to
+/// the file at `out`.
+///
+/// # Errors
+/// Fails if the program can't be read, or the manifest can't be written.
+pub fn write(out: &Path, options: &RunArgs, summary: &Summary) -> io::Result<()> {
+    let path = options.program.as_deref().unwrap_or(Path::new("-"));
+    let program = fs::read(path)?;
+    let manifest = manifest(path, &program, options, summary);
+    let mut text = serde_json::to_string_pretty(&manifest)?;
+    text.push('\n');
+    fs::write(out, text)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::cli::Opt;
+    use clap::Parser;
+
+    #[test]
+    fn fingerprints() {
+        assert_eq!(fnv1a(b""), 0xcbf2_9ce4_8422_2325);
+        assert_eq!(fnv1a(b"a"), 0xaf63_dc4c_8601_ec8c);
+    }
+
+    #[test]
+    fn recording_a_run() {
+        let options = Opt::parse_from(["bft", "a.b", "--seed", "7", "--extensions", "random"]).run;
+        let error = BftError::from("failed");
+        let summary = Summary {
+            error: Some(&error),
+            exit_code: 1,
+            output_closed: false,
+            executed: 12,
+            input_read: 1,
+            output_written: 2,
+            elapsed: Duration::from_millis(1500),
+        };
+        let manifest = manifest(Path::new("a.b"), b"+.", &options, &summary);
+        assert_eq!(manifest["program"]["bytes"], 2);
+        assert_eq!(manifest["options"]["seed"], 7);
+        assert_eq!(manifest["options"]["extensions"], json!(["random"]));
+        assert_eq!(manifest["options"]["dialect"], "brainfuck");
+        assert_eq!(manifest["result"]["status"], "error");
+        assert_eq!(manifest["result"]["error"], "failed");
+        assert_eq!(manifest["result"]["elapsed_seconds"], 1.5);
+    }
+}
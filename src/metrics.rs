@@ -0,0 +1,170 @@
+//! Usage metrics for the server, in the Prometheus text exposition format.
+
+use std::fmt::Write;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::time::Duration;
+
+/// Upper bounds of the buckets of the run duration histogram, in seconds.
+const DURATION_BUCKETS: [f64; 9] = [0.001, 0.005, 0.01, 0.05, 0.1, 0.5, 1.0, 5.0, 10.0];
+
+/// The kinds of error counted separately.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum ErrorClass {
+    /// The request couldn't be read or parsed.
+    BadRequest,
+    /// The program's brackets don't match.
+    Brackets,
+    /// The program executed too many instructions.
+    StepLimit,
+    /// The program ran for too long.
+    TimeLimit,
+    /// The program wrote too much output.
+    OutputLimit,
+    /// Reading input or writing output failed.
+    Io,
+    /// Any other error while running the program.
+    Runtime,
+}
+
+impl ErrorClass {
+    const ALL: [ErrorClass; 7] = [
+        ErrorClass::BadRequest,
+        ErrorClass::Brackets,
+        ErrorClass::StepLimit,
+        ErrorClass::TimeLimit,
+        ErrorClass::OutputLimit,
+        ErrorClass::Io,
+        ErrorClass::Runtime,
+    ];
+
+    /// The value of the `class` label.
+    fn label(self) -> &'static str {
+        match self {
+            ErrorClass::BadRequest => "bad_request",
+            ErrorClass::Brackets => "brackets",
+            ErrorClass::StepLimit => "step_limit",
+            ErrorClass::TimeLimit => "time_limit",
+            ErrorClass::OutputLimit => "output_limit",
+            ErrorClass::Io => "io",
+            ErrorClass::Runtime => "runtime",
+        }
+    }
+}
+
+/// Counters shared by every connection to the server.
+#[derive(Debug, Default)]
+pub struct Metrics {
+    programs_run: AtomicU64,
+    instructions: AtomicU64,
+    errors: [AtomicU64; ErrorClass::ALL.len()],
+    /// The number of runs taking at most each of the `DURATION_BUCKETS`.
+    duration_buckets: [AtomicU64; DURATION_BUCKETS.len()],
+    duration_nanos: AtomicU64,
+}
+
+impl Metrics {
+    /// Count an error.
+    pub fn record_error(&self, class: ErrorClass) {
+        self.errors[class as usize].fetch_add(1, Ordering::Relaxed);
+    }
+
+    /// Count a program that executed `steps` instructions in `duration`, and the error that
+    /// stopped it, if there was one.
+    pub fn record_run(&self, steps: u64, duration: Duration, error: Option<ErrorClass>) {
+        self.programs_run.fetch_add(1, Ordering::Relaxed);
+        self.instructions.fetch_add(steps, Ordering::Relaxed);
+        if let Some(class) = error {
+            self.record_error(class);
+        }
+        let seconds = duration.as_secs_f64();
+        for (bound, count) in DURATION_BUCKETS.iter().zip(&self.duration_buckets) {
+            if seconds <= *bound {
+                count.fetch_add(1, Ordering::Relaxed);
+            }
+        }
+        let nanos = u64::try_from(duration.as_nanos()).unwrap_or(u64::MAX);
+        self.duration_nanos.fetch_add(nanos, Ordering::Relaxed);
+    }
+
+    /// Write the metrics in the Prometheus text format.
+    pub fn render(&self) -> String {
+        let load = |counter: &AtomicU64| counter.load(Ordering::Relaxed);
+        let mut out = String::new();
+        let runs = load(&self.programs_run);
+        let _ = writeln!(
+            out,
+            "# HELP bft_programs_run_total Programs run.\n\
+             # TYPE bft_programs_run_total counter\n\
+             bft_programs_run_total {runs}"
+        );
+        let _ = writeln!(
+            out,
+            "# HELP bft_instructions_executed_total Instructions executed by all programs.\n\
+             # TYPE bft_instructions_executed_total counter\n\
+             bft_instructions_executed_total {}",
+            load(&self.instructions)
+        );
+        out.push_str(
+            "# HELP bft_errors_total Errors, by class.\n# TYPE bft_errors_total counter\n",
+        );
+        for (class, count) in ErrorClass::ALL.iter().zip(&self.errors) {
+            let _ = writeln!(
+                out,
+                "bft_errors_total{{class=\"{}\"}} {}",
+                class.label(),
+                load(count)
+            );
+        }
+        out.push_str(
+            "# HELP bft_run_duration_seconds Time taken to run each program.\n\
+             # TYPE bft_run_duration_seconds histogram\n",
+        );
+        for (bound, count) in DURATION_BUCKETS.iter().zip(&self.duration_buckets) {
+            let _ = writeln!(
+                out,
+                "bft_run_duration_seconds_bucket{{le=\"{bound}\"}} {}",
+                load(count)
+            );
+        }
+        let _ = writeln!(
+            out,
+            "bft_run_duration_seconds_bucket{{le=\"+Inf\"}} {runs}\n\
+             bft_run_duration_seconds_sum {}\n\
+             bft_run_duration_seconds_count {runs}",
+            Duration::from_nanos(load(&self.duration_nanos)).as_secs_f64()
+        );
+        out
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn rendering() {
+        let metrics = Metrics::default();
+        metrics.record_run(10, Duration::from_millis(2), None);
+        metrics.record_run(5, Duration::from_millis(200), Some(ErrorClass::StepLimit));
+        metrics.record_error(ErrorClass::BadRequest);
+        let text = metrics.render();
+        for line in [
+            "bft_programs_run_total 2",
+            "bft_instructions_executed_total 15",
+            "bft_errors_total{class=\"bad_request\"} 1",
+            "bft_errors_total{class=\"step_limit\"} 1",
+            "bft_errors_total{class=\"runtime\"} 0",
+            "bft_run_duration_seconds_bucket{le=\"0.001\"} 0",
+            "bft_run_duration_seconds_bucket{le=\"0.005\"} 1",
+            "bft_run_duration_seconds_bucket{le=\"0.5\"} 2",
+            "bft_run_duration_seconds_bucket{le=\"+Inf\"} 2",
+            "bft_run_duration_seconds_sum 0.202",
+            "bft_run_duration_seconds_count 2",
+        ] {
+            assert!(
+                text.lines().any(|l| l == line),
+                "{line} missing from:\n{text}"
+            );
+        }
+    }
+}
@@ -0,0 +1,59 @@
+//! Shrinking programs to their smallest equivalent source.
+
+use bft_types::{Alphabet, BFprogram, Instruction};
+
+/// The instruction that undoes `inst`, if there is one.
+fn inverse(inst: Instruction) -> Option<Instruction> {
+    match inst {
+        Instruction::Increment => Some(Instruction::Decrement),
+        Instruction::Decrement => Some(Instruction::Increment),
+        Instruction::MoveLeft => Some(Instruction::MoveRight),
+        Instruction::MoveRight => Some(Instruction::MoveLeft),
+        _ => None,
+    }
+}
+
+/// Write `program` without comments or whitespace, removing any pairs of instructions that
+/// cancel each other out, like `+-` or `<>`.
+///
+/// The only change in behaviour is that a `<>` pair at the left edge of the tape no longer
+/// moves the head off the tape.
+#[must_use]
+pub fn minify(program: &BFprogram) -> String {
+    let mut kept: Vec<Instruction> = Vec::new();
+    for inst in program.instructions() {
+        let inst = *inst.instruction();
+        if kept.last().is_some_and(|last| inverse(*last) == Some(inst)) {
+            kept.pop();
+        } else {
+            kept.push(inst);
+        }
+    }
+    let alphabet = Alphabet::default();
+    kept.into_iter()
+        .filter_map(|inst| alphabet.token_for(inst))
+        .map(|token| String::from_utf8_lossy(token).into_owned())
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn min(code: &str) -> String {
+        minify(&BFprogram::new("mod.test", code.as_bytes()))
+    }
+
+    #[test]
+    fn stripping_comments() {
+        assert_eq!(min("++ add two\n[ loop\n -\n]"), "++[-]");
+    }
+
+    #[test]
+    fn cancelling() {
+        assert_eq!(min("++-"), "+");
+        assert_eq!(min("+>+-<-"), "");
+        assert_eq!(min("<><+>"), "<+>");
+        assert_eq!(min("+[-]-"), "+[-]-");
+    }
+}
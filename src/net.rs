@@ -0,0 +1,108 @@
+//! Connecting a program's input and output to a TCP connection, so it can act as a network
+//! service or client.
+
+use std::io;
+use std::net::{TcpListener, TcpStream};
+
+/// Which end of a TCP connection a program is.
+#[derive(Debug, PartialEq, Eq)]
+pub enum Net {
+    /// Wait for a client to connect to this address.
+    Listen(String),
+
+    /// Connect to a server at this address.
+    Connect(String),
+}
+
+/// Fill in the host of an address like `:7000`, which only gives a port.
+fn with_host(addr: &str, host: &str) -> String {
+    if addr.starts_with(':') {
+        format!("{host}{addr}")
+    } else {
+        addr.to_string()
+    }
+}
+
+impl Net {
+    /// Parse the values of `--net`: a mode, `listen` or `connect`, and an address.
+    ///
+    /// # Errors
+    /// Fails if the mode isn't recognised.
+    pub fn parse(args: &[String]) -> Result<Self, String> {
+        match args {
+            [mode, addr] if mode == "listen" => Ok(Net::Listen(with_host(addr, "0.0.0.0"))),
+            [mode, addr] if mode == "connect" => Ok(Net::Connect(with_host(addr, "127.0.0.1"))),
+            [mode, _] => Err(format!(
+                "Unknown network mode '{mode}', expected 'listen' or 'connect'"
+            )),
+            _ => Err("--net needs a mode and an address".to_string()),
+        }
+    }
+
+    /// Open the connection, waiting for a client to connect when listening.
+    ///
+    /// # Errors
+    /// Fails if the address can't be listened on or connected to.
+    pub fn open(&self) -> io::Result<TcpStream> {
+        match self {
+            Net::Listen(addr) => {
+                let listener = TcpListener::bind(addr)?;
+                eprintln!("Listening on {}", listener.local_addr()?);
+                let (stream, peer) = listener.accept()?;
+                eprintln!("Connection from {peer}");
+                Ok(stream)
+            }
+            Net::Connect(addr) => TcpStream::connect(addr),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::io::{BufReader, Read, Write};
+    use std::net::Shutdown;
+
+    use bft_interp::BFVM;
+    use bft_types::BFprogram;
+
+    fn args(mode: &str, addr: &str) -> Vec<String> {
+        vec![mode.to_string(), addr.to_string()]
+    }
+
+    #[test]
+    fn parsing() {
+        assert_eq!(
+            Net::parse(&args("listen", ":7000")),
+            Ok(Net::Listen("0.0.0.0:7000".to_string()))
+        );
+        assert_eq!(
+            Net::parse(&args("connect", "example.com:80")),
+            Ok(Net::Connect("example.com:80".to_string()))
+        );
+        assert!(Net::parse(&args("serve", ":80")).is_err());
+    }
+
+    #[test]
+    fn echoing_over_a_connection() {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let addr = listener.local_addr().unwrap().to_string();
+        let server = std::thread::spawn(move || {
+            let (mut client, _) = listener.accept().unwrap();
+            client.write_all(b"hi").unwrap();
+            client.shutdown(Shutdown::Write).unwrap();
+            let mut echoed = Vec::new();
+            client.read_to_end(&mut echoed).unwrap();
+            echoed
+        });
+
+        let stream = Net::parse(&args("connect", &addr)).unwrap().open().unwrap();
+        let mut program = BFprogram::new("net.test", b",.,.");
+        program.validate_brackets().unwrap();
+        let mut vm: BFVM<u8> = BFVM::new(None, false);
+        vm.run(&program, &mut BufReader::new(&stream), &mut &stream)
+            .unwrap();
+        stream.shutdown(Shutdown::Write).unwrap();
+        assert_eq!(server.join().unwrap(), b"hi");
+    }
+}
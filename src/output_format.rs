@@ -0,0 +1,210 @@
+//! Showing a program's output in a form that is safe to print to a terminal, for programs that
+//! write binary data.
+
+use std::fmt::Write as _;
+use std::io;
+use std::io::Write;
+
+/// How to show a program's output.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, clap::ValueEnum)]
+pub enum OutputFormat {
+    /// The bytes exactly as the program wrote them.
+    #[default]
+    Raw,
+
+    /// A hex dump, with the offset of each line of 16 bytes, the bytes in hex, and the printable
+    /// ones as characters.
+    Hex,
+
+    /// Printable characters and newlines as they are, and other bytes as `\xNN`. Backslashes
+    /// are doubled.
+    Escaped,
+
+    /// Text decoded from UTF-8, holding back the bytes of each character until it's complete,
+    /// and showing invalid bytes as the replacement character `\u{FFFD}`.
+    Utf8,
+}
+
+/// Number of bytes on each line of a hex dump.
+const HEX_LINE: usize = 16;
+
+/// Output shown in one of the [`OutputFormat`]s.
+pub struct Formatted<W: Write> {
+    inner: W,
+    format: OutputFormat,
+
+    /// Offset of the first byte in `pending`.
+    offset: usize,
+
+    /// Bytes for the current line of a hex dump, or of an incomplete UTF-8 character.
+    pending: Vec<u8>,
+}
+
+impl<W: Write> Formatted<W> {
+    /// Write output to `inner` in `format`.
+    pub fn new(inner: W, format: OutputFormat) -> Self {
+        Formatted {
+            inner,
+            format,
+            offset: 0,
+            pending: Vec::with_capacity(HEX_LINE),
+        }
+    }
+
+    /// Write the bytes for the current line of a hex dump, if there are any.
+    fn write_hex_line(&mut self) -> io::Result<()> {
+        if self.pending.is_empty() {
+            return Ok(());
+        }
+        let mut line = format!("{:08x} ", self.offset);
+        for (idx, byte) in self.pending.iter().enumerate() {
+            if idx % 8 == 0 {
+                line.push(' ');
+            }
+            let _ = write!(line, "{byte:02x} ");
+        }
+        let missing = HEX_LINE - self.pending.len();
+        line.extend(std::iter::repeat_n(
+            ' ',
+            missing * 3 + usize::from(missing >= 8),
+        ));
+        line.push_str(" |");
+        line.extend(self.pending.iter().map(|byte| {
+            if byte.is_ascii_graphic() || *byte == b' ' {
+                char::from(*byte)
+            } else {
+                '.'
+            }
+        }));
+        line.push_str("|\n");
+        self.offset += self.pending.len();
+        self.pending.clear();
+        self.inner.write_all(line.as_bytes())
+    }
+
+    /// Write the text decoded from the pending bytes, keeping the bytes of an incomplete character
+    /// at the end unless the output is `ending`.
+    fn write_utf8(&mut self, ending: bool) -> io::Result<()> {
+        let mut start = 0;
+        loop {
+            match std::str::from_utf8(&self.pending[start..]) {
+                Ok(text) => {
+                    self.inner.write_all(text.as_bytes())?;
+                    start = self.pending.len();
+                    break;
+                }
+                Err(err) => {
+                    let valid = start + err.valid_up_to();
+                    self.inner.write_all(&self.pending[start..valid])?;
+                    match err.error_len() {
+                        Some(len) => start = valid + len,
+                        None if ending => start = self.pending.len(),
+                        None => {
+                            start = valid;
+                            break;
+                        }
+                    }
+                    self.inner
+                        .write_all(char::REPLACEMENT_CHARACTER.to_string().as_bytes())?;
+                }
+            }
+        }
+        self.pending.drain(..start);
+        Ok(())
+    }
+
+    /// Write anything held back, as the output is ending or being flushed.
+    fn write_pending(&mut self) -> io::Result<()> {
+        match self.format {
+            OutputFormat::Hex => self.write_hex_line(),
+            OutputFormat::Utf8 => self.write_utf8(true),
+            OutputFormat::Raw | OutputFormat::Escaped => Ok(()),
+        }
+    }
+}
+
+impl<W: Write> Write for Formatted<W> {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        match self.format {
+            OutputFormat::Raw => return self.inner.write(buf),
+            OutputFormat::Hex => {
+                for byte in buf {
+                    self.pending.push(*byte);
+                    if self.pending.len() == HEX_LINE {
+                        self.write_hex_line()?;
+                    }
+                }
+            }
+            OutputFormat::Escaped => {
+                let mut escaped = Vec::with_capacity(buf.len());
+                for byte in buf {
+                    match byte {
+                        b'\\' => escaped.extend_from_slice(b"\\\\"),
+                        b'\n' | b' '..=b'~' => escaped.push(*byte),
+                        _ => escaped.extend_from_slice(format!("\\x{byte:02x}").as_bytes()),
+                    }
+                }
+                self.inner.write_all(&escaped)?;
+            }
+            OutputFormat::Utf8 => {
+                self.pending.extend_from_slice(buf);
+                self.write_utf8(false)?;
+            }
+        }
+        Ok(buf.len())
+    }
+
+    /// Flush the output. In a hex dump, this ends the current line early, and in UTF-8 an
+    /// incomplete character is shown as invalid.
+    fn flush(&mut self) -> io::Result<()> {
+        self.write_pending()?;
+        self.inner.flush()
+    }
+}
+
+impl<W: Write> Drop for Formatted<W> {
+    fn drop(&mut self) {
+        // Errors can't be reported here, but the output still ends with anything held back.
+        let _ = self.write_pending();
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn format(format: OutputFormat, data: &[u8]) -> String {
+        let mut out = Vec::new();
+        let mut formatted = Formatted::new(&mut out, format);
+        formatted.write_all(data).unwrap();
+        drop(formatted);
+        String::from_utf8(out).unwrap()
+    }
+
+    #[test]
+    fn utf8() {
+        let mut out = Vec::new();
+        let mut formatted = Formatted::new(&mut out, OutputFormat::Utf8);
+        for byte in "añ€".bytes() {
+            formatted.write_all(&[byte]).unwrap();
+        }
+        formatted.write_all(b"\xffb\xe2\x82").unwrap();
+        assert_eq!(formatted.pending, b"\xe2\x82");
+        drop(formatted);
+        assert_eq!(String::from_utf8(out).unwrap(), "añ€\u{FFFD}b\u{FFFD}");
+    }
+
+    #[test]
+    fn formats() {
+        assert_eq!(format(OutputFormat::Raw, b"a\x01"), "a\x01");
+        assert_eq!(
+            format(OutputFormat::Escaped, b"a\\b\n\x00\xff"),
+            "a\\\\b\n\\x00\\xff"
+        );
+        assert_eq!(
+            format(OutputFormat::Hex, b"Hello, world!\n\x00\x01\x02"),
+            "00000000  48 65 6c 6c 6f 2c 20 77  6f 72 6c 64 21 0a 00 01  |Hello, world!...|\n\
+             00000010  02                                                |.|\n"
+        );
+    }
+}
@@ -0,0 +1,115 @@
+//! Recording where each byte of a program's output came from, so that a wrong byte can be traced
+//! back to the `.` that wrote it.
+//!
+//! The record is a JSON array with an entry for each byte written, in order:
+//!
+//! ```json
+//! [
+//! {"byte":72,"column":24,"index":0,"line":1,"offset":23,"step":115}
+//! ]
+//! ```
+//!
+//! where `index` is the position of the byte in the output, `step` is the number of instructions
+//! executed up to and including the `.` that wrote it, and `line`, `column` and `offset` give
+//! where that `.` is in the source.
+
+use std::error::Error;
+use std::io;
+use std::io::{Read, Write};
+
+use serde_json::json;
+
+use bft_interp::{StepOutcome, BFVM};
+use bft_types::{BFprogram, Instruction};
+
+/// Run `program` until it stops, writing an entry to `positions` for each byte of output.
+fn record<R: Read, W: Write, P: Write>(
+    program: &BFprogram,
+    vm: &mut BFVM<u8>,
+    input: &mut R,
+    output: &mut W,
+    positions: &mut P,
+) -> Result<(), Box<dyn Error>> {
+    let mut index = 0u64;
+    let mut outcome = StepOutcome::Running;
+    while outcome == StepOutcome::Running {
+        outcome = vm.step(program, input, output)?;
+        let Some(pc) = vm.last_executed() else {
+            break;
+        };
+        let inst = &program.instructions()[pc];
+        if *inst.instruction() == Instruction::Output && !vm.output_closed() {
+            let entry = json!({
+                "index": index,
+                "byte": vm.tape()[vm.head()],
+                "step": vm.executed(),
+                "line": inst.line_number(),
+                "column": inst.char_number(),
+                "offset": inst.offset(),
+            });
+            let separator = if index == 0 { "\n" } else { ",\n" };
+            write!(positions, "{separator}{entry}")?;
+            index += 1;
+        }
+    }
+    Ok(())
+}
+
+/// Run `program` to completion, writing the source location of each byte of output to
+/// `positions`.
+///
+/// # Errors
+/// Fails if the program fails, or if writing the positions fails.
+pub fn run_recorded<R: Read, W: Write, P: Write>(
+    program: &BFprogram,
+    vm: &mut BFVM<u8>,
+    input: &mut R,
+    output: &mut W,
+    positions: &mut P,
+) -> Result<(), Box<dyn Error>> {
+    write!(positions, "[")?;
+    let result = record(program, vm, input, output, positions);
+    // The positions are finished even if the program failed, as they lead up to the failure.
+    writeln!(positions, "\n]")?;
+    positions.flush()?;
+    result?;
+    // Output closed by whatever is reading it stops the program quietly, as with `BFVM::run`.
+    match output.flush() {
+        Err(err) if err.kind() != io::ErrorKind::BrokenPipe => Err(err.into()),
+        _ => Ok(()),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::Value;
+
+    #[test]
+    fn recording() {
+        let mut program = BFprogram::new("a.b", b"++++[>++++<-]>.\n,.<<");
+        program.validate_brackets().unwrap();
+        let mut vm = BFVM::new(None, false);
+        let mut output = Vec::new();
+        let mut positions = Vec::new();
+        let result = run_recorded(
+            &program,
+            &mut vm,
+            &mut &b"A"[..],
+            &mut output,
+            &mut positions,
+        );
+        // The head moves off the tape at the end, after the output has been recorded.
+        assert!(result.is_err());
+        assert_eq!(output, b"\x10A");
+
+        let positions: Value = serde_json::from_slice(&positions).unwrap();
+        assert_eq!(
+            positions,
+            json!([
+                {"index": 0, "byte": 16, "step": 39, "line": 1, "column": 15, "offset": 14},
+                {"index": 1, "byte": 65, "step": 41, "line": 2, "column": 2, "offset": 17},
+            ])
+        );
+    }
+}
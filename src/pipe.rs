@@ -0,0 +1,254 @@
+//! Running programs as a pipeline, with the output of each program feeding the input of the
+//! next, like a shell pipeline but without starting a process for each one.
+
+use std::error::Error;
+use std::io;
+use std::io::{LineWriter, Read, Write};
+use std::num::NonZeroUsize;
+use std::sync::mpsc::{sync_channel, Receiver, SyncSender};
+use std::thread;
+
+use bft_interp::{StepOutcome, BFVM};
+use bft_types::BFprogram;
+
+/// How many chunks of output can be waiting for the next program before the one writing them
+/// has to wait.
+const CHANNEL_CAPACITY: usize = 64;
+
+/// Limits applied to each program in the pipeline.
+#[derive(Clone, Copy, Debug)]
+pub struct Limits {
+    /// Number of cells on each program's tape.
+    pub cells: Option<NonZeroUsize>,
+
+    /// Allow each program's tape to grow.
+    pub extensible: bool,
+
+    /// Maximum number of instructions each program may execute.
+    pub max_steps: Option<u64>,
+}
+
+/// Reads the chunks of output sent by the previous program in the pipeline.
+struct ChannelReader {
+    receiver: Receiver<Vec<u8>>,
+    chunk: Vec<u8>,
+    pos: usize,
+}
+
+impl Read for ChannelReader {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        while self.pos == self.chunk.len() {
+            match self.receiver.recv() {
+                Ok(chunk) => {
+                    self.chunk = chunk;
+                    self.pos = 0;
+                }
+                // The previous program has finished.
+                Err(_) => return Ok(0),
+            }
+        }
+        let count = buf.len().min(self.chunk.len() - self.pos);
+        buf[..count].copy_from_slice(&self.chunk[self.pos..self.pos + count]);
+        self.pos += count;
+        Ok(count)
+    }
+}
+
+/// Sends output to the next program in the pipeline.
+struct ChannelWriter {
+    sender: SyncSender<Vec<u8>>,
+}
+
+impl Write for ChannelWriter {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        self.sender
+            .send(buf.to_vec())
+            .map_err(|_| io::Error::from(io::ErrorKind::BrokenPipe))?;
+        Ok(buf.len())
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        Ok(())
+    }
+}
+
+/// Run a single program in the pipeline, returning its exit status.
+fn run_stage<R: Read, W: Write>(
+    program: &BFprogram,
+    limits: Limits,
+    mut input: R,
+    mut output: W,
+) -> Result<u8, String> {
+    let mut vm: BFVM<u8> = BFVM::new(limits.cells, limits.extensible);
+    let mut steps = 0;
+    loop {
+        match vm.step(program, &mut input, &mut output) {
+            Ok(StepOutcome::Running) => {}
+            Ok(StepOutcome::Finished) => break,
+            // Like a process in a shell pipeline, stop quietly once nothing is reading.
+            Ok(StepOutcome::BrokenPipe) => return Ok(0),
+            Err(err) => return Err(err.to_string()),
+        }
+        steps += 1;
+        if limits.max_steps.is_some_and(|max| steps >= max) {
+            return Err(format!("step limit of {steps} exceeded"));
+        }
+    }
+    match output.flush() {
+        Err(err) if err.kind() != io::ErrorKind::BrokenPipe => Err(err.to_string()),
+        _ => Ok(vm.exit_status().unwrap_or(0)),
+    }
+}
+
+/// Run each program in `rest` on its own thread, connected by channels, and `last` on this one.
+fn run_concurrently<R: Read + Send, W: Write + Send>(
+    rest: &[BFprogram],
+    last: &BFprogram,
+    limits: Limits,
+    input: R,
+    output: W,
+) -> Vec<Result<u8, String>> {
+    thread::scope(|scope| {
+        let mut handles = Vec::new();
+        let mut input: Box<dyn Read + Send> = Box::new(input);
+        for program in rest {
+            let (sender, receiver) = sync_channel(CHANNEL_CAPACITY);
+            let next = ChannelReader {
+                receiver,
+                chunk: Vec::new(),
+                pos: 0,
+            };
+            let stage_input = std::mem::replace(&mut input, Box::new(next));
+            let stage_output = LineWriter::new(ChannelWriter { sender });
+            handles
+                .push(scope.spawn(move || run_stage(program, limits, stage_input, stage_output)));
+        }
+        let status = run_stage(last, limits, input, output);
+        let mut results: Vec<_> = handles
+            .into_iter()
+            .map(|handle| handle.join().expect("Pipeline stages shouldn't panic"))
+            .collect();
+        results.push(status);
+        results
+    })
+}
+
+/// Run each program in `rest` to completion in turn, buffering its output as the input of the
+/// next, then `last`. Used where threads aren't available, such as on WASI.
+fn run_sequentially<R: Read, W: Write>(
+    rest: &[BFprogram],
+    last: &BFprogram,
+    limits: Limits,
+    input: R,
+    output: W,
+) -> Vec<Result<u8, String>> {
+    let mut results = Vec::new();
+    let mut input: Box<dyn Read + '_> = Box::new(input);
+    for program in rest {
+        let mut buffer = Vec::new();
+        results.push(run_stage(program, limits, input, &mut buffer));
+        input = Box::new(io::Cursor::new(buffer));
+    }
+    results.push(run_stage(last, limits, input, output));
+    results
+}
+
+/// Run `programs` as a pipeline, with the first reading `input` and the last writing `output`.
+/// Each program runs on its own thread, with its own tape. On WASI, which has no threads, the
+/// programs run one after another instead, so a program that never stops writing won't end.
+///
+/// Returns the exit status of the last program.
+///
+/// # Errors
+/// Fails if any of the programs fail, describing each failure.
+pub fn run_pipeline<R: Read + Send, W: Write + Send>(
+    programs: &[BFprogram],
+    limits: Limits,
+    input: R,
+    output: W,
+) -> Result<u8, Box<dyn Error>> {
+    let Some((last, rest)) = programs.split_last() else {
+        return Ok(0);
+    };
+    let results = if cfg!(target_os = "wasi") {
+        run_sequentially(rest, last, limits, input, output)
+    } else {
+        run_concurrently(rest, last, limits, input, output)
+    };
+
+    let failures: Vec<String> = results
+        .iter()
+        .zip(programs)
+        .filter_map(|(result, program)| {
+            let err = result.as_ref().err()?;
+            Some(format!("{}: {err}", program.source().display()))
+        })
+        .collect();
+    if failures.is_empty() {
+        Ok(*results
+            .last()
+            .and_then(|result| result.as_ref().ok())
+            .unwrap_or(&0))
+    } else {
+        Err(failures.join("\n").into())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const LIMITS: Limits = Limits {
+        cells: None,
+        extensible: false,
+        max_steps: Some(10_000),
+    };
+
+    fn program(name: &str, code: &str) -> BFprogram {
+        let mut program = BFprogram::new(name, code.as_bytes());
+        program.validate_brackets().unwrap();
+        program
+    }
+
+    #[test]
+    fn chaining() {
+        // Add one to each byte, then swap adjacent pairs.
+        let programs = [program("inc.b", ",[+.,]"), program("swap.b", ",[>,.<.,]")];
+        let mut output = Vec::new();
+        // At the end of the input the cell is left unchanged, so each program ends its input
+        // with a zero byte.
+        let status = run_pipeline(&programs, LIMITS, &b"abcd\xff"[..], &mut output).unwrap();
+        assert_eq!(status, 0);
+        assert_eq!(output, b"cbed");
+    }
+
+    #[test]
+    fn failures() {
+        let programs = [program("a.b", "+[]"), program("b.b", ",.")];
+        let err = run_pipeline(&programs, LIMITS, io::empty(), io::sink()).unwrap_err();
+        assert_eq!(err.to_string(), "a.b: step limit of 10000 exceeded");
+    }
+
+    #[test]
+    fn sequential() {
+        let programs = [program("inc.b", ",[+.,]"), program("swap.b", ",[>,.<.,]")];
+        let (last, rest) = programs.split_last().unwrap();
+        let mut output = Vec::new();
+        let results = run_sequentially(rest, last, LIMITS, &b"abcd\xff"[..], &mut output);
+        assert_eq!(results, [Ok(0), Ok(0)]);
+        assert_eq!(output, b"cbed");
+    }
+
+    #[test]
+    fn downstream_stopping_early() {
+        // The first program writes forever, but stops once the second has finished reading.
+        let programs = [program("yes.b", "+[.]"), program("head.b", ",.,.")];
+        let limits = Limits {
+            max_steps: None,
+            ..LIMITS
+        };
+        let mut output = Vec::new();
+        run_pipeline(&programs, limits, io::empty(), &mut output).unwrap();
+        assert_eq!(output, b"\x01\x01");
+    }
+}
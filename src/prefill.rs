@@ -0,0 +1,106 @@
+//! Data placed on the tape before a program runs, so that it can be given parameters without
+//! reading them from its input.
+//!
+//! The data is laid out in blocks. Each block starts with a cell holding the number of bytes that
+//! follow in the block, and then each value, followed by a NUL byte. With `--args "foo bar"`,
+//! the arguments are split on whitespace, so the tape starts:
+//!
+//! ```text
+//! cell:   0  1  2  3  4  5  6  7  8
+//! value:  8  f  o  o  0  b  a  r  0
+//! ```
+//!
+//! The values of the variables named with `--env` form a block after the arguments, or at cell
+//! 0 without `--args`, in the order they were named. A variable that isn't set gets an empty
+//! value. The head starts on cell 0 as usual. As the length is held in a single cell, the values
+//! in a block can take at most 255 bytes.
+
+use std::env;
+
+/// A block holding `values`, which are described as `what` in errors.
+///
+/// # Errors
+/// Fails if the values take more than 255 bytes.
+fn block<'a, I>(what: &str, values: I) -> Result<Vec<u8>, String>
+where
+    I: IntoIterator<Item = &'a [u8]>,
+{
+    let mut cells = vec![0];
+    for value in values {
+        cells.extend_from_slice(value);
+        cells.push(0);
+    }
+    cells[0] = u8::try_from(cells.len() - 1).map_err(|_| {
+        format!(
+            "The {what} take {} bytes, but at most 255 fit",
+            cells.len() - 1
+        )
+    })?;
+    Ok(cells)
+}
+
+/// The block holding `args`.
+///
+/// # Errors
+/// Fails if the arguments take more than 255 bytes.
+pub fn args(args: &str) -> Result<Vec<u8>, String> {
+    block("arguments", args.split_whitespace().map(str::as_bytes))
+}
+
+/// The block holding the values of the environment variables called `names`.
+///
+/// # Errors
+/// Fails if the values take more than 255 bytes.
+pub fn env(names: &[String]) -> Result<Vec<u8>, String> {
+    let values: Vec<_> = names
+        .iter()
+        .map(|name| env::var_os(name).unwrap_or_default())
+        .collect();
+    block(
+        "environment variables",
+        values.iter().map(|value| value.as_encoded_bytes()),
+    )
+}
+
+/// The cells to start the tape with, holding `args` if they're given, and then the values of the
+/// environment variables called `env_names`, if there are any.
+///
+/// # Errors
+/// Fails if either block would take more than 255 bytes.
+pub fn cells(args: Option<&str>, env_names: &[String]) -> Result<Vec<u8>, String> {
+    let mut cells = match args {
+        Some(args) => self::args(args)?,
+        None => Vec::new(),
+    };
+    if !env_names.is_empty() {
+        cells.extend(env(env_names)?);
+    }
+    Ok(cells)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn encoding_args() {
+        assert_eq!(args("foo  bar").unwrap(), b"\x08foo\0bar\0");
+        assert_eq!(args("").unwrap(), b"\0");
+        assert_eq!(
+            args(&"x".repeat(255)).unwrap_err(),
+            "The arguments take 256 bytes, but at most 255 fit"
+        );
+    }
+
+    #[test]
+    fn environment_after_args() {
+        // Cargo sets this for the tests it runs.
+        let names = [
+            String::from("CARGO_PKG_NAME"),
+            String::from("BFT_TEST_UNSET_VARIABLE"),
+        ];
+        let cells = cells(Some("a"), &names).unwrap();
+        assert_eq!(cells, b"\x02a\0\x05bft\0\0");
+        assert!(self::cells(None, &[]).unwrap().is_empty());
+    }
+}
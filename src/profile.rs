@@ -0,0 +1,151 @@
+//! Profiling a run by counting the instructions executed on each line of the program, for
+//! `--annotate`.
+//!
+//! The report is the program's source with the count for each line, and its share of every
+//! instruction executed, in the margin, so that hot spots stand out:
+//!
+//! ```text
+//! count      %
+//!     5   2.6% | ++++[>++++<-]   set up the counter
+//!   180  94.2% | >[>+<-]
+//!                print it
+//!     6   3.1% | >.
+//! ```
+//!
+//! Lines where nothing was executed have an empty margin.
+
+use std::error::Error;
+use std::io;
+use std::io::{Read, Write};
+
+use bft_interp::{StepOutcome, BFVM};
+use bft_types::BFprogram;
+
+/// Run `program` until it stops, adding one to `counts` for each instruction executed.
+fn count<R: Read, W: Write>(
+    program: &BFprogram,
+    vm: &mut BFVM<u8>,
+    input: &mut R,
+    output: &mut W,
+    counts: &mut [u64],
+) -> Result<(), Box<dyn Error>> {
+    let mut outcome = StepOutcome::Running;
+    while outcome == StepOutcome::Running {
+        outcome = vm.step(program, input, output)?;
+        let Some(pc) = vm.last_executed() else {
+            break;
+        };
+        counts[pc] += 1;
+    }
+    Ok(())
+}
+
+/// Run `program` to completion, returning how many times each of its instructions was executed
+/// along with how the run ended. The counts are returned even if the program failed, as they
+/// lead up to the failure.
+pub fn run_profiled<R: Read, W: Write>(
+    program: &BFprogram,
+    vm: &mut BFVM<u8>,
+    input: &mut R,
+    output: &mut W,
+) -> (Vec<u64>, Result<(), Box<dyn Error>>) {
+    let mut counts = vec![0; program.instructions().len()];
+    let result = count(program, vm, input, output, &mut counts).and_then(|()| {
+        // Output closed by whatever is reading it stops the program quietly, as with `BFVM::run`.
+        match output.flush() {
+            Err(err) if err.kind() != io::ErrorKind::BrokenPipe => Err(err.into()),
+            _ => Ok(()),
+        }
+    });
+    (counts, result)
+}
+
+/// Write the `source` of `program`, annotated with the instructions executed on each line as
+/// given by `counts`, to `out`.
+///
+/// # Errors
+/// Fails if the report can't be written.
+pub fn annotate<W: Write>(
+    program: &BFprogram,
+    source: &[u8],
+    counts: &[u64],
+    out: &mut W,
+) -> io::Result<()> {
+    let lines: Vec<_> = source
+        .split(|byte| *byte == b'\n')
+        .map(|line| line.strip_suffix(b"\r").unwrap_or(line))
+        .collect();
+    // A file ending in a newline has no line after it.
+    let lines = match lines.split_last() {
+        Some(([], rest)) => rest,
+        _ => &lines[..],
+    };
+    let mut per_line = vec![0u64; lines.len()];
+    for (inst, count) in program.instructions().iter().zip(counts) {
+        if let Some(line) = per_line.get_mut(inst.line_number() - 1) {
+            *line += count;
+        }
+    }
+    let total = per_line.iter().sum::<u64>().max(1);
+    let width = per_line
+        .iter()
+        .map(|count| count.to_string().len())
+        .max()
+        .unwrap_or(0)
+        .max("count".len());
+    writeln!(out, "{:>width$}      %", "count")?;
+    for (line, count) in lines.iter().zip(per_line) {
+        let line = String::from_utf8_lossy(line);
+        let annotated = if count == 0 {
+            format!("{:width$}          {line}", "")
+        } else {
+            #[allow(clippy::cast_precision_loss)]
+            let percent = count as f64 * 100.0 / total as f64;
+            format!("{count:>width$} {percent:5.1}% | {line}")
+        };
+        writeln!(out, "{}", annotated.trim_end())?;
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn annotating() {
+        let source = b"++++[>++++<-]   set up\r\n\njust a comment\n>.\n";
+        let mut program = BFprogram::new("a.b", source);
+        program.validate_brackets().unwrap();
+        let mut vm = BFVM::new(None, false);
+        let mut output = Vec::new();
+        let (counts, result) = run_profiled(&program, &mut vm, &mut io::empty(), &mut output);
+        result.unwrap();
+        assert_eq!(output, [16]);
+        assert_eq!(counts.iter().sum::<u64>(), vm.executed());
+
+        let mut out = Vec::new();
+        annotate(&program, source, &counts, &mut out).unwrap();
+        assert_eq!(
+            String::from_utf8(out).unwrap(),
+            [
+                "count      %",
+                "   37  94.9% | ++++[>++++<-]   set up",
+                "",
+                "               just a comment",
+                "    2   5.1% | >.\n",
+            ]
+            .join("\n")
+        );
+    }
+
+    #[test]
+    fn failing_runs_are_counted() {
+        let mut program = BFprogram::new("a.b", b"+<");
+        program.validate_brackets().unwrap();
+        let mut vm = BFVM::new(None, false);
+        let (counts, result) = run_profiled(&program, &mut vm, &mut io::empty(), &mut io::sink());
+        assert!(result.is_err());
+        assert_eq!(counts, [1, 0]);
+    }
+}
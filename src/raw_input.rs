@@ -0,0 +1,229 @@
+//! Reading a terminal a key at a time, for interactive programs that respond to each key rather
+//! than to each line.
+//!
+//! In raw mode the terminal gives each key as soon as it's pressed, without waiting for Enter
+//! and without echoing it. Interrupting the program with Ctrl-C still works, and on Unix puts the
+//! terminal back the way it was before the program dies. As the terminal no
+//! longer ends the input itself, the usual end-of-input key for the platform is read as the end
+//! of the input: Ctrl-D on Unix and Ctrl-Z on Windows. Enter is read as `\n` on both.
+//!
+//! Raw mode only applies when stdin is a terminal. Input from a file or a pipe is read as usual.
+
+use std::io;
+use std::io::{IsTerminal, Read};
+
+/// The key read as the end of the input in raw mode.
+#[cfg(not(windows))]
+pub const EOF_KEY: u8 = 0x04;
+
+/// The key read as the end of the input in raw mode.
+#[cfg(windows)]
+pub const EOF_KEY: u8 = 0x1a;
+
+#[cfg(unix)]
+mod platform {
+    use std::io;
+    use std::mem::MaybeUninit;
+    use std::sync::OnceLock;
+
+    /// The terminal's settings from before raw mode, along with the handler for `SIGINT` that
+    /// was replaced.
+    pub struct Saved(libc::termios, libc::sighandler_t);
+
+    /// The terminal's settings from before raw mode, for the handler for `SIGINT`, which can't
+    /// reach the ones in [`Saved`].
+    static INTERRUPT_SETTINGS: OnceLock<libc::termios> = OnceLock::new();
+
+    /// Put the terminal back when the program is interrupted, then die of the signal as if this
+    /// handler hadn't been installed, so that the shell sees the program was interrupted.
+    extern "C" fn interrupted(signal: libc::c_int) {
+        // SAFETY: `tcsetattr`, `signal` and `raise` are async-signal-safe, and the settings were
+        // filled in by `tcgetattr` before the handler was installed.
+        unsafe {
+            if let Some(saved) = INTERRUPT_SETTINGS.get() {
+                libc::tcsetattr(libc::STDIN_FILENO, libc::TCSANOW, saved);
+            }
+            libc::signal(signal, libc::SIG_DFL);
+            libc::raise(signal);
+        }
+    }
+
+    pub fn enable() -> io::Result<Saved> {
+        let mut termios = MaybeUninit::uninit();
+        // SAFETY: `tcgetattr` fills in `termios` when it succeeds.
+        let saved = unsafe {
+            if libc::tcgetattr(libc::STDIN_FILENO, termios.as_mut_ptr()) != 0 {
+                return Err(io::Error::last_os_error());
+            }
+            termios.assume_init()
+        };
+        let mut raw = saved;
+        raw.c_lflag &= !(libc::ICANON | libc::ECHO);
+        raw.c_cc[libc::VMIN] = 1;
+        raw.c_cc[libc::VTIME] = 0;
+        // The terminal's settings are the same each time raw mode is enabled, so the first ones
+        // kept will do.
+        let _ = INTERRUPT_SETTINGS.set(saved);
+        let handler = interrupted as extern "C" fn(libc::c_int) as libc::sighandler_t;
+        // SAFETY: `raw` is a valid `termios`, as it's a copy of the terminal's own, and the
+        // handler only makes async-signal-safe calls.
+        unsafe {
+            let previous = libc::signal(libc::SIGINT, handler);
+            if libc::tcsetattr(libc::STDIN_FILENO, libc::TCSANOW, &raw const raw) != 0 {
+                let err = io::Error::last_os_error();
+                libc::signal(libc::SIGINT, previous);
+                return Err(err);
+            }
+            Ok(Saved(saved, previous))
+        }
+    }
+
+    pub fn restore(saved: &Saved) {
+        // SAFETY: `saved` holds the terminal's settings from `tcgetattr`, and the handler for
+        // `SIGINT` from before raw mode.
+        unsafe {
+            libc::tcsetattr(libc::STDIN_FILENO, libc::TCSANOW, &raw const saved.0);
+            libc::signal(libc::SIGINT, saved.1);
+        }
+    }
+}
+
+#[cfg(windows)]
+mod platform {
+    use std::io;
+    use windows_sys::Win32::Foundation::HANDLE;
+    use windows_sys::Win32::System::Console::{
+        GetConsoleMode, GetStdHandle, SetConsoleMode, CONSOLE_MODE, ENABLE_ECHO_INPUT,
+        ENABLE_LINE_INPUT, STD_INPUT_HANDLE,
+    };
+
+    /// The console's input mode from before raw mode.
+    pub struct Saved(HANDLE, CONSOLE_MODE);
+
+    pub fn enable() -> io::Result<Saved> {
+        let mut mode = 0;
+        // SAFETY: the handle is checked by `GetConsoleMode`, which fails for anything that isn't
+        // a console, and `mode` is a valid place for it to write to.
+        unsafe {
+            let handle = GetStdHandle(STD_INPUT_HANDLE);
+            if GetConsoleMode(handle, &raw mut mode) == 0 {
+                return Err(io::Error::last_os_error());
+            }
+            // Processed input is kept, so that Ctrl-C still interrupts the program.
+            if SetConsoleMode(handle, mode & !(ENABLE_LINE_INPUT | ENABLE_ECHO_INPUT)) == 0 {
+                return Err(io::Error::last_os_error());
+            }
+            Ok(Saved(handle, mode))
+        }
+    }
+
+    pub fn restore(saved: &Saved) {
+        // SAFETY: `saved` holds the console's handle and the mode it had.
+        unsafe {
+            SetConsoleMode(saved.0, saved.1);
+        }
+    }
+}
+
+#[cfg(not(any(unix, windows)))]
+mod platform {
+    use std::io;
+
+    pub struct Saved;
+
+    pub fn enable() -> io::Result<Saved> {
+        Err(io::Error::new(
+            io::ErrorKind::Unsupported,
+            "Raw input isn't supported on this platform",
+        ))
+    }
+
+    pub fn restore(_: &Saved) {}
+}
+
+/// The terminal in raw mode, which lasts until this is dropped.
+pub struct RawMode(platform::Saved);
+
+impl RawMode {
+    /// Put the terminal connected to stdin into raw mode, or return `None` if stdin isn't a
+    /// terminal.
+    ///
+    /// # Errors
+    /// Fails if the terminal's mode can't be changed.
+    pub fn enable() -> io::Result<Option<Self>> {
+        if !io::stdin().is_terminal() {
+            return Ok(None);
+        }
+        platform::enable().map(|saved| Some(RawMode(saved)))
+    }
+}
+
+impl Drop for RawMode {
+    fn drop(&mut self) {
+        platform::restore(&self.0);
+    }
+}
+
+/// Keys read from a terminal in raw mode, ending at [`EOF_KEY`].
+pub struct Keys<R> {
+    inner: R,
+
+    /// Whether the terminal is in raw mode. If not, the input is read as it is.
+    raw: bool,
+
+    /// Whether [`EOF_KEY`] has been read, so that the input has ended.
+    ended: bool,
+}
+
+impl<R: Read> Keys<R> {
+    /// Read keys from `inner`, which is a terminal in raw mode if `raw` is set.
+    pub fn new(inner: R, raw: bool) -> Self {
+        Keys {
+            inner,
+            raw,
+            ended: false,
+        }
+    }
+}
+
+impl<R: Read> Read for Keys<R> {
+    /// Read a single key, as the VM does, so that nothing after [`EOF_KEY`] is read.
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        if !self.raw || buf.is_empty() {
+            return self.inner.read(buf);
+        }
+        if self.ended || self.inner.read(&mut buf[..1])? == 0 {
+            return Ok(0);
+        }
+        match buf[0] {
+            EOF_KEY => {
+                self.ended = true;
+                Ok(0)
+            }
+            // Windows gives Enter as `\r` once line input is off.
+            b'\r' if cfg!(windows) => {
+                buf[0] = b'\n';
+                Ok(1)
+            }
+            _ => Ok(1),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn keys(raw: bool, data: &[u8]) -> Vec<u8> {
+        let mut out = Vec::new();
+        Keys::new(data, raw).read_to_end(&mut out).unwrap();
+        out
+    }
+
+    #[test]
+    fn ending_at_eof_key() {
+        let data = [b'a', b'b', EOF_KEY, b'c'];
+        assert_eq!(keys(true, &data), b"ab");
+        assert_eq!(keys(false, &data), data);
+    }
+}
@@ -0,0 +1,271 @@
+//! Running every program in a directory concurrently, and reporting how each one went.
+//!
+//! Each `NAME.b` program in the directory, or any directory below it, is run with `NAME.in` as
+//! its input, if that exists, on its own VM with the same limits as the others.
+
+use std::error::Error;
+use std::fs;
+use std::io::Write;
+use std::num::NonZeroUsize;
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::mpsc::channel;
+use std::thread;
+use std::time::{Duration, Instant};
+
+use serde_json::{json, Value};
+
+use bft_interp::{Uniform, BFVM};
+use bft_types::BFprogram;
+
+use crate::corpus::find_programs;
+
+/// Limits applied to each program.
+#[derive(Clone, Copy, Debug)]
+pub struct Limits {
+    /// Number of cells on each program's tape.
+    pub cells: NonZeroUsize,
+
+    /// Maximum number of instructions each program may execute.
+    pub max_steps: u64,
+}
+
+/// How running one of the programs went.
+#[derive(Debug)]
+struct Report {
+    /// The program's path, relative to the directory.
+    name: String,
+    output: Vec<u8>,
+    error: Option<String>,
+    steps: u64,
+    exit_status: Option<u8>,
+    duration: Duration,
+}
+
+impl Report {
+    fn to_json(&self) -> Value {
+        json!({
+            "program": self.name,
+            "passed": self.error.is_none(),
+            "output": String::from_utf8_lossy(&self.output),
+            "error": self.error,
+            "steps": self.steps,
+            "exit_status": self.exit_status,
+            "seconds": self.duration.as_secs_f64(),
+        })
+    }
+}
+
+/// Run the program at `path`.
+fn run_program(path: &Path, name: String, limits: Limits) -> Report {
+    let started = Instant::now();
+    let failed = |error: String| Report {
+        name: name.clone(),
+        output: Vec::new(),
+        error: Some(error),
+        steps: 0,
+        exit_status: None,
+        duration: started.elapsed(),
+    };
+    let mut program = match BFprogram::from_file(path) {
+        Ok(program) => program,
+        Err(err) => return failed(err.to_string()),
+    };
+    if let Err(err) = program.validate_brackets() {
+        return failed(err.to_string());
+    }
+    let input = fs::read(path.with_extension("in")).unwrap_or_default();
+
+    let mut vm: BFVM<u8> =
+        BFVM::new(Some(limits.cells), false).with_fuel(limits.max_steps, Uniform);
+    let mut output = Vec::new();
+    let result = vm.run(&program, &mut input.as_slice(), &mut output);
+    Report {
+        name,
+        output,
+        error: result.err().map(|err| err.to_string()),
+        steps: limits.max_steps - vm.fuel().unwrap_or_default(),
+        exit_status: vm.exit_status(),
+        duration: started.elapsed(),
+    }
+}
+
+/// The name to report for the program at `path`, below `dir`.
+fn program_name(dir: &Path, path: &Path) -> String {
+    path.strip_prefix(dir).unwrap_or(path).display().to_string()
+}
+
+/// Run `programs` one after another, returning their reports in the same order. Used where
+/// threads aren't available, such as on WASI.
+fn run_sequentially(dir: &Path, programs: &[PathBuf], limits: Limits) -> Vec<Report> {
+    programs
+        .iter()
+        .map(|path| run_program(path, program_name(dir, path), limits))
+        .collect()
+}
+
+/// Run `programs` on `jobs` threads, returning their reports in the same order.
+fn run_concurrently(
+    dir: &Path,
+    programs: &[PathBuf],
+    jobs: NonZeroUsize,
+    limits: Limits,
+) -> Vec<Report> {
+    let next = AtomicUsize::new(0);
+    let (sender, receiver) = channel();
+    thread::scope(|scope| {
+        for _ in 0..jobs.get().min(programs.len()) {
+            let sender = sender.clone();
+            let next = &next;
+            scope.spawn(move || loop {
+                let index = next.fetch_add(1, Ordering::Relaxed);
+                let Some(path) = programs.get(index) else {
+                    break;
+                };
+                let name = program_name(dir, path);
+                if sender
+                    .send((index, run_program(path, name, limits)))
+                    .is_err()
+                {
+                    break;
+                }
+            });
+        }
+    });
+    drop(sender);
+    let mut reports: Vec<_> = receiver.into_iter().collect();
+    reports.sort_by_key(|(index, _)| *index);
+    reports.into_iter().map(|(_, report)| report).collect()
+}
+
+/// Write a table of `reports` to `out`.
+fn write_table<W: Write>(reports: &[Report], out: &mut W) -> std::io::Result<()> {
+    let width = reports
+        .iter()
+        .map(|report| report.name.len())
+        .chain(["PROGRAM".len()])
+        .max()
+        .unwrap_or_default();
+    writeln!(
+        out,
+        "{:width$}  {:6}  {:>12}  {:>8}  {:>10}",
+        "PROGRAM", "STATUS", "STEPS", "OUTPUT", "TIME"
+    )?;
+    for report in reports {
+        writeln!(
+            out,
+            "{:width$}  {:6}  {:>12}  {:>8}  {:>10.3?}",
+            report.name,
+            if report.error.is_some() { "FAIL" } else { "OK" },
+            report.steps,
+            report.output.len(),
+            report.duration
+        )?;
+    }
+    for report in reports {
+        if let Some(error) = &report.error {
+            writeln!(out, "\n{}: {error}", report.name)?;
+        }
+    }
+    let failed = reports
+        .iter()
+        .filter(|report| report.error.is_some())
+        .count();
+    writeln!(out, "\n{} passed, {failed} failed", reports.len() - failed)
+}
+
+/// Run every program below `dir` using `jobs` threads, or one for each CPU, writing a table of
+/// the results to `out`, and a JSON report to `report` if it's given. On WASI, which has no
+/// threads, the programs run one after another instead.
+///
+/// Returns true if every program ran without an error.
+///
+/// # Errors
+/// Fails if the directory can't be read, or the results can't be written.
+pub fn run_all<W: Write>(
+    dir: &Path,
+    jobs: Option<NonZeroUsize>,
+    limits: Limits,
+    report: Option<&Path>,
+    out: &mut W,
+) -> Result<bool, Box<dyn Error>> {
+    let programs = find_programs(dir)?;
+    let reports = if cfg!(target_os = "wasi") {
+        run_sequentially(dir, &programs, limits)
+    } else {
+        let jobs = match jobs {
+            Some(jobs) => jobs,
+            None => thread::available_parallelism()?,
+        };
+        run_concurrently(dir, &programs, jobs, limits)
+    };
+    write_table(&reports, out)?;
+    if let Some(path) = report {
+        let json: Vec<Value> = reports.iter().map(Report::to_json).collect();
+        fs::write(path, serde_json::to_string_pretty(&json)? + "\n")?;
+    }
+    Ok(reports.iter().all(|report| report.error.is_none()))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const LIMITS: Limits = Limits {
+        cells: NonZeroUsize::new(30000).unwrap(),
+        max_steps: 1_000_000,
+    };
+
+    #[test]
+    fn running_corpus() {
+        let dir = Path::new("data/corpus");
+        let jobs = NonZeroUsize::new(3).unwrap();
+        let programs = find_programs(dir).unwrap();
+        for reports in [
+            run_concurrently(dir, &programs, jobs, LIMITS),
+            run_sequentially(dir, &programs, LIMITS),
+        ] {
+            let names: Vec<_> = reports.iter().map(|report| report.name.as_str()).collect();
+            assert_eq!(names, ["cat.b", "eof.b", "hello.b", "nested/a.b"]);
+            assert!(reports.iter().all(|report| report.error.is_none()));
+            assert_eq!(reports[0].output, fs::read("data/corpus/cat.out").unwrap());
+            assert_eq!(reports[2].to_json()["output"], "hello world");
+        }
+    }
+
+    #[test]
+    fn failures() {
+        let dir = std::env::temp_dir().join(format!("bft-run-all-{}", std::process::id()));
+        fs::create_dir_all(&dir).unwrap();
+        fs::write(dir.join("loop.b"), "+[]").unwrap();
+        fs::write(dir.join("ok.b"), "+.").unwrap();
+        fs::write(dir.join("open.b"), "[").unwrap();
+        let report = dir.join("report.json");
+        let limits = Limits {
+            max_steps: 100,
+            ..LIMITS
+        };
+
+        let mut out = Vec::new();
+        let jobs = NonZeroUsize::new(2);
+        assert!(!run_all(&dir, jobs, limits, Some(&report), &mut out).unwrap());
+        let out = String::from_utf8(out).unwrap();
+        assert!(out.starts_with("PROGRAM  STATUS"));
+        assert!(out.contains("\nloop.b   FAIL             100"));
+        assert!(out.contains("\nok.b     OK                 2         1"));
+        assert!(out.contains("\nloop.b: Ran out of fuel at ["));
+        assert!(out.ends_with("\n1 passed, 2 failed\n"));
+
+        let json: Value = serde_json::from_slice(&fs::read(&report).unwrap()).unwrap();
+        assert_eq!(json[1]["program"], "ok.b");
+        assert_eq!(json[1]["passed"], true);
+        assert_eq!(
+            json[2]["error"],
+            format!(
+                "Unmatched bracket '[' at [{}:1:1]",
+                dir.join("open.b").display()
+            )
+        );
+        fs::remove_dir_all(dir).unwrap();
+    }
+}
@@ -0,0 +1,110 @@
+//! Reporting diagnostics in the Static Analysis Results Interchange Format (SARIF), which code
+//! scanning services like GitHub's can display.
+
+use serde_json::{json, Value};
+
+use crate::diagnostic::{Diagnostic, Severity};
+use crate::lint::LINTS;
+
+/// The version of SARIF written.
+const VERSION: &str = "2.1.0";
+
+/// The schema of the SARIF version written.
+const SCHEMA: &str = "https://json.schemastore.org/sarif-2.1.0.json";
+
+/// The SARIF `level` of a result with `severity`.
+fn level(severity: Severity) -> &'static str {
+    match severity {
+        Severity::Error => "error",
+        Severity::Warning => "warning",
+        Severity::Note => "note",
+    }
+}
+
+/// A SARIF result for `diagnostic`.
+fn result(diagnostic: &Diagnostic) -> Value {
+    let mut message = diagnostic.message.clone();
+    if let Some(note) = &diagnostic.note {
+        message = format!("{message}\n{note}");
+    }
+    let mut result = json!({
+        "level": level(diagnostic.severity),
+        "message": { "text": message },
+        "locations": [{
+            "physicalLocation": {
+                "artifactLocation": {
+                    "uri": diagnostic.file.to_string_lossy().replace('\\', "/"),
+                },
+                "region": {
+                    "startLine": diagnostic.line,
+                    "startColumn": diagnostic.column,
+                    "endColumn": diagnostic.column + diagnostic.len,
+                },
+            },
+        }],
+    });
+    if let Some(code) = diagnostic.code {
+        result["ruleId"] = json!(code);
+    }
+    result
+}
+
+/// A SARIF log of a single run of bft, reporting `diagnostics`.
+pub fn log(diagnostics: &[Diagnostic]) -> Value {
+    let rules: Vec<Value> = LINTS
+        .iter()
+        .map(|lint| {
+            json!({
+                "id": lint.name,
+                "shortDescription": { "text": lint.description },
+            })
+        })
+        .collect();
+    json!({
+        "$schema": SCHEMA,
+        "version": VERSION,
+        "runs": [{
+            "tool": {
+                "driver": {
+                    "name": env!("CARGO_PKG_NAME"),
+                    "version": env!("CARGO_PKG_VERSION"),
+                    "rules": rules,
+                },
+            },
+            "results": diagnostics.iter().map(result).collect::<Vec<_>>(),
+        }],
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::diagnostic::check_source;
+    use crate::lint::LintConfig;
+    use std::path::Path;
+
+    #[test]
+    fn logging() {
+        let config = LintConfig::default();
+        let mut diagnostics = check_source(Path::new("dir/a.b"), b"+-", &config);
+        diagnostics.extend(check_source(Path::new("b.b"), b"\n]", &config));
+        let log = log(&diagnostics);
+        assert_eq!(log["version"], "2.1.0");
+        let run = &log["runs"][0];
+        assert_eq!(run["tool"]["driver"]["rules"][0]["id"], LINTS[0].name);
+
+        let results = &run["results"];
+        assert_eq!(results[0]["ruleId"], "cancelling-instructions");
+        assert_eq!(results[0]["level"], "warning");
+        let location = &results[0]["locations"][0]["physicalLocation"];
+        assert_eq!(location["artifactLocation"]["uri"], "dir/a.b");
+        assert_eq!(location["region"]["startColumn"], 1);
+
+        assert_eq!(results[1]["level"], "error");
+        assert_eq!(results[1].get("ruleId"), None);
+        assert_eq!(
+            results[1]["locations"][0]["physicalLocation"]["region"]["startLine"],
+            2
+        );
+    }
+}
@@ -0,0 +1,154 @@
+//! Checking an installation of bft, and the semantics it's configured with, by running a few
+//! built-in programs whose output is known.
+//!
+//! The programs are run with the options from `bft.toml` and the `BFT_` environment variables,
+//! so a configuration that changes how programs behave, like a tape too short for them, shows up
+//! as a failure.
+
+use std::error::Error;
+use std::io::Write;
+
+use bft_interp::{BftError, StepOutcome, BFVM};
+use bft_types::BFprogram;
+
+/// Most instructions each program may execute, which is far more than any of them needs.
+const MAX_STEPS: u64 = 1_000_000;
+
+/// A built-in program and what it should do.
+pub struct Case {
+    /// The name reported for the program.
+    pub name: &'static str,
+
+    /// What the program checks.
+    pub about: &'static str,
+
+    /// The program's code.
+    pub code: &'static str,
+
+    /// The program's input.
+    pub input: &'static [u8],
+
+    /// The output the program should write.
+    pub expected: &'static [u8],
+}
+
+/// The built-in programs.
+pub const CASES: [Case; 4] = [
+    Case {
+        name: "hello-world",
+        about: "loops and output work",
+        code: "++++++++[>++++[>++>+++>+++>+<<<<-]>+>+>->>+[<]<-]>>.>---.+++++++..+++.>>.<-.<.+++.\
+               ------.--------.>>+.>++.",
+        input: b"",
+        expected: b"Hello World!\n",
+    },
+    Case {
+        name: "cell-width",
+        about: "cells hold 8 bits, so 256 increments wrap around to 0",
+        code: ">++++++++++++++++[<++++++++++++++++>-]+<[>-<[-]]>[>++++++++[<<+++++++>>-]<<.[-]>-]\
+               ++++++++++.",
+        input: b"",
+        expected: b"8\n",
+    },
+    Case {
+        name: "wrapping",
+        about: "decrementing 0 wraps around to 255",
+        code: "-.",
+        input: b"",
+        expected: b"\xff",
+    },
+    Case {
+        name: "eof",
+        about: "reading at the end of the input leaves the cell unchanged",
+        code: "+++++[>++++++++++<-]>,.,.",
+        input: b"a",
+        expected: b"aa",
+    },
+];
+
+/// Run `case` on a VM from `new_vm`, returning its output, or a description of the problem if it
+/// fails.
+fn run_case(
+    case: &Case,
+    new_vm: &impl Fn() -> Result<BFVM<u8>, BftError>,
+) -> Result<Vec<u8>, String> {
+    let mut program = BFprogram::new(case.name, case.code.as_bytes());
+    program.validate_brackets().map_err(|err| err.to_string())?;
+    let mut vm = new_vm().map_err(|err| err.to_string())?;
+    let mut input = case.input;
+    let mut output = Vec::new();
+    let mut steps = 0;
+    while vm
+        .step(&program, &mut input, &mut output)
+        .map_err(|err| err.to_string())?
+        == StepOutcome::Running
+    {
+        steps += 1;
+        if steps >= MAX_STEPS {
+            return Err(format!("step limit of {MAX_STEPS} exceeded"));
+        }
+    }
+    Ok(output)
+}
+
+/// Run each of the built-in programs on a VM from `new_vm`, reporting the results to `out`.
+///
+/// Returns true if every program passed.
+///
+/// # Errors
+/// Fails if the report can't be written.
+pub fn run_selftest<W: Write>(
+    new_vm: impl Fn() -> Result<BFVM<u8>, BftError>,
+    out: &mut W,
+) -> Result<bool, Box<dyn Error>> {
+    let mut failed = 0;
+    for case in &CASES {
+        let problem = match run_case(case, &new_vm) {
+            Ok(output) if output == case.expected => {
+                writeln!(out, "PASS {}", case.name)?;
+                continue;
+            }
+            Ok(output) => format!(
+                "expected \"{}\", got \"{}\"",
+                case.expected.escape_ascii(),
+                output.escape_ascii()
+            ),
+            Err(problem) => problem,
+        };
+        failed += 1;
+        writeln!(out, "FAIL {}: {}", case.name, case.about)?;
+        writeln!(out, "  {problem}")?;
+    }
+    writeln!(out, "{} passed, {} failed", CASES.len() - failed, failed)?;
+    Ok(failed == 0)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::num::NonZeroUsize;
+
+    fn selftest(new_vm: impl Fn() -> Result<BFVM<u8>, BftError>) -> (bool, String) {
+        let mut out = Vec::new();
+        let passed = run_selftest(new_vm, &mut out).unwrap();
+        (passed, String::from_utf8(out).unwrap())
+    }
+
+    #[test]
+    fn passing() {
+        let (passed, out) = selftest(|| Ok(BFVM::new(None, false)));
+        assert!(passed);
+        assert_eq!(
+            out,
+            "PASS hello-world\nPASS cell-width\nPASS wrapping\nPASS eof\n4 passed, 0 failed\n"
+        );
+    }
+
+    #[test]
+    fn tape_too_short() {
+        let (passed, out) = selftest(|| Ok(BFVM::new(NonZeroUsize::new(3), false)));
+        assert!(!passed);
+        assert!(out.contains("FAIL hello-world: loops and output work\n  Head moved"));
+        assert!(out.ends_with("3 passed, 1 failed\n"));
+    }
+}
@@ -0,0 +1,663 @@
+//! A small HTTP server for a shared playground, running programs submitted from a browser.
+//!
+//! `GET /` serves the playground page. The other endpoints take a JSON object with the `code`
+//! of a program and its `input`:
+//!
+//! - `POST /run` runs the program, returning its output, any error, the number of steps it took
+//!   and static metrics about it.
+//! - `POST /step` runs the first `steps` instructions of the program, returning its output and
+//!   the state of the VM afterwards.
+//! - `POST /stream` sends the program's output as it is written, followed by any error.
+//! - `GET /metrics` reports usage of the server, in the Prometheus text format.
+//!
+//! Every run uses a fixed-size tape, and is stopped when it executes too many instructions,
+//! writes too much output, or takes too long.
+
+use std::io;
+use std::io::{BufRead, BufReader, LineWriter, Read, Write};
+use std::net::{TcpListener, TcpStream};
+use std::num::NonZeroUsize;
+use std::path::Path;
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::mpsc::{channel, RecvTimeoutError, Sender};
+use std::sync::Arc;
+use std::thread;
+use std::time::{Duration, Instant};
+
+use serde_json::{json, Value};
+
+use bft_interp::{StepOutcome, VMError, BFVM};
+use bft_types::BFprogram;
+
+use crate::metrics::{ErrorClass, Metrics};
+use crate::stats::Stats;
+
+/// The page served at `/`.
+const PLAYGROUND: &str = include_str!("playground.html");
+
+/// Name used for submitted programs in error messages.
+const SOURCE_NAME: &str = "playground";
+
+/// How long to wait for a client to send its request.
+const READ_TIMEOUT: Duration = Duration::from_secs(10);
+
+/// Maximum size of a request's line and headers together, in bytes.
+const MAX_HEADER_BYTES: u64 = 8 * 1024;
+
+/// Maximum number of connections handled at once. Others are turned away until one finishes.
+const MAX_CONNECTIONS: usize = 64;
+
+/// How many cells either side of the head to include in the state returned by `/step`.
+const TAPE_WINDOW: usize = 8;
+
+/// Limits on the programs run by the server.
+#[derive(Clone, Copy, Debug)]
+pub struct Limits {
+    /// Number of cells on the tape, which can't grow.
+    pub cells: NonZeroUsize,
+
+    /// Maximum number of instructions a program may execute.
+    pub max_steps: u64,
+
+    /// Maximum number of bytes a program may write.
+    pub max_output: usize,
+
+    /// Maximum size of a request body, in bytes.
+    pub max_request: usize,
+
+    /// Maximum time a program may run for.
+    pub max_time: Duration,
+}
+
+/// The parts of an HTTP request that the server looks at.
+#[derive(Debug)]
+struct Request {
+    method: String,
+    path: String,
+    body: Vec<u8>,
+}
+
+/// Read a request, without its headers other than `Content-Length`. Returns `None` if the client
+/// disconnects before sending one.
+fn read_request<R: BufRead>(input: &mut R, max_body: usize) -> io::Result<Option<Request>> {
+    let invalid = |message: &str| io::Error::new(io::ErrorKind::InvalidData, message.to_string());
+    let mut head = input.by_ref().take(MAX_HEADER_BYTES);
+    let mut read_line = |line: &mut String| {
+        let read = head.read_line(line)?;
+        if head.limit() == 0 && !line.ends_with('\n') {
+            return Err(invalid("Request headers are too large"));
+        }
+        Ok(read)
+    };
+    let mut line = String::new();
+    if read_line(&mut line)? == 0 {
+        return Ok(None);
+    }
+    let mut parts = line.split_whitespace();
+    let (Some(method), Some(path)) = (parts.next(), parts.next()) else {
+        return Err(invalid("Malformed request line"));
+    };
+    let (method, path) = (method.to_string(), path.to_string());
+    let mut content_length = 0;
+    loop {
+        line.clear();
+        if read_line(&mut line)? == 0 {
+            return Err(invalid("Unexpected end of headers"));
+        }
+        let line = line.trim_end();
+        if line.is_empty() {
+            break;
+        }
+        if let Some((name, value)) = line.split_once(':') {
+            if name.eq_ignore_ascii_case("Content-Length") {
+                content_length = value
+                    .trim()
+                    .parse()
+                    .map_err(|_| invalid("Invalid Content-Length"))?;
+            }
+        }
+    }
+    if content_length > max_body {
+        return Err(invalid("Request body is too large"));
+    }
+    let mut body = vec![0; content_length];
+    input.read_exact(&mut body)?;
+    Ok(Some(Request { method, path, body }))
+}
+
+/// Write a complete response.
+fn respond<W: Write>(out: &mut W, status: &str, content_type: &str, body: &[u8]) -> io::Result<()> {
+    write!(
+        out,
+        "HTTP/1.1 {status}\r\nContent-Type: {content_type}\r\nContent-Length: {}\r\nConnection: close\r\n\r\n",
+        body.len()
+    )?;
+    out.write_all(body)?;
+    out.flush()
+}
+
+fn respond_json<W: Write>(out: &mut W, status: &str, body: &Value) -> io::Result<()> {
+    respond(out, status, "application/json", body.to_string().as_bytes())
+}
+
+/// Writes the body of a response with chunked transfer encoding, so that it can be sent as it is
+/// produced.
+struct Chunked<W: Write> {
+    inner: W,
+}
+
+impl<W: Write> Chunked<W> {
+    /// Send the final, empty, chunk.
+    fn finish(mut self) -> io::Result<()> {
+        self.inner.write_all(b"0\r\n\r\n")?;
+        self.inner.flush()
+    }
+}
+
+impl<W: Write> Write for Chunked<W> {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        if !buf.is_empty() {
+            write!(self.inner, "{:x}\r\n", buf.len())?;
+            self.inner.write_all(buf)?;
+            self.inner.write_all(b"\r\n")?;
+        }
+        Ok(buf.len())
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        self.inner.flush()
+    }
+}
+
+/// Passes output through to `inner`, failing once more than the limit has been written.
+struct Limited<W> {
+    inner: W,
+    remaining: usize,
+    /// Whether a write has failed because of the limit.
+    exceeded: bool,
+}
+
+impl<W: Write> Write for Limited<W> {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        if buf.len() > self.remaining {
+            self.exceeded = true;
+            return Err(io::Error::other("output limit exceeded"));
+        }
+        self.remaining -= buf.len();
+        self.inner.write_all(buf)?;
+        Ok(buf.len())
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        self.inner.flush()
+    }
+}
+
+/// The program and input submitted with a request.
+struct Submission {
+    code: String,
+    input: String,
+    /// How many instructions to execute, for `/step`.
+    steps: Option<u64>,
+}
+
+impl Submission {
+    fn parse(body: &[u8]) -> Result<Self, String> {
+        let value: Value = serde_json::from_slice(body).map_err(|err| err.to_string())?;
+        let code = value["code"]
+            .as_str()
+            .ok_or("The request needs the program's \"code\"")?;
+        Ok(Submission {
+            code: code.to_string(),
+            input: value["input"].as_str().unwrap_or_default().to_string(),
+            steps: value["steps"].as_u64(),
+        })
+    }
+}
+
+/// The state of a VM after running a submitted program.
+struct Outcome {
+    vm: BFVM<u8>,
+    steps: u64,
+    finished: bool,
+    error: Option<String>,
+}
+
+/// Stop `vm` if it's still running after `limit`. Dropping the returned sender means that it
+/// has finished.
+fn watchdog(vm: &mut BFVM<u8>, limit: Duration) -> Sender<()> {
+    let handle = vm.stop_handle();
+    let (finished, watching) = channel();
+    thread::spawn(move || {
+        if watching.recv_timeout(limit) == Err(RecvTimeoutError::Timeout) {
+            handle.stop();
+        }
+    });
+    finished
+}
+
+/// Run the submitted program, writing its output to `output`. It is paused after the number of
+/// steps in the submission, if there is one, and otherwise stopped with an error when it reaches
+/// the step limit.
+///
+/// The run is counted in `metrics`. Returns an error if the program's brackets don't match.
+fn execute<W: Write>(
+    submission: &Submission,
+    limits: &Limits,
+    metrics: &Metrics,
+    output: W,
+) -> Result<Outcome, String> {
+    let mut program = BFprogram::new(SOURCE_NAME, submission.code.as_bytes());
+    program.validate_brackets().map_err(|err| {
+        metrics.record_error(ErrorClass::Brackets);
+        err.to_string()
+    })?;
+    let mut vm = BFVM::new(Some(limits.cells), false);
+    let mut input = submission.input.as_bytes();
+    let mut output = Limited {
+        inner: output,
+        remaining: limits.max_output,
+        exceeded: false,
+    };
+    let max_steps = submission
+        .steps
+        .unwrap_or(limits.max_steps)
+        .min(limits.max_steps);
+    let started = Instant::now();
+    let watching = watchdog(&mut vm, limits.max_time);
+    let mut steps = 0;
+    let mut finished = false;
+    let mut error = None;
+    while steps < max_steps {
+        match vm.step(&program, &mut input, &mut output) {
+            Ok(StepOutcome::Running) => steps += 1,
+            // The output is kept in memory, so it's never closed.
+            Ok(StepOutcome::Finished | StepOutcome::BrokenPipe) => {
+                steps += u64::from(vm.last_executed().is_some());
+                finished = true;
+                break;
+            }
+            Err(VMError::Cancelled(_)) => {
+                let message = format!("time limit of {:?} exceeded", limits.max_time);
+                error = Some((ErrorClass::TimeLimit, message));
+                break;
+            }
+            Err(err) => {
+                let class = match err {
+                    _ if output.exceeded => ErrorClass::OutputLimit,
+                    VMError::IOError(..) => ErrorClass::Io,
+                    _ => ErrorClass::Runtime,
+                };
+                error = Some((class, err.to_string()));
+                break;
+            }
+        }
+    }
+    drop(watching);
+    if !finished && error.is_none() && submission.steps.is_none() {
+        let message = format!("step limit of {} exceeded", limits.max_steps);
+        error = Some((ErrorClass::StepLimit, message));
+    }
+    if let Err(err) = output.flush() {
+        error.get_or_insert((ErrorClass::Io, err.to_string()));
+    }
+    metrics.record_run(
+        steps,
+        started.elapsed(),
+        error.as_ref().map(|(class, _)| *class),
+    );
+    Ok(Outcome {
+        vm,
+        steps,
+        finished,
+        error: error.map(|(_, message)| message),
+    })
+}
+
+/// The cells around the head, and the index of the first of them.
+fn tape_window(vm: &BFVM<u8>) -> (usize, &[u8]) {
+    let start = vm.head().saturating_sub(TAPE_WINDOW);
+    let end = (vm.head() + TAPE_WINDOW + 1).min(vm.tape().len());
+    (start, &vm.tape()[start..end])
+}
+
+/// Respond to `request`, writing the response to `out`.
+fn handle<W: Write>(
+    request: &Request,
+    limits: &Limits,
+    metrics: &Metrics,
+    out: &mut W,
+) -> io::Result<()> {
+    let submission = match (request.method.as_str(), request.path.as_str()) {
+        ("GET", "/" | "/index.html") => {
+            return respond(
+                out,
+                "200 OK",
+                "text/html; charset=utf-8",
+                PLAYGROUND.as_bytes(),
+            );
+        }
+        ("GET", "/metrics") => {
+            return respond(
+                out,
+                "200 OK",
+                "text/plain; version=0.0.4",
+                metrics.render().as_bytes(),
+            );
+        }
+        ("POST", "/run" | "/step" | "/stream") => match Submission::parse(&request.body) {
+            Ok(submission) => submission,
+            Err(error) => {
+                metrics.record_error(ErrorClass::BadRequest);
+                return respond_json(out, "400 Bad Request", &json!({ "error": error }));
+            }
+        },
+        ("GET" | "POST", _) => {
+            return respond_json(out, "404 Not Found", &json!({ "error": "Not found" }));
+        }
+        _ => {
+            return respond_json(
+                out,
+                "405 Method Not Allowed",
+                &json!({ "error": "Method not allowed" }),
+            );
+        }
+    };
+
+    if request.path == "/stream" {
+        write!(
+            out,
+            "HTTP/1.1 200 OK\r\nContent-Type: text/plain; charset=utf-8\r\nTransfer-Encoding: chunked\r\nConnection: close\r\n\r\n"
+        )?;
+        let mut body = LineWriter::new(Chunked { inner: &mut *out });
+        let error = match execute(
+            &Submission {
+                steps: None,
+                ..submission
+            },
+            limits,
+            metrics,
+            &mut body,
+        ) {
+            Ok(outcome) => outcome.error,
+            Err(error) => Some(error),
+        };
+        if let Some(error) = error {
+            write!(body, "\nbft: {error}\n")?;
+        }
+        return body
+            .into_inner()
+            .map_err(io::IntoInnerError::into_error)?
+            .finish();
+    }
+
+    let mut output = Vec::new();
+    let steps = if request.path == "/step" {
+        Some(submission.steps.unwrap_or(1))
+    } else {
+        None
+    };
+    let submission = Submission {
+        steps,
+        ..submission
+    };
+    let outcome = match execute(&submission, limits, metrics, &mut output) {
+        Ok(outcome) => outcome,
+        Err(error) => {
+            return respond_json(
+                out,
+                "200 OK",
+                &json!({ "output": "", "error": error, "steps": 0, "finished": true }),
+            );
+        }
+    };
+    let mut body = json!({
+        "output": String::from_utf8_lossy(&output),
+        "error": outcome.error,
+        "steps": outcome.steps,
+        "finished": outcome.finished,
+        "exit_status": outcome.vm.exit_status(),
+    });
+    if request.path == "/step" {
+        let (start, cells) = tape_window(&outcome.vm);
+        body["pc"] = json!(outcome.vm.pc());
+        body["head"] = json!(outcome.vm.head());
+        body["tape_start"] = json!(start);
+        body["tape"] = json!(cells);
+    } else {
+        body["stats"] = Stats::of(Path::new(SOURCE_NAME), submission.code.as_bytes()).to_json();
+    }
+    respond_json(out, "200 OK", &body)
+}
+
+/// Read a single request from `stream` and respond to it.
+fn handle_connection(stream: &TcpStream, limits: &Limits, metrics: &Metrics) -> io::Result<()> {
+    stream.set_read_timeout(Some(READ_TIMEOUT))?;
+    let mut out = stream;
+    match read_request(&mut BufReader::new(stream), limits.max_request) {
+        Ok(Some(request)) => handle(&request, limits, metrics, &mut out),
+        Ok(None) => Ok(()),
+        Err(err) => {
+            metrics.record_error(ErrorClass::BadRequest);
+            respond_json(
+                &mut out,
+                "400 Bad Request",
+                &json!({ "error": err.to_string() }),
+            )
+        }
+    }
+}
+
+/// A place among the [`MAX_CONNECTIONS`] connections being handled, given up when dropped.
+struct ConnectionSlot(Arc<AtomicUsize>);
+
+impl ConnectionSlot {
+    /// Take a place, counted in `active`, if one is free.
+    fn acquire(active: &Arc<AtomicUsize>) -> Option<Self> {
+        active
+            .fetch_update(Ordering::AcqRel, Ordering::Acquire, |n| {
+                (n < MAX_CONNECTIONS).then_some(n + 1)
+            })
+            .ok()?;
+        Some(ConnectionSlot(Arc::clone(active)))
+    }
+}
+
+impl Drop for ConnectionSlot {
+    fn drop(&mut self) {
+        self.0.fetch_sub(1, Ordering::AcqRel);
+    }
+}
+
+/// Serve the playground on `addr`, handling each connection on its own thread, until the
+/// process is stopped. Connections beyond [`MAX_CONNECTIONS`] at once are answered with
+/// `503 Service Unavailable`.
+///
+/// # Errors
+/// Fails if the server can't listen on `addr`.
+pub fn serve(addr: &str, limits: Limits) -> io::Result<()> {
+    let listener = TcpListener::bind(addr)?;
+    eprintln!(
+        "Serving the playground on http://{}",
+        listener.local_addr()?
+    );
+    let metrics = Arc::new(Metrics::default());
+    let active = Arc::new(AtomicUsize::new(0));
+    for stream in listener.incoming() {
+        let Ok(mut stream) = stream else {
+            continue;
+        };
+        let Some(slot) = ConnectionSlot::acquire(&active) else {
+            let _ = respond_json(
+                &mut stream,
+                "503 Service Unavailable",
+                &json!({ "error": "Too many connections" }),
+            );
+            continue;
+        };
+        let metrics = Arc::clone(&metrics);
+        thread::spawn(move || {
+            let _slot = slot;
+            if let Err(err) = handle_connection(&stream, &limits, &metrics) {
+                eprintln!("bft: {err}");
+            }
+        });
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const LIMITS: Limits = Limits {
+        cells: NonZeroUsize::new(16).unwrap(),
+        max_steps: 1000,
+        max_output: 8,
+        max_request: 1024,
+        max_time: Duration::from_secs(30),
+    };
+
+    fn request(method: &str, path: &str, body: &Value) -> String {
+        send(&Metrics::default(), method, path, body)
+    }
+
+    fn send(metrics: &Metrics, method: &str, path: &str, body: &Value) -> String {
+        let body = body.to_string();
+        let raw = format!(
+            "{method} {path} HTTP/1.1\r\nHost: localhost\r\nContent-Length: {}\r\n\r\n{body}",
+            body.len()
+        );
+        let request = read_request(&mut raw.as_bytes(), LIMITS.max_request)
+            .unwrap()
+            .unwrap();
+        let mut out = Vec::new();
+        handle(&request, &LIMITS, metrics, &mut out).unwrap();
+        String::from_utf8(out).unwrap()
+    }
+
+    fn json_body(response: &str) -> Value {
+        serde_json::from_str(response.split_once("\r\n\r\n").unwrap().1).unwrap()
+    }
+
+    #[test]
+    fn running() {
+        let response = request("POST", "/run", &json!({"code": ",.+.", "input": "a"}));
+        assert!(response.starts_with("HTTP/1.1 200 OK\r\n"));
+        let body = json_body(&response);
+        assert_eq!(body["output"], "ab");
+        assert_eq!(body["error"], Value::Null);
+        assert_eq!(body["steps"], 4);
+        assert_eq!(body["stats"]["instructions"], 4);
+
+        let body = json_body(&request("POST", "/run", &json!({"code": "+["})));
+        assert_eq!(body["error"], "Unmatched bracket '[' at [playground:1:2]");
+    }
+
+    #[test]
+    fn limits() {
+        let body = json_body(&request("POST", "/run", &json!({"code": "+[]"})));
+        assert_eq!(body["error"], "step limit of 1000 exceeded");
+        assert_eq!(body["finished"], false);
+
+        let body = json_body(&request("POST", "/run", &json!({"code": "+[.]"})));
+        assert_eq!(body["output"], "\u{1}".repeat(8));
+        assert!(body["error"]
+            .as_str()
+            .unwrap()
+            .contains("output limit exceeded"));
+
+        let raw = "POST /run HTTP/1.1\r\nContent-Length: 2048\r\n\r\n";
+        assert!(read_request(&mut raw.as_bytes(), LIMITS.max_request).is_err());
+
+        let limits = Limits {
+            max_steps: u64::MAX,
+            max_time: Duration::from_millis(50),
+            ..LIMITS
+        };
+        let submission = Submission::parse(br#"{"code": "+[]"}"#).unwrap();
+        let outcome = execute(&submission, &limits, &Metrics::default(), io::sink()).unwrap();
+        assert_eq!(outcome.error.unwrap(), "time limit of 50ms exceeded");
+    }
+
+    #[test]
+    fn stepping() {
+        let body = json_body(&request(
+            "POST",
+            "/step",
+            &json!({"code": "++>+.", "steps": 3}),
+        ));
+        assert_eq!(body["steps"], 3);
+        assert_eq!(body["pc"], 3);
+        assert_eq!(body["head"], 1);
+        assert_eq!(body["tape_start"], 0);
+        assert_eq!(body["tape"][0], 2);
+        assert_eq!(body["finished"], false);
+    }
+
+    #[test]
+    fn streaming() {
+        let response = request("POST", "/stream", &json!({"code": "+[]", "input": ""}));
+        assert!(response.contains("Transfer-Encoding: chunked\r\n"));
+        assert!(response.ends_with("bft: step limit of 1000 exceeded\n\r\n0\r\n\r\n"));
+    }
+
+    #[test]
+    fn pages() {
+        let response = request("GET", "/", &json!(null));
+        assert!(response.contains("<title>bft playground</title>"));
+        assert!(request("GET", "/missing", &json!(null)).starts_with("HTTP/1.1 404"));
+        assert!(request("DELETE", "/", &json!(null)).starts_with("HTTP/1.1 405"));
+    }
+
+    #[test]
+    fn metrics() {
+        let metrics = Metrics::default();
+        send(
+            &metrics,
+            "POST",
+            "/run",
+            &json!({"code": ",.+.", "input": "a"}),
+        );
+        send(&metrics, "POST", "/run", &json!({"code": "+[.]"}));
+        send(&metrics, "POST", "/run", &json!({"code": "+["}));
+        send(&metrics, "POST", "/run", &json!({"input": ""}));
+        let response = send(&metrics, "GET", "/metrics", &json!(null));
+        assert!(response.starts_with("HTTP/1.1 200 OK\r\nContent-Type: text/plain"));
+        for line in [
+            "bft_programs_run_total 2",
+            "bft_instructions_executed_total 22",
+            "bft_errors_total{class=\"output_limit\"} 1",
+            "bft_errors_total{class=\"brackets\"} 1",
+            "bft_errors_total{class=\"bad_request\"} 1",
+        ] {
+            assert!(response.lines().any(|l| l == line), "{line} missing");
+        }
+    }
+
+    #[test]
+    fn oversized_headers() {
+        let mut endless = io::repeat(b'a').take(1 << 20);
+        let mut endless = BufReader::new(&mut endless);
+        let err = read_request(&mut endless, LIMITS.max_request).unwrap_err();
+        assert_eq!(err.kind(), io::ErrorKind::InvalidData);
+
+        let raw = format!(
+            "GET / HTTP/1.1\r\nX-Padding: {}\r\n\r\n",
+            "a".repeat(10_000)
+        );
+        let err = read_request(&mut raw.as_bytes(), LIMITS.max_request).unwrap_err();
+        assert_eq!(err.to_string(), "Request headers are too large");
+    }
+
+    #[test]
+    fn connection_slots() {
+        let active = Arc::new(AtomicUsize::new(0));
+        let mut slots: Vec<_> = (0..MAX_CONNECTIONS)
+            .map(|_| ConnectionSlot::acquire(&active).unwrap())
+            .collect();
+        assert!(ConnectionSlot::acquire(&active).is_none());
+        slots.pop();
+        assert!(ConnectionSlot::acquire(&active).is_some());
+    }
+}
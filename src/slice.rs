@@ -0,0 +1,129 @@
+//! Cutting a loop out of a program, so that it can be run and tested on its own.
+//!
+//! The loop's source is kept as it was written, comments and all. Before it come comments saying
+//! where it came from, and setup code that gives the cell the loop starts on the value it holds
+//! when the full program reaches the loop, as far as that can be worked out without running it.
+
+use bft_types::{Alphabet, BFprogram, Location};
+
+use crate::translate::clean_comment;
+use crate::value_range::ValueRanges;
+
+/// Setup code that sets a zero cell to `value`, by counting up or down, whichever is shorter.
+fn set_cell(value: u8) -> String {
+    if value <= 128 {
+        "+".repeat(usize::from(value))
+    } else {
+        "-".repeat(256 - usize::from(value))
+    }
+}
+
+/// Cut the innermost loop containing `at` out of `program`, which was parsed from `data`, as the
+/// source of a program of its own. Returns `None` if no loop contains `at`.
+#[must_use]
+pub fn slice(program: &BFprogram, data: &[u8], at: Location) -> Option<String> {
+    let sliced = program.slice_loop(at)?;
+    let (first, last) = (
+        sliced.instructions().first()?,
+        sliced.instructions().last()?,
+    );
+    let start = program
+        .instructions()
+        .iter()
+        .position(|inst| inst == first)?;
+
+    let mut comments = vec![format!(
+        "The loop in {} from line {} column {} to line {} column {}",
+        program.source().display(),
+        first.line_number(),
+        first.char_number(),
+        last.line_number(),
+        last.char_number()
+    )];
+    let value = ValueRanges::of(program).before(start);
+    let setup = match value.map(|value| (value.known(), value)) {
+        None | Some((Some(0), _)) => {
+            comments.push(String::from(
+                "The program never runs it so its first cell starts at 1 here",
+            ));
+            set_cell(1)
+        }
+        Some((Some(known), _)) => {
+            comments.push(format!(
+                "Its first cell always holds {known} when the program reaches it"
+            ));
+            set_cell(known)
+        }
+        Some((None, value)) => {
+            let start = value.min.max(1);
+            comments.push(format!(
+                "Its first cell holds {} to {} when the program reaches it so it starts at {start} here",
+                value.min, value.max
+            ));
+            set_cell(start)
+        }
+    };
+    comments.push(String::from(
+        "Other cells start at zero so set them up below as the loop expects",
+    ));
+
+    let alphabet = Alphabet::default();
+    let mut out = String::new();
+    for comment in comments {
+        out.push_str(&String::from_utf8_lossy(&clean_comment(
+            comment.as_bytes(),
+            &alphabet,
+        )));
+        out.push('\n');
+    }
+    out.push_str(&setup);
+    out.push('\n');
+    let end = last.offset() + last.token_len();
+    out.push_str(&String::from_utf8_lossy(&data[first.offset()..end]));
+    out.push('\n');
+    Some(out)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn slice_at(code: &str, line: usize, column: usize) -> Option<String> {
+        let mut program = BFprogram::new("a.b", code.as_bytes());
+        program.validate_brackets().unwrap();
+        slice(&program, code.as_bytes(), Location { line, column })
+    }
+
+    #[test]
+    fn slicing() {
+        let code = "++++[>+++ add three\n[->+<]<-]";
+        assert_eq!(
+            slice_at(code, 2, 2).unwrap(),
+            "The loop in ab from line 2 column 1 to line 2 column 6\n\
+             Its first cell holds 0 to 255 when the program reaches it so it starts at 1 here\n\
+             Other cells start at zero so set them up below as the loop expects\n\
+             +\n\
+             [->+<]\n"
+        );
+        let sliced = slice_at(code, 1, 7).unwrap();
+        assert!(sliced.contains("Its first cell always holds 4 when"));
+        assert!(sliced.ends_with("\n++++\n[>+++ add three\n[->+<]<-]\n"));
+        assert_eq!(slice_at("+[-]-[+]", 1, 7), slice_at("+[-]-[+]", 1, 6));
+        assert!(slice_at("+[-]-[+]", 1, 6).unwrap().contains("\n-\n[+]"));
+        assert!(slice_at("[-]", 1, 2).unwrap().contains("never runs"));
+        assert_eq!(slice_at("+[-]", 1, 1), None);
+    }
+
+    #[test]
+    fn running_the_slice() {
+        // The loop counts down from 10, printing how far it has got.
+        let code = "++++++++++[->+.<]";
+        let sliced = slice_at(code, 1, 11).unwrap();
+        let mut program = BFprogram::new("a.b", sliced.as_bytes());
+        program.validate_brackets().unwrap();
+        let mut output = Vec::new();
+        let mut vm: bft_interp::BFVM<u8> = bft_interp::BFVM::new(None, false);
+        vm.run(&program, &mut &b""[..], &mut output).unwrap();
+        assert_eq!(output, (1..=10).collect::<Vec<u8>>());
+    }
+}
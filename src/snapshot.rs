@@ -0,0 +1,153 @@
+//! Reporting what changed between two snapshots of a run, which are the checkpoints saved by
+//! `--autosave` or by the debugger's `snapshot` command.
+//!
+//! The report lists the instructions executed in between, how each head moved, and each cell
+//! that changed, with its old and new values:
+//!
+//! ```text
+//! instructions: 1200 -> 5400 (+4200)
+//! head: 3 -> 7 (+4)
+//! cell 4: 0 -> 72
+//! cell 5: 12 -> 0
+//! 2 cells changed
+//! ```
+//!
+//! With more than one tape, the selected tape is reported, and heads and cells are prefixed with
+//! the tape they're on.
+
+use std::error::Error;
+use std::fs;
+use std::io;
+use std::io::Write;
+use std::path::Path;
+
+use bft_interp::{TapeDiff, BFVM};
+
+/// Load the snapshot saved at `path`.
+fn load(path: &Path) -> Result<BFVM<u8>, Box<dyn Error>> {
+    let data = fs::read(path)?;
+    BFVM::from_checkpoint(&data).map_err(|err| format!("{}: {err}", path.display()).into())
+}
+
+/// Compare the snapshots saved at `before` and `after`.
+///
+/// # Errors
+/// Fails if either snapshot can't be read or is damaged.
+pub fn diff(before: &Path, after: &Path) -> Result<TapeDiff, Box<dyn Error>> {
+    Ok(load(before)?.tape_diff(&load(after)?))
+}
+
+/// The change from `old` to `new`, with the difference in brackets if there is one.
+fn movement(old: u64, new: u64) -> String {
+    match new.cmp(&old) {
+        std::cmp::Ordering::Equal => format!("{old} -> {new}"),
+        std::cmp::Ordering::Greater => format!("{old} -> {new} (+{})", new - old),
+        std::cmp::Ordering::Less => format!("{old} -> {new} (-{})", old - new),
+    }
+}
+
+/// Write a report of `diff` to `out`.
+///
+/// # Errors
+/// Fails if the report can't be written.
+pub fn report<W: Write>(diff: &TapeDiff, out: &mut W) -> io::Result<()> {
+    let (before, after) = diff.executed;
+    writeln!(out, "instructions: {}", movement(before, after))?;
+    let tapes = diff.heads.len() > 1;
+    let prefix = |tape: usize| {
+        if tapes {
+            format!("tape {tape} ")
+        } else {
+            String::new()
+        }
+    };
+    if tapes {
+        let (before, after) = diff.tape_index;
+        writeln!(out, "selected tape: {before} -> {after}")?;
+    }
+    for (tape, (before, after)) in diff.heads.iter().enumerate() {
+        let (before, after) = (*before as u64, *after as u64);
+        writeln!(out, "{}head: {}", prefix(tape), movement(before, after))?;
+    }
+    for change in &diff.changes {
+        writeln!(
+            out,
+            "{}cell {}: {} -> {}",
+            prefix(change.tape),
+            change.cell,
+            change.old,
+            change.new
+        )?;
+    }
+    match diff.changes.len() {
+        1 => writeln!(out, "1 cell changed"),
+        count => writeln!(out, "{count} cells changed"),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use bft_interp::CellChange;
+
+    #[test]
+    fn reporting() {
+        let mut diff = TapeDiff {
+            changes: vec![CellChange {
+                tape: 0,
+                cell: 4,
+                old: 0,
+                new: 72,
+            }],
+            heads: vec![(7, 3)],
+            tape_index: (0, 0),
+            executed: (1200, 5400),
+        };
+        let mut out = Vec::new();
+        report(&diff, &mut out).unwrap();
+        assert_eq!(
+            String::from_utf8(out).unwrap(),
+            "instructions: 1200 -> 5400 (+4200)\n\
+             head: 7 -> 3 (-4)\n\
+             cell 4: 0 -> 72\n\
+             1 cell changed\n"
+        );
+
+        diff.heads.push((0, 0));
+        diff.tape_index = (0, 1);
+        diff.changes.clear();
+        let mut out = Vec::new();
+        report(&diff, &mut out).unwrap();
+        assert_eq!(
+            String::from_utf8(out).unwrap(),
+            "instructions: 1200 -> 5400 (+4200)\n\
+             selected tape: 0 -> 1\n\
+             tape 0 head: 7 -> 3 (-4)\n\
+             tape 1 head: 0 -> 0\n\
+             0 cells changed\n"
+        );
+    }
+
+    #[test]
+    fn diffing_files() {
+        let dir = std::env::temp_dir().join(format!("bft-snapshot-{}", std::process::id()));
+        fs::create_dir_all(&dir).unwrap();
+        let mut program = bft_types::BFprogram::new("a.b", b"+>++");
+        program.validate_brackets().unwrap();
+        let mut vm: BFVM<u8> = BFVM::new(None, false);
+        let (before, after) = (dir.join("before.bfsnap"), dir.join("after.bfsnap"));
+        fs::write(&before, vm.checkpoint(&program)).unwrap();
+        vm.run(&program, &mut io::empty(), &mut io::sink()).unwrap();
+        fs::write(&after, vm.checkpoint(&program)).unwrap();
+
+        let changed = diff(&before, &after).unwrap();
+        assert_eq!(changed.changes.len(), 2);
+        assert_eq!(changed.heads, [(0, 1)]);
+        fs::write(&after, b"damaged").unwrap();
+        assert!(diff(&before, &after)
+            .unwrap_err()
+            .to_string()
+            .ends_with("after.bfsnap: The checkpoint is damaged or incomplete"));
+        fs::remove_dir_all(&dir).unwrap();
+    }
+}
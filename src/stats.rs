@@ -0,0 +1,228 @@
+//! Static metrics about programs, gathered without running them.
+
+use std::fmt;
+use std::fmt::{Display, Formatter};
+use std::ops::RangeInclusive;
+use std::path::Path;
+
+use serde_json::{json, Value};
+
+use bft_types::{Alphabet, BFprogram, InputInstruction, Instruction, Token};
+
+use crate::disasm::symbol;
+
+/// Metrics for a single program.
+#[derive(Debug, PartialEq)]
+pub struct Stats {
+    /// Size of the source file in bytes.
+    pub size: usize,
+
+    /// How many times each instruction is used, in order of first use.
+    pub counts: Vec<(Instruction, usize)>,
+
+    /// Number of loops.
+    pub loops: usize,
+
+    /// How deeply the loops are nested.
+    pub max_depth: usize,
+
+    /// The number of cells the head visits if each loop body runs once.
+    pub tape_usage: usize,
+
+    /// The cells the head can reach, relative to where it starts, if that can be bounded.
+    pub head_range: Option<RangeInclusive<i64>>,
+
+    /// Number of bytes that aren't part of an instruction.
+    pub comment_bytes: usize,
+}
+
+/// A conservative bound on the cells the head can reach, relative to where it starts, or `None`
+/// if it can't be bounded.
+///
+/// A loop whose body moves the head back to where it started is balanced: each time it runs, it
+/// visits the same cells, so following the program once, as though each loop body ran once,
+/// visits every cell the head can reach. Any loop that isn't balanced may walk along the tape,
+/// and procedures, forks and other tapes aren't followed, so programs using them are unbounded.
+#[must_use]
+pub fn head_range(instructions: &[InputInstruction]) -> Option<RangeInclusive<i64>> {
+    // Where the head was when each open loop was entered.
+    let mut entered = Vec::new();
+    let (mut offset, mut lowest, mut highest) = (0i64, 0i64, 0i64);
+    for inst in instructions {
+        match inst.instruction() {
+            Instruction::MoveLeft => offset -= 1,
+            Instruction::MoveRight => offset += 1,
+            Instruction::BeginLoop => entered.push(offset),
+            Instruction::EndLoop if entered.pop() != Some(offset) => return None,
+            Instruction::BeginProcedure
+            | Instruction::EndProcedure
+            | Instruction::CallProcedure
+            | Instruction::Fork
+            | Instruction::NextTape
+            | Instruction::PreviousTape => return None,
+            _ => {}
+        }
+        lowest = lowest.min(offset);
+        highest = highest.max(offset);
+    }
+    entered.is_empty().then_some(lowest..=highest)
+}
+
+impl Stats {
+    /// Gather metrics for the program in `data`.
+    #[must_use]
+    pub fn of(source_name: &Path, data: &[u8]) -> Self {
+        let program = BFprogram::new(source_name, data);
+        let mut counts: Vec<(Instruction, usize)> = Vec::new();
+        let mut loops = 0;
+        let mut depth = 0usize;
+        let mut max_depth = 0;
+        let (mut offset, mut lowest, mut highest) = (0i64, 0i64, 0i64);
+        for inst in program.instructions() {
+            let inst = *inst.instruction();
+            match counts.iter_mut().find(|(i, _)| *i == inst) {
+                Some((_, count)) => *count += 1,
+                None => counts.push((inst, 1)),
+            }
+            match inst {
+                Instruction::BeginLoop => {
+                    loops += 1;
+                    depth += 1;
+                    max_depth = max_depth.max(depth);
+                }
+                Instruction::EndLoop => depth = depth.saturating_sub(1),
+                Instruction::MoveLeft => offset -= 1,
+                Instruction::MoveRight => offset += 1,
+                _ => {}
+            }
+            lowest = lowest.min(offset);
+            highest = highest.max(offset);
+        }
+        let comment_bytes = Alphabet::default()
+            .tokenize(data)
+            .iter()
+            .map(|token| match token {
+                Token::Comment(comment) => comment.len(),
+                Token::Instruction(..) => 0,
+            })
+            .sum();
+        Stats {
+            size: data.len(),
+            counts,
+            loops,
+            max_depth,
+            tape_usage: usize::try_from(highest - lowest + 1).unwrap_or(usize::MAX),
+            head_range: head_range(program.instructions()),
+            comment_bytes,
+        }
+    }
+
+    /// The total number of instructions.
+    #[must_use]
+    pub fn instructions(&self) -> usize {
+        self.counts.iter().map(|(_, count)| count).sum()
+    }
+
+    /// The proportion of the source that isn't instructions, from 0 to 1.
+    #[must_use]
+    #[allow(clippy::cast_precision_loss)]
+    pub fn comment_ratio(&self) -> f64 {
+        if self.size == 0 {
+            0.0
+        } else {
+            self.comment_bytes as f64 / self.size as f64
+        }
+    }
+
+    /// The metrics as a JSON object.
+    #[must_use]
+    pub fn to_json(&self) -> Value {
+        let counts: serde_json::Map<String, Value> = self
+            .counts
+            .iter()
+            .map(|(inst, count)| (symbol(*inst).to_string(), Value::from(*count)))
+            .collect();
+        json!({
+            "size": self.size,
+            "instructions": self.instructions(),
+            "counts": counts,
+            "loops": self.loops,
+            "max_depth": self.max_depth,
+            "tape_usage": self.tape_usage,
+            "head_range": self.head_range.as_ref().map(|range| json!({
+                "lowest": range.start(),
+                "highest": range.end(),
+            })),
+            "comment_ratio": self.comment_ratio(),
+        })
+    }
+}
+
+impl Display for Stats {
+    fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
+        writeln!(f, "file size: {} bytes", self.size)?;
+        writeln!(f, "instructions: {}", self.instructions())?;
+        for (inst, count) in &self.counts {
+            writeln!(f, "  {}  {inst}: {count}", symbol(*inst))?;
+        }
+        writeln!(f, "loops: {}", self.loops)?;
+        writeln!(f, "maximum nesting depth: {}", self.max_depth)?;
+        writeln!(f, "estimated minimum tape usage: {} cells", self.tape_usage)?;
+        match &self.head_range {
+            Some(range) => writeln!(
+                f,
+                "head range: {} to {} from the start",
+                range.start(),
+                range.end()
+            )?,
+            None => writeln!(f, "head range: unbounded")?,
+        }
+        writeln!(f, "comment ratio: {:.1}%", self.comment_ratio() * 100.0)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn metrics() {
+        let stats = Stats::of(Path::new("stats.test"), b"++ two\n[>[-<+>]<<]");
+        assert_eq!(stats.size, 18);
+        assert_eq!(stats.instructions(), 13);
+        assert_eq!(stats.counts[0], (Instruction::Increment, 3));
+        assert_eq!(stats.loops, 2);
+        assert_eq!(stats.max_depth, 2);
+        assert_eq!(stats.tape_usage, 3);
+        assert_eq!(stats.head_range, None);
+        assert_eq!(stats.comment_bytes, 5);
+        assert_eq!(
+            stats.to_string().lines().last(),
+            Some("comment ratio: 27.8%")
+        );
+        assert_eq!(stats.to_json()["counts"]["+"], 3);
+    }
+
+    fn range_of(code: &str) -> Option<RangeInclusive<i64>> {
+        head_range(BFprogram::new("stats.test", code.as_bytes()).instructions())
+    }
+
+    #[test]
+    fn head_ranges() {
+        assert_eq!(range_of(">>[-<+>]<[->>>+<<<]"), Some(0..=4));
+        assert_eq!(range_of("<>"), Some(-1..=0));
+        assert_eq!(range_of("+[>+]"), None);
+        assert_eq!(range_of(",[>,]"), None);
+        assert_eq!(range_of("[[]>]"), None);
+        assert_eq!(range_of("[>"), None);
+        assert_eq!(range_of("+>]"), None);
+    }
+
+    #[test]
+    fn empty_program() {
+        let stats = Stats::of(Path::new("stats.test"), b"");
+        assert_eq!(stats.tape_usage, 1);
+        assert_eq!(stats.head_range, Some(0..=0));
+        assert!(stats.comment_ratio().abs() < f64::EPSILON);
+    }
+}
@@ -0,0 +1,302 @@
+//! Recording execution traces, and replaying them afterwards for post-mortem debugging.
+//!
+//! A trace is a text file starting with a `bft-trace 1` header and a `program PATH` line,
+//! followed by one line per executed instruction:
+//!
+//! ```text
+//! PC LINE:COLUMN HEAD [CELL VALUE]
+//! ```
+//!
+//! where `PC` is the index of the instruction, `HEAD` is the head position after executing it,
+//! and `CELL VALUE` is present when the instruction wrote to the tape.
+
+use std::collections::BTreeMap;
+use std::error::Error;
+use std::io;
+use std::io::{BufRead, Read, Write};
+use std::path::PathBuf;
+
+use bft_interp::{StepOutcome, BFVM};
+use bft_types::{BFprogram, Instruction};
+
+const HEADER: &str = "bft-trace 1";
+
+/// A single executed instruction from a trace.
+#[derive(Debug, PartialEq)]
+pub struct TraceStep {
+    /// Index of the instruction that was executed.
+    pub pc: usize,
+
+    /// Location of the instruction in the source file.
+    pub location: String,
+
+    /// Position of the head after the instruction was executed.
+    pub head: usize,
+
+    /// The cell written by the instruction, and its new value.
+    pub write: Option<(usize, u8)>,
+}
+
+/// An execution trace loaded from a file.
+#[derive(Debug)]
+pub struct Trace {
+    /// The program that was run to produce the trace.
+    pub program: PathBuf,
+
+    /// Every instruction executed, in order.
+    pub steps: Vec<TraceStep>,
+}
+
+fn invalid(line_number: usize, message: &str) -> io::Error {
+    io::Error::new(
+        io::ErrorKind::InvalidData,
+        format!("trace line {line_number}: {message}"),
+    )
+}
+
+fn parse_step(line: &str, line_number: usize) -> io::Result<TraceStep> {
+    let fields: Vec<&str> = line.split_whitespace().collect();
+    let number = |idx: usize| -> io::Result<usize> {
+        fields[idx]
+            .parse()
+            .map_err(|_| invalid(line_number, &format!("bad number '{}'", fields[idx])))
+    };
+    match fields.len() {
+        3 | 5 => Ok(TraceStep {
+            pc: number(0)?,
+            location: fields[1].to_string(),
+            head: number(2)?,
+            write: if fields.len() == 5 {
+                let value = fields[4]
+                    .parse()
+                    .map_err(|_| invalid(line_number, &format!("bad value '{}'", fields[4])))?;
+                Some((number(3)?, value))
+            } else {
+                None
+            },
+        }),
+        _ => Err(invalid(line_number, "expected 3 or 5 fields")),
+    }
+}
+
+impl Trace {
+    /// Read a trace previously written by [`run_traced`].
+    ///
+    /// # Errors
+    /// Fails if the trace can't be read, or isn't in the expected format.
+    pub fn load<R: BufRead>(input: R) -> io::Result<Trace> {
+        let mut lines = input.lines();
+        if lines.next().transpose()?.as_deref() != Some(HEADER) {
+            return Err(invalid(1, "not a bft trace"));
+        }
+        let program = lines
+            .next()
+            .transpose()?
+            .and_then(|line| line.strip_prefix("program ").map(PathBuf::from))
+            .ok_or_else(|| invalid(2, "missing program name"))?;
+        let steps = lines
+            .enumerate()
+            .map(|(idx, line)| parse_step(&line?, idx + 3))
+            .collect::<io::Result<_>>()?;
+        Ok(Trace { program, steps })
+    }
+
+    /// Reconstruct the tape after the first `step` instructions have executed. Cells that were
+    /// never written are left out.
+    #[must_use]
+    pub fn tape_at(&self, step: usize) -> BTreeMap<usize, u8> {
+        self.steps
+            .iter()
+            .take(step)
+            .filter_map(|s| s.write)
+            .collect()
+    }
+
+    /// Find the first step that wrote to `cell`, counting steps from 1.
+    #[must_use]
+    pub fn first_write(&self, cell: usize) -> Option<(usize, &TraceStep)> {
+        self.steps
+            .iter()
+            .enumerate()
+            .find(|(_, s)| s.write.is_some_and(|(c, _)| c == cell))
+            .map(|(idx, s)| (idx + 1, s))
+    }
+}
+
+/// Run `program` to completion, recording every executed instruction to `trace`.
+///
+/// # Errors
+/// Fails if the program fails, or if writing the trace fails.
+pub fn run_traced<R: Read, W: Write, T: Write>(
+    program: &BFprogram,
+    vm: &mut BFVM<u8>,
+    input: &mut R,
+    output: &mut W,
+    trace: &mut T,
+) -> Result<(), Box<dyn Error>> {
+    writeln!(trace, "{HEADER}")?;
+    writeln!(trace, "program {}", program.source().display())?;
+    let mut outcome = StepOutcome::Running;
+    while outcome == StepOutcome::Running {
+        outcome = vm.step(program, input, output)?;
+        let Some(pc) = vm.last_executed() else {
+            break;
+        };
+        let inst = &program.instructions()[pc];
+        write!(trace, "{} {} {}", pc, inst.location(), vm.head())?;
+        match inst.instruction() {
+            Instruction::Increment
+            | Instruction::Decrement
+            | Instruction::Input
+            | Instruction::Fork
+            | Instruction::Retrieve
+            | Instruction::ShiftRight
+            | Instruction::ShiftLeft
+            | Instruction::Not
+            | Instruction::Xor
+            | Instruction::And
+            | Instruction::Or
+            | Instruction::Random => {
+                write!(trace, " {} {}", vm.head(), vm.tape()[vm.head()])?;
+            }
+            _ => {}
+        }
+        writeln!(trace)?;
+    }
+    // Output closed by whatever is reading it stops the program quietly, as with `BFVM::run`.
+    match output.flush() {
+        Err(err) if err.kind() != io::ErrorKind::BrokenPipe => return Err(err.into()),
+        _ => {}
+    }
+    trace.flush()?;
+    Ok(())
+}
+
+/// Print the state reconstructed from `trace` at `step`, along with `count` cells starting at
+/// `start` (or the head).
+///
+/// # Errors
+/// Fails if the report can't be written.
+pub fn report<W: Write>(
+    trace: &Trace,
+    step: Option<usize>,
+    start: Option<usize>,
+    count: usize,
+    out: &mut W,
+) -> io::Result<()> {
+    let step = step.unwrap_or(trace.steps.len()).min(trace.steps.len());
+    let head = if let Some(s) = step.checked_sub(1).map(|idx| &trace.steps[idx]) {
+        writeln!(
+            out,
+            "step {} of {}: executed {} at {}:{}, head at {}",
+            step,
+            trace.steps.len(),
+            s.pc,
+            trace.program.display(),
+            s.location,
+            s.head
+        )?;
+        s.head
+    } else {
+        writeln!(out, "step 0 of {}: head at 0", trace.steps.len())?;
+        0
+    };
+    let tape = trace.tape_at(step);
+    let start = start.unwrap_or(head);
+    for cell in start..start.saturating_add(count) {
+        let marker = if cell == head { " <- head" } else { "" };
+        writeln!(
+            out,
+            "cell {} = {}{}",
+            cell,
+            tape.get(&cell).copied().unwrap_or_default(),
+            marker
+        )?;
+    }
+    Ok(())
+}
+
+/// Print the first step of `trace` that wrote to `cell`.
+///
+/// # Errors
+/// Fails if the report can't be written.
+pub fn report_first_write<W: Write>(trace: &Trace, cell: usize, out: &mut W) -> io::Result<()> {
+    match trace.first_write(cell) {
+        Some((step, s)) => writeln!(
+            out,
+            "cell {} first written at step {} by {} at {}:{} with value {}",
+            cell,
+            step,
+            s.pc,
+            trace.program.display(),
+            s.location,
+            s.write.map_or(0, |(_, value)| value)
+        ),
+        None => writeln!(out, "cell {cell} is never written"),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::io::Cursor;
+
+    fn record(code: &str) -> Vec<u8> {
+        let mut program = BFprogram::new("mod.test", code.as_bytes());
+        program.validate_brackets().unwrap();
+        let mut vm = BFVM::new(None, false);
+        let mut trace = Vec::new();
+        run_traced(
+            &program,
+            &mut vm,
+            &mut io::empty(),
+            &mut io::sink(),
+            &mut trace,
+        )
+        .unwrap();
+        trace
+    }
+
+    #[test]
+    fn recording() {
+        assert_eq!(
+            String::from_utf8(record("+>\n+[-]")).unwrap(),
+            "bft-trace 1\nprogram mod.test\n0 1:1 0 0 1\n1 1:2 1\n2 2:1 1 1 1\n3 2:2 1\n4 2:3 1 1 0\n5 2:4 1\n"
+        );
+    }
+
+    #[test]
+    fn replaying() {
+        let trace = Trace::load(Cursor::new(record("++>+<-"))).unwrap();
+        assert_eq!(trace.program, PathBuf::from("mod.test"));
+        assert_eq!(trace.steps.len(), 6);
+        assert_eq!(trace.tape_at(0), BTreeMap::new());
+        assert_eq!(trace.tape_at(2), BTreeMap::from([(0, 2)]));
+        assert_eq!(trace.tape_at(6), BTreeMap::from([(0, 1), (1, 1)]));
+        assert_eq!(trace.first_write(1).map(|(step, _)| step), Some(4));
+        assert!(trace.first_write(2).is_none());
+
+        let mut out = Vec::new();
+        report(&trace, Some(4), None, 2, &mut out).unwrap();
+        assert_eq!(
+            String::from_utf8(out).unwrap(),
+            "step 4 of 6: executed 3 at mod.test:1:4, head at 1\ncell 1 = 1 <- head\ncell 2 = 0\n"
+        );
+
+        let mut out = Vec::new();
+        report_first_write(&trace, 1, &mut out).unwrap();
+        report_first_write(&trace, 7, &mut out).unwrap();
+        assert_eq!(
+            String::from_utf8(out).unwrap(),
+            "cell 1 first written at step 4 by 3 at mod.test:1:4 with value 1\ncell 7 is never written\n"
+        );
+    }
+
+    #[test]
+    fn bad_traces() {
+        let err = Trace::load(Cursor::new("hello")).unwrap_err();
+        assert_eq!(err.to_string(), "trace line 1: not a bft trace");
+        let err = Trace::load(Cursor::new("bft-trace 1\nprogram a.b\n1 1:1\n")).unwrap_err();
+        assert_eq!(err.to_string(), "trace line 3: expected 3 or 5 fields");
+    }
+}
@@ -0,0 +1,141 @@
+//! Converting programs between dialects that only differ in how instructions are spelled.
+
+use std::error::Error;
+
+use bft_types::{Alphabet, Instruction, Token};
+
+/// Rewrite `comment` so that none of it would be read as an instruction in `alphabet`. Comments
+/// that are only whitespace are reduced to their line breaks.
+pub(crate) fn clean_comment(comment: &[u8], alphabet: &Alphabet) -> Vec<u8> {
+    if comment.iter().all(u8::is_ascii_whitespace) {
+        return comment.iter().copied().filter(|c| *c == b'\n').collect();
+    }
+    let mut text = comment.to_vec();
+    loop {
+        let mut cleaned = Vec::with_capacity(text.len());
+        let mut pos = 0;
+        while pos < text.len() {
+            if let Some((_, len)) = alphabet.next_token(&text[pos..]) {
+                pos += len;
+            } else {
+                cleaned.push(text[pos]);
+                pos += 1;
+            }
+        }
+        // Removing a token can bring together the pieces of another one.
+        if cleaned.len() == text.len() {
+            return cleaned;
+        }
+        text = cleaned;
+    }
+}
+
+/// Write out `tokens` using the tokens in `alphabet`, dropping the comments unless
+/// `keep_comments` is set.
+fn render(
+    tokens: &[Token],
+    alphabet: &Alphabet,
+    keep_comments: bool,
+) -> Result<Vec<u8>, Box<dyn Error>> {
+    // Multi-character tokens, like those in Ook!, need to be kept apart from their neighbours.
+    let spaced = alphabet.tokens().any(|(token, _)| token.len() > 1);
+    let mut out = Vec::new();
+    for token in tokens {
+        let text = match token {
+            Token::Instruction(inst, written) => alphabet
+                .token_for(*inst)
+                .ok_or_else(|| {
+                    format!(
+                        "No token for '{}', the instruction '{inst}', in the target dialect",
+                        String::from_utf8_lossy(written)
+                    )
+                })?
+                .to_vec(),
+            Token::Comment(comment) if keep_comments => clean_comment(comment, alphabet),
+            Token::Comment(_) => continue,
+        };
+        let touching = out.last().is_some_and(|c: &u8| !c.is_ascii_whitespace())
+            && text.first().is_some_and(|c| !c.is_ascii_whitespace());
+        if spaced && touching {
+            out.push(b' ');
+        }
+        out.extend(text);
+    }
+    Ok(out)
+}
+
+/// Translate the program in `data`, written with the tokens in `from`, to use the tokens in `to`.
+///
+/// Comments are carried across, minus any text that would be read as instructions in the new
+/// dialect. If the comments can't be kept without changing the program, they are dropped.
+///
+/// # Errors
+/// Fails if the program uses an instruction that `to` has no token for.
+pub fn translate(data: &[u8], from: &Alphabet, to: &Alphabet) -> Result<Vec<u8>, Box<dyn Error>> {
+    let tokens = from.tokenize(data);
+    let out = render(&tokens, to, true)?;
+    if instructions(&out, to) == instructions(data, from) {
+        Ok(out)
+    } else {
+        render(&tokens, to, false)
+    }
+}
+
+/// The instructions in `data`, read using the tokens in `alphabet`.
+fn instructions(data: &[u8], alphabet: &Alphabet) -> Vec<Instruction> {
+    alphabet
+        .tokenize(data)
+        .into_iter()
+        .filter_map(|token| match token {
+            Token::Instruction(inst, _) => Some(inst),
+            Token::Comment(_) => None,
+        })
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn to_ook(code: &str) -> String {
+        String::from_utf8(
+            translate(code.as_bytes(), &Alphabet::default(), &Alphabet::ook()).unwrap(),
+        )
+        .unwrap()
+    }
+
+    #[test]
+    fn brainfuck_to_ook() {
+        assert_eq!(
+            to_ook("+> add one\n[-]"),
+            "Ook. Ook. Ook. Ook? add one\nOok! Ook? Ook! Ook! Ook? Ook!"
+        );
+        assert_eq!(to_ook("+Ook? Ook!+"), "Ook. Ook. Ook. Ook.");
+    }
+
+    #[test]
+    fn ook_to_brainfuck() {
+        let ook = b"Ook. Ook. Ook. Ook.\nOok! Ook? Ook! Ook! Ook? Ook! Ook! Ook. the end";
+        assert_eq!(
+            translate(ook, &Alphabet::ook(), &Alphabet::default()).unwrap(),
+            b"++\n[-]. the end"
+        );
+    }
+
+    #[test]
+    fn round_trip() {
+        let code = ",[.,] echo input\nuntil it runs out";
+        let ook = to_ook(code);
+        let back = translate(ook.as_bytes(), &Alphabet::ook(), &Alphabet::default()).unwrap();
+        assert_eq!(String::from_utf8(back).unwrap(), code);
+    }
+
+    #[test]
+    fn missing_tokens() {
+        let err = translate(b"-", &Alphabet::default(), &Alphabet::boolfuck()).unwrap_err();
+        assert_eq!(
+            err.to_string(),
+            "No token for '-', the instruction 'Decrement current location', in the target dialect"
+        );
+    }
+}
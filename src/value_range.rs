@@ -0,0 +1,378 @@
+//! Finding the values each cell may hold, without running the program.
+//!
+//! This is an abstract interpretation of the program over ranges of byte values. The program is
+//! followed once, and at the start of each loop, every cell that the loop body might change is
+//! assumed to hold any value, which covers every iteration of the loop at once. The head is
+//! followed relative to where the program starts, as long as each loop body moves it back to
+//! where it started. Once it can't be followed, every cell may hold any value.
+//!
+//! To keep the work bounded however large or deeply nested the program is, only so many cells
+//! and so many levels of loops are followed. Past that, the analysis forgets what it knows,
+//! which is always safe, just less precise.
+
+use std::collections::BTreeMap;
+use std::collections::BTreeSet;
+
+use bft_types::{BFprogram, InputInstruction, Instruction};
+
+/// Most cells followed at once, and most cells a loop body may change and still be followed.
+const MAX_TRACKED: usize = 256;
+
+/// Most levels of nested loops followed. Loops nested any deeper are assumed to change every
+/// cell.
+const MAX_DEPTH: usize = 256;
+
+/// The values a cell may hold.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct Value {
+    /// The smallest value the cell may hold.
+    pub min: u8,
+
+    /// The largest value the cell may hold.
+    pub max: u8,
+
+    /// Whether the cell holds a byte read by `,`, unchanged since it was read.
+    pub from_input: bool,
+}
+
+impl Value {
+    /// A cell that may hold any value.
+    const ANY: Value = Value {
+        min: 0,
+        max: u8::MAX,
+        from_input: false,
+    };
+
+    /// A cell that holds `value`.
+    const fn exactly(value: u8) -> Value {
+        Value {
+            min: value,
+            max: value,
+            from_input: false,
+        }
+    }
+
+    /// The value, if it is known.
+    #[must_use]
+    pub fn known(self) -> Option<u8> {
+        (self.min == self.max).then_some(self.min)
+    }
+
+    /// The values after adding one, wrapping around past 255.
+    fn incremented(self) -> Value {
+        match (self.min.checked_add(1), self.max.checked_add(1)) {
+            (Some(min), Some(max)) => Value {
+                min,
+                max,
+                from_input: false,
+            },
+            (None, _) => Value::exactly(0),
+            (Some(_), None) => Value::ANY,
+        }
+    }
+
+    /// The values after subtracting one, wrapping around past 0.
+    fn decremented(self) -> Value {
+        match (self.min.checked_sub(1), self.max.checked_sub(1)) {
+            (Some(min), Some(max)) => Value {
+                min,
+                max,
+                from_input: false,
+            },
+            (_, None) => Value::exactly(u8::MAX),
+            (None, Some(_)) => Value::ANY,
+        }
+    }
+}
+
+/// The values of the cells at some point in the program.
+#[derive(Clone, Debug)]
+struct State {
+    /// Where the head is, relative to where the program starts.
+    head: i64,
+
+    /// The values of cells that aren't `rest`, by their position relative to where the program
+    /// starts.
+    cells: BTreeMap<i64, Value>,
+
+    /// The values of every other cell.
+    rest: Value,
+}
+
+impl State {
+    /// The value of the current cell.
+    fn current(&self) -> Value {
+        self.cells.get(&self.head).copied().unwrap_or(self.rest)
+    }
+
+    fn set_current(&mut self, value: Value) {
+        if self.cells.len() >= MAX_TRACKED && !self.cells.contains_key(&self.head) {
+            self.forget();
+        }
+        self.cells.insert(self.head, value);
+    }
+
+    /// Forget everything about the cells, and where the head is.
+    fn forget(&mut self) {
+        *self = State {
+            head: 0,
+            cells: BTreeMap::new(),
+            rest: Value::ANY,
+        };
+    }
+}
+
+/// The cells a loop body may change, relative to where the head is when the loop starts, or
+/// `None` if the body doesn't always move the head back to where it started.
+type Changes = Option<BTreeSet<i64>>;
+
+/// Whether `inst` does something that the analysis doesn't follow, after which nothing is known
+/// about any cell.
+fn is_opaque(inst: Instruction) -> bool {
+    matches!(
+        inst,
+        Instruction::BeginProcedure
+            | Instruction::EndProcedure
+            | Instruction::CallProcedure
+            | Instruction::Fork
+            | Instruction::NextTape
+            | Instruction::PreviousTape
+    )
+}
+
+/// The bracket matching each `[`, and the cells each loop may change, by the index of its `[`.
+/// Loops nested more than `MAX_DEPTH` deep, or changing more than `MAX_TRACKED` cells, are
+/// treated as if their body didn't move the head back.
+fn loop_changes(instructions: &[InputInstruction]) -> BTreeMap<usize, (usize, Changes)> {
+    let mut loops = BTreeMap::new();
+    // The index of each open `[`, where the head was when it was entered, and what its body
+    // changes so far, by position relative to where the program starts.
+    let mut open: Vec<(usize, i64, Changes)> = Vec::new();
+    let mut head = 0i64;
+    for (idx, inst) in instructions.iter().enumerate() {
+        let inst = *inst.instruction();
+        match inst {
+            Instruction::MoveLeft => head -= 1,
+            Instruction::MoveRight => head += 1,
+            Instruction::BeginLoop => open.push((idx, head, Some(BTreeSet::new()))),
+            Instruction::EndLoop => {
+                let Some((start, entered, mut changes)) = open.pop() else {
+                    continue;
+                };
+                if head != entered || open.len() >= MAX_DEPTH {
+                    changes = None;
+                }
+                if let Some((_, _, outer)) = open.last_mut() {
+                    match (outer.as_mut(), &changes) {
+                        (Some(cells), Some(inner)) => {
+                            cells.extend(inner);
+                            if cells.len() > MAX_TRACKED {
+                                *outer = None;
+                            }
+                        }
+                        _ => *outer = None,
+                    }
+                }
+                let relative =
+                    changes.map(|cells| cells.iter().map(|cell| cell - entered).collect());
+                loops.insert(start, (idx, relative));
+            }
+            Instruction::Output => {}
+            _ if is_opaque(inst) => {
+                if let Some((_, _, changes)) = open.last_mut() {
+                    *changes = None;
+                }
+            }
+            _ => {
+                if let Some((_, _, changes)) = open.last_mut() {
+                    if let Some(cells) = changes {
+                        cells.insert(head);
+                        if cells.len() > MAX_TRACKED {
+                            *changes = None;
+                        }
+                    }
+                }
+            }
+        }
+    }
+    loops
+}
+
+/// The values the current cell may hold before each instruction of a program.
+#[derive(Clone, Debug)]
+pub struct ValueRanges {
+    /// The values before each instruction, or `None` for instructions that are never run.
+    before: Vec<Option<Value>>,
+}
+
+impl ValueRanges {
+    /// Find the values the current cell may hold before each instruction of `program`, assuming
+    /// cells are bytes.
+    #[must_use]
+    pub fn of(program: &BFprogram) -> Self {
+        let instructions = program.instructions();
+        let loops = loop_changes(instructions);
+        let mut before = vec![None; instructions.len()];
+        let mut state = State {
+            head: 0,
+            cells: BTreeMap::new(),
+            rest: Value::exactly(0),
+        };
+        // The state at the start of each loop that has been entered, covering every iteration.
+        let mut loop_heads: Vec<State> = Vec::new();
+        let mut idx = 0;
+        while let Some(inst) = instructions.get(idx) {
+            let inst = *inst.instruction();
+            before[idx] = Some(state.current());
+            match inst {
+                Instruction::MoveLeft => state.head -= 1,
+                Instruction::MoveRight => state.head += 1,
+                Instruction::Increment => state.set_current(state.current().incremented()),
+                Instruction::Decrement => state.set_current(state.current().decremented()),
+                Instruction::Input => state.set_current(Value {
+                    from_input: true,
+                    ..Value::ANY
+                }),
+                Instruction::Output | Instruction::EndProgram => {}
+                Instruction::BeginLoop => {
+                    let Some((end, changes)) = loops.get(&idx) else {
+                        // An unmatched `[`, after which nothing is known.
+                        state.forget();
+                        idx += 1;
+                        continue;
+                    };
+                    if state.current().known() == Some(0) {
+                        idx = end + 1;
+                        continue;
+                    }
+                    match changes {
+                        Some(cells) => {
+                            for cell in cells {
+                                state.cells.insert(state.head + cell, Value::ANY);
+                            }
+                            if state.cells.len() > MAX_TRACKED {
+                                state.forget();
+                            }
+                        }
+                        None => state.forget(),
+                    }
+                    loop_heads.push(state.clone());
+                    let value = state.current();
+                    state.set_current(Value {
+                        min: value.min.max(1),
+                        ..value
+                    });
+                }
+                Instruction::EndLoop => {
+                    match loop_heads.pop() {
+                        Some(head) => state = head,
+                        None => state.forget(),
+                    }
+                    state.set_current(Value::exactly(0));
+                }
+                _ if is_opaque(inst) => state.forget(),
+                _ => state.set_current(Value::ANY),
+            }
+            idx += 1;
+        }
+        ValueRanges { before }
+    }
+
+    /// The values the current cell may hold before the instruction at `idx`, or `None` if the
+    /// instruction is never run.
+    #[must_use]
+    pub fn before(&self, idx: usize) -> Option<Value> {
+        self.before.get(idx).copied().flatten()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn ranges(code: &str) -> Vec<Option<(u8, u8)>> {
+        let program = BFprogram::new("mod.test", code.as_bytes());
+        let ranges = ValueRanges::of(&program);
+        (0..program.instructions().len())
+            .map(|idx| ranges.before(idx).map(|value| (value.min, value.max)))
+            .collect()
+    }
+
+    #[test]
+    fn straight_line() {
+        assert_eq!(
+            ranges("++>-<-"),
+            [
+                Some((0, 0)),
+                Some((1, 1)),
+                Some((2, 2)),
+                Some((0, 0)),
+                Some((255, 255)),
+                Some((2, 2)),
+            ]
+        );
+        assert_eq!(
+            ranges(",+."),
+            [Some((0, 0)), Some((0, 255)), Some((0, 255))]
+        );
+    }
+
+    #[test]
+    fn loops() {
+        // The counter may be anything but zero in the loop, and the cell it adds to may be
+        // anything, but the third cell is never touched.
+        assert_eq!(
+            ranges("+++[>+<-]>>."),
+            [
+                Some((0, 0)),
+                Some((1, 1)),
+                Some((2, 2)),
+                Some((3, 3)),
+                Some((1, 255)),
+                Some((0, 255)),
+                Some((0, 255)),
+                Some((1, 255)),
+                Some((0, 254)),
+                Some((0, 0)),
+                Some((0, 255)),
+                Some((0, 0)),
+            ]
+        );
+        // A loop that starts on a zero cell never runs.
+        assert_eq!(ranges("[+]+"), [Some((0, 0)), None, None, Some((0, 0))]);
+        // Once the head can't be followed, nothing is known.
+        assert_eq!(
+            ranges("+[>]>."),
+            [
+                Some((0, 0)),
+                Some((1, 1)),
+                Some((1, 255)),
+                Some((0, 255)),
+                Some((0, 0)),
+                Some((0, 255))
+            ]
+        );
+    }
+
+    #[test]
+    fn deep_nesting() {
+        let depth = 200_000;
+        let code = "+[>".repeat(depth) + &"<]".repeat(depth) + ".";
+        let found = ranges(&code);
+        assert_eq!(found[1], Some((1, 1)));
+        assert_eq!(found.last(), Some(&Some((0, 0))));
+        // So many cells that they can't all be followed.
+        let found = ranges(&(">+".repeat(100_000) + "<+"));
+        assert_eq!(found[1], Some((0, 0)));
+        assert_eq!(found[199_999], Some((0, 255)));
+        assert_eq!(found.last(), Some(&Some((0, 255))));
+    }
+
+    #[test]
+    fn input() {
+        let program = BFprogram::new("mod.test", b",[-]");
+        let ranges = ValueRanges::of(&program);
+        assert!(ranges.before(1).is_some_and(|value| value.from_input));
+        assert!(ranges.before(2).is_some_and(|value| !value.from_input));
+    }
+}
@@ -0,0 +1,40 @@
+//! Settings from the environment, which take precedence over the configuration file. These run
+//! `bft` as a child process, so that setting its environment doesn't race with other tests.
+
+use std::fs;
+use std::path::Path;
+use std::process::Command;
+
+/// Run `bft` on a program writing random bytes in `dir`, with `args` and `env`, returning its
+/// output.
+fn random_bytes(dir: &Path, args: &[&str], env: &[(&str, &str)]) -> Vec<u8> {
+    let output = Command::new(env!("CARGO_BIN_EXE_bft"))
+        .current_dir(dir)
+        .env_remove("BFT_SEED")
+        .envs(env.iter().copied())
+        .args(args)
+        .arg("random.b")
+        .output()
+        .unwrap();
+    assert!(output.status.success(), "{output:?}");
+    output.stdout
+}
+
+#[test]
+fn environment_overrides_configuration() {
+    let dir = std::env::temp_dir().join(format!("bft-config-{}", std::process::id()));
+    fs::create_dir_all(&dir).unwrap();
+    fs::write(
+        dir.join("bft.toml"),
+        "seed = 7\nextensions = [\"random\"]\n",
+    )
+    .unwrap();
+    fs::write(dir.join("random.b"), "?.?.?.").unwrap();
+
+    let configured = random_bytes(&dir, &[], &[]);
+    assert_eq!(configured, random_bytes(&dir, &["--seed", "7"], &[]));
+    let overridden = random_bytes(&dir, &[], &[("BFT_SEED", "9")]);
+    assert_eq!(overridden, random_bytes(&dir, &["--seed", "9"], &[]));
+    assert_ne!(overridden, configured);
+    fs::remove_dir_all(&dir).unwrap();
+}